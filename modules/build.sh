@@ -2,4 +2,4 @@
 
 set -e
 
-cargo component build --target wasm32-unknown-unknown --release
+cargo component build --target wasm32-wasi --release
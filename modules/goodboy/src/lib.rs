@@ -0,0 +1,23 @@
+//! A sample module thanking whoever praises the bot: it's the easter egg the host used to have
+//! built in, as a module the operators can leave out.
+
+use libcommand::{impl_command, CommandClient};
+
+struct Component;
+
+impl libcommand::TrinityCommand for Component {
+    fn on_help(_topic: Option<&str>) -> String {
+        "Says thanks when told it's a good boy".to_owned()
+    }
+
+    fn on_msg(client: &mut CommandClient, content: &str) {
+        if !content.contains("you are a good boy") {
+            return;
+        }
+
+        client.react_with("👀".to_owned());
+        client.respond("thank [you](https://aapx.org/)");
+    }
+}
+
+impl_command!();
@@ -27,21 +27,217 @@ macro_rules! impl_command {
 
             fn consume_client(client: $crate::CommandClient) -> Vec<module::messaging::Action> {
                 let mut actions = Vec::new();
+                let notice = client.notice;
+                let urgency = client.urgency.map(|urgency| match urgency {
+                    $crate::Urgency::Low => module::messaging::Urgency::Low,
+                    $crate::Urgency::Normal => module::messaging::Urgency::Normal,
+                    $crate::Urgency::High => module::messaging::Urgency::High,
+                });
 
                 actions.extend(client.messages.into_iter().map(|msg| {
                     module::messaging::Action::Respond(module::messaging::Message {
                         text: msg.1,
                         html: None,
                         to: msg.0 .0,
+                        notice,
+                        urgency,
                     })
                 }));
 
-                actions.extend(
-                    client
-                        .reactions
-                        .into_iter()
-                        .map(|reaction| module::messaging::Action::React(reaction)),
-                );
+                actions.extend(client.replies.into_iter().map(|text| {
+                    module::messaging::Action::Reply(module::messaging::Message {
+                        text,
+                        html: None,
+                        to: String::new(),
+                        notice,
+                        urgency,
+                    })
+                }));
+
+                actions.extend(client.room_messages.into_iter().map(|(room, text)| {
+                    module::messaging::Action::SendToRoom(module::messaging::RoomMessage {
+                        room,
+                        message: module::messaging::Message {
+                            text,
+                            html: None,
+                            to: String::new(),
+                            notice,
+                            urgency,
+                        },
+                    })
+                }));
+
+                actions.extend(client.direct_messages.into_iter().map(|(user, text)| {
+                    module::messaging::Action::Dm(module::messaging::DirectMessage {
+                        user,
+                        message: module::messaging::Message {
+                            text,
+                            html: None,
+                            to: String::new(),
+                            notice,
+                            urgency,
+                        },
+                    })
+                }));
+
+                actions.extend(client.previews.into_iter().map(|(text, payload)| {
+                    module::messaging::Action::Preview(module::messaging::Preview {
+                        message: module::messaging::Message {
+                            text,
+                            html: None,
+                            to: String::new(),
+                            notice,
+                            urgency,
+                        },
+                        payload,
+                    })
+                }));
+
+                actions.extend(client.edits.into_iter().map(|(event_id, text)| {
+                    module::messaging::Action::Edit(module::messaging::Edit {
+                        event_id,
+                        message: module::messaging::Message {
+                            text,
+                            html: None,
+                            to: String::new(),
+                            notice,
+                            urgency,
+                        },
+                    })
+                }));
+
+                actions.extend(client.redactions.into_iter().map(|(event_id, reason)| {
+                    module::messaging::Action::Redact(module::messaging::Redaction {
+                        event_id,
+                        reason,
+                    })
+                }));
+
+                actions.extend(client.topic.map(module::messaging::Action::SetTopic));
+                actions.extend(client.room_name.map(module::messaging::Action::SetRoomName));
+
+                actions.extend(client.uploads.into_iter().map(|upload| {
+                    module::messaging::Action::Upload(module::messaging::Upload {
+                        filename: upload.filename,
+                        mime_type: upload.mime_type,
+                        data: upload.data,
+                    })
+                }));
+
+                actions.extend(client.progress_reports.into_iter().map(|report| {
+                    module::messaging::Action::Progress(module::messaging::Progress {
+                        key: report.key,
+                        text: report.text,
+                        percent: report.percent,
+                        done: report.done,
+                    })
+                }));
+
+                actions.extend(client.polls.into_iter().map(|change| match change {
+                    $crate::PollChange::Start(poll) => {
+                        module::messaging::Action::StartPoll(module::messaging::Poll {
+                            question: poll.question,
+                            answers: poll.answers,
+                            max_selections: poll.max_selections,
+                            disclosed: poll.disclosed,
+                        })
+                    }
+                    $crate::PollChange::End(poll_id) => module::messaging::Action::EndPoll(poll_id),
+                }));
+
+                if !client.reactions.is_empty() {
+                    actions.push(module::messaging::Action::React(
+                        module::messaging::Reaction {
+                            event_id: String::new(),
+                            keys: client.reactions,
+                        },
+                    ));
+                }
+                actions.extend(client.reactions_to.into_iter().map(|(event_id, keys)| {
+                    module::messaging::Action::React(module::messaging::Reaction { event_id, keys })
+                }));
+                actions.extend(client.unreactions.into_iter().map(|(event_id, keys)| {
+                    module::messaging::Action::Unreact(module::messaging::Reaction {
+                        event_id,
+                        keys,
+                    })
+                }));
+
+                actions.extend(client.timers.into_iter().map(|(delay_secs, payload)| {
+                    module::messaging::Action::Delayed(module::messaging::Delayed {
+                        delay_secs,
+                        payload,
+                    })
+                }));
+
+                actions.extend(client.jobs.into_iter().map(|job| match job {
+                    $crate::JobChange::Schedule(job) => {
+                        module::messaging::Action::Schedule(module::messaging::CronJob {
+                            name: job.name,
+                            schedule: job.schedule,
+                            timezone: job.timezone,
+                            payload: job.payload,
+                        })
+                    }
+                    $crate::JobChange::Unschedule(name) => {
+                        module::messaging::Action::Unschedule(name)
+                    }
+                }));
+
+                actions.extend(client.subscriptions.into_iter().map(|change| match change {
+                    $crate::SubscriptionChange::Subscribe { name, url } => {
+                        module::messaging::Action::Subscribe(module::messaging::Subscription {
+                            name,
+                            url,
+                        })
+                    }
+                    $crate::SubscriptionChange::Unsubscribe(name) => {
+                        module::messaging::Action::Unsubscribe(name)
+                    }
+                }));
+
+                actions.extend(client.publications.into_iter().map(|(topic, payload)| {
+                    module::messaging::Action::Publish(module::messaging::MqttMessage {
+                        topic,
+                        payload,
+                    })
+                }));
+
+                actions.extend(client.bus_events.into_iter().map(|(topic, payload)| {
+                    module::messaging::Action::Emit(module::messaging::BusEvent { topic, payload })
+                }));
+
+                actions.extend(client.alerts.into_iter().map(|change| {
+                    let alert = match change {
+                        $crate::AlertChange::Raise(alert) => module::messaging::Alert {
+                            key: alert.key,
+                            severity: alert.severity,
+                            title: alert.title,
+                            description: alert.description,
+                            resolved: false,
+                        },
+                        $crate::AlertChange::Resolve(key) => module::messaging::Alert {
+                            key,
+                            severity: String::new(),
+                            title: String::new(),
+                            description: String::new(),
+                            resolved: true,
+                        },
+                    };
+                    module::messaging::Action::RaiseAlert(alert)
+                }));
+
+                actions.extend(client.emails.into_iter().map(|email| {
+                    module::messaging::Action::SendEmail(module::messaging::Email {
+                        to: email.to,
+                        subject: email.subject,
+                        body: email.body,
+                    })
+                }));
+
+                if client.fall_through {
+                    actions.push(module::messaging::Action::FallThrough);
+                }
 
                 actions
             }
@@ -69,8 +265,10 @@ macro_rules! impl_command {
                     author_id: String,
                     _author_name: String,
                     room: String,
+                    trust: u8,
                 ) -> Vec<module::messaging::Action> {
-                    let mut client = $crate::CommandClient::new(room, author_id.clone());
+                    let mut client =
+                        $crate::CommandClient::new(room, author_id.clone()).with_trust(trust);
                     <Self as $crate::TrinityCommand>::on_msg(&mut client, &content);
                     consume_client(client)
                 }
@@ -84,6 +282,196 @@ macro_rules! impl_command {
                     <Self as $crate::TrinityCommand>::on_admin(&mut client, &cmd);
                     consume_client(client)
                 }
+
+                fn on_media(
+                    media: module::messaging::Media,
+                    author_id: String,
+                    room: String,
+                    trust: u8,
+                ) -> Vec<module::messaging::Action> {
+                    let mut client = $crate::CommandClient::new(room, author_id).with_trust(trust);
+                    let media = $crate::Media {
+                        event_id: media.event_id,
+                        kind: match media.kind {
+                            module::messaging::MediaKind::Image => $crate::MediaKind::Image,
+                            module::messaging::MediaKind::File => $crate::MediaKind::File,
+                            module::messaging::MediaKind::Audio => $crate::MediaKind::Audio,
+                            module::messaging::MediaKind::Video => $crate::MediaKind::Video,
+                        },
+                        body: media.body,
+                        mxc_uri: media.mxc_uri,
+                        mime_type: media.mime_type,
+                        size: media.size,
+                    };
+                    <Self as $crate::TrinityCommand>::on_media(&mut client, &media);
+                    consume_client(client)
+                }
+
+                fn on_ticket(ticket: module::messaging::Ticket) -> Vec<module::messaging::Action> {
+                    let mut client =
+                        $crate::CommandClient::new(ticket.room.clone(), ticket.author.clone());
+                    let ticket = $crate::Ticket {
+                        id: ticket.id,
+                        room: ticket.room,
+                        author: ticket.author,
+                        text: ticket.text,
+                        status: match ticket.status {
+                            module::messaging::TicketStatus::Open => $crate::TicketStatus::Open,
+                            module::messaging::TicketStatus::Claimed => {
+                                $crate::TicketStatus::Claimed
+                            }
+                            module::messaging::TicketStatus::Closed => $crate::TicketStatus::Closed,
+                        },
+                        assignee: ticket.assignee,
+                    };
+                    <Self as $crate::TrinityCommand>::on_ticket(&mut client, &ticket);
+                    consume_client(client)
+                }
+
+                fn on_timer(room: String, payload: String) -> Vec<module::messaging::Action> {
+                    let mut client = $crate::CommandClient::new(room, String::new());
+                    <Self as $crate::TrinityCommand>::on_timer(&mut client, &payload);
+                    consume_client(client)
+                }
+
+                fn on_cron(
+                    job: String,
+                    room: String,
+                    payload: String,
+                ) -> Vec<module::messaging::Action> {
+                    let mut client = $crate::CommandClient::new(room, String::new());
+                    <Self as $crate::TrinityCommand>::on_cron(&mut client, &job, &payload);
+                    consume_client(client)
+                }
+
+                fn on_stream_event(
+                    subscription: String,
+                    room: String,
+                    event: module::messaging::StreamEvent,
+                ) -> Vec<module::messaging::Action> {
+                    let mut client = $crate::CommandClient::new(room, String::new());
+                    let event = $crate::StreamEvent {
+                        kind: event.kind,
+                        data: event.data,
+                        id: event.id,
+                    };
+                    <Self as $crate::TrinityCommand>::on_stream_event(
+                        &mut client,
+                        &subscription,
+                        &event,
+                    );
+                    consume_client(client)
+                }
+
+                fn bus_topics() -> Vec<String> {
+                    <Self as $crate::TrinityCommand>::bus_topics()
+                }
+
+                fn on_bus_event(
+                    sender: String,
+                    room: String,
+                    event: module::messaging::BusEvent,
+                ) -> Vec<module::messaging::Action> {
+                    let mut client = $crate::CommandClient::new(room, String::new());
+                    <Self as $crate::TrinityCommand>::on_bus_event(
+                        &mut client,
+                        &sender,
+                        &event.topic,
+                        &event.payload,
+                    );
+                    consume_client(client)
+                }
+
+                fn on_poll_update(
+                    room: String,
+                    tally: module::messaging::PollTally,
+                ) -> Vec<module::messaging::Action> {
+                    let mut client = $crate::CommandClient::new(room, String::new());
+                    let tally = $crate::PollTally {
+                        poll_id: tally.poll_id,
+                        question: tally.question,
+                        counts: tally.counts,
+                        voters: tally.voters,
+                        ended: tally.ended,
+                    };
+                    <Self as $crate::TrinityCommand>::on_poll_update(&mut client, &tally);
+                    consume_client(client)
+                }
+
+                fn on_confirm(
+                    payload: String,
+                    author_id: String,
+                    room: String,
+                ) -> Vec<module::messaging::Action> {
+                    let mut client = $crate::CommandClient::new(room, author_id);
+                    <Self as $crate::TrinityCommand>::on_confirm(&mut client, &payload);
+                    consume_client(client)
+                }
+
+                fn on_reaction(
+                    event_id: String,
+                    key: String,
+                    author_id: String,
+                    room: String,
+                ) -> Vec<module::messaging::Action> {
+                    let mut client = $crate::CommandClient::new(room, author_id);
+                    <Self as $crate::TrinityCommand>::on_reaction(&mut client, &event_id, &key);
+                    consume_client(client)
+                }
+
+                fn on_join(
+                    user_id: String,
+                    display_name: Option<String>,
+                    room: String,
+                ) -> Vec<module::messaging::Action> {
+                    let mut client = $crate::CommandClient::new(room, user_id);
+                    <Self as $crate::TrinityCommand>::on_join(&mut client, display_name.as_deref());
+                    consume_client(client)
+                }
+
+                fn on_leave(user_id: String, room: String) -> Vec<module::messaging::Action> {
+                    let mut client = $crate::CommandClient::new(room, user_id);
+                    <Self as $crate::TrinityCommand>::on_leave(&mut client);
+                    consume_client(client)
+                }
+
+                fn state_event_types() -> Vec<String> {
+                    <Self as $crate::TrinityCommand>::state_event_types()
+                }
+
+                fn on_state_event(
+                    event_type: String,
+                    state_key: String,
+                    sender: String,
+                    room: String,
+                    content: String,
+                ) -> Vec<module::messaging::Action> {
+                    let mut client = $crate::CommandClient::new(room, sender);
+                    <Self as $crate::TrinityCommand>::on_state_event(
+                        &mut client,
+                        &event_type,
+                        &state_key,
+                        &content,
+                    );
+                    consume_client(client)
+                }
+
+                fn ticks() -> Vec<module::messaging::Tick> {
+                    <Self as $crate::TrinityCommand>::ticks()
+                        .into_iter()
+                        .map(|tick| module::messaging::Tick {
+                            name: tick.name,
+                            interval_secs: tick.interval_secs,
+                            room: tick.room,
+                        })
+                        .collect()
+                }
+
+                fn on_tick(tick: String, room: String) -> Vec<module::messaging::Action> {
+                    let mut client = $crate::CommandClient::new(room, String::new());
+                    <Self as $crate::TrinityCommand>::on_tick(&mut client, &tick);
+                    consume_client(client)
+                }
             }
         };
     };
@@ -91,11 +479,215 @@ macro_rules! impl_command {
 
 pub struct Recipient(pub String);
 
+/// How urgent the messages are: during the quiet hours, `Low` ones are held until their end, and
+/// `High` ones always get through.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Urgency {
+    Low,
+    Normal,
+    High,
+}
+
+/// Kind of a media message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MediaKind {
+    Image,
+    File,
+    Audio,
+    Video,
+}
+
+/// A media message, given to `TrinityCommand::on_media`.
+#[derive(Clone, Debug)]
+pub struct Media {
+    /// The message's event id, which `wit_media::download` takes.
+    pub event_id: String,
+    pub kind: MediaKind,
+    /// The file name, or a caption.
+    pub body: String,
+    /// `mxc://` URI of the content.
+    pub mxc_uri: String,
+    pub mime_type: Option<String>,
+    /// Size of the content, in bytes, if the sender gave it.
+    pub size: Option<u64>,
+}
+
+/// Status of a support ticket.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TicketStatus {
+    Open,
+    Claimed,
+    Closed,
+}
+
+/// A support ticket managed by the host's `!ticket` command.
+#[derive(Clone, Debug)]
+pub struct Ticket {
+    pub id: u64,
+    /// Room in which the ticket has been opened.
+    pub room: String,
+    /// Who opened the ticket.
+    pub author: String,
+    pub text: String,
+    pub status: TicketStatus,
+    /// Moderator who claimed the ticket, if any.
+    pub assignee: Option<String>,
+}
+
+/// A recurring job, run at the times matching a cron expression.
+#[derive(Clone, Debug)]
+pub struct CronJob {
+    /// Identifies the job in the room; scheduling a job with the same name replaces it.
+    pub name: String,
+    /// Cron expression: `minute hour day-of-month month day-of-week`.
+    pub schedule: String,
+    /// IANA timezone name the expression is read in, UTC if missing.
+    pub timezone: Option<String>,
+    pub payload: String,
+}
+
+/// A change to the recurring jobs, queued by the client.
+pub enum JobChange {
+    Schedule(CronJob),
+    Unschedule(String),
+}
+
+/// A change to the stream subscriptions, queued by the client.
+pub enum SubscriptionChange {
+    Subscribe { name: String, url: String },
+    Unsubscribe(String),
+}
+
+/// An event of a stream subscribed to with `CommandClient::subscribe`.
+#[derive(Clone, Debug)]
+pub struct StreamEvent {
+    /// The type of the server-sent event, `message` by default, or `chunk` for a chunk of a
+    /// response that isn't a server-sent events stream.
+    pub kind: String,
+    pub data: String,
+    pub id: Option<String>,
+}
+
+/// A recurring background tick, listed by `TrinityCommand::ticks`.
+#[derive(Clone, Debug)]
+pub struct Tick {
+    pub name: String,
+    /// At least 5 seconds.
+    pub interval_secs: u32,
+    /// The room `on_tick` is called in, by id or alias.
+    pub room: String,
+}
+
+/// An email sent with `CommandClient::send_email`.
+#[derive(Clone, Debug)]
+pub struct Email {
+    /// Addresses of the recipients.
+    pub to: Vec<String>,
+    pub subject: String,
+    pub body: String,
+}
+
+/// A file uploaded with `CommandClient::upload`.
+#[derive(Clone, Debug)]
+pub struct Upload {
+    pub filename: String,
+    /// e.g. `image/png`.
+    pub mime_type: String,
+    pub data: Vec<u8>,
+}
+
+/// A progress report queued with `CommandClient::progress`.
+#[derive(Clone, Debug)]
+pub struct ProgressReport {
+    /// Identifies the task among the module's tasks in the room.
+    pub key: String,
+    pub text: String,
+    /// From 0 to 100.
+    pub percent: Option<u8>,
+    /// Whether this is the final report of the task.
+    pub done: bool,
+}
+
+/// A poll started with `CommandClient::start_poll`.
+#[derive(Clone, Debug)]
+pub struct Poll {
+    pub question: String,
+    pub answers: Vec<String>,
+    /// How many answers each voter may select, at least 1.
+    pub max_selections: u8,
+    /// Whether the voters see the results before the poll ends.
+    pub disclosed: bool,
+}
+
+/// A change to the polls, queued by the client.
+pub enum PollChange {
+    Start(Poll),
+    End(String),
+}
+
+/// The tally of a poll, given to `TrinityCommand::on_poll_update`.
+#[derive(Clone, Debug)]
+pub struct PollTally {
+    /// The poll's event id, which `CommandClient::end_poll` takes.
+    pub poll_id: String,
+    pub question: String,
+    /// Number of votes for each answer, in the order of the answers.
+    pub counts: Vec<u32>,
+    /// Number of people who voted.
+    pub voters: u32,
+    /// Whether the poll ended; it's the last update then.
+    pub ended: bool,
+}
+
+/// An alert raised with `CommandClient::raise_alert`.
+#[derive(Clone, Debug)]
+pub struct Alert {
+    /// Deduplication key: raising an alert with the key of an open one only counts it.
+    pub key: String,
+    /// `info`, `warning` or `critical`.
+    pub severity: String,
+    pub title: String,
+    pub description: String,
+}
+
+/// A change to the alerts, queued by the client.
+pub enum AlertChange {
+    Raise(Alert),
+    Resolve(String),
+}
+
 pub struct CommandClient {
     inbound_msg_room: String,
     inbound_msg_author: String,
+    inbound_msg_trust: u8,
     pub messages: Vec<(Recipient, String)>,
+    pub replies: Vec<String>,
+    pub room_messages: Vec<(String, String)>,
+    pub direct_messages: Vec<(String, String)>,
+    pub edits: Vec<(String, String)>,
+    pub previews: Vec<(String, String)>,
     pub reactions: Vec<String>,
+    pub reactions_to: Vec<(String, Vec<String>)>,
+    pub unreactions: Vec<(String, Vec<String>)>,
+    pub redactions: Vec<(String, Option<String>)>,
+    pub uploads: Vec<Upload>,
+    pub progress_reports: Vec<ProgressReport>,
+    pub polls: Vec<PollChange>,
+    pub topic: Option<String>,
+    pub room_name: Option<String>,
+    pub timers: Vec<(u32, String)>,
+    pub jobs: Vec<JobChange>,
+    pub subscriptions: Vec<SubscriptionChange>,
+    pub publications: Vec<(String, String)>,
+    pub bus_events: Vec<(String, String)>,
+    pub emails: Vec<Email>,
+    pub alerts: Vec<AlertChange>,
+    /// Whether the messages are sent as notices; if missing, as configured for the module.
+    pub notice: Option<bool>,
+    /// How urgent the messages are; if missing, normal.
+    pub urgency: Option<Urgency>,
+    /// Whether the next modules handle the original message too.
+    pub fall_through: bool,
 }
 
 impl CommandClient {
@@ -103,11 +695,47 @@ impl CommandClient {
         Self {
             inbound_msg_room: room,
             inbound_msg_author: author,
+            inbound_msg_trust: 0,
             messages: Default::default(),
+            replies: Default::default(),
+            room_messages: Default::default(),
+            direct_messages: Default::default(),
+            edits: Default::default(),
+            previews: Default::default(),
             reactions: Default::default(),
+            reactions_to: Default::default(),
+            unreactions: Default::default(),
+            redactions: Default::default(),
+            uploads: Default::default(),
+            progress_reports: Default::default(),
+            polls: Default::default(),
+            topic: None,
+            room_name: None,
+            timers: Default::default(),
+            jobs: Default::default(),
+            subscriptions: Default::default(),
+            publications: Default::default(),
+            bus_events: Default::default(),
+            emails: Default::default(),
+            alerts: Default::default(),
+            notice: None,
+            urgency: None,
+            fall_through: false,
         }
     }
 
+    /// Sets how much the host trusts the author of the original message.
+    pub fn with_trust(mut self, trust: u8) -> Self {
+        self.inbound_msg_trust = trust;
+        self
+    }
+
+    /// How much does the host trust the author of the original message, from 0 (nothing known)
+    /// to 100 (admin)?
+    pub fn trust(&self) -> u8 {
+        self.inbound_msg_trust
+    }
+
     /// Who sent the original message we're reacting to?
     pub fn from(&self) -> &str {
         &self.inbound_msg_author
@@ -128,6 +756,52 @@ impl CommandClient {
         self.messages.push((Recipient(author), msg));
     }
 
+    /// Chooses whether the queued messages are sent as notices (`m.notice`), which other bots
+    /// ignore, regardless of the module's configuration.
+    pub fn send_as_notices(&mut self, notice: bool) {
+        self.notice = Some(notice);
+    }
+
+    /// Sets how urgent the queued messages are, e.g. `Urgency::Low` for a digest that can wait
+    /// until the end of the quiet hours.
+    pub fn set_urgency(&mut self, urgency: Urgency) {
+        self.urgency = Some(urgency);
+    }
+
+    /// Lets the next modules handle the original message too, e.g. for a module only logging or
+    /// reacting to it, instead of being the only one handling it.
+    pub fn fall_through(&mut self) {
+        self.fall_through = true;
+    }
+
+    /// Queues a message to be sent in reply to the original message, quoting it.
+    pub fn reply(&mut self, msg: impl Into<String>) {
+        self.replies.push(msg.into());
+    }
+
+    /// Queues a message to be sent in another room the bot is in, given by id or alias.
+    pub fn send_to_room(&mut self, room: impl Into<String>, msg: impl Into<String>) {
+        self.room_messages.push((room.into(), msg.into()));
+    }
+
+    /// Queues a message to be sent privately to a user, given by id, in the direct message room
+    /// with them.
+    pub fn dm(&mut self, user: impl Into<String>, msg: impl Into<String>) {
+        self.direct_messages.push((user.into(), msg.into()));
+    }
+
+    /// Queues a new version of a message the module sent in the same room, given by its event
+    /// id, e.g. as returned by `wit_sys::sent_messages`.
+    pub fn edit(&mut self, event_id: impl Into<String>, msg: impl Into<String>) {
+        self.edits.push((event_id.into(), msg.into()));
+    }
+
+    /// Queues a preview of what a command would do, for its author to confirm; once confirmed,
+    /// `on_confirm` is called back with the payload, which should describe what to do.
+    pub fn preview(&mut self, msg: impl Into<String>, payload: impl Into<String>) {
+        self.previews.push((msg.into(), payload.into()));
+    }
+
     pub fn react_with(&mut self, reaction: String) {
         self.reactions.push(reaction);
     }
@@ -135,6 +809,149 @@ impl CommandClient {
     pub fn react_with_ok(&mut self) {
         self.react_with("👌".to_owned());
     }
+
+    /// Queues reactions to a message of the room, given by its event id, e.g. reaction buttons
+    /// under a message the module sent.
+    pub fn react_to(&mut self, event_id: impl Into<String>, keys: Vec<String>) {
+        self.reactions_to.push((event_id.into(), keys));
+    }
+
+    /// Queues the removal of the bot's reactions with the keys from a message of the room, all of
+    /// them if there are none.
+    pub fn unreact(&mut self, event_id: impl Into<String>, keys: Vec<String>) {
+        self.unreactions.push((event_id.into(), keys));
+    }
+
+    /// Queues the redaction of a message of the room, given by its event id, which needs the bot
+    /// to have the power to redact.
+    pub fn redact(&mut self, event_id: impl Into<String>, reason: Option<String>) {
+        self.redactions.push((event_id.into(), reason));
+    }
+
+    /// Queues the redaction of the original message.
+    pub fn redact_original(&mut self, reason: Option<String>) {
+        self.redact(String::new(), reason);
+    }
+
+    /// Sets the topic of the room, if the bot has the power to; the last topic set wins.
+    pub fn set_topic(&mut self, topic: impl Into<String>) {
+        self.topic = Some(topic.into());
+    }
+
+    /// Sets the name of the room, if the bot has the power to; the last name set wins.
+    pub fn set_room_name(&mut self, name: impl Into<String>) {
+        self.room_name = Some(name.into());
+    }
+
+    /// Queues a file to upload and post in the room, e.g. a rendered graph; images, videos and
+    /// audio files are posted as such, according to their MIME type.
+    pub fn upload(
+        &mut self,
+        filename: impl Into<String>,
+        mime_type: impl Into<String>,
+        data: Vec<u8>,
+    ) {
+        self.uploads.push(Upload {
+            filename: filename.into(),
+            mime_type: mime_type.into(),
+            data,
+        });
+    }
+
+    /// Queues a progress report of a long task, e.g. `("deploy", "Deploying…", Some(40))`: the
+    /// first report with a given key posts a message in the room, and the next ones edit it, at a
+    /// limited rate.
+    pub fn progress(
+        &mut self,
+        key: impl Into<String>,
+        text: impl Into<String>,
+        percent: Option<u8>,
+    ) {
+        self.progress_reports.push(ProgressReport {
+            key: key.into(),
+            text: text.into(),
+            percent,
+            done: false,
+        });
+    }
+
+    /// Queues the final progress report of a long task, which is always applied.
+    pub fn progress_done(&mut self, key: impl Into<String>, text: impl Into<String>) {
+        self.progress_reports.push(ProgressReport {
+            key: key.into(),
+            text: text.into(),
+            percent: None,
+            done: true,
+        });
+    }
+
+    /// Queues a poll to start in the room; `on_poll_update` is called back with its tally after
+    /// each response.
+    pub fn start_poll(&mut self, poll: Poll) {
+        self.polls.push(PollChange::Start(poll));
+    }
+
+    /// Ends a poll started in the room, given by the `poll_id` of its tally.
+    pub fn end_poll(&mut self, poll_id: impl Into<String>) {
+        self.polls.push(PollChange::End(poll_id.into()));
+    }
+
+    /// Asks the host to call `on_timer` back with the payload, in the same room, after the given
+    /// number of seconds.
+    pub fn call_back_in(&mut self, delay_secs: u32, payload: impl Into<String>) {
+        self.timers.push((delay_secs, payload.into()));
+    }
+
+    /// Asks the host to call `on_cron` back regularly, in the same room.
+    pub fn schedule(&mut self, job: CronJob) {
+        self.jobs.push(JobChange::Schedule(job));
+    }
+
+    /// Removes a job scheduled in the same room.
+    pub fn unschedule(&mut self, name: impl Into<String>) {
+        self.jobs.push(JobChange::Unschedule(name.into()));
+    }
+
+    /// Asks the host to keep a connection to the URL open, and to call `on_stream_event` back
+    /// with its events, in the same room.
+    pub fn subscribe(&mut self, name: impl Into<String>, url: impl Into<String>) {
+        self.subscriptions.push(SubscriptionChange::Subscribe {
+            name: name.into(),
+            url: url.into(),
+        });
+    }
+
+    /// Removes a subscription made in the same room.
+    pub fn unsubscribe(&mut self, name: impl Into<String>) {
+        self.subscriptions
+            .push(SubscriptionChange::Unsubscribe(name.into()));
+    }
+
+    /// Asks the host to publish a message to the MQTT broker it's bridged to.
+    pub fn publish(&mut self, topic: impl Into<String>, payload: impl Into<String>) {
+        self.publications.push((topic.into(), payload.into()));
+    }
+
+    /// Asks the host to deliver an event to the other modules listening to its topic, in the same
+    /// room.
+    pub fn emit(&mut self, topic: impl Into<String>, payload: impl Into<String>) {
+        self.bus_events.push((topic.into(), payload.into()));
+    }
+
+    /// Asks the host to send an email through its SMTP server.
+    pub fn send_email(&mut self, email: Email) {
+        self.emails.push(email);
+    }
+
+    /// Asks the host to raise an alert in its alerts room, escalated until acknowledged.
+    pub fn raise_alert(&mut self, alert: Alert) {
+        self.alerts.push(AlertChange::Raise(alert));
+    }
+
+    /// Resolves the open alert with the given key.
+    pub fn resolve_alert(&mut self, key: impl Into<String>) {
+        self.alerts.push(AlertChange::Resolve(key.into()));
+    }
 }
 
 pub trait TrinityCommand {
@@ -164,4 +981,112 @@ pub trait TrinityCommand {
     ///
     /// By default this does nothing, as admin commands are facultative.
     fn on_admin(_client: &mut CommandClient, _command: &str) {}
+
+    /// Get notified when a support ticket gets opened, claimed or closed, to integrate with
+    /// external ticketing systems.
+    ///
+    /// The client's author is the ticket's author, and its room the ticket's room. By default this
+    /// does nothing.
+    fn on_ticket(_client: &mut CommandClient, _ticket: &Ticket) {}
+
+    /// Handle a media message: an image, a file, an audio or a video file, whose content can be
+    /// downloaded with `wit_media::download`, given the `media` capability.
+    ///
+    /// The client's author is the sender of the message. By default this does nothing.
+    fn on_media(_client: &mut CommandClient, _media: &Media) {}
+
+    /// Handle a timer set with `CommandClient::call_back_in` firing, with the payload given then.
+    ///
+    /// The client's room is the one where the timer was set, and it has no author, so responses
+    /// must be sent with `respond_to`. By default this does nothing.
+    fn on_timer(_client: &mut CommandClient, _payload: &str) {}
+
+    /// Handle a recurring job scheduled with `CommandClient::schedule` being due, with its name
+    /// and payload.
+    ///
+    /// As for timers, the client's room is the job's and it has no author. By default this does
+    /// nothing.
+    fn on_cron(_client: &mut CommandClient, _job: &str, _payload: &str) {}
+
+    /// Handle an event of a stream subscribed to with `CommandClient::subscribe`, with the
+    /// subscription's name.
+    ///
+    /// As for timers, the client's room is the subscription's and it has no author. By default
+    /// this does nothing.
+    fn on_stream_event(_client: &mut CommandClient, _subscription: &str, _event: &StreamEvent) {}
+
+    /// Topics of the events emitted by other modules that `on_bus_event` receives. By default,
+    /// none.
+    fn bus_topics() -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Handle an event emitted by another module with `CommandClient::emit`, on one of the
+    /// `bus_topics`.
+    ///
+    /// As for timers, the client's room is the one where the event was emitted and it has no
+    /// author. By default this does nothing.
+    fn on_bus_event(_client: &mut CommandClient, _sender: &str, _topic: &str, _payload: &str) {}
+
+    /// Handle the new tally of a poll started with `CommandClient::start_poll`, after a response
+    /// or when the poll ends.
+    ///
+    /// As for timers, the client's room is the poll's and it has no author. By default this does
+    /// nothing.
+    fn on_poll_update(_client: &mut CommandClient, _tally: &PollTally) {}
+
+    /// Handle the confirmation of a preview queued with `CommandClient::preview`, with its
+    /// payload, by doing what was previewed.
+    ///
+    /// The client's author is the one who confirmed, and its room the preview's. By default this
+    /// does nothing.
+    fn on_confirm(_client: &mut CommandClient, _payload: &str) {}
+
+    /// Handle a reaction with `key` to the message `event_id`, e.g. an approval by reacting ✅ to
+    /// a request the module posted, as listed by `wit_sys::sent_messages`.
+    ///
+    /// The client's author is the one who reacted. Reactions can't be reacted to. By default this
+    /// does nothing.
+    fn on_reaction(_client: &mut CommandClient, _event_id: &str, _key: &str) {}
+
+    /// Handle a user joining the room, e.g. to greet them; `display_name` is the one they joined
+    /// with, if any.
+    ///
+    /// The client's author is the user who joined. By default this does nothing.
+    fn on_join(_client: &mut CommandClient, _display_name: Option<&str>) {}
+
+    /// Handle a user leaving the room, or being kicked or banned from it.
+    ///
+    /// The client's author is the user who left. By default this does nothing.
+    fn on_leave(_client: &mut CommandClient) {}
+
+    /// Types of the state events `on_state_event` receives, e.g. `m.room.topic` or
+    /// `m.room.power_levels`. By default, none.
+    fn state_event_types() -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Handle a state event of one of the `state_event_types`, with its `content` as JSON, e.g. a
+    /// topic change.
+    ///
+    /// The client's author is the sender of the event. By default this does nothing.
+    fn on_state_event(
+        _client: &mut CommandClient,
+        _event_type: &str,
+        _state_key: &str,
+        _content: &str,
+    ) {
+    }
+
+    /// Background ticks `on_tick` is called for, e.g. every 30 seconds to poll a service. By
+    /// default, none.
+    fn ticks() -> Vec<Tick> {
+        Vec::new()
+    }
+
+    /// Handle one of the `ticks` being due, with its name.
+    ///
+    /// As for timers, the client's room is the tick's and it has no author. By default this does
+    /// nothing.
+    fn on_tick(_client: &mut CommandClient, _tick: &str) {}
 }
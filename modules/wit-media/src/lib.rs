@@ -0,0 +1,10 @@
+mod wit {
+    wit_bindgen::generate!("media-world" in "../../wit/media.wit");
+    pub use self::trinity::api::media::*;
+}
+
+/// Content of the media message given by its room and event id, e.g. as passed to
+/// `TrinityCommand::on_media`; fails if it's larger than the module is allowed to download.
+pub fn download(room: &str, event_id: &str) -> Result<Vec<u8>, ()> {
+    wit::download(room, event_id)
+}
@@ -3,4 +3,7 @@ mod wit {
     pub use self::trinity::api::sys::*;
 }
 
-pub use wit::rand_u64;
+pub use wit::{
+    describe_member, describe_room, is_member_of, is_opted_out, rand_u64, render_markdown,
+    resolve_room, sent_messages, MemberInfo, RoomInfo,
+};
@@ -0,0 +1,149 @@
+//! Activation windows of the modules: a module's configuration can restrict it to some hours of
+//! the day and days of the week, e.g. a meme module outside working hours only, or a stand-up
+//! prompt on weekdays only. Outside its window, a module isn't passed the messages at all.
+//!
+//! The `active_hours` (e.g. `18:00-09:00`), `active_days` (e.g. `mon-fri` or `sat,sun`) and
+//! `active_timezone` (an IANA name, UTC by default) keys apply to all the rooms; suffixed with a
+//! room id, e.g. `active_hours.!abc:example.com`, they override it in that room.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Datelike as _, NaiveTime, Utc, Weekday};
+use chrono_tz::Tz;
+use matrix_sdk::ruma::{OwnedRoomId, RoomId};
+
+use crate::schedule::{hours_include, parse_hours, parse_timezone};
+
+const ACTIVE_HOURS_KEY: &str = "active_hours";
+const ACTIVE_DAYS_KEY: &str = "active_days";
+const ACTIVE_TIMEZONE_KEY: &str = "active_timezone";
+
+/// The settings of a window, as configured for all the rooms or for one room.
+#[derive(Clone, Default)]
+struct Settings {
+    hours: Option<(NaiveTime, NaiveTime)>,
+    days: Option<Vec<Weekday>>,
+    timezone: Option<Tz>,
+}
+
+impl Settings {
+    /// Whether any restriction is set.
+    fn is_restricted(&self) -> bool {
+        self.hours.is_some() || self.days.is_some()
+    }
+
+    /// The settings of the room, falling back to the ones of all the rooms.
+    fn or(&self, defaults: &Settings) -> Settings {
+        Settings {
+            hours: self.hours.or(defaults.hours),
+            days: self.days.clone().or_else(|| defaults.days.clone()),
+            timezone: self.timezone.or(defaults.timezone),
+        }
+    }
+
+    /// Whether the window includes the given time.
+    fn includes(&self, now: DateTime<Utc>) -> bool {
+        let local = now.with_timezone(&self.timezone.unwrap_or(Tz::UTC));
+        if let Some(days) = &self.days {
+            if !days.contains(&local.weekday()) {
+                return false;
+            }
+        }
+        match self.hours {
+            Some(hours) => hours_include(hours, local.time()),
+            None => true,
+        }
+    }
+}
+
+fn parse_weekday(day: &str) -> anyhow::Result<Weekday> {
+    day.trim()
+        .parse::<Weekday>()
+        .map_err(|_| anyhow::anyhow!("invalid week day {day}"))
+}
+
+/// Parses a list of days and ranges of days, e.g. `mon-fri` or `sat,sun`.
+fn parse_days(value: &str) -> anyhow::Result<Vec<Weekday>> {
+    let mut days = Vec::new();
+    for part in value.split(',') {
+        match part.split_once('-') {
+            Some((start, end)) => {
+                let (mut day, end) = (parse_weekday(start)?, parse_weekday(end)?);
+                days.push(day);
+                while day != end {
+                    day = day.succ();
+                    days.push(day);
+                }
+            }
+            None => days.push(parse_weekday(part)?),
+        }
+    }
+    Ok(days)
+}
+
+/// The activation windows of a module.
+#[derive(Default)]
+struct Windows {
+    all_rooms: Settings,
+    rooms: HashMap<OwnedRoomId, Settings>,
+}
+
+pub(crate) struct Activation {
+    /// The windows of the modules configuring some.
+    modules: HashMap<String, Windows>,
+}
+
+impl Activation {
+    pub fn new(modules_config: &HashMap<String, HashMap<String, String>>) -> anyhow::Result<Self> {
+        let mut modules = HashMap::new();
+        for (module, config) in modules_config {
+            let mut windows = Windows::default();
+            for (key, value) in config {
+                let (name, room) = match key.split_once('.') {
+                    Some((name, room)) => (name, Some(room)),
+                    None => (key.as_str(), None),
+                };
+                if ![ACTIVE_HOURS_KEY, ACTIVE_DAYS_KEY, ACTIVE_TIMEZONE_KEY].contains(&name) {
+                    continue;
+                }
+                let settings = match room {
+                    Some(room) => {
+                        let room = OwnedRoomId::try_from(room).map_err(|err| {
+                            anyhow::anyhow!("invalid room in {key} for module {module}: {err}")
+                        })?;
+                        windows.rooms.entry(room).or_default()
+                    }
+                    None => &mut windows.all_rooms,
+                };
+                let parsed = match name {
+                    ACTIVE_HOURS_KEY => {
+                        parse_hours(value).map(|hours| settings.hours = Some(hours))
+                    }
+                    ACTIVE_DAYS_KEY => parse_days(value).map(|days| settings.days = Some(days)),
+                    _ => parse_timezone(Some(value.trim()))
+                        .map(|timezone| settings.timezone = Some(timezone)),
+                };
+                parsed
+                    .map_err(|err| anyhow::anyhow!("invalid {key} for module {module}: {err}"))?;
+            }
+            if windows.all_rooms.is_restricted()
+                || windows.rooms.values().any(Settings::is_restricted)
+            {
+                modules.insert(module.clone(), windows);
+            }
+        }
+        Ok(Self { modules })
+    }
+
+    /// Whether the module is active in the room at the moment.
+    pub fn is_active(&self, module: &str, room_id: &RoomId) -> bool {
+        let Some(windows) = self.modules.get(module) else {
+            return true;
+        };
+        let settings = match windows.rooms.get(room_id) {
+            Some(settings) => settings.or(&windows.all_rooms),
+            None => windows.all_rooms.clone(),
+        };
+        settings.includes(Utc::now())
+    }
+}
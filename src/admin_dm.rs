@@ -0,0 +1,23 @@
+use matrix_sdk::{
+    ruma::{events::room::message::RoomMessageEventContent, UserId},
+    Client,
+};
+
+use crate::{outbox, utils::dm_room};
+
+/// Sends a notification to the admin, in the direct message room with them.
+pub async fn notify(
+    client: &Client,
+    admin_user_id: &UserId,
+    text: &str,
+    html: Option<&str>,
+) -> anyhow::Result<()> {
+    let room = dm_room(client, admin_user_id).await?;
+    let content = if let Some(html) = html {
+        RoomMessageEventContent::text_html(text, html)
+    } else {
+        RoomMessageEventContent::text_plain(text)
+    };
+    outbox::send(&room, content).await?;
+    Ok(())
+}
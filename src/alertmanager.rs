@@ -0,0 +1,322 @@
+//! Receiver for the notifications of Prometheus Alertmanager, on the `/alertmanager` webhook:
+//! each notification, for a group of alerts, is rendered in a single message of the configured
+//! room, colored by severity, with the firing and resolved alerts listed separately. Alerts
+//! silenced or inhibited since they were grouped are marked as such, according to the
+//! Alertmanager API, and notifications of only such alerts aren't posted.
+
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{DateTime, Utc};
+use matrix_sdk::{
+    ruma::{events::room::message::RoomMessageEventContent, OwnedRoomId},
+    Client,
+};
+use serde::Deserialize;
+use tokio::time::Duration;
+use tracing::{debug, warn};
+
+use crate::{alerts::Severity, outbox};
+
+/// Configuration for the Alertmanager receiver.
+#[derive(Clone, Debug, Deserialize)]
+pub struct AlertmanagerConfig {
+    /// room where the notifications are posted.
+    pub room: OwnedRoomId,
+    /// base URL of the Alertmanager API, used to find out which alerts are silenced; the
+    /// `externalURL` of the notifications if missing.
+    pub api_url: Option<String>,
+    /// whether notifications whose firing alerts are all silenced or inhibited are posted anyway.
+    #[serde(default)]
+    pub show_silenced: bool,
+}
+
+/// Timeout of the requests to the Alertmanager API.
+const API_TIMEOUT: Duration = Duration::from_secs(5);
+/// Color of the resolved notifications.
+const RESOLVED_COLOR: &str = "#43a047";
+
+/// A notification of the Alertmanager webhook, for a group of alerts.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Notification {
+    #[serde(default)]
+    group_labels: BTreeMap<String, String>,
+    #[serde(default)]
+    common_labels: BTreeMap<String, String>,
+    #[serde(default, rename = "externalURL")]
+    external_url: String,
+    alerts: Vec<Alert>,
+    /// Number of alerts left out of the notification, past the receiver's `max_alerts`.
+    #[serde(default)]
+    truncated_alerts: u64,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Alert {
+    /// `firing` or `resolved`.
+    status: String,
+    #[serde(default)]
+    labels: BTreeMap<String, String>,
+    #[serde(default)]
+    annotations: BTreeMap<String, String>,
+    starts_at: Option<DateTime<Utc>>,
+    #[serde(default, rename = "generatorURL")]
+    generator_url: String,
+    #[serde(default)]
+    fingerprint: String,
+}
+
+impl Alert {
+    fn is_firing(&self) -> bool {
+        self.status == "firing"
+    }
+
+    fn severity(&self) -> Severity {
+        self.labels
+            .get("severity")
+            .and_then(|severity| Severity::parse(severity))
+            .unwrap_or_default()
+    }
+
+    fn summary(&self) -> &str {
+        ["summary", "description", "message"]
+            .iter()
+            .find_map(|key| self.annotations.get(*key))
+            .or_else(|| self.labels.get("alertname"))
+            .map_or("unnamed alert", |summary| summary.as_str())
+    }
+}
+
+/// Why an alert isn't notified anymore, according to the Alertmanager API.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Suppression {
+    Silenced,
+    Inhibited,
+}
+
+impl Suppression {
+    fn label(self) -> &'static str {
+        match self {
+            Self::Silenced => "🔕 silenced",
+            Self::Inhibited => "🔇 inhibited",
+        }
+    }
+}
+
+/// An alert as listed by the Alertmanager API.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiAlert {
+    fingerprint: String,
+    status: ApiAlertStatus,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiAlertStatus {
+    #[serde(default)]
+    silenced_by: Vec<String>,
+    #[serde(default)]
+    inhibited_by: Vec<String>,
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+pub(crate) struct Alertmanager {
+    config: Option<AlertmanagerConfig>,
+    http: reqwest::Client,
+}
+
+impl Alertmanager {
+    pub fn new(config: Option<AlertmanagerConfig>) -> anyhow::Result<Self> {
+        let http = reqwest::Client::builder().timeout(API_TIMEOUT).build()?;
+        Ok(Self { config, http })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.is_some()
+    }
+
+    /// Fetches the suppressed alerts from the Alertmanager API, by fingerprint.
+    async fn suppressed(&self, api_url: &str) -> anyhow::Result<HashMap<String, Suppression>> {
+        let url = format!(
+            "{}/api/v2/alerts?active=false&silenced=true&inhibited=true&unprocessed=false",
+            api_url.trim_end_matches('/')
+        );
+        let alerts: Vec<ApiAlert> = self
+            .http
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(alerts
+            .into_iter()
+            .filter_map(|alert| {
+                let suppression = if !alert.status.silenced_by.is_empty() {
+                    Suppression::Silenced
+                } else if !alert.status.inhibited_by.is_empty() {
+                    Suppression::Inhibited
+                } else {
+                    return None;
+                };
+                Some((alert.fingerprint, suppression))
+            })
+            .collect())
+    }
+
+    /// Posts a notification of Alertmanager in the configured room.
+    pub async fn receive(&self, client: &Client, notification: Notification) -> anyhow::Result<()> {
+        let Some(config) = &self.config else {
+            anyhow::bail!("the Alertmanager receiver isn't configured");
+        };
+        let Some(room) = client.get_room(&config.room) else {
+            anyhow::bail!("unknown Alertmanager room {}", config.room);
+        };
+
+        let api_url = config
+            .api_url
+            .as_deref()
+            .unwrap_or(&notification.external_url);
+        let suppressed = if api_url.is_empty() {
+            HashMap::new()
+        } else {
+            match self.suppressed(api_url).await {
+                Ok(suppressed) => suppressed,
+                Err(err) => {
+                    warn!("couldn't fetch the silenced alerts from Alertmanager: {err:#}");
+                    HashMap::new()
+                }
+            }
+        };
+
+        let (firing, resolved): (Vec<_>, Vec<_>) =
+            notification.alerts.iter().partition(|a| a.is_firing());
+        let all_suppressed = !firing.is_empty()
+            && resolved.is_empty()
+            && firing
+                .iter()
+                .all(|a| suppressed.contains_key(&a.fingerprint));
+        if all_suppressed && !config.show_silenced {
+            debug!("skipping an Alertmanager notification of silenced alerts");
+            return Ok(());
+        }
+
+        let content = Self::render(&notification, &firing, &resolved, &suppressed);
+        outbox::send(&room, content).await?;
+        Ok(())
+    }
+
+    fn render(
+        notification: &Notification,
+        firing: &[&Alert],
+        resolved: &[&Alert],
+        suppressed: &HashMap<String, Suppression>,
+    ) -> RoomMessageEventContent {
+        let labels = if notification.group_labels.is_empty() {
+            &notification.common_labels
+        } else {
+            &notification.group_labels
+        };
+        let group = labels
+            .iter()
+            .map(|(name, value)| format!("{name}={value}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let mut plain = String::new();
+        let mut html = String::new();
+
+        if !firing.is_empty() {
+            let severity = firing
+                .iter()
+                .map(|a| a.severity())
+                .max_by_key(|severity| *severity as u8)
+                .unwrap_or_default();
+            plain.push_str(&format!(
+                "{} [FIRING:{}] {group}",
+                severity.emoji(),
+                firing.len()
+            ));
+            html.push_str(&format!(
+                "{} <font color=\"{}\"><b>[FIRING:{}]</b></font> <b>{}</b><ul>",
+                severity.emoji(),
+                severity.color(),
+                firing.len(),
+                escape_html(&group)
+            ));
+            for alert in firing {
+                let mut line = format!("{} {}", alert.severity().emoji(), alert.summary());
+                if let Some(starts_at) = alert.starts_at {
+                    line.push_str(&format!(
+                        ", since {}",
+                        starts_at.format("%Y-%m-%d %H:%M UTC")
+                    ));
+                }
+                if let Some(suppression) = suppressed.get(&alert.fingerprint) {
+                    line.push_str(&format!(" ({})", suppression.label()));
+                }
+                plain.push_str(&format!("\n- {line}"));
+                html.push_str(&Self::html_item(alert, &line));
+            }
+            html.push_str("</ul>");
+        }
+
+        if !resolved.is_empty() {
+            if !plain.is_empty() {
+                plain.push('\n');
+            }
+            plain.push_str(&format!("✅ [RESOLVED:{}] {group}", resolved.len()));
+            html.push_str(&format!(
+                "✅ <font color=\"{RESOLVED_COLOR}\"><b>[RESOLVED:{}]</b></font> <b>{}</b><ul>",
+                resolved.len(),
+                escape_html(&group)
+            ));
+            for alert in resolved {
+                let line = alert.summary().to_owned();
+                plain.push_str(&format!("\n- {line}"));
+                html.push_str(&Self::html_item(alert, &line));
+            }
+            html.push_str("</ul>");
+        }
+
+        if notification.truncated_alerts > 0 {
+            plain.push_str(&format!(
+                "\n(and {} more alerts)",
+                notification.truncated_alerts
+            ));
+            html.push_str(&format!(
+                "<i>and {} more alerts</i><br>",
+                notification.truncated_alerts
+            ));
+        }
+        if !notification.external_url.is_empty() {
+            plain.push_str(&format!("\n{}", notification.external_url));
+            html.push_str(&format!(
+                "<a href=\"{}\">Alertmanager</a>",
+                escape_html(&notification.external_url)
+            ));
+        }
+
+        RoomMessageEventContent::text_html(plain, html)
+    }
+
+    fn html_item(alert: &Alert, line: &str) -> String {
+        if alert.generator_url.is_empty() {
+            format!("<li>{}</li>", escape_html(line))
+        } else {
+            format!(
+                "<li>{} (<a href=\"{}\">source</a>)</li>",
+                escape_html(line),
+                escape_html(&alert.generator_url)
+            )
+        }
+    }
+}
@@ -0,0 +1,497 @@
+//! Alerting pipeline, in the spirit of PagerDuty or Opsgenie: alerts raised through the webhook
+//! listener or by modules are posted in the alerts room, formatted by severity, and deduplicated by
+//! key while they're open. An alert is acknowledged by reacting to it, or with `!alert ack`; until
+//! then, it's escalated regularly, pinging again in the room and messaging the on-call user of the
+//! configured rotation directly.
+
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use matrix_sdk::{
+    room::Room,
+    ruma::{
+        events::room::message::RoomMessageEventContent, OwnedEventId, OwnedRoomId, OwnedUserId,
+        UserId,
+    },
+    Client,
+};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::Mutex,
+    time::{sleep, Duration},
+};
+use tracing::{debug, error, warn};
+
+use crate::{
+    host_table, maintenance::Maintenance, oncall, outbox, utils::dm_room, wasm, ShareableDatabase,
+};
+
+/// Name of the host table keeping the alerts.
+const TABLE: &str = "alerts";
+/// Key of the open alerts.
+const OPEN_KEY: &str = "open";
+/// Key of the last alert id.
+const LAST_ID_KEY: &str = "last_id";
+/// Delay between two checks of the alerts to escalate.
+const ESCALATION_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+const USAGE: &str = "usage: !alert (list|ack NUMBER|resolve NUMBER)";
+
+/// Configuration for the alerts.
+#[derive(Clone, Debug, Deserialize)]
+pub struct AlertsConfig {
+    /// room where the alerts are posted and handled.
+    pub room: OwnedRoomId,
+    /// delay after which an unacknowledged alert is escalated, and between two escalations.
+    #[serde(default = "default_ack_timeout_minutes")]
+    pub ack_timeout_minutes: u64,
+    /// number of escalations of an unacknowledged alert, after which it's left alone.
+    #[serde(default = "default_max_escalations")]
+    pub max_escalations: u32,
+    /// name of the `!oncall` rotation whose on-call user is messaged on escalations.
+    #[serde(default)]
+    pub rotation: Option<String>,
+}
+
+fn default_ack_timeout_minutes() -> u64 {
+    15
+}
+
+fn default_max_escalations() -> u32 {
+    3
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Severity {
+    Info,
+    #[default]
+    Warning,
+    Critical,
+}
+
+impl Severity {
+    pub fn parse(severity: &str) -> Option<Self> {
+        match severity.trim().to_lowercase().as_str() {
+            "info" => Some(Self::Info),
+            "warning" => Some(Self::Warning),
+            "critical" => Some(Self::Critical),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Info => "info",
+            Self::Warning => "warning",
+            Self::Critical => "critical",
+        }
+    }
+
+    pub fn emoji(self) -> &'static str {
+        match self {
+            Self::Info => "🔵",
+            Self::Warning => "🟠",
+            Self::Critical => "🔴",
+        }
+    }
+
+    pub fn color(self) -> &'static str {
+        match self {
+            Self::Info => "#1e88e5",
+            Self::Warning => "#fb8c00",
+            Self::Critical => "#e53935",
+        }
+    }
+}
+
+/// An alert being raised or resolved, by a webhook or a module.
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct NewAlert {
+    /// deduplication key: raising an alert with the key of an open one only counts it.
+    pub key: String,
+    #[serde(default)]
+    pub severity: Severity,
+    pub title: String,
+    #[serde(default)]
+    pub description: String,
+    /// whether the open alert with this key is resolved, instead.
+    #[serde(default)]
+    pub resolved: bool,
+}
+
+impl NewAlert {
+    /// Converts an alert raised by a module, defaulting to a warning for unknown severities.
+    pub fn from_wasm(alert: wasm::Alert) -> Self {
+        Self {
+            key: alert.key,
+            severity: Severity::parse(&alert.severity).unwrap_or_default(),
+            title: alert.title,
+            description: alert.description,
+            resolved: alert.resolved,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Alert {
+    id: u64,
+    key: String,
+    severity: Severity,
+    title: String,
+    description: String,
+    /// `webhook`, or the name of the module that raised it.
+    source: String,
+    /// Message posted for the alert, which is acknowledged by reacting to it.
+    event_id: Option<OwnedEventId>,
+    raised_at: DateTime<Utc>,
+    /// Number of times the alert was raised while open.
+    count: u32,
+    acked_by: Option<OwnedUserId>,
+    escalations: u32,
+    next_escalation: DateTime<Utc>,
+}
+
+impl Alert {
+    fn summary(&self) -> String {
+        let mut summary = format!(
+            "#{} {} [{}] {}",
+            self.id,
+            self.severity.emoji(),
+            self.severity.name(),
+            self.title
+        );
+        if self.count > 1 {
+            summary.push_str(&format!(" (×{})", self.count));
+        }
+        match &self.acked_by {
+            Some(user) => summary.push_str(&format!(", acknowledged by {user}")),
+            None => summary.push_str(", unacknowledged"),
+        }
+        summary
+    }
+
+    fn to_content(&self) -> RoomMessageEventContent {
+        let mut plain = format!(
+            "{} [{}] alert #{}: {}",
+            self.severity.emoji(),
+            self.severity.name().to_uppercase(),
+            self.id,
+            self.title
+        );
+        let mut html = format!(
+            "{} <font color=\"{}\"><b>[{}]</b></font> alert #{}: <b>{}</b>",
+            self.severity.emoji(),
+            self.severity.color(),
+            self.severity.name().to_uppercase(),
+            self.id,
+            escape_html(&self.title)
+        );
+        if !self.description.is_empty() {
+            plain.push_str(&format!("\n{}", self.description));
+            html.push_str(&format!(
+                "<br>{}",
+                escape_html(&self.description).replace('\n', "<br>")
+            ));
+        }
+        plain.push_str(&format!("\n(from {}; react to acknowledge)", self.source));
+        html.push_str(&format!(
+            "<br><i>from {}; react to acknowledge</i>",
+            escape_html(&self.source)
+        ));
+        RoomMessageEventContent::text_html(plain, html)
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+pub(crate) struct Alerts {
+    config: Option<AlertsConfig>,
+    db: ShareableDatabase,
+    /// Serializes the read-modify-write cycles on the alerts, including the posting of the new
+    /// ones, so their event id is recorded before any reaction is handled.
+    lock: Mutex<()>,
+}
+
+impl Alerts {
+    pub fn new(config: Option<AlertsConfig>, db: ShareableDatabase) -> Self {
+        Self {
+            config,
+            db,
+            lock: Mutex::new(()),
+        }
+    }
+
+    fn read_open(&self) -> anyhow::Result<Vec<Alert>> {
+        Ok(host_table::read_json(&self.db, TABLE, OPEN_KEY)?.unwrap_or_default())
+    }
+
+    fn write_open(&self, alerts: &[Alert]) -> anyhow::Result<()> {
+        host_table::write_json(&self.db, TABLE, OPEN_KEY, &alerts)
+    }
+
+    fn ack_timeout(config: &AlertsConfig) -> ChronoDuration {
+        ChronoDuration::minutes(config.ack_timeout_minutes as i64)
+    }
+
+    /// Raises or resolves an alert, coming from `source`, a webhook or a module.
+    pub async fn raise(
+        &self,
+        client: &Client,
+        source: &str,
+        new_alert: NewAlert,
+    ) -> anyhow::Result<()> {
+        let Some(config) = &self.config else {
+            warn!("dropping an alert from {source}, no alerts room is configured");
+            return Ok(());
+        };
+        let Some(room) = client.get_room(&config.room) else {
+            anyhow::bail!("unknown alerts room {}", config.room);
+        };
+
+        let _guard = self.lock.lock().await;
+        let mut alerts = self.read_open()?;
+        let existing = alerts.iter().position(|a| a.key == new_alert.key);
+
+        if new_alert.resolved {
+            let Some(index) = existing else {
+                debug!("no open alert {} to resolve", new_alert.key);
+                return Ok(());
+            };
+            let alert = alerts.remove(index);
+            self.write_open(&alerts)?;
+            let text = format!("✅ alert #{} resolved: {}", alert.id, alert.title);
+            outbox::send(&room, RoomMessageEventContent::text_plain(text)).await?;
+            return Ok(());
+        }
+
+        if let Some(index) = existing {
+            alerts[index].count += 1;
+            debug!("deduplicated alert {}", new_alert.key);
+            return self.write_open(&alerts);
+        }
+
+        let id = host_table::read_u64(&self.db, TABLE, LAST_ID_KEY)? + 1;
+        host_table::write_u64(&self.db, TABLE, LAST_ID_KEY, id)?;
+        let now = Utc::now();
+        let mut alert = Alert {
+            id,
+            key: new_alert.key,
+            severity: new_alert.severity,
+            title: new_alert.title,
+            description: new_alert.description,
+            source: source.to_owned(),
+            event_id: None,
+            raised_at: now,
+            count: 1,
+            acked_by: None,
+            escalations: 0,
+            next_escalation: now + Self::ack_timeout(config),
+        };
+        alert.event_id = Some(outbox::send(&room, alert.to_content()).await?);
+        alerts.push(alert);
+        self.write_open(&alerts)
+    }
+
+    /// Acknowledges the alert, returning a message describing the outcome.
+    async fn ack(
+        &self,
+        pick: impl Fn(&Alert) -> bool,
+        sender: &UserId,
+    ) -> anyhow::Result<Option<String>> {
+        let _guard = self.lock.lock().await;
+        let mut alerts = self.read_open()?;
+        let Some(alert) = alerts.iter_mut().find(|a| pick(a)) else {
+            return Ok(None);
+        };
+        if let Some(acked_by) = &alert.acked_by {
+            return Ok(Some(format!(
+                "alert #{} was already acknowledged by {acked_by}",
+                alert.id
+            )));
+        }
+        alert.acked_by = Some(sender.to_owned());
+        let msg = format!("alert #{} acknowledged by {sender}", alert.id);
+        self.write_open(&alerts)?;
+        Ok(Some(msg))
+    }
+
+    fn is_alerts_room(&self, room: &Room) -> bool {
+        self.config
+            .as_ref()
+            .is_some_and(|config| config.room == room.room_id())
+    }
+
+    /// Acknowledges the alert whose message got a reaction.
+    pub async fn on_reaction(
+        &self,
+        room: &Room,
+        sender: &UserId,
+        relates_to: &OwnedEventId,
+    ) -> anyhow::Result<()> {
+        if !self.is_alerts_room(room) {
+            return Ok(());
+        }
+        let acked = self
+            .ack(|a| a.event_id.as_ref() == Some(relates_to), sender)
+            .await?;
+        if let Some(msg) = acked {
+            outbox::send(room, RoomMessageEventContent::text_plain(msg)).await?;
+        }
+        Ok(())
+    }
+
+    /// Escalates the unacknowledged alerts whose timeout expired.
+    async fn escalate(&self, client: &Client, config: &AlertsConfig) -> anyhow::Result<()> {
+        let Some(room) = client.get_room(&config.room) else {
+            anyhow::bail!("unknown alerts room {}", config.room);
+        };
+
+        let _guard = self.lock.lock().await;
+        let mut alerts = self.read_open()?;
+        let now = Utc::now();
+        let on_call = match &config.rotation {
+            Some(rotation) => oncall::on_call(&self.db, rotation)?,
+            None => None,
+        };
+
+        let mut changed = false;
+        for alert in alerts.iter_mut() {
+            if alert.acked_by.is_some()
+                || alert.escalations >= config.max_escalations
+                || alert.next_escalation > now
+            {
+                continue;
+            }
+            alert.escalations += 1;
+            alert.next_escalation = now + Self::ack_timeout(config);
+            changed = true;
+
+            let mut text = format!(
+                "⏰ alert #{} is still unacknowledged (escalation {}/{}): {}",
+                alert.id, alert.escalations, config.max_escalations, alert.title
+            );
+            if let Some(user) = &on_call {
+                text.push_str(&format!(" — {user}, you're on call"));
+            }
+            outbox::send(&room, RoomMessageEventContent::text_plain(text)).await?;
+
+            if let Some(user) = &on_call {
+                let text = format!(
+                    "{} alert #{} is unacknowledged in {}: {}\nReact to it there, or send `!alert ack {}`.",
+                    alert.severity.emoji(),
+                    alert.id,
+                    config.room,
+                    alert.title,
+                    alert.id
+                );
+                let dm = dm_room(client, user).await;
+                let sent = match dm {
+                    Ok(dm) => outbox::send(&dm, RoomMessageEventContent::text_plain(text))
+                        .await
+                        .map(|_| ()),
+                    Err(err) => Err(err),
+                };
+                if let Err(err) = sent {
+                    warn!("couldn't message {user} about alert #{}: {err:#}", alert.id);
+                }
+            }
+        }
+
+        if changed {
+            self.write_open(&alerts)?;
+        }
+        Ok(())
+    }
+
+    /// Periodically escalates the unacknowledged alerts.
+    pub async fn run(&self, client: Client, maintenance: &Maintenance) {
+        let Some(config) = &self.config else {
+            return;
+        };
+        loop {
+            sleep(ESCALATION_CHECK_INTERVAL).await;
+            if maintenance.is_on() {
+                // The unacknowledged alerts get escalated once maintenance is over.
+                continue;
+            }
+            if let Err(err) = self.escalate(&client, config).await {
+                error!("error when escalating alerts: {err:#}");
+            }
+        }
+    }
+
+    async fn handle(&self, sender: &UserId, rest: &str) -> anyhow::Result<String> {
+        let (cmd, arg) = rest.split_once(' ').unwrap_or((rest, ""));
+        let arg = arg.trim();
+
+        Ok(match cmd {
+            "" | "list" => {
+                let alerts = self.read_open()?;
+                if alerts.is_empty() {
+                    "no open alerts".to_owned()
+                } else {
+                    let mut msg = format!("{} open alert(s):", alerts.len());
+                    for alert in alerts {
+                        msg.push_str("\n- ");
+                        msg.push_str(&alert.summary());
+                    }
+                    msg
+                }
+            }
+
+            "ack" | "resolve" => {
+                let Some(id) = arg.trim_start_matches('#').parse::<u64>().ok() else {
+                    return Ok(format!("usage: !alert {cmd} NUMBER"));
+                };
+                if cmd == "ack" {
+                    self.ack(|a| a.id == id, sender)
+                        .await?
+                        .unwrap_or_else(|| format!("no open alert #{id}"))
+                } else {
+                    let _guard = self.lock.lock().await;
+                    let mut alerts = self.read_open()?;
+                    let before = alerts.len();
+                    alerts.retain(|a| a.id != id);
+                    if alerts.len() == before {
+                        format!("no open alert #{id}")
+                    } else {
+                        self.write_open(&alerts)?;
+                        format!("✅ alert #{id} resolved by {sender}")
+                    }
+                }
+            }
+
+            _ => USAGE.to_owned(),
+        })
+    }
+
+    /// Try to handle a message assuming it's an `!alert` command, in the alerts room.
+    ///
+    /// Returns whether the message was such a command.
+    pub async fn try_handle(
+        &self,
+        room: &Room,
+        sender: &UserId,
+        content: &str,
+    ) -> anyhow::Result<bool> {
+        let Some(rest) = content
+            .strip_prefix("!alerts")
+            .or_else(|| content.strip_prefix("!alert"))
+        else {
+            return Ok(false);
+        };
+        if !rest.is_empty() && !rest.starts_with(' ') {
+            return Ok(false);
+        }
+        if !self.is_alerts_room(room) {
+            return Ok(false);
+        }
+
+        let response = self.handle(sender, rest.trim()).await?;
+        outbox::send(room, RoomMessageEventContent::text_plain(response)).await?;
+        Ok(true)
+    }
+}
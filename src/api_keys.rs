@@ -0,0 +1,234 @@
+//! API keys for the HTTP surfaces (the webhooks, the live stream and the dashboard's API), each
+//! with its own scopes and, optionally, rooms, so a script posting in a room doesn't need the
+//! token that can reconfigure the bot.
+//!
+//! The admin creates and revokes them with `!admin host api-keys`; a new key is sent to them in
+//! direct message, and only its SHA-256 hash is kept in the database.
+
+use std::{collections::BTreeSet, fmt::Write as _};
+
+use chrono::{DateTime, Utc};
+use matrix_sdk::{
+    ruma::{OwnedRoomId, RoomId, UserId},
+    Client,
+};
+use rand::Rng as _;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
+use tracing::{info, warn};
+
+use crate::{
+    admin_dm, host_table,
+    utils::{resolve_room, split_args},
+    ShareableDatabase,
+};
+
+/// Name of the host table keeping the keys, by the hash of the key.
+const TABLE: &str = "api_keys";
+
+/// Prefix of the keys, making them recognizable, e.g. by secret scanners.
+const KEY_PREFIX: &str = "tg_";
+
+const USAGE: &str = "usage: !admin host api-keys [list | create NAME SCOPE[,SCOPE...] [ROOM...] \
+                     | revoke NAME], with the scopes send, metrics and admin";
+
+/// What a key can be used for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Scope {
+    /// Sending messages, and raising alerts through the webhooks.
+    Send,
+    /// Reading the activity of the bot: its modules, errors and metrics, and the live stream.
+    Metrics,
+    /// Everything, including enabling and disabling the modules.
+    Admin,
+}
+
+impl Scope {
+    fn parse(scope: &str) -> anyhow::Result<Self> {
+        match scope {
+            "send" => Ok(Self::Send),
+            "metrics" => Ok(Self::Metrics),
+            "admin" => Ok(Self::Admin),
+            _ => anyhow::bail!("unknown scope {scope}, expected send, metrics or admin"),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Send => "send",
+            Self::Metrics => "metrics",
+            Self::Admin => "admin",
+        }
+    }
+}
+
+/// A key, as stored.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct ApiKey {
+    name: String,
+    scopes: BTreeSet<Scope>,
+    /// Rooms the key is restricted to; all of them if empty.
+    rooms: BTreeSet<OwnedRoomId>,
+    created_at: DateTime<Utc>,
+}
+
+impl ApiKey {
+    /// Whether the key allows the scope, in the room if the request targets one; a key
+    /// restricted to some rooms can't be used for requests that don't target one of them.
+    pub fn allows(&self, scope: Scope, room_id: Option<&RoomId>) -> bool {
+        let scoped = self.scopes.contains(&scope) || self.scopes.contains(&Scope::Admin);
+        let in_room = match room_id {
+            Some(room_id) => self.rooms.is_empty() || self.rooms.contains(room_id),
+            None => self.rooms.is_empty(),
+        };
+        scoped && in_room
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn hash(key: &str) -> String {
+    hex(&Sha256::digest(key.as_bytes()))
+}
+
+pub(crate) struct ApiKeys {
+    db: ShareableDatabase,
+}
+
+impl ApiKeys {
+    pub fn new(db: ShareableDatabase) -> Self {
+        Self { db }
+    }
+
+    /// The key, if it exists.
+    pub fn get(&self, key: &str) -> Option<ApiKey> {
+        if !key.starts_with(KEY_PREFIX) {
+            return None;
+        }
+        host_table::read_json(&self.db, TABLE, &hash(key)).unwrap_or_else(|err| {
+            warn!("couldn't read the API keys: {err:#}");
+            None
+        })
+    }
+
+    /// Whether the key exists and allows the scope, see [`ApiKey::allows`].
+    pub fn authorize(&self, key: &str, scope: Scope, room_id: Option<&RoomId>) -> bool {
+        self.get(key).is_some_and(|key| key.allows(scope, room_id))
+    }
+
+    fn entries(&self) -> anyhow::Result<Vec<(String, ApiKey)>> {
+        host_table::entries(&self.db, TABLE, "")?
+            .into_iter()
+            .map(|(hash, value)| Ok((hash, serde_json::from_slice(&value)?)))
+            .collect()
+    }
+
+    fn list(&self) -> anyhow::Result<String> {
+        let mut entries = self.entries()?;
+        if entries.is_empty() {
+            return Ok("no API keys".to_owned());
+        }
+        entries.sort_by(|(_, a), (_, b)| a.name.cmp(&b.name));
+        Ok(entries
+            .iter()
+            .map(|(_, entry)| {
+                let scopes = entry.scopes.iter().map(|scope| scope.name());
+                let mut line = format!(
+                    "{}: {}, created {}",
+                    entry.name,
+                    scopes.collect::<Vec<_>>().join(","),
+                    entry.created_at.format("%Y-%m-%d")
+                );
+                if !entry.rooms.is_empty() {
+                    let rooms = entry.rooms.iter().map(|room| room.as_str());
+                    let _ = write!(line, ", in {}", rooms.collect::<Vec<_>>().join(" "));
+                }
+                line
+            })
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+
+    async fn create(
+        &self,
+        client: &Client,
+        admin_user_id: &UserId,
+        name: &str,
+        scopes: &str,
+        rooms: &[&str],
+    ) -> anyhow::Result<String> {
+        if self.entries()?.iter().any(|(_, entry)| entry.name == name) {
+            anyhow::bail!("there's already a key named {name}, revoke it first");
+        }
+        let scopes = scopes
+            .split(',')
+            .map(Scope::parse)
+            .collect::<anyhow::Result<BTreeSet<_>>>()?;
+        let mut room_ids = BTreeSet::new();
+        for room in rooms {
+            room_ids.insert(resolve_room(client, room).await?);
+        }
+
+        let key = format!("{KEY_PREFIX}{}", hex(&rand::thread_rng().gen::<[u8; 24]>()));
+        let entry = ApiKey {
+            name: name.to_owned(),
+            scopes,
+            rooms: room_ids,
+            created_at: Utc::now(),
+        };
+        host_table::write_json(&self.db, TABLE, &hash(&key), &entry)?;
+        info!("created the API key {name}");
+
+        let text = format!("API key {name}: {key}");
+        if let Err(err) = admin_dm::notify(client, admin_user_id, &text, None).await {
+            // The key can't be shown again, so don't keep it.
+            host_table::remove(&self.db, TABLE, &hash(&key))?;
+            return Err(err.context("couldn't send the key in direct message"));
+        }
+        Ok(format!(
+            "created the API key {name}, sent in direct message"
+        ))
+    }
+
+    fn revoke(&self, name: &str) -> anyhow::Result<String> {
+        let Some((key_hash, _)) = self
+            .entries()?
+            .into_iter()
+            .find(|(_, entry)| entry.name == name)
+        else {
+            return Ok(format!("no API key named {name}"));
+        };
+        host_table::remove(&self.db, TABLE, &key_hash)?;
+        info!("revoked the API key {name}");
+        Ok(format!("revoked the API key {name}"))
+    }
+
+    /// Try to handle an `!admin host api-keys` command.
+    pub async fn try_handle_admin(
+        &self,
+        client: &Client,
+        admin_user_id: &UserId,
+        content: &str,
+    ) -> Option<String> {
+        let rest = content.strip_prefix("!admin host api-keys")?;
+        if !rest.is_empty() && !rest.starts_with(' ') {
+            return None;
+        }
+
+        let args = split_args(rest);
+        let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+        let result = match args.as_slice() {
+            [] | ["list"] => self.list(),
+            ["create", name, scopes, rooms @ ..] => {
+                self.create(client, admin_user_id, name, scopes, rooms)
+                    .await
+            }
+            ["revoke", name] => self.revoke(name),
+            _ => Ok(USAGE.to_owned()),
+        };
+        Some(result.unwrap_or_else(|err| format!("error: {err:#}")))
+    }
+}
@@ -0,0 +1,337 @@
+//! Compliance archive: the messages of the configured rooms are recorded in an append-only store
+//! in the database, and optionally mirrored into another room. Redactions are honored by
+//! tombstoning the archived entries, whose content is erased, and redacting their mirrored copies.
+//! Entries older than the retention period are purged, and the admin can export a room's archive
+//! as JSON lines with `!admin host archive export ROOM [DAYS]`.
+
+use chrono::{DateTime, Utc};
+use matrix_sdk::{
+    attachment::AttachmentConfig,
+    room::Room,
+    ruma::{
+        events::room::{
+            message::{OriginalSyncRoomMessageEvent, RoomMessageEventContent},
+            redaction::OriginalSyncRoomRedactionEvent,
+        },
+        OwnedEventId, OwnedRoomId, OwnedUserId,
+    },
+    Client,
+};
+use serde::{Deserialize, Serialize};
+use tokio::time::{sleep, Duration};
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    host_table, outbox,
+    utils::{dm_room, now_secs, resolve_room},
+    ShareableDatabase,
+};
+
+/// Configuration for the compliance archive.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ArchiveConfig {
+    /// rooms whose messages are archived.
+    pub rooms: Vec<OwnedRoomId>,
+    /// room the archived messages are mirrored into, if any.
+    pub mirror_room: Option<OwnedRoomId>,
+    /// how long the entries are kept, in days; forever if missing.
+    pub retention_days: Option<u64>,
+}
+
+/// Name of the host table keeping the archive.
+const TABLE: &str = "archive";
+/// Prefix of the keys of the entries, followed by the room id, the timestamp and the event id.
+const ENTRY_PREFIX: &str = "entry/";
+/// Prefix of the keys mapping event ids to the keys of their entries.
+const INDEX_PREFIX: &str = "event/";
+/// Delay between two purges of the expired entries.
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+const USAGE: &str = "usage: !admin host archive (status | export ROOM [DAYS])";
+
+/// Why an archived message's content was erased.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Tombstone {
+    redacted_by: OwnedUserId,
+    reason: Option<String>,
+    at: DateTime<Utc>,
+}
+
+/// An archived message.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Entry {
+    room_id: OwnedRoomId,
+    event_id: OwnedEventId,
+    sender: OwnedUserId,
+    /// When the message was sent, in milliseconds since the epoch.
+    ts: u64,
+    /// The message's content, as sent; null once redacted.
+    content: serde_json::Value,
+    /// The copy of the message in the mirror room, if any.
+    mirror_event_id: Option<OwnedEventId>,
+    tombstone: Option<Tombstone>,
+}
+
+fn entry_key(room_id: &OwnedRoomId, ts: u64, event_id: &OwnedEventId) -> String {
+    format!("{ENTRY_PREFIX}{room_id}/{ts:013} {event_id}")
+}
+
+pub(crate) struct Archive {
+    config: Option<ArchiveConfig>,
+    db: ShareableDatabase,
+}
+
+impl Archive {
+    pub fn new(config: Option<ArchiveConfig>, db: ShareableDatabase) -> Self {
+        Self { config, db }
+    }
+
+    fn is_archived(&self, room: &Room) -> bool {
+        self.config
+            .as_ref()
+            .is_some_and(|config| config.rooms.iter().any(|r| r == room.room_id()))
+    }
+
+    fn write_entry(&self, key: &str, entry: &Entry) -> anyhow::Result<()> {
+        host_table::write_json(&self.db, TABLE, key, entry)
+    }
+
+    /// Posts a copy of the message in the mirror room, returning its event id.
+    async fn mirror(
+        &self,
+        client: &Client,
+        mirror_room: &OwnedRoomId,
+        room: &Room,
+        ev: &OriginalSyncRoomMessageEvent,
+    ) -> anyhow::Result<OwnedEventId> {
+        let Some(mirror) = client.get_room(mirror_room) else {
+            anyhow::bail!("unknown archive mirror room {mirror_room}");
+        };
+        let text = format!(
+            "[{}] {}: {}",
+            room.display_name().await?,
+            ev.sender,
+            ev.content.body()
+        );
+        outbox::send(&mirror, RoomMessageEventContent::notice_plain(text)).await
+    }
+
+    /// Archives a message of one of the configured rooms.
+    pub async fn record(
+        &self,
+        client: &Client,
+        room: &Room,
+        ev: &OriginalSyncRoomMessageEvent,
+    ) -> anyhow::Result<()> {
+        let Some(config) = &self.config else {
+            return Ok(());
+        };
+        if !self.is_archived(room) {
+            return Ok(());
+        }
+
+        let room_id = room.room_id().to_owned();
+        let ts = u64::from(ev.origin_server_ts.0);
+        let mut entry = Entry {
+            room_id: room_id.clone(),
+            event_id: ev.event_id.clone(),
+            sender: ev.sender.clone(),
+            ts,
+            content: serde_json::to_value(&ev.content)?,
+            mirror_event_id: None,
+            tombstone: None,
+        };
+
+        if let Some(mirror_room) = config.mirror_room.as_ref().filter(|r| **r != room_id) {
+            match self.mirror(client, mirror_room, room, ev).await {
+                Ok(event_id) => entry.mirror_event_id = Some(event_id),
+                Err(err) => warn!("couldn't mirror an archived message: {err:#}"),
+            }
+        }
+
+        let key = entry_key(&room_id, ts, &ev.event_id);
+        self.write_entry(&key, &entry)?;
+        let index_key = format!("{INDEX_PREFIX}{}", ev.event_id);
+        host_table::write(&self.db, TABLE, &index_key, key.as_bytes())?;
+        debug!("archived {} from {room_id}", ev.event_id);
+        Ok(())
+    }
+
+    /// Tombstones the archived entry of a redacted message, and redacts its mirrored copy.
+    pub async fn on_redaction(
+        &self,
+        client: &Client,
+        room: &Room,
+        ev: &OriginalSyncRoomRedactionEvent,
+    ) -> anyhow::Result<()> {
+        let Some(config) = &self.config else {
+            return Ok(());
+        };
+        if !self.is_archived(room) {
+            return Ok(());
+        }
+        let Some(redacted) = ev.redacts.as_ref().or(ev.content.redacts.as_ref()) else {
+            return Ok(());
+        };
+
+        let index_key = format!("{INDEX_PREFIX}{redacted}");
+        let Some(key) = host_table::read(&self.db, TABLE, &index_key)? else {
+            return Ok(());
+        };
+        let key = String::from_utf8(key)?;
+        let Some(mut entry) = host_table::read_json::<Entry>(&self.db, TABLE, &key)? else {
+            return Ok(());
+        };
+        if entry.tombstone.is_some() {
+            return Ok(());
+        }
+
+        entry.content = serde_json::Value::Null;
+        entry.tombstone = Some(Tombstone {
+            redacted_by: ev.sender.clone(),
+            reason: ev.content.reason.clone(),
+            at: Utc::now(),
+        });
+        self.write_entry(&key, &entry)?;
+        info!("tombstoned the archived {redacted}");
+
+        if let (Some(mirror_room), Some(mirror_event_id)) =
+            (&config.mirror_room, &entry.mirror_event_id)
+        {
+            if let Some(mirror) = client.get_room(mirror_room) {
+                outbox::redact(
+                    &mirror,
+                    mirror_event_id,
+                    Some("redacted in the original room"),
+                )
+                .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Lists the archived entries of a room, optionally only those after a given time.
+    fn list(&self, room_id: &OwnedRoomId, since: Option<u64>) -> anyhow::Result<Vec<Entry>> {
+        let prefix = format!("{ENTRY_PREFIX}{room_id}/");
+        host_table::entries(&self.db, TABLE, &prefix)?
+            .into_iter()
+            .map(|(_, value)| Ok(serde_json::from_slice::<Entry>(&value)?))
+            .filter(|entry| match (entry, since) {
+                (Ok(entry), Some(since)) => entry.ts >= since,
+                _ => true,
+            })
+            .collect()
+    }
+
+    /// Removes the entries older than the retention period.
+    fn purge(&self, retention: Duration) -> anyhow::Result<usize> {
+        let cutoff = (now_secs() * 1000).saturating_sub(retention.as_millis() as u64);
+        let mut purged = 0;
+        for (key, value) in host_table::entries(&self.db, TABLE, ENTRY_PREFIX)? {
+            let entry: Entry = serde_json::from_slice(&value)?;
+            if entry.ts >= cutoff {
+                continue;
+            }
+            host_table::remove(&self.db, TABLE, &key)?;
+            let index_key = format!("{INDEX_PREFIX}{}", entry.event_id);
+            host_table::remove(&self.db, TABLE, &index_key)?;
+            purged += 1;
+        }
+        Ok(purged)
+    }
+
+    /// Periodically purges the entries older than the retention period.
+    pub async fn run(&self) {
+        let Some(retention_days) = self.config.as_ref().and_then(|c| c.retention_days) else {
+            return;
+        };
+        let retention = Duration::from_secs(retention_days * 24 * 60 * 60);
+        loop {
+            match self.purge(retention) {
+                Ok(0) => {}
+                Ok(purged) => info!("purged {purged} archived messages past the retention"),
+                Err(err) => error!("couldn't purge the archive: {err:#}"),
+            }
+            sleep(PURGE_INTERVAL).await;
+        }
+    }
+
+    async fn export(
+        &self,
+        client: &Client,
+        admin_user_id: &OwnedUserId,
+        target: &str,
+        days: Option<&str>,
+    ) -> anyhow::Result<String> {
+        let room_id = resolve_room(client, target).await?;
+        let since = match days {
+            Some(days) => {
+                let days: u64 = days.parse()?;
+                Some((now_secs() * 1000).saturating_sub(days * 24 * 60 * 60 * 1000))
+            }
+            None => None,
+        };
+
+        let entries = self.list(&room_id, since)?;
+        if entries.is_empty() {
+            return Ok(format!("nothing archived for {target}"));
+        }
+        let mut data = Vec::new();
+        for entry in &entries {
+            serde_json::to_writer(&mut data, entry)?;
+            data.push(b'\n');
+        }
+
+        // The archive may be sensitive, so it's only sent to the admin.
+        let dm = dm_room(client, admin_user_id).await?;
+        let filename = format!("archive-{room_id}.jsonl");
+        let content_type = "application/x-ndjson".parse()?;
+        outbox::send_attachment(&dm, &filename, &content_type, data, AttachmentConfig::new())
+            .await?;
+        Ok(format!(
+            "exported {} archived messages of {target} in direct message",
+            entries.len()
+        ))
+    }
+
+    /// Try to handle an `!admin host archive` command.
+    pub async fn try_handle_admin(
+        &self,
+        client: &Client,
+        admin_user_id: &OwnedUserId,
+        content: &str,
+    ) -> Option<String> {
+        let rest = content.strip_prefix("!admin host archive")?;
+        if !rest.is_empty() && !rest.starts_with(' ') {
+            return None;
+        }
+        let Some(config) = &self.config else {
+            return Some("the archive isn't configured".to_owned());
+        };
+
+        let args = rest.split_whitespace().collect::<Vec<_>>();
+        let result = match args.as_slice() {
+            ["status"] => {
+                let mut lines = Vec::new();
+                for room_id in &config.rooms {
+                    let line = match self.list(room_id, None) {
+                        Ok(entries) => {
+                            let redacted = entries.iter().filter(|e| e.tombstone.is_some()).count();
+                            format!("{room_id}: {} messages, {redacted} redacted", entries.len())
+                        }
+                        Err(err) => format!("{room_id}: error: {err:#}"),
+                    };
+                    lines.push(line);
+                }
+                if let Some(days) = config.retention_days {
+                    lines.push(format!("retention: {days} days"));
+                }
+                Ok(lines.join("\n"))
+            }
+            ["export", room] => self.export(client, admin_user_id, room, None).await,
+            ["export", room, days] => self.export(client, admin_user_id, room, Some(days)).await,
+            _ => Ok(USAGE.to_owned()),
+        };
+        Some(result.unwrap_or_else(|err| format!("error: {err:#}")))
+    }
+}
@@ -0,0 +1,178 @@
+//! Automatic reactions: per-room rules reacting with an emoji to the messages matching a pattern,
+//! e.g. 🎉 to "shipped", set up by the admin with `!admin host auto-react`. They're applied before
+//! the modules handle the message.
+
+use std::{collections::HashMap, sync::Mutex};
+
+use matrix_sdk::{
+    room::Room,
+    ruma::{
+        events::{reaction::ReactionEventContent, relation::Annotation},
+        EventId, OwnedRoomId, RoomId,
+    },
+    Client,
+};
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::{
+    host_table, outbox,
+    utils::{resolve_room, split_args},
+    ShareableDatabase,
+};
+
+/// Name of the host table keeping the rules of each room.
+const TABLE: &str = "auto_reactions";
+
+const USAGE: &str = "usage: !admin host auto-react ROOM [add \"PATTERN\" EMOJI | remove NUMBER], \
+                     where PATTERN is a case-insensitive regular expression";
+
+/// Reacts with the emoji to the messages matching the pattern.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Rule {
+    pattern: String,
+    reaction: String,
+}
+
+impl Rule {
+    fn compile(&self) -> anyhow::Result<Regex> {
+        Ok(RegexBuilder::new(&self.pattern)
+            .case_insensitive(true)
+            .build()?)
+    }
+}
+
+pub(crate) struct AutoReactions {
+    db: ShareableDatabase,
+    /// The compiled rules of the rooms, with their reactions, filled lazily.
+    compiled: Mutex<HashMap<OwnedRoomId, Vec<(Regex, String)>>>,
+}
+
+impl AutoReactions {
+    pub fn new(db: ShareableDatabase) -> Self {
+        Self {
+            db,
+            compiled: Default::default(),
+        }
+    }
+
+    fn rules(&self, room_id: &RoomId) -> anyhow::Result<Vec<Rule>> {
+        Ok(host_table::read_json(&self.db, TABLE, room_id.as_str())?.unwrap_or_default())
+    }
+
+    fn set_rules(&self, room_id: &RoomId, rules: &[Rule]) -> anyhow::Result<()> {
+        host_table::write_json(&self.db, TABLE, room_id.as_str(), &rules)?;
+        self.compiled.lock().unwrap().remove(room_id);
+        Ok(())
+    }
+
+    /// The compiled rules of the room.
+    fn compiled(&self, room_id: &RoomId) -> anyhow::Result<Vec<(Regex, String)>> {
+        if let Some(compiled) = self.compiled.lock().unwrap().get(room_id) {
+            return Ok(compiled.clone());
+        }
+        let compiled = self
+            .rules(room_id)?
+            .into_iter()
+            .filter_map(|rule| Some((rule.compile().ok()?, rule.reaction)))
+            .collect::<Vec<_>>();
+        self.compiled
+            .lock()
+            .unwrap()
+            .insert(room_id.to_owned(), compiled.clone());
+        Ok(compiled)
+    }
+
+    /// Reacts to the message according to the rules of the room, each emoji at most once.
+    pub async fn on_message(
+        &self,
+        room: &Room,
+        event_id: &EventId,
+        content: &str,
+    ) -> anyhow::Result<()> {
+        let mut reactions = Vec::new();
+        for (regex, reaction) in self.compiled(room.room_id())? {
+            if regex.is_match(content) && !reactions.contains(&reaction) {
+                reactions.push(reaction);
+            }
+        }
+        for reaction in reactions {
+            debug!("automatically reacting with {reaction} to {event_id}");
+            let reaction =
+                ReactionEventContent::new(Annotation::new(event_id.to_owned(), reaction));
+            outbox::send(room, reaction).await?;
+        }
+        Ok(())
+    }
+
+    fn list(&self, room_id: &RoomId, room: &str) -> anyhow::Result<String> {
+        let rules = self.rules(room_id)?;
+        if rules.is_empty() {
+            return Ok(format!("no automatic reactions in {room}"));
+        }
+        Ok(rules
+            .iter()
+            .enumerate()
+            .map(|(i, rule)| format!("{}. \"{}\" → {}", i + 1, rule.pattern, rule.reaction))
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+
+    fn add(&self, room_id: &RoomId, pattern: &str, reaction: &str) -> anyhow::Result<String> {
+        let rule = Rule {
+            pattern: pattern.to_owned(),
+            reaction: reaction.to_owned(),
+        };
+        if let Err(err) = rule.compile() {
+            return Ok(format!("invalid pattern: {err:#}"));
+        }
+        let mut rules = self.rules(room_id)?;
+        rules.push(rule);
+        self.set_rules(room_id, &rules)?;
+        Ok(format!("added automatic reaction {}", rules.len()))
+    }
+
+    fn remove(&self, room_id: &RoomId, number: &str) -> anyhow::Result<String> {
+        let mut rules = self.rules(room_id)?;
+        let Some(index) = number
+            .parse::<usize>()
+            .ok()
+            .filter(|n| (1..=rules.len()).contains(n))
+        else {
+            return Ok(format!("no automatic reaction {number}"));
+        };
+        let rule = rules.remove(index - 1);
+        self.set_rules(room_id, &rules)?;
+        Ok(format!(
+            "removed the automatic reaction to \"{}\"",
+            rule.pattern
+        ))
+    }
+
+    /// Try to handle an `!admin host auto-react` command.
+    pub async fn try_handle_admin(&self, client: &Client, content: &str) -> Option<String> {
+        let rest = content.strip_prefix("!admin host auto-react")?;
+        if !rest.is_empty() && !rest.starts_with(' ') {
+            return None;
+        }
+
+        let args = split_args(rest);
+        let Some((room, args)) = args.split_first() else {
+            return Some(USAGE.to_owned());
+        };
+        let room_id = match resolve_room(client, room).await {
+            Ok(room_id) => room_id,
+            Err(err) => return Some(format!("couldn't resolve room {room}: {err:#}")),
+        };
+
+        let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+        let result = match args.as_slice() {
+            [] => self.list(&room_id, room),
+            ["add", pattern, reaction] => self.add(&room_id, pattern, reaction),
+            ["remove", number] => self.remove(&room_id, number),
+            _ => Ok(USAGE.to_owned()),
+        };
+        Some(result.unwrap_or_else(|err| format!("error: {err:#}")))
+    }
+}
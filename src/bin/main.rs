@@ -36,13 +36,45 @@ async fn real_main() -> anyhow::Result<()> {
     .with_target("matrix_sdk_crypto::backups", Level::ERROR)
     .with_default(Level::WARN);
 
-    tracing_subscriber::registry()
-        .with(tracing_subscriber::fmt::layer())
-        .with(filter)
-        .init();
+    let registry = tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer().with_filter(filter));
+
+    // The console layer needs its own, unfiltered, view of the runtime's events.
+    #[cfg(feature = "tokio-console")]
+    let registry = registry.with(console_subscriber::spawn());
+
+    registry.init();
+
+    let mut args = std::env::args().skip(1).peekable();
+
+    // `--dry-run` logs the actions in rooms instead of carrying them out, for all the bots.
+    if args.next_if(|arg| arg == "--dry-run").is_some() {
+        trinity::enable_dry_run();
+    }
+
+    // `--safe-mode` starts without modules nor schedulers, answering only the host admin commands.
+    if args.next_if(|arg| arg == "--safe-mode").is_some() {
+        trinity::enable_safe_mode();
+    }
+
+    // `tritongue supervise <dir>` runs one bot per configuration file of the directory.
+    if args.next_if(|arg| arg == "supervise").is_some() {
+        let Some(dir) = args.next() else { bail!("usage: tritongue supervise <config directory>") };
+        return trinity::supervise(Path::new(&dir)).await;
+    }
+
+    // `tritongue inspect <module.wasm>` describes a module file.
+    if args.next_if(|arg| arg == "inspect").is_some() {
+        let Some(path) = args.next() else { bail!("usage: tritongue inspect <module.wasm>") };
+        print!("{}", trinity::inspect(Path::new(&path))?);
+        return Ok(());
+    }
+
+    // `tritongue doctor [config]` checks the environment instead of running the bot.
+    let doctor = args.next_if(|arg| arg == "doctor").is_some();
 
     // This really shouldn't be checked if path is given.
-    let config_param = std::env::args().nth(1);
+    let config_param = args.next();
     let Ok(filename) = config_dir_filename(config_param, "config.toml")
         else { anyhow::bail!("error looking for config file") }; // FIXME: Propagate actual error.
     // Check for a config file, then fallback to env if none found.
@@ -53,6 +85,13 @@ async fn real_main() -> anyhow::Result<()> {
         BotConfig::from_env()?
     };
 
+    if doctor {
+        if !trinity::doctor(&config).await {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     tracing::debug!("creating client...");
     trinity::run(config).await
 }
@@ -0,0 +1,158 @@
+//! Bus between modules: a module emits an event on a topic with an `emit` action, e.g. "karma
+//! changed" or "new issue filed", and the host delivers it, in the same room, to the `on-bus-event`
+//! export of the other modules listening to that topic, as listed by their `bus-topics` export.
+//!
+//! Events are queued and delivered in a later dispatch cycle, one at a time, so a module never
+//! runs while another one is handling a message. Events emitted in response to bus events are
+//! delivered as well, up to a few hops, so modules can't ping-pong forever.
+
+use std::sync::Mutex;
+
+use matrix_sdk::{
+    ruma::{OwnedRoomId, RoomId},
+    Client,
+};
+use tokio::sync::mpsc;
+use tracing::{debug, error, warn};
+
+use crate::{diagnostics::APP_CTX_LOCK, handle_module_actions, wasm, App};
+
+/// Most events waiting to be delivered to the modules.
+const MAX_PENDING_EVENTS: usize = 1000;
+/// Most events emitted in a row in response to bus events, past the first one.
+const MAX_HOPS: u8 = 8;
+
+struct Delivery {
+    /// Module that emitted the event.
+    sender: String,
+    room: OwnedRoomId,
+    event: wasm::BusEvent,
+    /// Number of bus events that led to this one.
+    hops: u8,
+}
+
+pub(crate) struct Bus {
+    deliveries: mpsc::Sender<Delivery>,
+    receiver: Mutex<Option<mpsc::Receiver<Delivery>>>,
+}
+
+impl Default for Bus {
+    fn default() -> Self {
+        let (deliveries, receiver) = mpsc::channel(MAX_PENDING_EVENTS);
+        Self {
+            deliveries,
+            receiver: Mutex::new(Some(receiver)),
+        }
+    }
+}
+
+impl Bus {
+    /// Queues an event emitted by the module, to be delivered by [`run`].
+    pub fn emit(&self, module: &str, room_id: &RoomId, event: wasm::BusEvent) {
+        self.queue(Delivery {
+            sender: module.to_owned(),
+            room: room_id.to_owned(),
+            event,
+            hops: 0,
+        });
+    }
+
+    fn queue(&self, delivery: Delivery) {
+        let topic = delivery.event.topic.clone();
+        if self.deliveries.try_send(delivery).is_err() {
+            warn!("dropping a bus event on {topic}, too many events are pending");
+        }
+    }
+}
+
+/// Delivers the events emitted by the modules to the modules listening to their topic.
+pub(crate) async fn run(app: App, client: Client) {
+    let Some(mut receiver) = app.bus.receiver.lock().unwrap().take() else {
+        error!("the bus is already running");
+        return;
+    };
+
+    while let Some(delivery) = receiver.recv().await {
+        if app.maintenance.is_on() {
+            debug!(
+                "dropping a bus event on {} during maintenance",
+                delivery.event.topic
+            );
+            continue;
+        }
+        let Some(room) = client.get_room(&delivery.room) else {
+            debug!(
+                "bus event on {} is in unknown room {}",
+                delivery.event.topic, delivery.room
+            );
+            continue;
+        };
+
+        let inner = app.inner.clone();
+        let crash_reporter = app.crash_reporter.clone();
+        let response_limits = app.response_limits.clone();
+        let (sender, room_id, event) = (
+            delivery.sender.clone(),
+            delivery.room.clone(),
+            delivery.event.clone(),
+        );
+        let responses = tokio::task::spawn_blocking(move || {
+            let ctx = &mut *futures::executor::block_on(APP_CTX_LOCK.lock(&inner, "bus"));
+            let (store, modules) = ctx.modules.iter();
+            let mut responses = Vec::new();
+            for module in modules {
+                if module.name() == sender || !module.listens_to(&event.topic) {
+                    continue;
+                }
+                match module.on_bus_event(&mut *store, &sender, &room_id, &event) {
+                    Ok(actions) => responses.push((
+                        module.name().to_owned(),
+                        response_limits.apply(module.name(), actions),
+                    )),
+                    Err(err) => {
+                        warn!("wasm module {} ran into an error: {err}", module.name());
+                        module.record_error(&err);
+                        crash_reporter.module_error(module.name(), Some(&room_id), None, &err);
+                    }
+                }
+            }
+            responses
+        })
+        .await;
+
+        let responses = match responses {
+            Ok(responses) => responses,
+            Err(err) => {
+                error!("delivering a bus event failed: {err}");
+                continue;
+            }
+        };
+        for (module, actions) in responses {
+            // Events emitted in response are queued with one more hop, to bound chains.
+            let (emitted, actions): (Vec<_>, Vec<_>) = actions
+                .into_iter()
+                .partition(|action| matches!(action, wasm::Action::Emit(_)));
+            for action in emitted {
+                let wasm::Action::Emit(event) = action else {
+                    continue;
+                };
+                if delivery.hops >= MAX_HOPS {
+                    warn!(
+                        "dropping a bus event of {module} on {}, after too many hops",
+                        event.topic
+                    );
+                    continue;
+                }
+                app.bus.queue(Delivery {
+                    sender: module.clone(),
+                    room: delivery.room.clone(),
+                    event,
+                    hops: delivery.hops + 1,
+                });
+            }
+            if let Err(err) = handle_module_actions(&app, &room, &module, actions).await {
+                warn!("couldn't handle the actions of {module} for a bus event: {err:#}");
+            }
+        }
+    }
+}
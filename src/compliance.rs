@@ -0,0 +1,178 @@
+//! Tagging of the bot's messages, for deployments that must identify automated messages: a
+//! header and a footer added to the messages, and a machine-readable field in the event content
+//! naming the bot and the module the message comes from.
+
+use chrono::Utc;
+use matrix_sdk::{
+    room::Room,
+    ruma::{
+        events::room::message::{
+            AddMentions, ForwardThread, MessageType, OriginalRoomMessageEvent, ReplacementMetadata,
+            RoomMessageEventContent,
+        },
+        OwnedEventId,
+    },
+};
+use serde::Deserialize;
+
+use crate::outbox;
+
+/// Name of the event content field identifying the origin of a message.
+const ORIGIN_FIELD: &str = "org.tritongue.origin";
+
+/// Configuration for the tagging of the bot's messages.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ComplianceConfig {
+    /// line added before every message, where `{bot}` is replaced with the bot's user id,
+    /// `{module}` with the responding module's name and `{time}` with the current UTC time.
+    pub header: Option<String>,
+    /// line added after every message, with the same variables as the header.
+    pub footer: Option<String>,
+    /// whether an `org.tritongue.origin` field naming the bot and module is added to the events.
+    #[serde(default)]
+    pub origin_field: bool,
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// The content of a reply to the original message, with the standard fallback quoting it.
+pub(crate) fn reply_content(
+    original: &OriginalRoomMessageEvent,
+    content: RoomMessageEventContent,
+) -> RoomMessageEventContent {
+    content.make_reply_to(original, ForwardThread::Yes, AddMentions::Yes)
+}
+
+pub(crate) struct Compliance {
+    config: ComplianceConfig,
+}
+
+impl Compliance {
+    pub fn new(config: ComplianceConfig) -> Self {
+        Self { config }
+    }
+
+    fn expand(template: &str, bot: &str, module: &str, time: &str) -> String {
+        template
+            .replace("{bot}", bot)
+            .replace("{module}", module)
+            .replace("{time}", time)
+    }
+
+    /// Adds the header and footer to the bodies of a text message.
+    fn add_lines(&self, bot: &str, module: &str, content: &mut RoomMessageEventContent) {
+        if self.config.header.is_none() && self.config.footer.is_none() {
+            return;
+        }
+        let (body, formatted) = match &mut content.msgtype {
+            MessageType::Text(text) => (&mut text.body, &mut text.formatted),
+            MessageType::Notice(notice) => (&mut notice.body, &mut notice.formatted),
+            _ => return,
+        };
+
+        let time = Utc::now().format("%Y-%m-%d %H:%M:%S UTC").to_string();
+        let header = self
+            .config
+            .header
+            .as_deref()
+            .map(|header| Self::expand(header, bot, module, &time));
+        let footer = self
+            .config
+            .footer
+            .as_deref()
+            .map(|footer| Self::expand(footer, bot, module, &time));
+
+        if let Some(header) = &header {
+            body.insert_str(0, &format!("{header}\n"));
+        }
+        if let Some(footer) = &footer {
+            body.push_str(&format!("\n{footer}"));
+        }
+        if let Some(formatted) = formatted {
+            if let Some(header) = &header {
+                formatted
+                    .body
+                    .insert_str(0, &format!("<p>{}</p>", escape_html(header)));
+            }
+            if let Some(footer) = &footer {
+                formatted
+                    .body
+                    .push_str(&format!("<p>{}</p>", escape_html(footer)));
+            }
+        }
+    }
+
+    /// Sends a message from the given module (or `host`), tagged as configured.
+    pub async fn send(
+        &self,
+        room: &Room,
+        module: &str,
+        mut content: RoomMessageEventContent,
+    ) -> anyhow::Result<OwnedEventId> {
+        let client = room.client();
+        let bot = client.user_id().map(|bot| bot.as_str()).unwrap_or_default();
+        self.add_lines(bot, module, &mut content);
+        self.send_tagged(room, bot, module, content).await
+    }
+
+    /// Replaces a message previously sent by the given module with a new version, tagged as
+    /// configured.
+    pub async fn edit(
+        &self,
+        room: &Room,
+        module: &str,
+        event_id: OwnedEventId,
+        mut content: RoomMessageEventContent,
+    ) -> anyhow::Result<OwnedEventId> {
+        let client = room.client();
+        let bot = client.user_id().map(|bot| bot.as_str()).unwrap_or_default();
+        self.add_lines(bot, module, &mut content);
+        let content = content.make_replacement(ReplacementMetadata::new(event_id, None), None);
+        self.send_tagged(room, bot, module, content).await
+    }
+
+    /// Sends a message from the given module in reply to another one, tagged as configured, with
+    /// the standard fallback quoting the original message.
+    pub async fn reply(
+        &self,
+        room: &Room,
+        module: &str,
+        original: &OriginalRoomMessageEvent,
+        mut content: RoomMessageEventContent,
+    ) -> anyhow::Result<OwnedEventId> {
+        let client = room.client();
+        let bot = client.user_id().map(|bot| bot.as_str()).unwrap_or_default();
+        // The lines are added first, so the fallback stays at the start of the body.
+        self.add_lines(bot, module, &mut content);
+        self.send_tagged(room, bot, module, reply_content(original, content))
+            .await
+    }
+
+    async fn send_tagged(
+        &self,
+        room: &Room,
+        bot: &str,
+        module: &str,
+        content: RoomMessageEventContent,
+    ) -> anyhow::Result<OwnedEventId> {
+        let mut content = serde_json::to_value(content)?;
+        if self.config.origin_field {
+            if let Some(fields) = content.as_object_mut() {
+                fields.insert(
+                    ORIGIN_FIELD.to_owned(),
+                    serde_json::json!({
+                        "bot": bot,
+                        "module": module,
+                        "version": env!("CARGO_PKG_VERSION"),
+                    }),
+                );
+            }
+        }
+
+        outbox::send_raw(room, "m.room.message", content).await
+    }
+}
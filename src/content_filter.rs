@@ -0,0 +1,269 @@
+//! Configurable content filter: messages matching a word list or regular expressions get handled
+//! according to the policy associated to the rule's severity.
+
+use std::collections::HashMap;
+
+use matrix_sdk::{
+    room::Room,
+    ruma::{events::room::message::RoomMessageEventContent, EventId, OwnedRoomId, OwnedUserId, UserId},
+    Client,
+};
+use regex::Regex;
+use serde::Deserialize;
+use tracing::{debug, warn};
+
+use crate::{admin_dm, host_table, outbox, ShareableDatabase};
+
+/// Name of the host table keeping track of the offenses.
+const TABLE: &str = "content_filter";
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Low,
+    Medium,
+    High,
+}
+
+/// What to do when a message matches a rule.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterAction {
+    /// warn the user in the room.
+    Warn,
+    /// redact the offending message.
+    Redact,
+    /// notify the moderators.
+    Notify,
+    /// kick the user once they've reached the configured number of offenses.
+    Escalate,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct FilterRule {
+    /// rooms this rule applies to. If empty, all the rooms.
+    #[serde(default)]
+    pub rooms: Vec<OwnedRoomId>,
+    /// words or phrases matched case insensitively, as whole words; the punctuation in between
+    /// doesn't matter, e.g. `f-word` matches `F word`.
+    #[serde(default)]
+    pub words: Vec<String>,
+    /// regular expressions.
+    #[serde(default)]
+    pub regexes: Vec<String>,
+    pub severity: Severity,
+}
+
+/// Configuration for the content filter.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ContentFilterConfig {
+    pub rules: Vec<FilterRule>,
+    /// actions to run for each severity level.
+    pub policies: HashMap<Severity, Vec<FilterAction>>,
+    /// room where moderators get notified. If missing, the admin gets notified by DM.
+    pub moderators_room: Option<OwnedRoomId>,
+    /// number of offenses after which the `escalate` action kicks the user.
+    #[serde(default = "default_kick_after")]
+    pub kick_after: u64,
+}
+
+fn default_kick_after() -> u64 {
+    3
+}
+
+/// Splits a text into its lowercase words, dropping the spaces and punctuation.
+fn split_words(text: &str) -> Vec<String> {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Whether the phrase, split in words, appears in the words of a text. The phrase mustn't be
+/// empty.
+fn contains_phrase(words: &[String], phrase: &[String]) -> bool {
+    words.windows(phrase.len()).any(|window| window == phrase)
+}
+
+struct CompiledRule {
+    rooms: Vec<OwnedRoomId>,
+    /// the words and phrases, split in words.
+    phrases: Vec<Vec<String>>,
+    regexes: Vec<Regex>,
+    severity: Severity,
+}
+
+impl CompiledRule {
+    fn matches(&self, room: &Room, content: &str) -> bool {
+        if !self.rooms.is_empty() && !self.rooms.iter().any(|r| r == room.room_id()) {
+            return false;
+        }
+        let words = split_words(content);
+        self.phrases
+            .iter()
+            .any(|phrase| contains_phrase(&words, phrase))
+            || self.regexes.iter().any(|re| re.is_match(content))
+    }
+}
+
+pub(crate) struct ContentFilter {
+    db: ShareableDatabase,
+    admin_user_id: OwnedUserId,
+    rules: Vec<CompiledRule>,
+    policies: HashMap<Severity, Vec<FilterAction>>,
+    moderators_room: Option<OwnedRoomId>,
+    kick_after: u64,
+}
+
+impl ContentFilter {
+    pub fn new(
+        config: Option<ContentFilterConfig>,
+        db: ShareableDatabase,
+        admin_user_id: OwnedUserId,
+    ) -> anyhow::Result<Self> {
+        let (rules, policies, moderators_room, kick_after) = match config {
+            Some(config) => {
+                let rules = config
+                    .rules
+                    .into_iter()
+                    .map(|rule| {
+                        Ok(CompiledRule {
+                            rooms: rule.rooms,
+                            phrases: rule
+                                .words
+                                .iter()
+                                .map(|word| {
+                                    let phrase = split_words(word);
+                                    anyhow::ensure!(
+                                        !phrase.is_empty(),
+                                        "content filter: {word:?} has no letters nor digits"
+                                    );
+                                    Ok(phrase)
+                                })
+                                .collect::<anyhow::Result<_>>()?,
+                            regexes: rule
+                                .regexes
+                                .iter()
+                                .map(|re| Regex::new(re))
+                                .collect::<Result<_, _>>()?,
+                            severity: rule.severity,
+                        })
+                    })
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                (rules, config.policies, config.moderators_room, config.kick_after)
+            }
+            None => (Vec::new(), HashMap::new(), None, default_kick_after()),
+        };
+
+        Ok(Self {
+            db,
+            admin_user_id,
+            rules,
+            policies,
+            moderators_room,
+            kick_after,
+        })
+    }
+
+    /// Returns the highest severity of the rules matched by this message, if any.
+    fn severity(&self, room: &Room, content: &str) -> Option<Severity> {
+        self.rules
+            .iter()
+            .filter(|rule| rule.matches(room, content))
+            .map(|rule| rule.severity)
+            .max()
+    }
+
+    async fn notify_moderators(&self, client: &Client, text: &str) -> anyhow::Result<()> {
+        match self
+            .moderators_room
+            .as_ref()
+            .and_then(|room_id| client.get_room(room_id))
+        {
+            Some(room) => {
+                outbox::send(&room, RoomMessageEventContent::text_plain(text)).await?;
+            }
+            None => admin_dm::notify(client, &self.admin_user_id, text, None).await?,
+        }
+        Ok(())
+    }
+
+    /// Checks a message against the filter rules, and applies the policy if it matches.
+    ///
+    /// Returns whether the message was filtered, in which case it mustn't be handled any further.
+    pub async fn check(
+        &self,
+        client: &Client,
+        room: &Room,
+        event_id: &EventId,
+        sender: &UserId,
+        content: &str,
+    ) -> anyhow::Result<bool> {
+        let Some(severity) = self.severity(room, content) else {
+            return Ok(false);
+        };
+
+        debug!("message {event_id} from {sender} matched a {severity:?} filter rule");
+
+        let key = format!("{}|{sender}", room.room_id());
+        let offenses = host_table::read_u64(&self.db, TABLE, &key)? + 1;
+        host_table::write_u64(&self.db, TABLE, &key, offenses)?;
+
+        let actions = self.policies.get(&severity).map_or(&[][..], |a| a.as_slice());
+        for action in actions {
+            let result: anyhow::Result<()> = match action {
+                FilterAction::Warn => {
+                    let text = format!("{sender}, please mind the rules of this room.");
+                    outbox::send(room, RoomMessageEventContent::text_plain(text))
+                        .await
+                        .map(|_| ())
+                }
+                FilterAction::Redact => {
+                    outbox::redact(room, event_id, Some("content filter")).await
+                }
+                FilterAction::Notify => {
+                    let text = format!(
+                        "{sender} posted filtered content ({severity:?}) in {}, offense #{offenses}: {content}",
+                        room.room_id()
+                    );
+                    self.notify_moderators(client, &text).await
+                }
+                FilterAction::Escalate => {
+                    if offenses >= self.kick_after {
+                        outbox::kick(room, sender, Some("repeated content filter offenses")).await
+                    } else {
+                        Ok(())
+                    }
+                }
+            };
+            if let Err(err) = result {
+                warn!("content filter action {action:?} failed: {err:#}");
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn words() {
+        assert_eq!(split_words("  F-word, heck!"), ["f", "word", "heck"]);
+        assert!(split_words(" -!? ").is_empty());
+    }
+
+    #[test]
+    fn phrases() {
+        let words = split_words("Well, what the HECK is this?");
+        assert!(contains_phrase(&words, &split_words("heck")));
+        assert!(contains_phrase(&words, &split_words("the heck")));
+        assert!(contains_phrase(&words, &split_words("the-heck")));
+        assert!(!contains_phrase(&words, &split_words("he")));
+        assert!(!contains_phrase(&words, &split_words("what heck")));
+        assert!(!contains_phrase(&words, &split_words("this is")));
+    }
+}
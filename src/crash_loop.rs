@@ -0,0 +1,150 @@
+//! Crash-loop detection: each startup is recorded, and when the bot has restarted more than
+//! `max_restarts` times within `window_mins` minutes, e.g. restarted over and over by systemd
+//! because a module crashes it, it enters the safe mode on its own, and tells the admin once
+//! connected. The startups are forgotten once the bot has been up for the whole window.
+
+use std::time::Duration;
+
+use chrono::Utc;
+use matrix_sdk::{ruma::OwnedUserId, Client};
+use serde::Deserialize;
+use tracing::{error, info, warn};
+
+use crate::{admin_dm, host_table, ShareableDatabase};
+
+/// Name of the host table keeping the startups.
+const TABLE: &str = "crash_loop";
+/// Key of the times of the recent startups, in seconds since the epoch.
+const STARTUPS_KEY: &str = "startups";
+
+/// Configuration for the crash-loop detection.
+#[derive(Clone, Debug, Deserialize)]
+pub struct CrashLoopConfig {
+    /// restarting more often than this within the window enters the safe mode; 0 disables the
+    /// detection.
+    #[serde(default = "default_max_restarts")]
+    pub max_restarts: usize,
+    /// the window, in minutes.
+    #[serde(default = "default_window_mins")]
+    pub window_mins: u64,
+}
+
+impl Default for CrashLoopConfig {
+    fn default() -> Self {
+        Self {
+            max_restarts: default_max_restarts(),
+            window_mins: default_window_mins(),
+        }
+    }
+}
+
+fn default_max_restarts() -> usize {
+    5
+}
+
+fn default_window_mins() -> u64 {
+    10
+}
+
+pub(crate) struct CrashLoop {
+    config: CrashLoopConfig,
+    db: ShareableDatabase,
+    /// Whether this startup was detected as part of a crash loop.
+    detected: bool,
+}
+
+impl CrashLoop {
+    /// Records the startup, and detects whether the bot is crash-looping.
+    pub fn startup(config: CrashLoopConfig, db: ShareableDatabase) -> anyhow::Result<Self> {
+        let now = Utc::now().timestamp();
+        let window = (config.window_mins * 60) as i64;
+        let mut startups: Vec<i64> =
+            host_table::read_json(&db, TABLE, STARTUPS_KEY)?.unwrap_or_default();
+        startups.retain(|&startup| now - startup < window);
+        startups.push(now);
+        host_table::write_json(&db, TABLE, STARTUPS_KEY, &startups)?;
+
+        // The first startup of the window isn't a restart.
+        let restarts = startups.len() - 1;
+        let detected = config.max_restarts > 0 && restarts > config.max_restarts;
+        if detected {
+            error!(
+                "restarted {restarts} times in the last {} minutes, entering safe mode",
+                config.window_mins
+            );
+        }
+        Ok(Self {
+            config,
+            db,
+            detected,
+        })
+    }
+
+    /// Whether the bot is crash-looping, and must start in safe mode.
+    pub fn detected(&self) -> bool {
+        self.detected
+    }
+
+    fn restarts(&self) -> anyhow::Result<usize> {
+        let startups: Vec<i64> =
+            host_table::read_json(&self.db, TABLE, STARTUPS_KEY)?.unwrap_or_default();
+        Ok(startups.len().saturating_sub(1))
+    }
+
+    fn reset(&self) -> anyhow::Result<()> {
+        host_table::remove(&self.db, TABLE, STARTUPS_KEY)
+    }
+
+    /// Tells the admin about the crash loop, if any, then forgets the startups once the bot has
+    /// been up for the whole window.
+    pub async fn run(&self, client: Client, admin_user_id: OwnedUserId) {
+        if self.detected {
+            let text = format!(
+                "I restarted more than {} times in {} minutes, so I started in safe mode: no \
+                 modules nor schedulers, and only the host admin commands. Fix the \
+                 configuration, then restart me; `!admin host crash-loop reset` makes the next \
+                 start a normal one.",
+                self.config.max_restarts, self.config.window_mins
+            );
+            if let Err(err) = admin_dm::notify(&client, &admin_user_id, &text, None).await {
+                warn!("couldn't tell the admin about the crash loop: {err:#}");
+            }
+        }
+
+        tokio::time::sleep(Duration::from_secs(self.config.window_mins * 60)).await;
+        match self.reset() {
+            Ok(()) => info!(
+                "up for {} minutes, forgot the startups",
+                self.config.window_mins
+            ),
+            Err(err) => warn!("couldn't forget the startups: {err:#}"),
+        }
+    }
+
+    /// Try to handle an `!admin host crash-loop` command.
+    pub fn try_handle_admin(&self, content: &str) -> Option<String> {
+        let rest = content.strip_prefix("!admin host crash-loop")?;
+        if !rest.is_empty() && !rest.starts_with(' ') {
+            return None;
+        }
+
+        let result = match rest.trim() {
+            "" => self.restarts().map(|restarts| {
+                format!(
+                    "{restarts} restarts in the last {} minutes{}",
+                    self.config.window_mins,
+                    if self.detected {
+                        ", running in safe mode"
+                    } else {
+                        ""
+                    }
+                )
+            }),
+            "reset" => self
+                .reset()
+                .map(|()| "forgot the recent startups".to_owned()),
+            _ => Ok("usage: !admin host crash-loop [reset]".to_owned()),
+        };
+        Some(result.unwrap_or_else(|err| format!("error: {err:#}")))
+    }
+}
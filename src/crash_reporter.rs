@@ -0,0 +1,145 @@
+//! Optional Sentry integration, reporting panics, module errors and repeated failures to send
+//! responses, so that operators of several bots get a central view of their errors.
+
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash as _, Hasher as _},
+    sync::Mutex,
+};
+
+use matrix_sdk::ruma::{EventId, OwnedRoomId, RoomId};
+use serde::Deserialize;
+use tracing::{debug, warn};
+
+/// Configuration for the crash reporter.
+#[derive(Clone, Debug, Deserialize)]
+pub struct CrashReporterConfig {
+    /// Sentry DSN the reports are sent to.
+    pub dsn: String,
+    /// ratio of the reports actually sent, between 0 and 1.
+    #[serde(default = "default_sample_rate")]
+    pub sample_rate: f32,
+    /// environment the reports are tagged with, e.g. `production`.
+    pub environment: Option<String>,
+    /// number of consecutive failures to send to a room after which it's reported.
+    #[serde(default = "default_send_failures_threshold")]
+    pub send_failures_threshold: u32,
+}
+
+fn default_sample_rate() -> f32 {
+    1.0
+}
+
+fn default_send_failures_threshold() -> u32 {
+    3
+}
+
+/// Hashes an event id, so the reports can be correlated without leaking it.
+fn hash_event_id(event_id: &EventId) -> String {
+    let mut hasher = DefaultHasher::new();
+    event_id.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+/// Reports an error message with the given tags; does nothing if no crash reporter is configured.
+pub fn capture(message: &str, tags: &[(&str, String)]) {
+    sentry::with_scope(
+        |scope| {
+            for (key, value) in tags {
+                scope.set_tag(key, value);
+            }
+        },
+        || sentry::capture_message(message, sentry::Level::Error),
+    );
+}
+
+pub(crate) struct CrashReporter {
+    /// Keeps the Sentry client alive; reporting is a no-op when there's none.
+    guard: Option<sentry::ClientInitGuard>,
+    send_failures_threshold: u32,
+    /// Number of consecutive failures to send messages, per room.
+    send_failures: Mutex<HashMap<OwnedRoomId, u32>>,
+}
+
+impl CrashReporter {
+    pub fn new(config: Option<CrashReporterConfig>) -> anyhow::Result<Self> {
+        let Some(config) = config else {
+            return Ok(Self {
+                guard: None,
+                send_failures_threshold: default_send_failures_threshold(),
+                send_failures: Default::default(),
+            });
+        };
+
+        let guard = sentry::init(sentry::ClientOptions {
+            dsn: Some(config.dsn.parse()?),
+            sample_rate: config.sample_rate,
+            environment: config.environment.map(Into::into),
+            release: sentry::release_name!(),
+            ..Default::default()
+        });
+        debug!("crash reporter enabled");
+
+        Ok(Self {
+            guard: Some(guard),
+            send_failures_threshold: config.send_failures_threshold,
+            send_failures: Default::default(),
+        })
+    }
+
+    fn capture(&self, message: &str, tags: &[(&str, String)]) {
+        if self.guard.is_some() {
+            capture(message, tags);
+        }
+    }
+
+    /// Reports an error returned by a module.
+    pub fn module_error(
+        &self,
+        module: &str,
+        room: Option<&RoomId>,
+        event_id: Option<&EventId>,
+        err: &anyhow::Error,
+    ) {
+        let mut tags = vec![("module", module.to_owned())];
+        if let Some(room) = room {
+            tags.push(("room", room.to_string()));
+        }
+        if let Some(event_id) = event_id {
+            tags.push(("event_id_hash", hash_event_id(event_id)));
+        }
+        self.capture(
+            &format!("module {module} ran into an error: {err:#}"),
+            &tags,
+        );
+    }
+
+    /// Keeps track of the results of sending messages to a room, reporting when too many
+    /// consecutive ones failed.
+    pub fn send_result(&self, room: &RoomId, module: &str, result: &anyhow::Result<()>) {
+        let failures = {
+            let mut send_failures = self.send_failures.lock().unwrap();
+            match result {
+                Ok(()) => {
+                    send_failures.remove(room);
+                    return;
+                }
+                Err(_) => {
+                    let failures = send_failures.entry(room.to_owned()).or_default();
+                    *failures += 1;
+                    *failures
+                }
+            }
+        };
+
+        if failures == self.send_failures_threshold {
+            warn!("{failures} consecutive failures to send to {room}");
+            if let Err(err) = result {
+                self.capture(
+                    &format!("{failures} consecutive failures to send to a room: {err:#}"),
+                    &[("room", room.to_string()), ("module", module.to_owned())],
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,227 @@
+//! Recurring jobs of the modules, scheduled with cron expressions through `schedule` actions: the
+//! host calls the module's `on-cron` export back with the job's name and payload, in the room
+//! where it was scheduled, each time the job is due.
+//!
+//! The jobs are persisted, so they survive restarts and hot reloads. Occurrences missed while the
+//! bot was down are skipped.
+
+use chrono::{DateTime, Timelike as _, Utc};
+use matrix_sdk::{
+    ruma::{OwnedRoomId, RoomId},
+    Client,
+};
+use serde::{Deserialize, Serialize};
+use tokio::time::{sleep, Duration};
+use tracing::{debug, error, warn};
+
+use crate::{
+    diagnostics::APP_CTX_LOCK, handle_module_actions, host_table, schedule::CronSchedule, wasm,
+    App, ShareableDatabase,
+};
+
+/// Name of the host table keeping the jobs.
+const TABLE: &str = "cron";
+/// Key of the list of all the jobs.
+const JOBS_KEY: &str = "jobs";
+/// Most jobs a module may have scheduled at once.
+const MAX_JOBS_PER_MODULE: usize = 100;
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Job {
+    module: String,
+    room: OwnedRoomId,
+    name: String,
+    schedule: String,
+    timezone: Option<String>,
+    payload: String,
+}
+
+struct ScheduledJob {
+    job: Job,
+    schedule: CronSchedule,
+    next: Option<DateTime<Utc>>,
+}
+
+impl ScheduledJob {
+    fn new(job: Job) -> anyhow::Result<Self> {
+        let schedule = CronSchedule::new(&job.schedule, job.timezone.as_deref())?;
+        let next = schedule.next_after(Utc::now());
+        Ok(Self {
+            job,
+            schedule,
+            next,
+        })
+    }
+}
+
+pub(crate) struct CronScheduler {
+    db: ShareableDatabase,
+    jobs: Vec<ScheduledJob>,
+}
+
+impl CronScheduler {
+    /// Creates the scheduler, with the jobs persisted in the database.
+    pub fn new(db: ShareableDatabase) -> anyhow::Result<Self> {
+        let jobs: Vec<Job> = host_table::read_json(&db, TABLE, JOBS_KEY)?.unwrap_or_default();
+        let jobs = jobs
+            .into_iter()
+            .filter_map(|job| {
+                let (module, name) = (job.module.clone(), job.name.clone());
+                ScheduledJob::new(job)
+                    .map_err(|err| warn!("ignoring invalid job {name} of {module}: {err:#}"))
+                    .ok()
+            })
+            .collect();
+        Ok(Self { db, jobs })
+    }
+
+    fn save(&self) -> anyhow::Result<()> {
+        let jobs = self.jobs.iter().map(|j| &j.job).collect::<Vec<_>>();
+        host_table::write_json(&self.db, TABLE, JOBS_KEY, &jobs)
+    }
+
+    fn position(&self, module: &str, room: &RoomId, name: &str) -> Option<usize> {
+        self.jobs
+            .iter()
+            .position(|j| j.job.module == module && &*j.job.room == room && j.job.name == name)
+    }
+
+    /// Schedules a job for the module in the room, replacing the one with the same name if any.
+    fn schedule(&mut self, module: &str, room: &RoomId, job: wasm::CronJob) -> anyhow::Result<()> {
+        let job = ScheduledJob::new(Job {
+            module: module.to_owned(),
+            room: room.to_owned(),
+            name: job.name,
+            schedule: job.schedule,
+            timezone: job.timezone,
+            payload: job.payload,
+        })?;
+        match self.position(module, room, &job.job.name) {
+            Some(index) => self.jobs[index] = job,
+            None => {
+                let count = self.jobs.iter().filter(|j| j.job.module == module).count();
+                if count >= MAX_JOBS_PER_MODULE {
+                    anyhow::bail!("too many jobs ({count})");
+                }
+                self.jobs.push(job);
+            }
+        }
+        self.save()
+    }
+
+    fn unschedule(&mut self, module: &str, room: &RoomId, name: &str) -> anyhow::Result<()> {
+        if let Some(index) = self.position(module, room, name) {
+            self.jobs.remove(index);
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    /// Returns the jobs that are due, and computes their next occurrence.
+    fn pop_due(&mut self, now: DateTime<Utc>) -> Vec<Job> {
+        let mut due = Vec::new();
+        for job in &mut self.jobs {
+            if job.next.is_some_and(|next| next <= now) {
+                due.push(job.job.clone());
+                job.next = job.schedule.next_after(now);
+            }
+        }
+        due
+    }
+}
+
+/// Schedules a job requested by a module in a room.
+pub(crate) async fn schedule(app: &App, module: &str, room: &RoomId, job: wasm::CronJob) {
+    let name = job.name.clone();
+    let result = APP_CTX_LOCK
+        .lock(&app.inner, "cron scheduling")
+        .await
+        .cron
+        .schedule(module, room, job);
+    if let Err(err) = result {
+        warn!("couldn't schedule job {name} of {module}: {err:#}");
+    }
+}
+
+/// Removes a job scheduled by a module in a room.
+pub(crate) async fn unschedule(app: &App, module: &str, room: &RoomId, name: &str) {
+    let result = APP_CTX_LOCK
+        .lock(&app.inner, "cron scheduling")
+        .await
+        .cron
+        .unschedule(module, room, name);
+    if let Err(err) = result {
+        warn!("couldn't unschedule job {name} of {module}: {err:#}");
+    }
+}
+
+/// Runs the jobs when they're due, calling the modules back and handling their responses.
+pub(crate) async fn run(app: App, client: Client) {
+    loop {
+        // Jobs have a minute granularity: check at the start of every minute.
+        let second = Utc::now().second().min(59);
+        sleep(Duration::from_secs(u64::from(60 - second))).await;
+
+        let inner = app.inner.clone();
+        let crash_reporter = app.crash_reporter.clone();
+        let response_limits = app.response_limits.clone();
+        let maintenance = app.maintenance.clone();
+        let ran = tokio::task::spawn_blocking(move || {
+            let ctx = &mut *futures::executor::block_on(APP_CTX_LOCK.lock(&inner, "cron"));
+            let due = ctx.cron.pop_due(Utc::now());
+            if maintenance.is_on() {
+                // Like while the bot is down, the occurrences are skipped.
+                debug!("skipping {} jobs during maintenance", due.len());
+                return Vec::new();
+            }
+            let (store, modules) = ctx.modules.iter();
+
+            let mut ran = Vec::new();
+            for job in due {
+                let Some(module) = modules.clone().find(|m| m.name() == job.module) else {
+                    debug!(
+                        "skipping job {} of {}, which isn't loaded",
+                        job.name, job.module
+                    );
+                    continue;
+                };
+                match module.on_cron(&mut *store, &job.name, &job.room, &job.payload) {
+                    Ok(actions) => {
+                        let actions = response_limits.apply(module.name(), actions);
+                        ran.push((job, actions));
+                    }
+                    Err(err) => {
+                        warn!("wasm module {} ran into an error: {err}", module.name());
+                        module.record_error(&err);
+                        crash_reporter.module_error(module.name(), Some(&job.room), None, &err);
+                    }
+                }
+            }
+            ran
+        })
+        .await;
+
+        let ran = match ran {
+            Ok(ran) => ran,
+            Err(err) => {
+                error!("running the cron jobs failed: {err}");
+                continue;
+            }
+        };
+        for (job, actions) in ran {
+            let Some(room) = client.get_room(&job.room) else {
+                debug!(
+                    "job {} of {} is in unknown room {}",
+                    job.name, job.module, job.room
+                );
+                continue;
+            };
+            if let Err(err) = handle_module_actions(&app, &room, &job.module, actions).await {
+                warn!(
+                    "couldn't handle the actions of job {} of {}: {err:#}",
+                    job.name, job.module
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,361 @@
+//! Web admin dashboard, served by the bot on its own listener: the modules, with toggles to
+//! disable them in all the rooms, the rooms, with their modules' settings, the recent errors of
+//! the modules, graphs of the activity of the last hour, and a form to post a message in a room.
+//!
+//! The page is a static one, talking to a small JSON API under `/api/`, whose requests must carry
+//! the configured token, or an API key with the right scope, as a bearer token in the
+//! `Authorization` header.
+
+use std::{collections::VecDeque, net::SocketAddr, sync::Arc};
+
+use chrono::{DateTime, Utc};
+use hyper::{
+    header::{self, HeaderValue},
+    Body, Method, Request, Response, StatusCode,
+};
+use matrix_sdk::{
+    ruma::{events::room::message::RoomMessageEventContent, OwnedRoomId, RoomId},
+    Client, RoomState,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{debug, error, warn};
+
+use crate::{
+    api_keys::{ApiKey, Scope},
+    diagnostics::APP_CTX_LOCK,
+    event_export::Event,
+    room_modules::RoomModulesConfig,
+    room_resolver::percent_decode,
+    utils::resolve_room,
+    webhooks::{read_body, respond},
+    App, ListenConfig,
+};
+
+/// Configuration for the dashboard.
+#[derive(Clone, Debug, Deserialize)]
+pub struct DashboardConfig {
+    /// secret the API requests can carry, allowing everything; without it, only the API keys
+    /// are accepted.
+    pub token: Option<String>,
+}
+
+/// Address the listener binds to, unless configured otherwise; only reachable locally, e.g.
+/// through an SSH tunnel or a reverse proxy.
+const DEFAULT_ADDRESS: ([u8; 4], u16) = ([127, 0, 0, 1], 43212);
+/// Number of minutes of activity kept for the graphs.
+const METRICS_MINUTES: usize = 60;
+
+const PAGE: &str = include_str!("dashboard.html");
+
+/// The activity of a minute.
+#[derive(Clone, Default, Serialize)]
+struct Minute {
+    /// Start of the minute, in seconds since the epoch.
+    start: i64,
+    messages: u64,
+    actions: u64,
+    calls: u64,
+    errors: u64,
+    total_ms: u64,
+}
+
+/// The activity of the last minutes, fed by the events of the bot.
+#[derive(Default)]
+struct Metrics {
+    minutes: std::sync::Mutex<VecDeque<Minute>>,
+}
+
+impl Metrics {
+    fn record(&self, event: &Event) {
+        let start = Utc::now().timestamp() / 60 * 60;
+        let mut minutes = self.minutes.lock().unwrap();
+        if minutes.back().map_or(true, |minute| minute.start != start) {
+            minutes.push_back(Minute {
+                start,
+                ..Default::default()
+            });
+            if minutes.len() > METRICS_MINUTES {
+                minutes.pop_front();
+            }
+        }
+        let Some(minute) = minutes.back_mut() else {
+            return;
+        };
+        match event {
+            Event::Message { .. } => minute.messages += 1,
+            Event::Action { .. } => minute.actions += 1,
+            Event::ModuleCall { ms, error, .. } => {
+                minute.calls += 1;
+                minute.total_ms += ms;
+                if error.is_some() {
+                    minute.errors += 1;
+                }
+            }
+            Event::ModuleStats { .. } => {}
+        }
+    }
+
+    fn snapshot(&self) -> Vec<Minute> {
+        self.minutes.lock().unwrap().iter().cloned().collect()
+    }
+
+    async fn run(&self, app: App) {
+        let mut events = app.event_export.subscribe();
+        loop {
+            match events.recv().await {
+                Ok(event) => self.record(&event),
+                Err(RecvError::Lagged(count)) => {
+                    debug!("the dashboard metrics missed {count} events");
+                }
+                Err(RecvError::Closed) => return,
+            }
+        }
+    }
+}
+
+/// What the connections share.
+struct State {
+    config: DashboardConfig,
+    metrics: Metrics,
+}
+
+#[derive(Serialize)]
+struct ModuleInfo {
+    name: String,
+    enabled: bool,
+    loaded_at: DateTime<Utc>,
+    capabilities: String,
+    last_error: Option<ErrorInfo>,
+}
+
+#[derive(Serialize)]
+struct ErrorInfo {
+    module: String,
+    at: DateTime<Utc>,
+    message: String,
+}
+
+#[derive(Serialize)]
+struct RoomInfo {
+    id: OwnedRoomId,
+    name: String,
+    config: RoomModulesConfig,
+    /// Modules toggled by the admin in the room, and whether they're enabled.
+    toggled: std::collections::BTreeMap<String, bool>,
+}
+
+#[derive(Deserialize)]
+struct Toggle {
+    enabled: bool,
+}
+
+#[derive(Deserialize)]
+struct NewMessage {
+    room: String,
+    /// Markdown.
+    text: String,
+}
+
+fn json<T: Serialize>(value: &T) -> Response<Body> {
+    match serde_json::to_string(value) {
+        Ok(body) => {
+            let mut response = Response::new(Body::from(body));
+            response.headers_mut().insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/json"),
+            );
+            response
+        }
+        Err(err) => respond(StatusCode::INTERNAL_SERVER_ERROR, &format!("{err}")),
+    }
+}
+
+/// Who's making a request: the holder of the dashboard's token, or of an API key.
+enum Caller {
+    Token,
+    Key(ApiKey),
+}
+
+impl Caller {
+    fn allows(&self, scope: Scope, room_id: Option<&RoomId>) -> bool {
+        match self {
+            Self::Token => true,
+            Self::Key(key) => key.allows(scope, room_id),
+        }
+    }
+}
+
+fn caller(app: &App, config: &DashboardConfig, req: &Request<Body>) -> Option<Caller> {
+    let token = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))?;
+    if config.token.as_deref() == Some(token) {
+        Some(Caller::Token)
+    } else {
+        app.api_keys.get(token).map(Caller::Key)
+    }
+}
+
+fn forbidden() -> Response<Body> {
+    respond(StatusCode::FORBIDDEN, "the API key doesn't allow this")
+}
+
+async fn modules(app: &App) -> anyhow::Result<Vec<ModuleInfo>> {
+    let disabled = app.room_modules.disabled_everywhere()?;
+    let mut ctx = APP_CTX_LOCK.lock(&app.inner, "dashboard").await;
+    let (_, modules) = ctx.modules.iter();
+    Ok(modules
+        .map(|module| ModuleInfo {
+            name: module.name().to_owned(),
+            enabled: !disabled.contains(module.name()),
+            loaded_at: module.loaded_at(),
+            capabilities: module.capabilities().to_string(),
+            last_error: module.last_error().map(|(at, message)| ErrorInfo {
+                module: module.name().to_owned(),
+                at,
+                message,
+            }),
+        })
+        .collect())
+}
+
+async fn rooms(app: &App, client: &Client) -> anyhow::Result<Vec<RoomInfo>> {
+    let mut rooms = Vec::new();
+    for room in client.rooms() {
+        if room.state() != RoomState::Joined {
+            continue;
+        }
+        let (config, toggled) = app.room_modules.room_settings(room.room_id())?;
+        rooms.push(RoomInfo {
+            id: room.room_id().to_owned(),
+            name: room.display_name().await?.to_string(),
+            config,
+            toggled,
+        });
+    }
+    rooms.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(rooms)
+}
+
+async fn send(app: &App, client: &Client, room_id: &RoomId, text: String) -> anyhow::Result<()> {
+    let room = client
+        .get_room(room_id)
+        .filter(|room| room.state() == RoomState::Joined)
+        .ok_or_else(|| anyhow::anyhow!("the bot isn't in {room_id}"))?;
+    let content = RoomMessageEventContent::text_markdown(text);
+    app.compliance.send(&room, "dashboard", content).await?;
+    Ok(())
+}
+
+/// Parses the body of the request as JSON.
+async fn parse<T: serde::de::DeserializeOwned>(req: Request<Body>) -> anyhow::Result<T> {
+    Ok(serde_json::from_slice(&read_body(req).await?)?)
+}
+
+async fn handle(app: &App, client: &Client, state: &State, req: Request<Body>) -> Response<Body> {
+    let path = req.uri().path().to_owned();
+    if path == "/" && req.method() == Method::GET {
+        let mut response = Response::new(Body::from(PAGE));
+        response.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("text/html; charset=utf-8"),
+        );
+        return response;
+    }
+    if !path.starts_with("/api/") {
+        return respond(StatusCode::NOT_FOUND, "not found");
+    }
+    let Some(caller) = caller(app, &state.config, &req) else {
+        return respond(StatusCode::UNAUTHORIZED, "missing or invalid token");
+    };
+    if req.method() == Method::GET && !caller.allows(Scope::Metrics, None) {
+        return forbidden();
+    }
+
+    let segments = path["/api/".len()..].split('/').collect::<Vec<_>>();
+    let result = match (req.method().clone(), segments.as_slice()) {
+        (Method::GET, ["modules"]) => modules(app).await.map(|modules| json(&modules)),
+        (Method::POST, ["modules", _]) if !caller.allows(Scope::Admin, None) => Ok(forbidden()),
+        (Method::POST, ["modules", module]) => {
+            let module = module.to_string();
+            match parse::<Toggle>(req).await {
+                Ok(toggle) => app
+                    .room_modules
+                    .set_enabled_everywhere(&module, toggle.enabled)
+                    .map(|()| respond(StatusCode::OK, "ok")),
+                Err(err) => Ok(respond(StatusCode::BAD_REQUEST, &format!("{err:#}"))),
+            }
+        }
+        (Method::GET, ["rooms"]) => rooms(app, client).await.map(|rooms| json(&rooms)),
+        (Method::POST, ["rooms", room, "modules", module]) => {
+            let (room, module) = (percent_decode(room), module.to_string());
+            match (OwnedRoomId::try_from(room), parse::<Toggle>(req).await) {
+                (Ok(room_id), _) if !caller.allows(Scope::Admin, Some(&room_id)) => Ok(forbidden()),
+                (Ok(room_id), Ok(toggle)) => app
+                    .room_modules
+                    .toggle(&room_id, room_id.as_str(), &module, toggle.enabled)
+                    .map(|text| respond(StatusCode::OK, &text)),
+                (Err(err), _) => Ok(respond(StatusCode::BAD_REQUEST, &format!("{err}"))),
+                (_, Err(err)) => Ok(respond(StatusCode::BAD_REQUEST, &format!("{err:#}"))),
+            }
+        }
+        (Method::GET, ["errors"]) => modules(app).await.map(|modules| {
+            let mut errors = modules
+                .into_iter()
+                .filter_map(|module| module.last_error)
+                .collect::<Vec<_>>();
+            errors.sort_by(|a, b| b.at.cmp(&a.at));
+            json(&errors)
+        }),
+        (Method::GET, ["metrics"]) => Ok(json(&state.metrics.snapshot())),
+        (Method::POST, ["messages"]) => match parse::<NewMessage>(req).await {
+            Ok(message) => match resolve_room(client, &message.room).await {
+                Ok(room_id) if !caller.allows(Scope::Send, Some(&room_id)) => Ok(forbidden()),
+                Ok(room_id) => send(app, client, &room_id, message.text)
+                    .await
+                    .map(|()| respond(StatusCode::OK, "sent")),
+                Err(err) => Ok(respond(StatusCode::BAD_REQUEST, &format!("{err:#}"))),
+            },
+            Err(err) => Ok(respond(StatusCode::BAD_REQUEST, &format!("{err:#}"))),
+        },
+        _ => Ok(respond(StatusCode::NOT_FOUND, "unknown endpoint")),
+    };
+    result.unwrap_or_else(|err| {
+        warn!("dashboard request to {path} failed: {err:#}");
+        respond(StatusCode::INTERNAL_SERVER_ERROR, &format!("{err:#}"))
+    })
+}
+
+/// Serves the dashboard, if it's configured.
+pub(crate) async fn run(
+    app: App,
+    client: Client,
+    config: Option<DashboardConfig>,
+    listen: ListenConfig,
+) {
+    let Some(config) = config else {
+        return;
+    };
+    let state = Arc::new(State {
+        config,
+        metrics: Metrics::default(),
+    });
+    {
+        let (state, app) = (state.clone(), app.clone());
+        tokio::spawn(async move { state.metrics.run(app).await });
+    }
+
+    let handler = move |req| {
+        let (app, client, state) = (app.clone(), client.clone(), state.clone());
+        async move { handle(&app, &client, &state, req).await }
+    };
+    if let Err(err) = listen
+        .serve("dashboard", SocketAddr::from(DEFAULT_ADDRESS), handler)
+        .await
+    {
+        error!("couldn't serve the dashboard: {err:#}");
+    }
+}
@@ -0,0 +1,95 @@
+//! Decoration of the modules' responses (prefix, suffix, or a whole template), configured for all
+//! rooms or per room.
+
+use std::collections::HashMap;
+
+use chrono::Utc;
+use matrix_sdk::ruma::{OwnedRoomId, RoomId};
+use serde::Deserialize;
+
+use crate::wasm;
+
+/// How responses are decorated.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct DecorationStyle {
+    /// template applied to responses, where `{text}` is replaced with the response, `{module}`
+    /// with the responding module's name and `{time}` with the current UTC time, e.g.
+    /// `[{module}] {text}`.
+    pub template: Option<String>,
+    /// text added before responses, e.g. an emoji.
+    pub prefix: Option<String>,
+    /// text added after responses.
+    pub suffix: Option<String>,
+}
+
+/// Configuration for the decoration of responses.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct DecorationConfig {
+    /// style used in the rooms that don't have their own.
+    #[serde(flatten)]
+    pub default: DecorationStyle,
+    /// style for specific rooms.
+    #[serde(default)]
+    pub rooms: HashMap<OwnedRoomId, DecorationStyle>,
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+impl DecorationStyle {
+    fn is_empty(&self) -> bool {
+        self.template.is_none() && self.prefix.is_none() && self.suffix.is_none()
+    }
+
+    /// Decorates `text`, with `escape` applied to the decoration itself.
+    fn apply(&self, text: &str, module: &str, time: &str, escape: fn(&str) -> String) -> String {
+        let mut decorated = match &self.template {
+            Some(template) => escape(template)
+                .replace("{module}", &escape(module))
+                .replace("{time}", time)
+                .replace("{text}", text),
+            None => text.to_owned(),
+        };
+        if let Some(prefix) = &self.prefix {
+            decorated.insert_str(0, &escape(prefix));
+        }
+        if let Some(suffix) = &self.suffix {
+            decorated.push_str(&escape(suffix));
+        }
+        decorated
+    }
+}
+
+pub(crate) struct Decoration {
+    config: DecorationConfig,
+}
+
+impl Decoration {
+    pub fn new(config: DecorationConfig) -> Self {
+        Self { config }
+    }
+
+    /// Applies the room's style to a response from the given module.
+    pub fn apply(&self, room_id: &RoomId, module: &str, msg: wasm::Message) -> wasm::Message {
+        let style = self
+            .config
+            .rooms
+            .get(room_id)
+            .unwrap_or(&self.config.default);
+        if style.is_empty() {
+            return msg;
+        }
+
+        let time = Utc::now().format("%H:%M").to_string();
+        wasm::Message {
+            text: style.apply(&msg.text, module, &time, str::to_owned),
+            html: msg
+                .html
+                .map(|html| style.apply(&html, module, &time, escape_html)),
+            ..msg
+        }
+    }
+}
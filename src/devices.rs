@@ -0,0 +1,179 @@
+//! Watches the devices logged into the bot account, and lets the admin remove unknown ones.
+
+use std::collections::HashSet;
+
+use matrix_sdk::{
+    ruma::{
+        api::client::uiaa::{AuthData, Password, UserIdentifier},
+        OwnedDeviceId, OwnedUserId, UserId,
+    },
+    Client,
+};
+use tokio::time::{sleep, Duration};
+use tracing::{debug, error, warn};
+
+use crate::{admin_dm, admin_table, html_text::escape_html, ShareableDatabase};
+
+/// Key for the list of known devices in the admin table.
+const KNOWN_DEVICES_ENTRY: &str = "known_devices";
+
+/// How often the device list is checked for new devices.
+const CHECK_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+pub(crate) struct DeviceWatcher {
+    db: ShareableDatabase,
+    admin_user_id: OwnedUserId,
+    /// Password of the bot account, used to authenticate device removals.
+    password: Option<String>,
+}
+
+impl DeviceWatcher {
+    pub fn new(db: ShareableDatabase, admin_user_id: OwnedUserId, password: Option<String>) -> Self {
+        Self {
+            db,
+            admin_user_id,
+            password,
+        }
+    }
+
+    fn read_known(&self) -> anyhow::Result<Option<HashSet<String>>> {
+        Ok(admin_table::read_str(&self.db, KNOWN_DEVICES_ENTRY)?
+            .map(|list| list.lines().map(ToOwned::to_owned).collect()))
+    }
+
+    fn write_known(&self, known: &HashSet<String>) -> anyhow::Result<()> {
+        let list = known.iter().cloned().collect::<Vec<_>>().join("\n");
+        admin_table::write_str(&self.db, KNOWN_DEVICES_ENTRY, &list)
+    }
+
+    /// Checks the device list once, notifying the admin about devices never seen before.
+    async fn check(&self, client: &Client) -> anyhow::Result<()> {
+        let devices = client.devices().await?.devices;
+
+        let Some(mut known) = self.read_known()? else {
+            // First run: trust all the devices that already exist.
+            debug!("recording {} existing devices", devices.len());
+            let known = devices.iter().map(|d| d.device_id.to_string()).collect();
+            return self.write_known(&known);
+        };
+
+        let mut changed = false;
+        for device in devices {
+            let device_id = device.device_id.to_string();
+            if known.contains(&device_id) {
+                continue;
+            }
+
+            warn!("new device logged into the bot account: {device_id}");
+            let name = device.display_name.as_deref().unwrap_or("<no display name>");
+            let ip = device.last_seen_ip.as_deref().unwrap_or("unknown IP");
+            let text = format!(
+                "A new device logged into the bot account: {device_id} ({name}, last seen from {ip}). \
+                If this wasn't you, remove it with: !admin devices delete {device_id}"
+            );
+            // The device id and name are chosen by whoever logged in.
+            let (device_id_html, name, ip) =
+                (escape_html(&device_id), escape_html(name), escape_html(ip));
+            let html = format!(
+                "A new device logged into the bot account: <code>{device_id_html}</code> ({name}, last seen from {ip}).<br>\
+                If this wasn't you, remove it with: <code>!admin devices delete {device_id_html}</code>"
+            );
+            admin_dm::notify(client, &self.admin_user_id, &text, Some(&html)).await?;
+
+            known.insert(device_id);
+            changed = true;
+        }
+
+        if changed {
+            self.write_known(&known)?;
+        }
+        Ok(())
+    }
+
+    /// Periodically checks the device list. Never returns.
+    pub async fn run(&self, client: Client) {
+        loop {
+            if let Err(err) = self.check(&client).await {
+                error!("error when checking the bot's devices: {err:#}");
+            }
+            sleep(CHECK_INTERVAL).await;
+        }
+    }
+
+    async fn delete(&self, client: &Client, device_id: &str) -> anyhow::Result<String> {
+        if client.device_id().map_or(false, |id| id.as_str() == device_id) {
+            return Ok("refusing to delete the device the bot is currently using".to_owned());
+        }
+
+        let devices = [OwnedDeviceId::from(device_id)];
+        if let Err(err) = client.delete_devices(&devices, None).await {
+            let Some(info) = err.as_uiaa_response() else {
+                return Err(err.into());
+            };
+            let (Some(password), Some(user_id)) = (&self.password, client.user_id()) else {
+                return Ok("deleting a device requires the bot's password to be configured".to_owned());
+            };
+            let mut auth = Password::new(
+                UserIdentifier::UserIdOrLocalpart(user_id.localpart().to_owned()),
+                password.clone(),
+            );
+            auth.session = info.session.clone();
+            client
+                .delete_devices(&devices, Some(AuthData::Password(auth)))
+                .await?;
+        }
+
+        Ok(format!("device {device_id} deleted"))
+    }
+
+    async fn list(&self, client: &Client) -> anyhow::Result<String> {
+        let devices = client.devices().await?.devices;
+        let mut msg = String::from("Devices logged into the bot account:");
+        for device in devices {
+            let current = if client
+                .device_id()
+                .map_or(false, |id| id.as_str() == device.device_id.as_str())
+            {
+                " (current)"
+            } else {
+                ""
+            };
+            msg.push_str(&format!(
+                "\n- {}: {}{current}",
+                device.device_id,
+                device.display_name.as_deref().unwrap_or("<no display name>")
+            ));
+        }
+        Ok(msg)
+    }
+
+    /// Try to handle a message assuming it's an `!admin devices` command.
+    ///
+    /// Returns the response to send back, if the message was such a command.
+    pub async fn try_handle_admin(
+        &self,
+        client: &Client,
+        content: &str,
+        sender: &UserId,
+    ) -> Option<String> {
+        if sender != self.admin_user_id {
+            return None;
+        }
+
+        let rest = content.strip_prefix("!admin devices")?;
+        if !rest.is_empty() && !rest.starts_with(' ') {
+            return None;
+        }
+        let rest = rest.trim();
+
+        let result = if rest.is_empty() || rest == "list" {
+            self.list(client).await
+        } else if let Some(device_id) = rest.strip_prefix("delete ") {
+            self.delete(client, device_id.trim()).await
+        } else {
+            Ok("usage: !admin devices [list|delete DEVICE_ID]".to_owned())
+        };
+
+        Some(result.unwrap_or_else(|err| format!("error when handling devices command: {err:#}")))
+    }
+}
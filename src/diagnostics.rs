@@ -0,0 +1,337 @@
+//! `!admin host diag`: runtime diagnostics (tasks, contention on the modules' lock, modules,
+//! database), to debug the "bot got slow" kind of issues. Long holds of the modules' lock are
+//! also warned about as they happen.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+
+use matrix_sdk::{attachment::AttachmentConfig, room::Room};
+use serde::Deserialize;
+use tokio::{
+    sync::{Mutex, MutexGuard},
+    time::{timeout, Duration, Instant},
+};
+use tracing::warn;
+
+use crate::{crash_reporter, outbox, self_report::ProcessStats, AppCtx, ShareableDatabase};
+
+/// Contention statistics for the [`AppCtx`] lock.
+pub(crate) static APP_CTX_LOCK: LockStats = LockStats::new();
+
+/// Configuration for the diagnostics.
+#[derive(Clone, Debug, Deserialize)]
+pub struct DiagnosticsConfig {
+    /// holding the modules' lock for longer than this, in milliseconds, triggers a warning and a
+    /// report to the crash reporter.
+    #[serde(default = "default_lock_hold_threshold_ms")]
+    pub lock_hold_threshold_ms: u64,
+}
+
+impl Default for DiagnosticsConfig {
+    fn default() -> Self {
+        Self {
+            lock_hold_threshold_ms: default_lock_hold_threshold_ms(),
+        }
+    }
+}
+
+fn default_lock_hold_threshold_ms() -> u64 {
+    1000
+}
+
+/// How long `!admin host diag` waits for the [`AppCtx`] lock before giving up on the modules.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long a lock has been held from a given code path.
+#[derive(Default)]
+struct HoldStats {
+    count: u64,
+    total_us: u64,
+    max_us: u64,
+    over_threshold: u64,
+}
+
+/// Contention statistics of a lock, updated by locking through [`LockStats::lock`].
+pub(crate) struct LockStats {
+    acquisitions: AtomicU64,
+    contended: AtomicU64,
+    total_wait_us: AtomicU64,
+    max_wait_us: AtomicU64,
+    waiting: AtomicUsize,
+    hold_threshold_us: AtomicU64,
+    /// Hold statistics per code path.
+    holds: std::sync::Mutex<BTreeMap<&'static str, HoldStats>>,
+}
+
+impl LockStats {
+    const fn new() -> Self {
+        Self {
+            acquisitions: AtomicU64::new(0),
+            contended: AtomicU64::new(0),
+            total_wait_us: AtomicU64::new(0),
+            max_wait_us: AtomicU64::new(0),
+            waiting: AtomicUsize::new(0),
+            hold_threshold_us: AtomicU64::new(u64::MAX),
+            holds: std::sync::Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Sets for how long the lock may be held before warning about it.
+    pub fn set_hold_threshold(&self, threshold: Duration) {
+        self.hold_threshold_us
+            .store(threshold.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Locks the mutex, keeping track of how long it took, and of how long it's held from the given
+    /// code path.
+    pub async fn lock<'a, T>(
+        &'a self,
+        mutex: &'a Mutex<T>,
+        path: &'static str,
+    ) -> TrackedGuard<'a, T> {
+        let guard = self.acquire(mutex).await;
+        TrackedGuard {
+            guard,
+            stats: self,
+            path,
+            since: Instant::now(),
+        }
+    }
+
+    async fn acquire<'a, T>(&self, mutex: &'a Mutex<T>) -> MutexGuard<'a, T> {
+        self.acquisitions.fetch_add(1, Ordering::Relaxed);
+        if let Ok(guard) = mutex.try_lock() {
+            return guard;
+        }
+
+        self.contended.fetch_add(1, Ordering::Relaxed);
+        self.waiting.fetch_add(1, Ordering::Relaxed);
+        // Decrements the waiting count even if this future gets cancelled.
+        let waiting = WaitingGuard(&self.waiting);
+        let start = Instant::now();
+        let guard = mutex.lock().await;
+        let waited = start.elapsed().as_micros() as u64;
+        drop(waiting);
+        self.total_wait_us.fetch_add(waited, Ordering::Relaxed);
+        self.max_wait_us.fetch_max(waited, Ordering::Relaxed);
+        guard
+    }
+
+    fn record_hold(&self, path: &'static str, held: Duration) {
+        let held_us = held.as_micros() as u64;
+        let threshold_us = self.hold_threshold_us.load(Ordering::Relaxed);
+        {
+            let mut holds = self.holds.lock().unwrap();
+            let stats = holds.entry(path).or_default();
+            stats.count += 1;
+            stats.total_us += held_us;
+            stats.max_us = stats.max_us.max(held_us);
+            if held_us > threshold_us {
+                stats.over_threshold += 1;
+            }
+        }
+
+        if held_us > threshold_us {
+            warn!(
+                "the modules lock was held for {}ms by {path}",
+                held_us / 1000
+            );
+            crash_reporter::capture(
+                &format!("the modules lock was held for {}ms", held_us / 1000),
+                &[("lock_path", path.to_owned())],
+            );
+        }
+    }
+
+    fn report(&self, out: &mut String) {
+        let acquisitions = self.acquisitions.load(Ordering::Relaxed);
+        let contended = self.contended.load(Ordering::Relaxed);
+        let total_wait_us = self.total_wait_us.load(Ordering::Relaxed);
+        let _ = writeln!(out, "  acquisitions: {acquisitions}");
+        let _ = writeln!(out, "  contended: {contended}");
+        if contended > 0 {
+            let _ = writeln!(
+                out,
+                "  mean wait when contended: {}ms",
+                total_wait_us / contended / 1000
+            );
+        }
+        let _ = writeln!(
+            out,
+            "  max wait: {}ms",
+            self.max_wait_us.load(Ordering::Relaxed) / 1000
+        );
+        let _ = writeln!(
+            out,
+            "  currently waiting: {}",
+            self.waiting.load(Ordering::Relaxed)
+        );
+        for (path, stats) in self.holds.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "  held by {path}: {} time(s), mean {}ms, max {}ms, {} over threshold",
+                stats.count,
+                stats.total_us / stats.count.max(1) / 1000,
+                stats.max_us / 1000,
+                stats.over_threshold
+            );
+        }
+    }
+}
+
+/// A lock guard reporting for how long it's been held, when dropped.
+pub(crate) struct TrackedGuard<'a, T> {
+    guard: MutexGuard<'a, T>,
+    stats: &'a LockStats,
+    path: &'static str,
+    since: Instant,
+}
+
+impl<T> Deref for TrackedGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for TrackedGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T> Drop for TrackedGuard<'_, T> {
+    fn drop(&mut self) {
+        self.stats.record_hold(self.path, self.since.elapsed());
+    }
+}
+
+struct WaitingGuard<'a>(&'a AtomicUsize);
+
+impl Drop for WaitingGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub(crate) struct Diagnostics {
+    db: ShareableDatabase,
+    started: Instant,
+}
+
+impl Diagnostics {
+    pub fn new(config: DiagnosticsConfig, db: ShareableDatabase) -> Self {
+        APP_CTX_LOCK.set_hold_threshold(Duration::from_millis(config.lock_hold_threshold_ms));
+        Self {
+            db,
+            started: Instant::now(),
+        }
+    }
+
+    fn db_report(&self, out: &mut String) -> anyhow::Result<()> {
+        let tables = self.db.begin_read()?.list_tables()?.count();
+        let txn = self.db.begin_write()?;
+        let stats = txn.stats()?;
+        txn.abort()?;
+        let _ = writeln!(out, "  tables: {tables}");
+        let _ = writeln!(out, "  stored bytes: {}", stats.stored_bytes());
+        let _ = writeln!(out, "  metadata bytes: {}", stats.metadata_bytes());
+        let _ = writeln!(out, "  fragmented bytes: {}", stats.fragmented_bytes());
+        let _ = writeln!(out, "  tree height: {}", stats.tree_height());
+        Ok(())
+    }
+
+    async fn report(&self, app_ctx: &Mutex<AppCtx>) -> String {
+        let mut out = String::from("Diagnostics\n");
+        let _ = writeln!(out, "uptime: {}s", self.started.elapsed().as_secs());
+
+        let metrics = tokio::runtime::Handle::current().metrics();
+        out.push_str("tokio:\n");
+        let _ = writeln!(out, "  workers: {}", metrics.num_workers());
+        let _ = writeln!(out, "  alive tasks: {}", metrics.num_alive_tasks());
+
+        out.push_str("process:\n");
+        match ProcessStats::sample() {
+            Ok(stats) => {
+                let _ = writeln!(out, "  cpu time: {}s", stats.cpu_time().as_secs());
+                let _ = writeln!(out, "  resident memory: {} bytes", stats.rss_bytes);
+                let _ = writeln!(out, "  open files: {}", stats.open_fds);
+            }
+            Err(err) => {
+                let _ = writeln!(out, "  error when reading stats: {err:#}");
+            }
+        }
+
+        out.push_str("modules lock:\n");
+        APP_CTX_LOCK.report(&mut out);
+
+        out.push_str("modules:\n");
+        match timeout(LOCK_TIMEOUT, APP_CTX_LOCK.lock(app_ctx, "diagnostics")).await {
+            Ok(mut ctx) => {
+                let mut instances = BTreeMap::<String, usize>::new();
+                for module in ctx.modules.iter().1 {
+                    *instances.entry(module.name().to_owned()).or_default() += 1;
+                }
+                let _ = writeln!(out, "  needs recompile: {}", ctx.needs_recompile);
+                for (name, count) in instances {
+                    let _ = writeln!(out, "  {name}: {count} instance(s)");
+                }
+            }
+            Err(_) => {
+                let _ = writeln!(
+                    out,
+                    "  lock still held after {}s, something is hogging it!",
+                    LOCK_TIMEOUT.as_secs()
+                );
+            }
+        }
+
+        out.push_str("database:\n");
+        if let Err(err) = self.db_report(&mut out) {
+            let _ = writeln!(out, "  error when reading stats: {err:#}");
+        }
+        out
+    }
+
+    /// Try to handle a message assuming it's an `!admin host diag [file]` command.
+    pub async fn try_handle_admin(
+        &self,
+        app_ctx: &Mutex<AppCtx>,
+        room: &Room,
+        content: &str,
+    ) -> Option<String> {
+        let rest = content.strip_prefix("!admin host diag")?;
+        if !rest.is_empty() && !rest.starts_with(' ') {
+            return None;
+        }
+
+        let report = self.report(app_ctx).await;
+        if rest.trim() != "file" {
+            return Some(report);
+        }
+
+        let filename = format!(
+            "diagnostics-{}.txt",
+            chrono::Utc::now().format("%Y%m%d-%H%M%S")
+        );
+        Some(
+            match outbox::send_attachment(
+                room,
+                &filename,
+                &mime::TEXT_PLAIN_UTF_8,
+                report.into_bytes(),
+                AttachmentConfig::new(),
+            )
+            .await
+            {
+                Ok(_) => "diagnostics uploaded".to_owned(),
+                Err(err) => format!("couldn't upload the diagnostics: {err:#}"),
+            },
+        )
+    }
+}
@@ -0,0 +1,230 @@
+//! Directory integration: group memberships looked up in LDAP, or an HTTP endpoint of the identity
+//! provider (e.g. an OIDC provider's groups API), so that access can follow the organization's
+//! groups without maintaining duplicate lists in Matrix.
+//!
+//! Modules look memberships up with the `is-member-of` function of the `sys` API, and a module's
+//! `required_group` configuration key restricts it to the members of a group: messages from
+//! anyone else aren't passed to it.
+//!
+//! Matrix users are mapped to directory users by their localpart, and only the users of the
+//! configured homeserver are looked up; the others aren't members of any group.
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context as _;
+use ldap3::{ldap_escape, LdapConnAsync, Scope};
+use matrix_sdk::ruma::{OwnedServerName, UserId};
+use serde::Deserialize;
+use tracing::warn;
+
+/// Module configuration key restricting a module to the members of a directory group.
+const REQUIRED_GROUP_KEY: &str = "required_group";
+
+/// Configuration for the directory integration.
+#[derive(Clone, Debug, Deserialize)]
+pub struct DirectoryConfig {
+    /// homeserver whose users are in the directory, by localpart.
+    pub server_name: OwnedServerName,
+    /// how long a membership is cached, in seconds.
+    #[serde(default = "default_cache_secs")]
+    pub cache_secs: u64,
+    #[serde(flatten)]
+    pub backend: DirectoryBackend,
+}
+
+/// Where the group memberships are looked up. In the templates, `{user}` is the localpart of the
+/// Matrix user, and `{group}` the group name.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum DirectoryBackend {
+    Ldap {
+        /// e.g. `ldaps://ldap.example.com`.
+        url: String,
+        /// DN and password to bind with; anonymous if missing.
+        bind_dn: Option<String>,
+        bind_password: Option<String>,
+        /// where the groups are searched, e.g. `ou=groups,dc=example,dc=com`.
+        base_dn: String,
+        /// filter matching the group if the user is a member of it.
+        #[serde(default = "default_ldap_filter")]
+        filter: String,
+    },
+    Http {
+        /// URL template of the list of the user's groups, e.g.
+        /// `https://idp.example.com/api/users/{user}/groups`. The response is a JSON array of
+        /// group names, or of objects with a `name`.
+        url: String,
+        /// bearer token the requests are authenticated with, if any.
+        token: Option<String>,
+    },
+}
+
+fn default_cache_secs() -> u64 {
+    5 * 60
+}
+
+fn default_ldap_filter() -> String {
+    "(&(objectClass=posixGroup)(cn={group})(memberUid={user}))".to_owned()
+}
+
+/// Timeout of the lookups.
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub(crate) struct Directory {
+    config: Option<DirectoryConfig>,
+    http: reqwest::Client,
+    /// Memberships looked up recently, by user and group, with when they were.
+    cache: Mutex<HashMap<(String, String), (bool, Instant)>>,
+}
+
+impl Directory {
+    pub fn new(config: Option<DirectoryConfig>) -> anyhow::Result<Self> {
+        let http = reqwest::Client::builder().timeout(LOOKUP_TIMEOUT).build()?;
+        Ok(Self {
+            config,
+            http,
+            cache: Default::default(),
+        })
+    }
+
+    async fn ldap_lookup(
+        url: &str,
+        bind: Option<(&str, &str)>,
+        base_dn: &str,
+        filter: &str,
+    ) -> anyhow::Result<bool> {
+        let (conn, mut ldap) = LdapConnAsync::new(url).await?;
+        ldap3::drive!(conn);
+        ldap.with_timeout(LOOKUP_TIMEOUT);
+        if let Some((dn, password)) = bind {
+            ldap.simple_bind(dn, password).await?.success()?;
+        }
+        let (entries, _) = ldap
+            .search(base_dn, Scope::Subtree, filter, vec!["cn"])
+            .await?
+            .success()?;
+        let _ = ldap.unbind().await;
+        Ok(!entries.is_empty())
+    }
+
+    async fn http_lookup(
+        &self,
+        url: &str,
+        token: Option<&str>,
+        group: &str,
+    ) -> anyhow::Result<bool> {
+        let mut request = self.http.get(url);
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+        let groups: Vec<serde_json::Value> = request
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .context("invalid list of groups")?;
+        Ok(groups.iter().any(|value| {
+            let name = value.as_str().or_else(|| value["name"].as_str());
+            name == Some(group)
+        }))
+    }
+
+    async fn lookup(
+        &self,
+        config: &DirectoryConfig,
+        user: &str,
+        group: &str,
+    ) -> anyhow::Result<bool> {
+        match &config.backend {
+            DirectoryBackend::Ldap {
+                url,
+                bind_dn,
+                bind_password,
+                base_dn,
+                filter,
+            } => {
+                let filter = filter
+                    .replace("{user}", &ldap_escape(user))
+                    .replace("{group}", &ldap_escape(group));
+                let bind = bind_dn
+                    .as_deref()
+                    .map(|dn| (dn, bind_password.as_deref().unwrap_or_default()));
+                Self::ldap_lookup(url, bind, base_dn, &filter).await
+            }
+            DirectoryBackend::Http { url, token } => {
+                let url = url.replace("{user}", &urlencode(user));
+                self.http_lookup(&url, token.as_deref(), group).await
+            }
+        }
+    }
+
+    /// Whether the user is a member of the group; lookup failures are logged, and deny
+    /// membership.
+    pub async fn is_member_of(&self, user_id: &str, group: &str) -> bool {
+        let Some(config) = &self.config else {
+            return false;
+        };
+        let Ok(user_id) = <&UserId>::try_from(user_id) else {
+            return false;
+        };
+        if user_id.server_name() != config.server_name {
+            return false;
+        }
+        let user = user_id.localpart();
+
+        let key = (user.to_owned(), group.to_owned());
+        let ttl = Duration::from_secs(config.cache_secs);
+        if let Some((member, at)) = self.cache.lock().unwrap().get(&key) {
+            if at.elapsed() < ttl {
+                return *member;
+            }
+        }
+
+        match self.lookup(config, user, group).await {
+            Ok(member) => {
+                self.cache
+                    .lock()
+                    .unwrap()
+                    .insert(key, (member, Instant::now()));
+                member
+            }
+            Err(err) => {
+                warn!("couldn't look up whether {user_id} is a member of {group}: {err:#}");
+                false
+            }
+        }
+    }
+
+    /// Whether the module's configuration lets the user's messages through, according to its
+    /// `required_group`.
+    ///
+    /// Must be called from a blocking context.
+    pub fn allows(
+        &self,
+        module_config: Option<&HashMap<String, String>>,
+        user_id: &UserId,
+    ) -> bool {
+        let Some(group) = module_config.and_then(|config| config.get(REQUIRED_GROUP_KEY)) else {
+            return true;
+        };
+        futures::executor::block_on(self.is_member_of(user_id.as_str(), group.trim()))
+    }
+}
+
+/// Percent-encodes a URL path segment.
+fn urlencode(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
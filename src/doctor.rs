@@ -0,0 +1,400 @@
+//! The `doctor` command: checks the environment the bot runs in, and prints a report that
+//! operators can go through (or attach to a bug report) before anything else.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+use matrix_sdk::ruma::UserId;
+use serde::Deserialize;
+
+use crate::{base_dir, wasm, BotConfig};
+
+/// Clock skew with the homeserver above which the check fails, in seconds.
+const MAX_CLOCK_SKEW_SECS: i64 = 30;
+
+enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+struct Report {
+    failures: usize,
+}
+
+impl Report {
+    fn check(&mut self, name: &str, status: Status, details: impl AsRef<str>) {
+        let label = match status {
+            Status::Pass => "PASS",
+            Status::Warn => "WARN",
+            Status::Fail => {
+                self.failures += 1;
+                "FAIL"
+            }
+        };
+        println!("[{label}] {name}: {}", details.as_ref());
+    }
+}
+
+#[derive(Deserialize)]
+struct ClientWellKnown {
+    #[serde(rename = "m.homeserver")]
+    homeserver: HomeserverInfo,
+}
+
+#[derive(Deserialize)]
+struct HomeserverInfo {
+    base_url: String,
+}
+
+#[derive(Deserialize)]
+struct ServerWellKnown {
+    #[serde(rename = "m.server")]
+    server: String,
+}
+
+#[derive(Deserialize)]
+struct Versions {
+    versions: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct FederationVersion {
+    server: FederationServer,
+}
+
+#[derive(Deserialize)]
+struct FederationServer {
+    name: Option<String>,
+    version: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct LoginFlows {
+    flows: Vec<LoginFlow>,
+}
+
+#[derive(Deserialize)]
+struct LoginFlow {
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+async fn get_json<T: for<'de> Deserialize<'de>>(
+    http: &reqwest::Client,
+    url: &str,
+) -> anyhow::Result<T> {
+    Ok(http
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?)
+}
+
+/// Checks that `path`, or its closest existing ancestor if it doesn't exist yet, is writable.
+fn check_writable(path: &Path) -> anyhow::Result<String> {
+    if path.is_file() {
+        fs::OpenOptions::new().read(true).write(true).open(path)?;
+        return Ok(format!("{} is writable", path.display()));
+    }
+
+    let mut dir = path;
+    while !dir.exists() {
+        dir = dir
+            .parent()
+            .ok_or_else(|| anyhow::anyhow!("no existing parent for {}", path.display()))?;
+    }
+    let probe = dir.join(".tritongue-doctor");
+    fs::write(&probe, b"")?;
+    fs::remove_file(&probe)?;
+    if dir == path {
+        Ok(format!("{} is writable", path.display()))
+    } else {
+        Ok(format!(
+            "{} doesn't exist yet, but {} is writable",
+            path.display(),
+            dir.display()
+        ))
+    }
+}
+
+/// Returns the paths of the modules found in the configured module paths.
+fn module_files(modules_paths: &[PathBuf]) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for modules_path in modules_paths {
+        for entry in fs::read_dir(modules_path)? {
+            let path = entry?.path();
+            if path.extension().map_or(false, |ext| ext == "wasm") {
+                files.push(path);
+            }
+        }
+    }
+    Ok(files)
+}
+
+async fn check_homeserver(report: &mut Report, config: &BotConfig, http: &reqwest::Client) {
+    let user_id = match UserId::parse(config.user_id.as_str()) {
+        Ok(user_id) => user_id,
+        Err(err) => {
+            report.check(
+                "user id",
+                Status::Fail,
+                format!("{}: {err}", config.user_id),
+            );
+            return;
+        }
+    };
+    let server_name = user_id.server_name();
+
+    // Discovery, as done by the client at startup.
+    let discovered = get_json::<ClientWellKnown>(
+        http,
+        &format!("https://{server_name}/.well-known/matrix/client"),
+    )
+    .await;
+    let base_url = match (&discovered, &config.home_server) {
+        (Ok(well_known), _) => {
+            report.check(
+                "client discovery",
+                Status::Pass,
+                format!(
+                    "{server_name} delegates to {}",
+                    well_known.homeserver.base_url
+                ),
+            );
+            well_known.homeserver.base_url.clone()
+        }
+        (Err(err), Some(home_server)) => {
+            report.check(
+                "client discovery",
+                Status::Warn,
+                format!("no usable .well-known/matrix/client on {server_name} ({err}), using {home_server}"),
+            );
+            home_server.clone()
+        }
+        (Err(err), None) => {
+            report.check(
+                "client discovery",
+                Status::Warn,
+                format!("no usable .well-known/matrix/client on {server_name} ({err})"),
+            );
+            format!("https://{server_name}")
+        }
+    };
+    let base_url = base_url.trim_end_matches('/').to_owned();
+
+    match get_json::<ServerWellKnown>(
+        http,
+        &format!("https://{server_name}/.well-known/matrix/server"),
+    )
+    .await
+    {
+        Ok(well_known) => report.check(
+            "federation discovery",
+            Status::Pass,
+            format!("{server_name} federates through {}", well_known.server),
+        ),
+        Err(err) => report.check(
+            "federation discovery",
+            Status::Warn,
+            format!("no usable .well-known/matrix/server on {server_name} ({err}); fine if federation uses port 8448 or SRV records"),
+        ),
+    }
+
+    // Reachability and supported versions; the response's date also gives the clock skew.
+    let response = match http
+        .get(format!("{base_url}/_matrix/client/versions"))
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+    {
+        Ok(response) => response,
+        Err(err) => {
+            report.check(
+                "homeserver",
+                Status::Fail,
+                format!("{base_url} is unreachable: {err}"),
+            );
+            return;
+        }
+    };
+    let server_date = response
+        .headers()
+        .get(reqwest::header::DATE)
+        .and_then(|date| date.to_str().ok())
+        .and_then(|date| DateTime::parse_from_rfc2822(date).ok());
+    match response.json::<Versions>().await {
+        Ok(versions) => report.check(
+            "homeserver",
+            Status::Pass,
+            format!(
+                "{base_url} is reachable, supports {}",
+                versions.versions.join(", ")
+            ),
+        ),
+        Err(err) => report.check(
+            "homeserver",
+            Status::Fail,
+            format!("{base_url} didn't answer like a Matrix homeserver: {err}"),
+        ),
+    }
+
+    match get_json::<FederationVersion>(http, &format!("{base_url}/_matrix/federation/v1/version"))
+        .await
+    {
+        Ok(version) => report.check(
+            "server version",
+            Status::Pass,
+            format!(
+                "{} {}",
+                version.server.name.as_deref().unwrap_or("unknown server"),
+                version
+                    .server
+                    .version
+                    .as_deref()
+                    .unwrap_or("(unknown version)")
+            ),
+        ),
+        Err(err) => report.check(
+            "server version",
+            Status::Warn,
+            format!("not exposed on {base_url} ({err})"),
+        ),
+    }
+
+    match server_date {
+        Some(server_date) => {
+            let skew = (Utc::now() - server_date.with_timezone(&Utc)).num_seconds();
+            let status = if skew.abs() > MAX_CLOCK_SKEW_SECS {
+                Status::Fail
+            } else {
+                Status::Pass
+            };
+            report.check(
+                "clock skew",
+                status,
+                format!("{skew}s compared to the homeserver"),
+            );
+        }
+        None => report.check(
+            "clock skew",
+            Status::Warn,
+            "the homeserver didn't send its date",
+        ),
+    }
+
+    // The login method the configuration will use must be offered by the homeserver.
+    let (method, flow) = if config.access_token.is_some() {
+        ("access token", None)
+    } else if config.password.is_some() {
+        ("password", Some("m.login.password"))
+    } else {
+        ("SSO", Some("m.login.sso"))
+    };
+    match get_json::<LoginFlows>(http, &format!("{base_url}/_matrix/client/v3/login")).await {
+        Ok(flows) => {
+            let offered = flows
+                .flows
+                .iter()
+                .map(|flow| flow.kind.as_str())
+                .collect::<Vec<_>>();
+            let status = match flow {
+                Some(flow) if !offered.contains(&flow) => Status::Fail,
+                _ => Status::Pass,
+            };
+            report.check(
+                "login",
+                status,
+                format!(
+                    "using {method}, the homeserver offers {}",
+                    offered.join(", ")
+                ),
+            );
+        }
+        Err(err) => report.check(
+            "login",
+            Status::Fail,
+            format!("couldn't list the login methods: {err}"),
+        ),
+    }
+}
+
+fn check_local(report: &mut Report, config: &BotConfig) {
+    let base_dir = base_dir();
+    for (name, path) in [
+        ("matrix store", &config.matrix_store_path),
+        ("database", &config.redb_path),
+    ] {
+        match check_writable(&base_dir.join(path)) {
+            Ok(details) => report.check(name, Status::Pass, details),
+            Err(err) => report.check(name, Status::Fail, format!("{path}: {err}")),
+        }
+    }
+
+    let engine_config = config.wasm_engine.clone().unwrap_or_default();
+    let engine = match wasm::new_engine(&engine_config).and_then(|engine| {
+        wasmtime::component::Component::new(&engine, "(component)")?;
+        Ok(engine)
+    }) {
+        Ok(engine) => {
+            report.check(
+                "wasmtime",
+                Status::Pass,
+                format!("compiles for this CPU ({})", std::env::consts::ARCH),
+            );
+            engine
+        }
+        Err(err) => {
+            report.check("wasmtime", Status::Fail, err.to_string());
+            return;
+        }
+    };
+
+    let files = match module_files(&config.modules_paths) {
+        Ok(files) => files,
+        Err(err) => {
+            report.check("modules", Status::Fail, err.to_string());
+            return;
+        }
+    };
+    if files.is_empty() {
+        report.check("modules", Status::Warn, "no module found");
+    }
+    for file in files {
+        let name = format!("module {}", file.display());
+        match wasm::check_module(&engine, &file) {
+            Ok(()) => report.check(&name, Status::Pass, "compatible with this host"),
+            Err(err) => report.check(&name, Status::Fail, format!("{err:#}")),
+        }
+    }
+}
+
+/// Checks the environment described by the configuration, printing a report on the standard
+/// output. Returns whether all the checks passed.
+pub async fn doctor(config: &BotConfig) -> bool {
+    let mut report = Report { failures: 0 };
+
+    match reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(10))
+        .build()
+    {
+        Ok(http) => check_homeserver(&mut report, config, &http).await,
+        Err(err) => report.check("http client", Status::Fail, err.to_string()),
+    }
+
+    // Compiling the modules blocks for a while, but nothing else runs meanwhile.
+    check_local(&mut report, config);
+
+    if report.failures == 0 {
+        println!("all checks passed");
+        true
+    } else {
+        println!("{} check(s) failed", report.failures);
+        false
+    }
+}
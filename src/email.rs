@@ -0,0 +1,418 @@
+//! Ingestion of emails into rooms, for the services whose alerts only arrive by email: an IMAP
+//! mailbox is polled, and its unread emails are posted in rooms according to filtering rules, with
+//! their subject, sender, an excerpt of their body and their attachments.
+
+use std::{collections::HashSet, net::TcpStream, time::Duration};
+
+use mailparse::{DispositionType, MailHeaderMap as _, ParsedMail};
+use matrix_sdk::{
+    attachment::AttachmentConfig,
+    ruma::{events::room::message::RoomMessageEventContent, OwnedRoomId},
+    Client,
+};
+use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
+use tokio::sync::Mutex;
+use tracing::{debug, info, warn};
+
+use crate::{html_text, outbox};
+
+/// Configuration for the email ingestion.
+#[derive(Clone, Debug, Deserialize)]
+pub struct EmailConfig {
+    /// host name of the IMAP server, which must support TLS.
+    pub host: String,
+    /// port of the IMAP server.
+    #[serde(default = "default_port")]
+    pub port: u16,
+    pub username: String,
+    pub password: String,
+    /// mailbox whose unread emails are posted.
+    #[serde(default = "default_mailbox")]
+    pub mailbox: String,
+    /// room where the emails matching no rule are posted; they're ignored if unset.
+    pub room: Option<OwnedRoomId>,
+    /// delay between two checks of the mailbox.
+    #[serde(default = "default_poll_interval_secs")]
+    pub poll_interval_secs: u64,
+    /// length of the excerpt of the body posted with each email, in characters.
+    #[serde(default = "default_excerpt_chars")]
+    pub excerpt_chars: usize,
+    /// filtering rules; the first one matching an email decides where it's posted.
+    #[serde(default)]
+    pub rules: Vec<EmailRule>,
+}
+
+/// A filtering rule, matching the emails whose sender and subject match its regexes
+/// (case-insensitively).
+#[derive(Clone, Debug, Deserialize)]
+pub struct EmailRule {
+    pub from: Option<String>,
+    pub subject: Option<String>,
+    /// room where the matching emails are posted; the default room if unset.
+    pub room: Option<OwnedRoomId>,
+    /// whether the matching emails are ignored.
+    #[serde(default)]
+    pub ignore: bool,
+}
+
+fn default_port() -> u16 {
+    993
+}
+
+fn default_mailbox() -> String {
+    "INBOX".to_owned()
+}
+
+fn default_poll_interval_secs() -> u64 {
+    60
+}
+
+fn default_excerpt_chars() -> usize {
+    500
+}
+
+/// Most emails fetched at each check of the mailbox; the others wait for the next ones.
+const MAX_EMAILS_PER_POLL: usize = 20;
+/// Largest attachment uploaded in a room.
+const MAX_ATTACHMENT_BYTES: usize = 10 * 1024 * 1024;
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn case_insensitive(regex: &str) -> anyhow::Result<Regex> {
+    Ok(RegexBuilder::new(regex).case_insensitive(true).build()?)
+}
+
+struct CompiledRule {
+    from: Option<Regex>,
+    subject: Option<Regex>,
+    room: Option<OwnedRoomId>,
+    ignore: bool,
+}
+
+impl CompiledRule {
+    fn matches(&self, email: &Email) -> bool {
+        self.from
+            .as_ref()
+            .map_or(true, |re| re.is_match(&email.from))
+            && self
+                .subject
+                .as_ref()
+                .map_or(true, |re| re.is_match(&email.subject))
+    }
+}
+
+struct Attachment {
+    filename: String,
+    content_type: mime::Mime,
+    data: Vec<u8>,
+}
+
+/// The parts of an email that are posted.
+struct Email {
+    uid: u32,
+    from: String,
+    subject: String,
+    body: String,
+    attachments: Vec<Attachment>,
+}
+
+impl Email {
+    fn parse(uid: u32, raw: &[u8]) -> anyhow::Result<Self> {
+        let mail = mailparse::parse_mail(raw)?;
+        let from = mail.headers.get_first_value("From").unwrap_or_default();
+        let subject = mail
+            .headers
+            .get_first_value("Subject")
+            .unwrap_or_else(|| "(no subject)".to_owned());
+
+        let mut plain = None;
+        let mut html = None;
+        let mut attachments = Vec::new();
+        collect_parts(&mail, &mut plain, &mut html, &mut attachments);
+        let body = plain
+            .or_else(|| html.map(|html| html_text::html_to_text(&html)))
+            .unwrap_or_default();
+
+        Ok(Self {
+            uid,
+            from,
+            subject,
+            body,
+            attachments,
+        })
+    }
+
+    fn excerpt(&self, max_chars: usize) -> String {
+        let body = self.body.trim();
+        match body.char_indices().nth(max_chars) {
+            Some((end, _)) => format!("{}…", &body[..end]),
+            None => body.to_owned(),
+        }
+    }
+
+    fn to_content(&self, max_chars: usize) -> RoomMessageEventContent {
+        let excerpt = self.excerpt(max_chars);
+        let mut plain = format!("📧 {}\nfrom {}", self.subject, self.from);
+        let mut html = format!(
+            "📧 <b>{}</b><br>from {}",
+            escape_html(&self.subject),
+            escape_html(&self.from)
+        );
+        if !excerpt.is_empty() {
+            plain.push_str(&format!("\n\n{excerpt}"));
+            html.push_str(&format!(
+                "<blockquote>{}</blockquote>",
+                escape_html(&excerpt).replace('\n', "<br>")
+            ));
+        }
+        RoomMessageEventContent::text_html(plain, html)
+    }
+}
+
+/// Walks the parts of an email, keeping the first plain text and HTML bodies, and the attachments.
+fn collect_parts(
+    part: &ParsedMail<'_>,
+    plain: &mut Option<String>,
+    html: &mut Option<String>,
+    attachments: &mut Vec<Attachment>,
+) {
+    if !part.subparts.is_empty() {
+        for subpart in &part.subparts {
+            collect_parts(subpart, plain, html, attachments);
+        }
+        return;
+    }
+
+    let disposition = part.get_content_disposition();
+    let filename = disposition
+        .params
+        .get("filename")
+        .or_else(|| part.ctype.params.get("name"))
+        .cloned();
+    let mimetype = part.ctype.mimetype.to_lowercase();
+
+    if disposition.disposition == DispositionType::Attachment || filename.is_some() {
+        let data = match part.get_body_raw() {
+            Ok(data) => data,
+            Err(err) => {
+                warn!("couldn't decode an email attachment: {err}");
+                return;
+            }
+        };
+        if data.len() > MAX_ATTACHMENT_BYTES {
+            debug!(
+                "not uploading a large email attachment ({} bytes)",
+                data.len()
+            );
+            return;
+        }
+        attachments.push(Attachment {
+            filename: filename.unwrap_or_else(|| "attachment".to_owned()),
+            content_type: mimetype.parse().unwrap_or(mime::APPLICATION_OCTET_STREAM),
+            data,
+        });
+        return;
+    }
+
+    let body = match mimetype.as_str() {
+        "text/plain" if plain.is_none() => plain,
+        "text/html" if html.is_none() => html,
+        _ => return,
+    };
+    match part.get_body() {
+        Ok(text) => *body = Some(text),
+        Err(err) => warn!("couldn't decode an email body: {err}"),
+    }
+}
+
+type ImapSession = imap::Session<native_tls::TlsStream<TcpStream>>;
+
+fn connect(config: &EmailConfig) -> anyhow::Result<ImapSession> {
+    let tls = native_tls::TlsConnector::builder().build()?;
+    let client = imap::connect((config.host.as_str(), config.port), &config.host, &tls)?;
+    let mut session = client
+        .login(&config.username, &config.password)
+        .map_err(|(err, _)| err)?;
+    session.select(&config.mailbox)?;
+    Ok(session)
+}
+
+/// Fetches the unread emails of the mailbox, without marking them as read.
+///
+/// Must be called from a blocking context.
+fn fetch_unread(config: &EmailConfig) -> anyhow::Result<Vec<(u32, Vec<u8>)>> {
+    let mut session = connect(config)?;
+    let mut uids = Vec::from_iter(session.uid_search("UNSEEN")?);
+    uids.sort_unstable();
+    uids.truncate(MAX_EMAILS_PER_POLL);
+
+    let mut emails = Vec::new();
+    if !uids.is_empty() {
+        let set = uids
+            .iter()
+            .map(u32::to_string)
+            .collect::<Vec<_>>()
+            .join(",");
+        for fetch in session.uid_fetch(set, "(UID BODY.PEEK[])")?.iter() {
+            if let (Some(uid), Some(body)) = (fetch.uid, fetch.body()) {
+                emails.push((uid, body.to_vec()));
+            }
+        }
+    }
+    session.logout()?;
+    Ok(emails)
+}
+
+/// Marks the emails as read, so they're not posted again.
+///
+/// Must be called from a blocking context.
+fn mark_read(config: &EmailConfig, uids: &[u32]) -> anyhow::Result<()> {
+    let mut session = connect(config)?;
+    let set = uids
+        .iter()
+        .map(u32::to_string)
+        .collect::<Vec<_>>()
+        .join(",");
+    session.uid_store(set, "+FLAGS (\\Seen)")?;
+    session.logout()?;
+    Ok(())
+}
+
+pub(crate) struct EmailIngest {
+    config: Option<EmailConfig>,
+    rules: Vec<CompiledRule>,
+    /// Emails already handled, in case they couldn't be marked as read, or in dry-run mode where
+    /// they're not.
+    handled: Mutex<HashSet<u32>>,
+}
+
+impl EmailIngest {
+    pub fn new(config: Option<EmailConfig>) -> anyhow::Result<Self> {
+        let rules = config
+            .iter()
+            .flat_map(|config| &config.rules)
+            .map(|rule| {
+                Ok(CompiledRule {
+                    from: rule.from.as_deref().map(case_insensitive).transpose()?,
+                    subject: rule.subject.as_deref().map(case_insensitive).transpose()?,
+                    room: rule.room.clone(),
+                    ignore: rule.ignore,
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self {
+            config,
+            rules,
+            handled: Default::default(),
+        })
+    }
+
+    /// Room where the email is posted, if it's not ignored.
+    fn route<'a>(&'a self, config: &'a EmailConfig, email: &Email) -> Option<&'a OwnedRoomId> {
+        match self.rules.iter().find(|rule| rule.matches(email)) {
+            Some(rule) if rule.ignore => None,
+            Some(rule) => rule.room.as_ref().or(config.room.as_ref()),
+            None => config.room.as_ref(),
+        }
+    }
+
+    async fn post(
+        &self,
+        client: &Client,
+        config: &EmailConfig,
+        email: &Email,
+    ) -> anyhow::Result<()> {
+        let Some(room_id) = self.route(config, email) else {
+            debug!("ignoring the email {:?} from {}", email.subject, email.from);
+            return Ok(());
+        };
+        let Some(room) = client.get_room(room_id) else {
+            anyhow::bail!("unknown room {room_id}");
+        };
+        outbox::send(&room, email.to_content(config.excerpt_chars)).await?;
+        for attachment in &email.attachments {
+            outbox::send_attachment(
+                &room,
+                &attachment.filename,
+                &attachment.content_type,
+                attachment.data.clone(),
+                AttachmentConfig::new(),
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Polls the mailbox, and posts its new emails.
+    pub async fn run(&self, client: Client) {
+        let Some(config) = &self.config else {
+            return;
+        };
+        info!("checking the mailbox {} on {}", config.mailbox, config.host);
+
+        let mut interval = tokio::time::interval(Duration::from_secs(config.poll_interval_secs));
+        loop {
+            interval.tick().await;
+
+            let fetch_config = config.clone();
+            let raw_emails =
+                match tokio::task::spawn_blocking(move || fetch_unread(&fetch_config)).await {
+                    Ok(Ok(raw_emails)) => raw_emails,
+                    Ok(Err(err)) => {
+                        warn!("couldn't check the mailbox: {err:#}");
+                        continue;
+                    }
+                    Err(err) => {
+                        warn!("couldn't check the mailbox: {err}");
+                        continue;
+                    }
+                };
+
+            let mut posted = Vec::new();
+            for (uid, raw) in raw_emails {
+                if self.handled.lock().await.contains(&uid) {
+                    continue;
+                }
+                let email = match Email::parse(uid, &raw) {
+                    Ok(email) => email,
+                    Err(err) => {
+                        warn!("couldn't parse the email {uid}: {err}");
+                        posted.push(uid);
+                        continue;
+                    }
+                };
+                match self.post(&client, config, &email).await {
+                    Ok(()) => posted.push(email.uid),
+                    // Left unread, to be retried at the next check.
+                    Err(err) => warn!("couldn't post the email {uid}: {err:#}"),
+                }
+            }
+
+            if posted.is_empty() {
+                continue;
+            }
+            if outbox::is_dry_run() {
+                self.handled.lock().await.extend(posted);
+                continue;
+            }
+            let mark_config = config.clone();
+            let uids = posted.clone();
+            match tokio::task::spawn_blocking(move || mark_read(&mark_config, &uids)).await {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => {
+                    warn!("couldn't mark the emails as read: {err:#}");
+                    self.handled.lock().await.extend(posted);
+                }
+                Err(err) => {
+                    warn!("couldn't mark the emails as read: {err}");
+                    self.handled.lock().await.extend(posted);
+                }
+            }
+        }
+    }
+}
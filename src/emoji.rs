@@ -0,0 +1,157 @@
+//! Expansion of `:shortcode:` emojis in the bot's responses, using the standard emoji table, the
+//! operator's own shortcodes, and the room's custom emotes (MSC2545) in HTML bodies.
+
+use std::{collections::HashMap, sync::OnceLock};
+
+use matrix_sdk::{
+    deserialized_responses::RawAnySyncOrStrippedState,
+    room::Room,
+    ruma::events::{room::message::FormattedBody, StateEventType},
+};
+use regex::{Captures, Regex};
+use serde::Deserialize;
+use tracing::debug;
+
+use crate::wasm;
+
+/// State event type of the room custom emotes, as per MSC2545.
+const ROOM_EMOTES_EVENT_TYPE: &str = "im.ponies.room_emotes";
+
+/// Configuration for the emoji shortcodes.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct EmojiConfig {
+    /// additional shortcodes, or replacements for the standard ones, e.g. `ok = "👌"`.
+    #[serde(default)]
+    pub shortcodes: HashMap<String, String>,
+}
+
+/// The MSC2545 room emotes state event, only keeping what we're interested in.
+#[derive(Deserialize)]
+struct RoomEmotesEvent {
+    content: RoomEmotes,
+}
+
+#[derive(Deserialize)]
+struct RoomEmotes {
+    #[serde(default)]
+    images: HashMap<String, RoomEmote>,
+}
+
+#[derive(Deserialize)]
+struct RoomEmote {
+    url: String,
+}
+
+fn shortcode_regex() -> &'static Regex {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    REGEX.get_or_init(|| Regex::new(r":([a-zA-Z0-9_+\-]+):").expect("valid regex"))
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+pub(crate) struct Emoji {
+    config: EmojiConfig,
+}
+
+impl Emoji {
+    pub fn new(config: EmojiConfig) -> Self {
+        Self { config }
+    }
+
+    fn lookup(&self, shortcode: &str) -> Option<&str> {
+        self.config
+            .shortcodes
+            .get(shortcode)
+            .map(String::as_str)
+            .or_else(|| emojis::get_by_shortcode(shortcode).map(|emoji| emoji.as_str()))
+    }
+
+    /// Replaces the known shortcodes of the text with their emoji.
+    pub fn expand_text(&self, text: &str) -> String {
+        shortcode_regex()
+            .replace_all(text, |caps: &Captures<'_>| {
+                self.lookup(&caps[1]).unwrap_or(&caps[0]).to_owned()
+            })
+            .into_owned()
+    }
+
+    /// Returns the custom emotes of the room, indexed by shortcode.
+    async fn room_emotes(room: &Room) -> HashMap<String, String> {
+        let event = match room
+            .get_state_event(StateEventType::from(ROOM_EMOTES_EVENT_TYPE), "")
+            .await
+        {
+            Ok(Some(event)) => event,
+            Ok(None) => return HashMap::new(),
+            Err(err) => {
+                debug!(
+                    "couldn't read the custom emotes of {}: {err}",
+                    room.room_id()
+                );
+                return HashMap::new();
+            }
+        };
+        let event = match event {
+            RawAnySyncOrStrippedState::Sync(raw) => raw.deserialize_as::<RoomEmotesEvent>(),
+            RawAnySyncOrStrippedState::Stripped(raw) => raw.deserialize_as::<RoomEmotesEvent>(),
+        };
+        event
+            .map(|event| {
+                event
+                    .content
+                    .images
+                    .into_iter()
+                    .map(|(shortcode, emote)| (shortcode, emote.url))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Expands the shortcodes of a message: emojis in both bodies, and the room's custom emotes
+    /// in the HTML body, creating it if needs be.
+    pub async fn expand(&self, room: &Room, msg: &mut wasm::Message) {
+        if !shortcode_regex().is_match(&msg.text)
+            && !msg
+                .html
+                .as_ref()
+                .map_or(false, |html| shortcode_regex().is_match(html))
+        {
+            return;
+        }
+
+        msg.text = self.expand_text(&msg.text);
+        if let Some(html) = &msg.html {
+            msg.html = Some(self.expand_text(html));
+        }
+
+        let emotes = Self::room_emotes(room).await;
+        let uses_emotes = shortcode_regex()
+            .captures_iter(&msg.text)
+            .any(|caps| emotes.contains_key(&caps[1]));
+        if !uses_emotes {
+            return;
+        }
+
+        let html = msg.html.take().unwrap_or_else(|| {
+            FormattedBody::markdown(&msg.text)
+                .map(|formatted| formatted.body)
+                .unwrap_or_else(|| escape_html(&msg.text).replace('\n', "<br>"))
+        });
+        let html = shortcode_regex().replace_all(&html, |caps: &Captures<'_>| match emotes
+            .get(&caps[1])
+        {
+            Some(url) => format!(
+                r#"<img data-mx-emoticon src="{}" alt="{shortcode}" title="{shortcode}" height="32">"#,
+                escape_html(url),
+                shortcode = &caps[0],
+            ),
+            None => caps[0].to_owned(),
+        });
+        msg.html = Some(html.into_owned());
+    }
+}
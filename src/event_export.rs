@@ -0,0 +1,469 @@
+//! Export of the bot's activity, for analytics pipelines: normalized events (messages seen, once
+//! the policies and opt-outs applied, actions taken by the modules, and periodic statistics of
+//! the modules) are appended as JSON lines to a file, and/or published to a NATS server, on
+//! `SUBJECT.TYPE` subjects, e.g. `tritongue.message`. Kafka and other pipelines can consume
+//! either, e.g. through a NATS connector or a file tailer.
+//!
+//! The export is best-effort: events are dropped rather than slowing the bot down when the sinks
+//! can't keep up, or while the NATS server can't be reached.
+//!
+//! The live stream of the webhooks listener's `/ws` endpoint subscribes to the same events, along
+//! with the calls to the modules, which aren't exported.
+
+use std::{collections::BTreeMap, path::PathBuf, sync::Mutex};
+
+use chrono::Utc;
+use matrix_sdk::ruma::{EventId, RoomId, UserId};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+    sync::broadcast::{self, error::RecvError},
+    time::{Duration, Instant},
+};
+use tracing::{error, info, warn};
+
+use crate::wasm;
+
+/// Configuration for the event export.
+#[derive(Clone, Debug, Deserialize)]
+pub struct EventExportConfig {
+    /// file the events are appended to, as JSON lines.
+    pub file: Option<PathBuf>,
+    /// NATS server the events are published to, as `host:port`.
+    pub nats: Option<String>,
+    /// token the NATS server is authenticated to with, if any.
+    pub nats_token: Option<String>,
+    /// prefix of the NATS subjects, followed by the type of the events.
+    #[serde(default = "default_subject")]
+    pub subject: String,
+    /// whether the bodies of the messages are exported; only their metadata otherwise.
+    #[serde(default)]
+    pub include_bodies: bool,
+    /// delay between two exports of the modules' statistics, in seconds.
+    #[serde(default = "default_stats_interval_secs")]
+    pub stats_interval_secs: u64,
+}
+
+fn default_subject() -> String {
+    "tritongue".to_owned()
+}
+
+fn default_stats_interval_secs() -> u64 {
+    300
+}
+
+/// Most events waiting to be exported; older ones are dropped past that.
+const QUEUE_SIZE: usize = 1024;
+/// Timeout of the connection to the NATS server.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Delay before connecting to the NATS server again after a failure.
+const RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// An event of the bot's activity.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum Event {
+    /// A message passed on to the modules.
+    Message {
+        room: String,
+        sender: String,
+        event_id: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        body: Option<String>,
+    },
+    /// An action a module took.
+    Action {
+        room: String,
+        module: String,
+        action: &'static str,
+    },
+    /// A call to a module handling a message; only streamed live.
+    ModuleCall {
+        room: String,
+        module: String,
+        ms: u64,
+        responded: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    /// Statistics of a module's handling of the messages since the previous ones.
+    ModuleStats {
+        module: String,
+        calls: u64,
+        responses: u64,
+        errors: u64,
+        avg_ms: u64,
+        max_ms: u64,
+    },
+}
+
+impl Event {
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::Message { .. } => "message",
+            Self::Action { .. } => "action",
+            Self::ModuleCall { .. } => "module_call",
+            Self::ModuleStats { .. } => "module_stats",
+        }
+    }
+}
+
+/// An exported event, with its time.
+#[derive(Serialize)]
+struct Record<'a> {
+    ts: String,
+    #[serde(flatten)]
+    event: &'a Event,
+}
+
+/// The event as a JSON object, with its time.
+pub(crate) fn to_json(event: &Event) -> serde_json::Result<String> {
+    serde_json::to_string(&Record {
+        ts: Utc::now().to_rfc3339(),
+        event,
+    })
+}
+
+/// The name of an action, as exported.
+fn action_name(action: &wasm::Action) -> &'static str {
+    match action {
+        wasm::Action::Respond(_) => "respond",
+        wasm::Action::Reply(_) => "reply",
+        wasm::Action::React(_) => "react",
+        wasm::Action::Unreact(_) => "unreact",
+        wasm::Action::Delayed(_) => "delayed",
+        wasm::Action::Schedule(_) => "schedule",
+        wasm::Action::Unschedule(_) => "unschedule",
+        wasm::Action::Subscribe(_) => "subscribe",
+        wasm::Action::Unsubscribe(_) => "unsubscribe",
+        wasm::Action::Publish(_) => "publish",
+        wasm::Action::Emit(_) => "emit",
+        wasm::Action::SendEmail(_) => "send_email",
+        wasm::Action::RaiseAlert(_) => "raise_alert",
+        wasm::Action::SendToRoom(_) => "send_to_room",
+        wasm::Action::Dm(_) => "dm",
+        wasm::Action::Edit(_) => "edit",
+        wasm::Action::Redact(_) => "redact",
+        wasm::Action::Upload(_) => "upload",
+        wasm::Action::Progress(_) => "progress",
+        wasm::Action::StartPoll(_) => "start_poll",
+        wasm::Action::EndPoll(_) => "end_poll",
+        wasm::Action::Preview(_) => "preview",
+        wasm::Action::SetTopic(_) => "set_topic",
+        wasm::Action::SetRoomName(_) => "set_room_name",
+        wasm::Action::FallThrough => "fall_through",
+    }
+}
+
+/// Statistics of a module's calls.
+#[derive(Default)]
+struct CallStats {
+    calls: u64,
+    responses: u64,
+    errors: u64,
+    total_ms: u64,
+    max_ms: u64,
+}
+
+/// A connection to a NATS server, speaking its text protocol.
+struct NatsConnection {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+}
+
+impl NatsConnection {
+    async fn connect(address: &str, token: Option<&str>) -> anyhow::Result<Self> {
+        let stream = tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(address)).await??;
+        let (reader, writer) = stream.into_split();
+        let mut conn = Self {
+            lines: BufReader::new(reader).lines(),
+            writer,
+        };
+
+        // The server greets with an INFO line first.
+        match conn.lines.next_line().await? {
+            Some(line) if line.starts_with("INFO") => {}
+            line => anyhow::bail!("unexpected greeting from {address}: {line:?}"),
+        }
+        let mut options = serde_json::json!({
+            "verbose": false,
+            "pedantic": false,
+            "name": "tritongue",
+            "lang": "rust",
+            "version": env!("CARGO_PKG_VERSION"),
+        });
+        if let Some(token) = token {
+            options["auth_token"] = token.into();
+        }
+        conn.writer
+            .write_all(format!("CONNECT {options}\r\n").as_bytes())
+            .await?;
+        Ok(conn)
+    }
+
+    async fn publish(&mut self, subject: &str, payload: &str) -> std::io::Result<()> {
+        let len = payload.len();
+        self.writer
+            .write_all(format!("PUB {subject} {len}\r\n{payload}\r\n").as_bytes())
+            .await
+    }
+}
+
+/// Where the events go.
+struct Sinks<'a> {
+    config: &'a EventExportConfig,
+    file: Option<File>,
+    nats: Option<NatsConnection>,
+    /// When the NATS server may be connected to again.
+    next_attempt: Instant,
+}
+
+impl Sinks<'_> {
+    /// The next line the NATS server sends, if connected.
+    async fn next_nats_line(&mut self) -> std::io::Result<Option<String>> {
+        match &mut self.nats {
+            Some(nats) => nats.lines.next_line().await,
+            None => std::future::pending().await,
+        }
+    }
+
+    async fn on_nats_line(&mut self, line: std::io::Result<Option<String>>) {
+        match line {
+            Ok(Some(line)) if line == "PING" => {
+                if let Some(nats) = &mut self.nats {
+                    if let Err(err) = nats.writer.write_all(b"PONG\r\n").await {
+                        warn!("couldn't answer the NATS server: {err}");
+                        self.nats = None;
+                    }
+                }
+            }
+            Ok(Some(line)) if line.starts_with("-ERR") => warn!("NATS server error: {line}"),
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                warn!("the NATS server closed the connection");
+                self.nats = None;
+            }
+            Err(err) => {
+                warn!("NATS connection error: {err}");
+                self.nats = None;
+            }
+        }
+    }
+
+    /// The connection to the NATS server, connecting to it if needed and not retried too soon.
+    async fn nats(&mut self) -> Option<&mut NatsConnection> {
+        let address = self.config.nats.as_deref()?;
+        if self.nats.is_none() && Instant::now() >= self.next_attempt {
+            match NatsConnection::connect(address, self.config.nats_token.as_deref()).await {
+                Ok(nats) => {
+                    info!("exporting the events to the NATS server {address}");
+                    self.nats = Some(nats);
+                }
+                Err(err) => {
+                    warn!("couldn't connect to the NATS server {address}: {err:#}");
+                    self.next_attempt = Instant::now() + RECONNECT_DELAY;
+                }
+            }
+        }
+        self.nats.as_mut()
+    }
+
+    async fn export(&mut self, event: &Event) {
+        let payload = match to_json(event) {
+            Ok(payload) => payload,
+            Err(err) => {
+                warn!("couldn't serialize an exported event: {err}");
+                return;
+            }
+        };
+
+        if let Some(file) = &mut self.file {
+            let line = format!("{payload}\n");
+            if let Err(err) = file.write_all(line.as_bytes()).await {
+                warn!("couldn't append an event to the export file: {err}");
+            }
+        }
+
+        let subject = format!("{}.{}", self.config.subject, event.kind());
+        if let Some(nats) = self.nats().await {
+            if let Err(err) = nats.publish(&subject, &payload).await {
+                warn!("couldn't publish an event to the NATS server: {err}");
+                self.nats = None;
+            }
+        }
+    }
+}
+
+pub(crate) struct EventExport {
+    config: Option<EventExportConfig>,
+    events: broadcast::Sender<Event>,
+    /// Statistics of the modules' calls since the last export, by module.
+    stats: Mutex<BTreeMap<String, CallStats>>,
+}
+
+impl EventExport {
+    pub fn new(config: Option<EventExportConfig>) -> Self {
+        Self {
+            config,
+            events: broadcast::channel(QUEUE_SIZE).0,
+            stats: Default::default(),
+        }
+    }
+
+    /// Subscribes to the events, e.g. for a live stream.
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.events.subscribe()
+    }
+
+    /// Whether anyone is subscribed to the events, i.e. whether they're worth building.
+    fn is_watched(&self) -> bool {
+        self.events.receiver_count() > 0
+    }
+
+    fn publish(&self, event: Event) {
+        // Only fails when nobody is subscribed anymore.
+        let _ = self.events.send(event);
+    }
+
+    /// Exports a message passed on to the modules.
+    pub fn message(&self, room_id: &RoomId, sender: &UserId, event_id: &EventId, body: &str) {
+        if !self.is_watched() {
+            return;
+        }
+        let include_body = self
+            .config
+            .as_ref()
+            .is_some_and(|config| config.include_bodies);
+        self.publish(Event::Message {
+            room: room_id.to_string(),
+            sender: sender.to_string(),
+            event_id: event_id.to_string(),
+            body: include_body.then(|| body.to_owned()),
+        });
+    }
+
+    /// Exports an action a module took.
+    pub fn action(&self, room_id: &RoomId, module: &str, action: &wasm::Action) {
+        if !self.is_watched() {
+            return;
+        }
+        self.publish(Event::Action {
+            room: room_id.to_string(),
+            module: module.to_owned(),
+            action: action_name(action),
+        });
+    }
+
+    /// Records a call to a module handling a message, for its statistics.
+    pub fn record_call(
+        &self,
+        room_id: &RoomId,
+        module: &str,
+        elapsed: Duration,
+        result: &anyhow::Result<Vec<wasm::Action>>,
+    ) {
+        let elapsed_ms = elapsed.as_millis() as u64;
+        if self.is_watched() {
+            self.publish(Event::ModuleCall {
+                room: room_id.to_string(),
+                module: module.to_owned(),
+                ms: elapsed_ms,
+                responded: result.as_ref().is_ok_and(|actions| !actions.is_empty()),
+                error: result.as_ref().err().map(|err| format!("{err:#}")),
+            });
+        }
+
+        if self.config.is_none() {
+            return;
+        }
+        let mut stats = self.stats.lock().unwrap();
+        let stats = stats.entry(module.to_owned()).or_default();
+        stats.calls += 1;
+        match result {
+            Ok(actions) if !actions.is_empty() => stats.responses += 1,
+            Ok(_) => {}
+            Err(_) => stats.errors += 1,
+        }
+        stats.total_ms += elapsed_ms;
+        stats.max_ms = stats.max_ms.max(elapsed_ms);
+    }
+
+    /// The statistics of the modules since the last time, as events.
+    fn take_stats(&self) -> Vec<Event> {
+        std::mem::take(&mut *self.stats.lock().unwrap())
+            .into_iter()
+            .map(|(module, stats)| Event::ModuleStats {
+                module,
+                calls: stats.calls,
+                responses: stats.responses,
+                errors: stats.errors,
+                avg_ms: stats.total_ms / stats.calls.max(1),
+                max_ms: stats.max_ms,
+            })
+            .collect()
+    }
+
+    /// Exports the events to the configured sinks.
+    pub async fn run(&self) {
+        let Some(config) = &self.config else {
+            return;
+        };
+        let mut events = self.events.subscribe();
+
+        let file = match &config.file {
+            Some(path) => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .await;
+                match file {
+                    Ok(file) => Some(file),
+                    Err(err) => {
+                        error!("couldn't open the export file {}: {err}", path.display());
+                        None
+                    }
+                }
+            }
+            None => None,
+        };
+        let mut sinks = Sinks {
+            config,
+            file,
+            nats: None,
+            next_attempt: Instant::now(),
+        };
+
+        let mut stats_interval =
+            tokio::time::interval(Duration::from_secs(config.stats_interval_secs.max(1)));
+        // The first tick is immediate, and there are no statistics yet.
+        stats_interval.tick().await;
+
+        loop {
+            let exported = tokio::select! {
+                event = events.recv() => match event {
+                    Ok(Event::ModuleCall { .. }) => continue,
+                    Ok(event) => vec![event],
+                    Err(RecvError::Lagged(count)) => {
+                        warn!("the event export can't keep up, dropped {count} events");
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return,
+                },
+                _ = stats_interval.tick() => self.take_stats(),
+                line = sinks.next_nats_line() => {
+                    sinks.on_nats_line(line).await;
+                    continue;
+                }
+            };
+            for event in &exported {
+                sinks.export(event).await;
+            }
+        }
+    }
+}
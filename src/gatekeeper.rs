@@ -0,0 +1,261 @@
+//! Entry gate for new joiners: in the configured rooms, new members have to answer a question (or
+//! react to the bot's challenge) within a given delay, or they get kicked.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::ensure;
+use matrix_sdk::{
+    room::Room,
+    ruma::{
+        events::room::{
+            member::{MembershipChange, OriginalSyncRoomMemberEvent},
+            message::RoomMessageEventContent,
+        },
+        OwnedEventId, OwnedRoomId, OwnedServerName, OwnedUserId, RoomId, UserId,
+    },
+    Client,
+};
+use serde::Deserialize;
+use tokio::time::{sleep, Duration};
+use tracing::{debug, warn};
+
+use crate::outbox;
+
+/// Configuration for the entry gate.
+#[derive(Clone, Debug, Deserialize)]
+pub struct GatekeeperConfig {
+    /// rooms in which new members have to pass the gate.
+    pub rooms: Vec<OwnedRoomId>,
+    /// question asked to new members.
+    pub question: String,
+    /// expected answer (case insensitive). If missing, only reacting to the challenge works.
+    pub answer: Option<String>,
+    /// delay after which members who didn't pass get kicked, in minutes.
+    #[serde(default = "default_timeout_minutes")]
+    pub timeout_minutes: u64,
+    /// members from these servers don't have to pass the gate.
+    #[serde(default)]
+    pub exempt_servers: Vec<OwnedServerName>,
+}
+
+fn default_timeout_minutes() -> u64 {
+    10
+}
+
+/// A member who hasn't passed the gate yet.
+struct Pending {
+    /// the message asking the question, to which the member can react.
+    challenge: OwnedEventId,
+    /// the join event which set up the gate, so that the timer of a previous join doesn't kick
+    /// a member who left and joined again.
+    join: OwnedEventId,
+}
+
+pub(crate) struct Gatekeeper {
+    config: Option<GatekeeperConfig>,
+    pending: Mutex<HashMap<(OwnedRoomId, OwnedUserId), Pending>>,
+}
+
+impl Gatekeeper {
+    pub fn new(config: Option<GatekeeperConfig>) -> anyhow::Result<Self> {
+        if let Some(config) = &config {
+            ensure!(
+                config.timeout_minutes.checked_mul(60).is_some(),
+                "gatekeeper: timeout_minutes is too large"
+            );
+        }
+        Ok(Self {
+            config,
+            pending: Default::default(),
+        })
+    }
+
+    fn is_pending(&self, room: &Room, user_id: &UserId) -> bool {
+        self.pending
+            .lock()
+            .unwrap()
+            .contains_key(&(room.room_id().to_owned(), user_id.to_owned()))
+    }
+
+    /// Lets the member through. Returns whether they were waiting at the gate.
+    fn pass(&self, room_id: &RoomId, user_id: &UserId) -> bool {
+        self.pending
+            .lock()
+            .unwrap()
+            .remove(&(room_id.to_owned(), user_id.to_owned()))
+            .is_some()
+    }
+
+    /// Forgets a member waiting at the gate, e.g. once they were removed from the room.
+    pub fn forget(&self, room_id: &RoomId, user_id: &UserId) {
+        self.pass(room_id, user_id);
+    }
+
+    /// Sets up the gate for members joining one of the configured rooms.
+    pub async fn on_member(
+        self: Arc<Self>,
+        ev: &OriginalSyncRoomMemberEvent,
+        room: &Room,
+        client: &Client,
+    ) -> anyhow::Result<()> {
+        let Some(config) = &self.config else {
+            return Ok(());
+        };
+
+        if !config.rooms.iter().any(|r| r == room.room_id()) {
+            return Ok(());
+        }
+        let user_id = ev.state_key.clone();
+        match ev.membership_change() {
+            MembershipChange::Joined => {}
+            MembershipChange::Left
+            | MembershipChange::Kicked
+            | MembershipChange::Banned
+            | MembershipChange::KickedAndBanned => {
+                self.forget(room.room_id(), &user_id);
+                return Ok(());
+            }
+            _ => return Ok(()),
+        }
+
+        if Some(&*user_id) == client.user_id() {
+            return Ok(());
+        }
+        if config
+            .exempt_servers
+            .iter()
+            .any(|server| server == user_id.server_name())
+        {
+            debug!("{user_id} is exempt from the gate");
+            return Ok(());
+        }
+
+        let hint = if config.answer.is_some() {
+            "answer in this room, or react to this message"
+        } else {
+            "react to this message"
+        };
+        let text = format!(
+            "Welcome {user_id}! Before participating, please {hint} within {} minutes: {}",
+            config.timeout_minutes, config.question
+        );
+        let challenge = outbox::send(room, RoomMessageEventContent::text_plain(text)).await?;
+
+        let key = (room.room_id().to_owned(), user_id.clone());
+        let join = ev.event_id.clone();
+        let pending = Pending {
+            challenge,
+            join: join.clone(),
+        };
+        self.pending.lock().unwrap().insert(key.clone(), pending);
+
+        let this = self.clone();
+        let room = room.clone();
+        let timeout = Duration::from_secs(config.timeout_minutes * 60);
+        tokio::spawn(async move {
+            sleep(timeout).await;
+            let expired = {
+                let mut pending = this.pending.lock().unwrap();
+                match pending.get(&key) {
+                    Some(p) if p.join == join => pending.remove(&key).is_some(),
+                    _ => false,
+                }
+            };
+            if !expired {
+                return;
+            }
+            debug!("{user_id} didn't pass the gate in time, kicking");
+            if let Err(err) = outbox::kick(
+                &room,
+                &user_id,
+                Some("didn't answer the entry question in time"),
+            )
+            .await
+            {
+                warn!("couldn't kick {user_id} from {}: {err:#}", room.room_id());
+                let text = format!("{user_id} didn't answer the entry question in time.");
+                let _ = outbox::send(&room, RoomMessageEventContent::text_plain(text)).await;
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Lets members through when they react to their challenge.
+    pub async fn on_reaction(
+        &self,
+        room: &Room,
+        sender: &UserId,
+        relates_to: &OwnedEventId,
+    ) -> anyhow::Result<()> {
+        let key = (room.room_id().to_owned(), sender.to_owned());
+        let passed = {
+            let mut pending = self.pending.lock().unwrap();
+            match pending.get(&key) {
+                Some(p) if &p.challenge == relates_to => pending.remove(&key).is_some(),
+                _ => false,
+            }
+        };
+        if passed {
+            let text = format!("Thanks {sender}, welcome in!");
+            outbox::send(room, RoomMessageEventContent::text_plain(text)).await?;
+        }
+        Ok(())
+    }
+
+    /// Handles messages sent by members who haven't passed the gate yet.
+    ///
+    /// Returns whether the message has been consumed by the gate, in which case it mustn't be
+    /// handled any further.
+    pub async fn on_message(
+        &self,
+        room: &Room,
+        sender: &UserId,
+        content: &str,
+    ) -> anyhow::Result<bool> {
+        let Some(config) = &self.config else {
+            return Ok(false);
+        };
+        if !self.is_pending(room, sender) {
+            return Ok(false);
+        }
+
+        let answered = config
+            .answer
+            .as_ref()
+            .map_or(false, |answer| content.trim().eq_ignore_ascii_case(answer.trim()));
+
+        let text = if answered && self.pass(room.room_id(), sender) {
+            format!("Thanks {sender}, welcome in!")
+        } else {
+            format!("{sender}, please answer the entry question first: {}", config.question)
+        };
+        outbox::send(room, RoomMessageEventContent::text_plain(text)).await?;
+        Ok(true)
+    }
+
+    /// Try to handle a message assuming it's an `!admin host gatekeeper pass USER` command,
+    /// letting the user through in the current room.
+    pub fn try_handle_admin(&self, content: &str, room: &Room) -> Option<String> {
+        let rest = content.strip_prefix("!admin host gatekeeper")?;
+        if !rest.is_empty() && !rest.starts_with(' ') {
+            return None;
+        }
+
+        let Some(user) = rest.trim().strip_prefix("pass ") else {
+            return Some("usage: !admin host gatekeeper pass USER_ID".to_owned());
+        };
+        let Ok(user_id) = UserId::parse(user.trim()) else {
+            return Some(format!("invalid user id: {}", user.trim()));
+        };
+
+        Some(if self.pass(room.room_id(), &user_id) {
+            format!("{user_id} has been let in")
+        } else {
+            format!("{user_id} wasn't waiting at the gate")
+        })
+    }
+}
@@ -0,0 +1,246 @@
+//! Grafana integration: `!graph` renders a panel with the Grafana image renderer, and posts the
+//! PNG in the room, so graphs can be pulled on demand; `!graph annotate` adds an annotation,
+//! e.g. to mark a deployment or an incident on the dashboards.
+
+use std::collections::BTreeMap;
+
+use chrono::Utc;
+use matrix_sdk::{
+    attachment::AttachmentConfig,
+    room::Room,
+    ruma::{events::room::message::RoomMessageEventContent, UserId},
+};
+use serde::Deserialize;
+use tokio::time::Duration;
+use tracing::info;
+
+use crate::{outbox, utils::parse_duration};
+
+/// Configuration for the Grafana integration.
+#[derive(Clone, Debug, Deserialize)]
+pub struct GrafanaConfig {
+    /// base URL of Grafana, e.g. `https://grafana.example.com`.
+    pub url: String,
+    /// service account token the requests are authenticated with.
+    pub token: String,
+    /// time range of the graphs when none is given, e.g. `6h`.
+    #[serde(default = "default_range")]
+    pub default_range: String,
+    /// size of the rendered graphs, in pixels.
+    #[serde(default = "default_width")]
+    pub width: u32,
+    #[serde(default = "default_height")]
+    pub height: u32,
+    /// IANA timezone the graphs are rendered in, the browser's if missing.
+    pub timezone: Option<String>,
+    /// short names for panels, given as `DASHBOARD_UID/PANEL_ID`, e.g. `cpu = "node-exporter/4"`.
+    #[serde(default)]
+    pub panels: BTreeMap<String, String>,
+}
+
+fn default_range() -> String {
+    "6h".to_owned()
+}
+
+fn default_width() -> u32 {
+    1000
+}
+
+fn default_height() -> u32 {
+    500
+}
+
+/// Timeout of the requests to Grafana; rendering a panel can take a while.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+/// Longest time range of a graph.
+const MAX_RANGE: Duration = Duration::from_secs(366 * 24 * 60 * 60);
+
+const USAGE: &str = "usage: !graph [PANEL [RANGE] | annotate TEXT], where PANEL is a panel \
+                     name or DASHBOARD_UID/PANEL_ID, and RANGE is e.g. 30m, 6h or 7d";
+
+pub(crate) struct Grafana {
+    config: Option<GrafanaConfig>,
+    http: reqwest::Client,
+}
+
+impl Grafana {
+    pub fn new(config: Option<GrafanaConfig>) -> anyhow::Result<Self> {
+        let http = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()?;
+        Ok(Self { config, http })
+    }
+
+    /// Renders a panel of a dashboard as a PNG image, over the last `range`.
+    pub async fn render_panel(
+        &self,
+        config: &GrafanaConfig,
+        dashboard: &str,
+        panel_id: u32,
+        range: Duration,
+    ) -> anyhow::Result<Vec<u8>> {
+        let url = format!(
+            "{}/render/d-solo/{dashboard}/graph",
+            config.url.trim_end_matches('/')
+        );
+        let mut query = vec![
+            ("panelId", panel_id.to_string()),
+            ("from", format!("now-{}s", range.as_secs())),
+            ("to", "now".to_owned()),
+            ("width", config.width.to_string()),
+            ("height", config.height.to_string()),
+        ];
+        if let Some(tz) = &config.timezone {
+            query.push(("tz", tz.clone()));
+        }
+
+        let response = self
+            .http
+            .get(url)
+            .bearer_auth(&config.token)
+            .query(&query)
+            .send()
+            .await?
+            .error_for_status()?;
+        let is_image = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with("image/png"));
+        anyhow::ensure!(
+            is_image,
+            "Grafana didn't return an image; is the image renderer installed?"
+        );
+        Ok(response.bytes().await?.to_vec())
+    }
+
+    /// Renders a panel and posts it in the room.
+    pub async fn post_panel(
+        &self,
+        room: &Room,
+        config: &GrafanaConfig,
+        dashboard: &str,
+        panel_id: u32,
+        range: Duration,
+    ) -> anyhow::Result<()> {
+        let png = self
+            .render_panel(config, dashboard, panel_id, range)
+            .await?;
+        let filename = format!("{dashboard}-{panel_id}.png");
+        outbox::send_attachment(
+            room,
+            &filename,
+            &mime::IMAGE_PNG,
+            png,
+            AttachmentConfig::new(),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Adds an organization-wide annotation, tagged with the room it comes from.
+    async fn annotate(
+        &self,
+        config: &GrafanaConfig,
+        room: &Room,
+        sender: &UserId,
+        text: &str,
+    ) -> anyhow::Result<()> {
+        if outbox::is_dry_run() {
+            info!("dry run: would add a Grafana annotation: {text}");
+            return Ok(());
+        }
+        let url = format!("{}/api/annotations", config.url.trim_end_matches('/'));
+        self.http
+            .post(url)
+            .bearer_auth(&config.token)
+            .json(&serde_json::json!({
+                "time": Utc::now().timestamp_millis(),
+                "text": format!("{text} ({sender})"),
+                "tags": ["tritongue", room.room_id().as_str()],
+            }))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    /// Parses a panel, given by name or as `DASHBOARD_UID/PANEL_ID`.
+    fn parse_panel<'a>(config: &'a GrafanaConfig, panel: &'a str) -> Option<(&'a str, u32)> {
+        let panel = config.panels.get(panel).map_or(panel, |p| p.as_str());
+        let (dashboard, panel_id) = panel.rsplit_once('/')?;
+        Some((dashboard, panel_id.parse().ok()?))
+    }
+
+    async fn handle(
+        &self,
+        config: &GrafanaConfig,
+        room: &Room,
+        sender: &UserId,
+        rest: &str,
+    ) -> anyhow::Result<Option<String>> {
+        if rest.is_empty() {
+            return Ok(Some(if config.panels.is_empty() {
+                USAGE.to_owned()
+            } else {
+                let names = config.panels.keys().cloned().collect::<Vec<_>>();
+                format!("panels: {}\n{USAGE}", names.join(", "))
+            }));
+        }
+
+        if let Some(text) = rest.strip_prefix("annotate") {
+            let text = text.trim();
+            if text.is_empty() {
+                return Ok(Some(USAGE.to_owned()));
+            }
+            self.annotate(config, room, sender, text).await?;
+            return Ok(Some("annotation added".to_owned()));
+        }
+
+        let mut args = rest.split_whitespace();
+        let panel = args.next().unwrap_or_default();
+        let Some((dashboard, panel_id)) = Self::parse_panel(config, panel) else {
+            return Ok(Some(format!("unknown panel {panel}\n{USAGE}")));
+        };
+        let range = args.next().unwrap_or(&config.default_range);
+        let Some(range) = parse_duration(range).filter(|r| !r.is_zero() && *r <= MAX_RANGE) else {
+            return Ok(Some(format!("invalid range {range}, e.g. 30m, 6h or 7d")));
+        };
+        if args.next().is_some() {
+            return Ok(Some(USAGE.to_owned()));
+        }
+
+        self.post_panel(room, config, dashboard, panel_id, range)
+            .await?;
+        Ok(None)
+    }
+
+    /// Try to handle a message assuming it's a `!graph` command.
+    ///
+    /// Returns whether the message was such a command.
+    pub async fn try_handle(
+        &self,
+        room: &Room,
+        sender: &UserId,
+        content: &str,
+    ) -> anyhow::Result<bool> {
+        let Some(config) = &self.config else {
+            return Ok(false);
+        };
+        let Some(rest) = content.strip_prefix("!graph") else {
+            return Ok(false);
+        };
+        if !rest.is_empty() && !rest.starts_with(' ') {
+            return Ok(false);
+        }
+
+        let response = match self.handle(config, room, sender, rest.trim()).await {
+            Ok(response) => response,
+            Err(err) => Some(format!("couldn't reach Grafana: {err:#}")),
+        };
+        if let Some(response) = response {
+            outbox::send(room, RoomMessageEventContent::text_plain(response)).await?;
+        }
+        Ok(true)
+    }
+}
@@ -0,0 +1,122 @@
+//! Persistent storage for the host features, with one database table per feature.
+
+use redb::{ReadableTable, TableDefinition};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::ShareableDatabase;
+
+/// Name of the table for the given feature. The `@` prefix avoids clashes with modules' tables.
+fn table_name(feature: &str) -> String {
+    format!("@{feature}")
+}
+
+/// Reads a given key in the feature's table.
+///
+/// Returns `Ok(None)` if the value wasn't present, `Ok(Some)` if it did exist.
+pub fn read(db: &ShareableDatabase, feature: &str, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+    let name = table_name(feature);
+    let table_def = TableDefinition::<str, [u8]>::new(&name);
+    let txn = db.begin_read()?;
+    let table = match txn.open_table(table_def) {
+        Ok(table) => table,
+        Err(err) => match err {
+            redb::Error::DatabaseAlreadyOpen
+            | redb::Error::InvalidSavepoint
+            | redb::Error::Corrupted(_)
+            | redb::Error::TableTypeMismatch(_)
+            | redb::Error::DbSizeMismatch { .. }
+            | redb::Error::TableAlreadyOpen(_, _)
+            | redb::Error::OutOfSpace
+            | redb::Error::Io(_)
+            | redb::Error::LockPoisoned(_) => Err(err)?,
+            redb::Error::TableDoesNotExist(_) => return Ok(None),
+        },
+    };
+    Ok(table.get(key)?.map(|val| val.to_vec()))
+}
+
+/// Writes a given key in the feature's table.
+pub fn write(db: &ShareableDatabase, feature: &str, key: &str, value: &[u8]) -> anyhow::Result<()> {
+    let name = table_name(feature);
+    let table_def = TableDefinition::<str, [u8]>::new(&name);
+    let txn = db.begin_write()?;
+    {
+        let mut table = txn.open_table(table_def)?;
+        table.insert(key, value)?;
+    }
+    txn.commit()?;
+    Ok(())
+}
+
+/// Removes a given key from the feature's table.
+pub fn remove(db: &ShareableDatabase, feature: &str, key: &str) -> anyhow::Result<()> {
+    let name = table_name(feature);
+    let table_def = TableDefinition::<str, [u8]>::new(&name);
+    let txn = db.begin_write()?;
+    {
+        let mut table = txn.open_table(table_def)?;
+        table.remove(key)?;
+    }
+    txn.commit()?;
+    Ok(())
+}
+
+/// Same as [`read`], but for a counter value.
+pub fn read_u64(db: &ShareableDatabase, feature: &str, key: &str) -> anyhow::Result<u64> {
+    Ok(match read(db, feature, key)? {
+        Some(bytes) => u64::from_le_bytes(
+            bytes
+                .try_into()
+                .map_err(|_| anyhow::anyhow!("invalid counter value for {key}"))?,
+        ),
+        None => 0,
+    })
+}
+
+/// Same as [`write`], but for a counter value.
+pub fn write_u64(db: &ShareableDatabase, feature: &str, key: &str, value: u64) -> anyhow::Result<()> {
+    write(db, feature, key, &value.to_le_bytes())
+}
+
+/// Same as [`read`], but for a value serialized as JSON.
+pub fn read_json<T: DeserializeOwned>(
+    db: &ShareableDatabase,
+    feature: &str,
+    key: &str,
+) -> anyhow::Result<Option<T>> {
+    match read(db, feature, key)? {
+        Some(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+        None => Ok(None),
+    }
+}
+
+/// Same as [`write`], but for a value serialized as JSON.
+pub fn write_json<T: Serialize>(
+    db: &ShareableDatabase,
+    feature: &str,
+    key: &str,
+    value: &T,
+) -> anyhow::Result<()> {
+    write(db, feature, key, &serde_json::to_vec(value)?)
+}
+
+/// Lists the entries of the feature's table whose key starts with the given prefix, in key order.
+pub fn entries(
+    db: &ShareableDatabase,
+    feature: &str,
+    prefix: &str,
+) -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+    let name = table_name(feature);
+    let table_def = TableDefinition::<str, [u8]>::new(&name);
+    let txn = db.begin_read()?;
+    let table = match txn.open_table(table_def) {
+        Ok(table) => table,
+        Err(redb::Error::TableDoesNotExist(_)) => return Ok(Vec::new()),
+        Err(err) => Err(err)?,
+    };
+    Ok(table
+        .range(prefix..)?
+        .take_while(|(key, _)| key.starts_with(prefix))
+        .map(|(key, value)| (key.to_owned(), value.to_vec()))
+        .collect())
+}
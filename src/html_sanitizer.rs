@@ -0,0 +1,376 @@
+//! Sanitization of the HTML bodies leaving the bot: only the tags and attributes the Matrix spec
+//! lists as safe are kept, links and images may only point to the allowed schemes, and the contents
+//! of the scripts and styles are dropped. Other tags are removed, keeping their text.
+
+use crate::html_text::decode_entity;
+
+/// Tags the clients may render, as per the spec's recommendations.
+const ALLOWED_TAGS: &[&str] = &[
+    "font",
+    "del",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "blockquote",
+    "p",
+    "a",
+    "ul",
+    "ol",
+    "sup",
+    "sub",
+    "li",
+    "b",
+    "i",
+    "u",
+    "strong",
+    "em",
+    "strike",
+    "s",
+    "code",
+    "hr",
+    "br",
+    "div",
+    "table",
+    "thead",
+    "tbody",
+    "tr",
+    "th",
+    "td",
+    "caption",
+    "pre",
+    "span",
+    "img",
+    "details",
+    "summary",
+    "mx-reply",
+];
+
+/// Tags without content nor closing tag.
+const VOID_TAGS: &[&str] = &["br", "hr", "img"];
+
+/// Tags removed along with their content.
+const DROPPED_TAGS: &[&str] = &[
+    "script", "style", "head", "title", "iframe", "object", "embed", "textarea", "noscript",
+    "template", "svg", "math",
+];
+
+/// Schemes the links may use.
+const LINK_SCHEMES: &[&str] = &["https", "http", "ftp", "mailto", "magnet", "matrix"];
+
+/// Deepest nesting of tags kept, as recommended by the spec; deeper tags are removed.
+const MAX_DEPTH: usize = 100;
+
+/// The attributes allowed on a tag.
+fn allowed_attributes(tag: &str) -> &'static [&'static str] {
+    match tag {
+        "font" => &["color", "data-mx-bg-color", "data-mx-color"],
+        "span" => &["data-mx-bg-color", "data-mx-color", "data-mx-spoiler"],
+        "a" => &["name", "target", "href"],
+        "img" => &["width", "height", "alt", "title", "src"],
+        "ol" => &["start"],
+        "code" => &["class"],
+        _ => &[],
+    }
+}
+
+/// Whether an attribute value, already decoded, may be kept.
+fn is_allowed_value(tag: &str, attribute: &str, value: &str) -> bool {
+    match (tag, attribute) {
+        ("a", "href") => value.split_once(':').is_some_and(|(scheme, _)| {
+            LINK_SCHEMES.contains(&scheme.to_ascii_lowercase().as_str())
+        }),
+        ("img", "src") => value.starts_with("mxc://"),
+        ("code", "class") => value.starts_with("language-"),
+        _ => true,
+    }
+}
+
+fn decode_entities(value: &str) -> String {
+    let mut decoded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let entity = rest
+            .find(';')
+            .filter(|&end| end <= 10)
+            .and_then(|end| Some((decode_entity(&rest[1..end])?, end)));
+        match entity {
+            Some((c, end)) => {
+                decoded.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+fn escape_attribute(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// A parsed tag.
+struct Tag {
+    name: String,
+    closing: bool,
+    self_closing: bool,
+    attributes: Vec<(String, String)>,
+}
+
+/// Parses the tag at the start of the input, returning it along with the length of its source, or
+/// nothing if the input doesn't start with a well-formed tag.
+fn parse_tag(input: &str) -> Option<(Tag, usize)> {
+    let bytes = input.as_bytes();
+    let mut pos = 1;
+    let closing = bytes.get(pos) == Some(&b'/');
+    if closing {
+        pos += 1;
+    }
+    let name_len = input[pos..]
+        .find(|c: char| !c.is_ascii_alphanumeric() && c != '-')
+        .unwrap_or(input.len() - pos);
+    if name_len == 0 || !bytes[pos].is_ascii_alphabetic() {
+        return None;
+    }
+    let name = input[pos..pos + name_len].to_ascii_lowercase();
+    pos += name_len;
+
+    let mut attributes = Vec::new();
+    let mut self_closing = false;
+    loop {
+        while bytes.get(pos).is_some_and(u8::is_ascii_whitespace) {
+            pos += 1;
+        }
+        match bytes.get(pos)? {
+            b'>' => {
+                pos += 1;
+                break;
+            }
+            b'/' if bytes.get(pos + 1) == Some(&b'>') => {
+                self_closing = true;
+                pos += 2;
+                break;
+            }
+            _ => {}
+        }
+
+        let attr_len = input[pos..]
+            .find(|c: char| c.is_ascii_whitespace() || matches!(c, '=' | '>' | '/'))
+            .unwrap_or(input.len() - pos)
+            .max(1);
+        let attribute = input[pos..pos + attr_len].to_ascii_lowercase();
+        pos += attr_len;
+        while bytes.get(pos).is_some_and(u8::is_ascii_whitespace) {
+            pos += 1;
+        }
+        if bytes.get(pos) != Some(&b'=') {
+            attributes.push((attribute, String::new()));
+            continue;
+        }
+        pos += 1;
+        while bytes.get(pos).is_some_and(u8::is_ascii_whitespace) {
+            pos += 1;
+        }
+        let value = match bytes.get(pos)? {
+            quote @ (b'"' | b'\'') => {
+                let len = input[pos + 1..].find(*quote as char)?;
+                let value = &input[pos + 1..pos + 1 + len];
+                pos += len + 2;
+                value
+            }
+            _ => {
+                let len = input[pos..]
+                    .find(|c: char| c.is_ascii_whitespace() || c == '>')
+                    .unwrap_or(input.len() - pos);
+                let value = &input[pos..pos + len];
+                pos += len;
+                value
+            }
+        };
+        attributes.push((attribute, decode_entities(value)));
+    }
+
+    let tag = Tag {
+        name,
+        closing,
+        self_closing,
+        attributes,
+    };
+    Some((tag, pos))
+}
+
+/// Removes the end of the input up to the closing tag of the given one, included.
+fn skip_content<'a>(input: &'a str, name: &str) -> &'a str {
+    let closing = format!("</{name}");
+    let lowercase = input.to_ascii_lowercase();
+    let Some(start) = lowercase.find(&closing) else {
+        return "";
+    };
+    match input[start..].find('>') {
+        Some(end) => &input[start + end + 1..],
+        None => "",
+    }
+}
+
+/// Keeps only the allowed tags and attributes of the HTML, and closes the tags left open.
+pub fn sanitize(html: &str) -> String {
+    let mut sanitized = String::with_capacity(html.len());
+    // The allowed tags currently open.
+    let mut open: Vec<String> = Vec::new();
+    let mut rest = html;
+
+    while let Some(start) = rest.find(['<', '>']) {
+        sanitized.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if rest.starts_with('>') {
+            sanitized.push_str("&gt;");
+            rest = &rest[1..];
+            continue;
+        }
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+        let Some((tag, len)) = parse_tag(rest) else {
+            sanitized.push_str("&lt;");
+            rest = &rest[1..];
+            continue;
+        };
+        rest = &rest[len..];
+
+        if DROPPED_TAGS.contains(&tag.name.as_str()) {
+            if !tag.closing && !tag.self_closing {
+                rest = skip_content(rest, &tag.name);
+            }
+            continue;
+        }
+        if !ALLOWED_TAGS.contains(&tag.name.as_str()) {
+            continue;
+        }
+        let is_void = VOID_TAGS.contains(&tag.name.as_str());
+
+        if tag.closing {
+            // Close the tags left open inside this one too, if it's open at all.
+            if let Some(index) = open.iter().rposition(|name| *name == tag.name) {
+                for name in open.drain(index..).rev() {
+                    sanitized.push_str(&format!("</{name}>"));
+                }
+            }
+            continue;
+        }
+        if open.len() >= MAX_DEPTH {
+            continue;
+        }
+
+        sanitized.push('<');
+        sanitized.push_str(&tag.name);
+        let allowed = allowed_attributes(&tag.name);
+        for (attribute, value) in &tag.attributes {
+            if allowed.contains(&attribute.as_str())
+                && is_allowed_value(&tag.name, attribute, value)
+            {
+                sanitized.push_str(&format!(" {attribute}=\"{}\"", escape_attribute(value)));
+            }
+        }
+        sanitized.push('>');
+
+        if tag.self_closing && !is_void {
+            sanitized.push_str(&format!("</{}>", tag.name));
+        } else if !is_void {
+            open.push(tag.name);
+        }
+    }
+    sanitized.push_str(rest);
+
+    for name in open.iter().rev() {
+        sanitized.push_str(&format!("</{name}>"));
+    }
+    sanitized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keep_allowed_tags() {
+        let html = r#"<p>Hello <b>world</b><br>"#;
+        assert_eq!(sanitize(html), "<p>Hello <b>world</b><br></p>");
+        assert_eq!(sanitize(""), "");
+        assert_eq!(sanitize("plain text"), "plain text");
+    }
+
+    #[test]
+    fn drop_dangerous_content() {
+        assert_eq!(sanitize("<script>alert(1)</script>hi"), "hi");
+        assert_eq!(sanitize("<SCRIPT>alert(1)</SCRIPT>hi"), "hi");
+        assert_eq!(sanitize("<style>p { color: red }"), "");
+        assert_eq!(sanitize("<!-- <b>hidden</b> -->shown"), "shown");
+        assert_eq!(sanitize("<marquee>text</marquee>"), "text");
+    }
+
+    #[test]
+    fn filter_attributes() {
+        assert_eq!(
+            sanitize(r#"<a href="https://example.com" onclick="steal()">x</a>"#),
+            r#"<a href="https://example.com">x</a>"#
+        );
+        assert_eq!(
+            sanitize(r#"<a href="javascript:alert(1)">x</a>"#),
+            "<a>x</a>"
+        );
+        assert_eq!(
+            sanitize(r#"<a href="JavaScript&#58;alert(1)">x</a>"#),
+            "<a>x</a>"
+        );
+        assert_eq!(
+            sanitize(r#"<img src="https://example.com/a.png">"#),
+            "<img>"
+        );
+        assert_eq!(
+            sanitize(r#"<img src="mxc://example.com/a">"#),
+            r#"<img src="mxc://example.com/a">"#
+        );
+        assert_eq!(
+            sanitize(r#"<code class=language-rust>x</code>"#),
+            r#"<code class="language-rust">x</code>"#
+        );
+        assert_eq!(
+            sanitize(r#"<a href="https://example.com/?a=1&amp;b=&quot;2">x</a>"#),
+            r#"<a href="https://example.com/?a=1&amp;b=&quot;2">x</a>"#
+        );
+    }
+
+    #[test]
+    fn escape_stray_brackets() {
+        assert_eq!(sanitize("1 < 2 > 0"), "1 &lt; 2 &gt; 0");
+        assert_eq!(
+            sanitize(r#"<a href="unterminated"#),
+            r#"&lt;a href="unterminated"#
+        );
+    }
+
+    #[test]
+    fn balance_tags() {
+        assert_eq!(sanitize("<b><i>text</b>"), "<b><i>text</i></b>");
+        assert_eq!(sanitize("text</b>"), "text");
+        assert_eq!(sanitize("<p/><br/>"), "<p></p><br>");
+        let deep = "<b>".repeat(MAX_DEPTH + 10);
+        let sanitized = sanitize(&deep);
+        assert_eq!(sanitized.matches("<b>").count(), MAX_DEPTH);
+        assert_eq!(sanitized.matches("</b>").count(), MAX_DEPTH);
+    }
+}
@@ -0,0 +1,188 @@
+//! Derivation of a plain text body from an HTML one, for the clients that don't render HTML, and
+//! rendering of Markdown into HTML.
+
+use matrix_sdk::ruma::events::room::message::FormattedBody;
+
+use crate::wasm;
+
+/// Tags after which a line break is added.
+const BLOCK_TAGS: &[&str] = &[
+    "p",
+    "div",
+    "li",
+    "tr",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "pre",
+    "blockquote",
+    "ul",
+    "ol",
+    "table",
+    "hr",
+];
+
+/// Decodes a single HTML entity, given without its `&` and `;`.
+pub fn decode_entity(entity: &str) -> Option<char> {
+    Some(match entity {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => ' ',
+        _ => {
+            let code = if let Some(hex) = entity
+                .strip_prefix("#x")
+                .or_else(|| entity.strip_prefix("#X"))
+            {
+                u32::from_str_radix(hex, 16).ok()?
+            } else {
+                entity.strip_prefix('#')?.parse().ok()?
+            };
+            char::from_u32(code)?
+        }
+    })
+}
+
+/// Ends the current line, if there's one.
+fn new_line(text: &mut String) {
+    let trimmed = text.trim_end_matches(' ').len();
+    text.truncate(trimmed);
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+}
+
+/// Converts HTML into readable plain text: tags are removed, block elements and line breaks
+/// become new lines, list items get a dash, links keep their target, and entities are decoded.
+pub fn html_to_text(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut rest = html;
+    // Target of the link being read, to append it after the link's text if it differs.
+    let mut link: Option<String> = None;
+    let mut link_start = 0;
+    // Whitespace is only meaningful in preformatted blocks.
+    let mut in_pre = false;
+
+    while let Some(c) = rest.chars().next() {
+        match c {
+            '<' => {
+                let Some(end) = rest.find('>') else {
+                    text.push_str(rest);
+                    break;
+                };
+                let tag = &rest[1..end];
+                rest = &rest[end + 1..];
+
+                let closing = tag.starts_with('/');
+                let name = tag
+                    .trim_start_matches('/')
+                    .split(|c: char| c.is_whitespace() || c == '/')
+                    .next()
+                    .unwrap_or("")
+                    .to_ascii_lowercase();
+
+                if name == "mx-reply" && !closing {
+                    // Drop the reply fallback altogether.
+                    match rest.find("</mx-reply>") {
+                        Some(end) => rest = &rest[end + "</mx-reply>".len()..],
+                        None => rest = "",
+                    }
+                    continue;
+                }
+
+                if name == "pre" {
+                    in_pre = !closing;
+                }
+
+                if name == "a" {
+                    if closing {
+                        if let Some(href) = link.take() {
+                            if text.get(link_start..) != Some(href.as_str()) {
+                                text.push_str(&format!(" ({href})"));
+                            }
+                        }
+                    } else {
+                        link = tag
+                            .split("href=")
+                            .nth(1)
+                            .and_then(|value| {
+                                let quote = value.chars().next()?;
+                                let value = value.strip_prefix(['"', '\''])?;
+                                value.split(quote).next()
+                            })
+                            .map(str::to_owned);
+                        link_start = text.len();
+                    }
+                } else if name == "li" && !closing {
+                    new_line(&mut text);
+                    text.push_str("- ");
+                } else if name == "br" {
+                    text.push('\n');
+                } else if BLOCK_TAGS.contains(&name.as_str()) && (closing || name == "hr") {
+                    new_line(&mut text);
+                }
+            }
+
+            '&' => {
+                let decoded = rest
+                    .find(';')
+                    .filter(|&end| end <= 10)
+                    .and_then(|end| Some((decode_entity(&rest[1..end])?, end)));
+                match decoded {
+                    Some((c, end)) => {
+                        text.push(c);
+                        rest = &rest[end + 1..];
+                    }
+                    None => {
+                        text.push('&');
+                        rest = &rest[1..];
+                    }
+                }
+            }
+
+            c if c.is_whitespace() && !in_pre => {
+                if !text.is_empty() && !text.ends_with([' ', '\n']) {
+                    text.push(' ');
+                }
+                rest = &rest[c.len_utf8()..];
+            }
+
+            c => {
+                text.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+
+    text.trim().to_owned()
+}
+
+/// Escapes the text for HTML.
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Renders CommonMark into HTML, the same way the host renders the text body of the messages
+/// without an HTML body; text without any formatting is only escaped.
+pub fn markdown_to_html(markdown: &str) -> String {
+    match FormattedBody::markdown(markdown) {
+        Some(formatted) => formatted.body,
+        None => escape_html(markdown).replace('\n', "<br>"),
+    }
+}
+
+/// Fills the text body of a message that only has an HTML one.
+pub fn fill_text(msg: &mut wasm::Message) {
+    if msg.text.is_empty() {
+        if let Some(html) = &msg.html {
+            msg.text = html_to_text(html);
+        }
+    }
+}
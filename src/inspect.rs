@@ -0,0 +1,145 @@
+//! Introspection of the modules: `!admin host inspect <module>` for the loaded ones, and the
+//! `inspect` command for a module file, without starting the bot.
+
+use std::{fmt::Write as _, path::Path, sync::Arc};
+
+use tokio::sync::Mutex;
+
+use crate::{
+    diagnostics::APP_CTX_LOCK,
+    wasm::{self, Capabilities, FileInfo},
+    AppCtx,
+};
+
+/// Describes what can be known about a module from its file alone.
+fn file_report(out: &mut String, path: &Path, info: &FileInfo) {
+    let _ = writeln!(out, "sha256: {}", info.hash);
+    let _ = writeln!(out, "size: {} bytes", info.size);
+    let manifest_path = Capabilities::manifest_path(path);
+    if manifest_path.is_file() {
+        let _ = writeln!(out, "manifest: {}", manifest_path.display());
+    } else {
+        out.push_str("manifest: none, capabilities derived from the imports\n");
+    }
+    match wasm::required_host_version(path) {
+        Ok(Some(version)) => {
+            let _ = writeln!(
+                out,
+                "requires host: {version} or newer, this host is {}",
+                wasm::HOST_VERSION
+            );
+        }
+        Ok(None) => {}
+        Err(err) => {
+            let _ = writeln!(out, "requires host: invalid, {err:#}");
+        }
+    }
+    match Capabilities::declared(path, info) {
+        Ok(declared) => {
+            let _ = writeln!(out, "declared capabilities: {declared}");
+        }
+        Err(err) => {
+            let _ = writeln!(out, "declared capabilities: invalid, {err:#}");
+        }
+    }
+
+    let missing = info.missing_imports();
+    let denied = info.denied_imports();
+    out.push_str("imports:\n");
+    if info.imports.is_empty() {
+        out.push_str("  none\n");
+    }
+    for import in &info.imports {
+        let provided = if denied.contains(&import.as_str()) {
+            "linked, without any access (no directories nor network)"
+        } else if missing.contains(&import.as_str()) {
+            "not provided by this host"
+        } else {
+            "provided"
+        };
+        let _ = writeln!(out, "  {import}: {provided}");
+    }
+
+    out.push_str("exports:\n");
+    for export in &info.exports {
+        let _ = writeln!(out, "  {export}");
+    }
+}
+
+/// Inspects the module file at `path`, checking whether it could be loaded by this host.
+pub fn inspect(path: &Path) -> anyhow::Result<String> {
+    let info = FileInfo::read(path)?;
+    let mut out = format!("{}\n", path.display());
+    file_report(&mut out, path, &info);
+
+    let compatible =
+        wasm::new_engine(&Default::default()).and_then(|engine| wasm::check_module(&engine, path));
+    match compatible {
+        Ok(()) => out.push_str("compatible with this host\n"),
+        Err(err) => {
+            let _ = writeln!(out, "not compatible with this host: {err:#}");
+        }
+    }
+    Ok(out)
+}
+
+/// Try to handle a message assuming it's an `!admin host inspect <module>` command.
+pub(crate) async fn try_handle_admin(
+    app_ctx: &Arc<Mutex<AppCtx>>,
+    content: &str,
+) -> Option<String> {
+    let rest = content.strip_prefix("!admin host inspect")?;
+    if !rest.is_empty() && !rest.starts_with(' ') {
+        return None;
+    }
+    let name = rest.trim().to_owned();
+    if name.is_empty() {
+        return Some("usage: !admin host inspect <module>".to_owned());
+    }
+
+    let app_ctx = app_ctx.clone();
+    let report = tokio::task::spawn_blocking(move || {
+        let ctx =
+            &mut *futures::executor::block_on(APP_CTX_LOCK.lock(&app_ctx, "module inspection"));
+        let (store, mut modules) = ctx.modules.iter();
+        let module = modules.find(|module| module.name() == name)?;
+
+        let mut out = format!("{name}\n");
+        let _ = writeln!(out, "path: {}", module.path().display());
+        let _ = writeln!(
+            out,
+            "loaded at: {}",
+            module.loaded_at().format("%Y-%m-%d %H:%M:%S UTC")
+        );
+        match module.last_error() {
+            Some((time, err)) => {
+                let _ = writeln!(
+                    out,
+                    "last error: {err} (at {})",
+                    time.format("%Y-%m-%d %H:%M:%S UTC")
+                );
+            }
+            None => out.push_str("last error: none\n"),
+        }
+        file_report(&mut out, module.path(), module.file_info());
+        let _ = writeln!(out, "granted capabilities: {}", module.capabilities());
+
+        // The help is the closest thing to a list of commands modules declare.
+        match module.help(&mut *store, None) {
+            Ok(help) => {
+                let _ = writeln!(out, "commands: {help}");
+            }
+            Err(err) => {
+                let _ = writeln!(out, "commands: unknown, help failed: {err:#}");
+            }
+        }
+        Some(out)
+    })
+    .await;
+
+    Some(match report {
+        Ok(Some(report)) => report,
+        Ok(None) => format!("module {} not found", rest.trim()),
+        Err(err) => format!("error when inspecting the module: {err}"),
+    })
+}
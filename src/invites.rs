@@ -0,0 +1,105 @@
+//! Self-service room invitations: allowlisted users can DM the bot `invite me to #room`.
+
+use anyhow::Context as _;
+use matrix_sdk::{
+    room::Room,
+    ruma::{OwnedRoomId, OwnedUserId, UserId},
+    Client, RoomMemberships,
+};
+use serde::Deserialize;
+use tracing::debug;
+
+use crate::{outbox, utils::resolve_room};
+
+/// Configuration for the self-service invitations.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct InvitesConfig {
+    /// users allowed to ask for invitations.
+    #[serde(default)]
+    pub allowed_users: Vec<OwnedUserId>,
+    /// rooms (ids or aliases) users may ask to be invited to. If empty, any room the bot is in.
+    #[serde(default)]
+    pub allowed_rooms: Vec<String>,
+}
+
+pub(crate) struct Invites {
+    config: InvitesConfig,
+}
+
+impl Invites {
+    pub fn new(config: InvitesConfig) -> Self {
+        Self { config }
+    }
+
+    async fn is_allowed_room(&self, client: &Client, room_id: &OwnedRoomId) -> bool {
+        if self.config.allowed_rooms.is_empty() {
+            return true;
+        }
+        for allowed in &self.config.allowed_rooms {
+            match resolve_room(client, allowed).await {
+                Ok(allowed) if &allowed == room_id => return true,
+                Ok(_) => {}
+                Err(err) => debug!("couldn't resolve allowed room {allowed}: {err:#}"),
+            }
+        }
+        false
+    }
+
+    async fn invite(&self, client: &Client, target: &str, sender: &UserId) -> anyhow::Result<String> {
+        let Ok(room_id) = resolve_room(client, target).await else {
+            return Ok(format!("I don't know about {target}"));
+        };
+
+        if !self.is_allowed_room(client, &room_id).await {
+            return Ok(format!("invitations to {target} aren't allowed"));
+        }
+
+        let Some(room) = client.get_room(&room_id) else {
+            return Ok(format!("I'm not in {target}"));
+        };
+
+        let bot_user_id = client.user_id().context("missing bot user id")?;
+        if !room.can_user_invite(bot_user_id).await? {
+            return Ok(format!("I'm not allowed to invite people to {target}"));
+        }
+
+        let already_in = room
+            .members(RoomMemberships::JOIN | RoomMemberships::INVITE)
+            .await?
+            .iter()
+            .any(|member| member.user_id() == sender);
+        if already_in {
+            return Ok(format!("you're already in (or invited to) {target}"));
+        }
+
+        outbox::invite(&room, sender).await?;
+        Ok(format!("invited you to {target}"))
+    }
+
+    /// Try to handle a message assuming it's an invitation request sent in a direct message.
+    ///
+    /// Returns the response to send back, if the message was such a request.
+    pub async fn try_handle(
+        &self,
+        client: &Client,
+        room: &Room,
+        content: &str,
+        sender: &UserId,
+    ) -> Option<String> {
+        let target = content.trim().strip_prefix("invite me to ")?.trim();
+
+        if !room.is_direct().await.unwrap_or(false) {
+            return None;
+        }
+
+        if !self.config.allowed_users.iter().any(|user| user == sender) {
+            return Some("sorry, you're not allowed to ask for invitations".to_owned());
+        }
+
+        Some(
+            self.invite(client, target, sender)
+                .await
+                .unwrap_or_else(|err| format!("error when inviting you: {err:#}")),
+        )
+    }
+}
@@ -0,0 +1,207 @@
+//! Latency SLO of the responses to the messages, e.g. "95% of the commands answered within 2s":
+//! the latency of each response is measured from the origin timestamp of the message to the
+//! completion of the send, and checked against the objective over a rolling window. A warning is
+//! posted in the ops room when it's violated, pointing at the slowest module, and another one when
+//! it's met again.
+
+use std::{collections::VecDeque, fmt::Write as _, sync::Mutex};
+
+use matrix_sdk::{
+    ruma::{
+        events::room::message::RoomMessageEventContent, MilliSecondsSinceUnixEpoch, OwnedRoomId,
+    },
+    Client,
+};
+use serde::Deserialize;
+use tokio::time::{sleep, Duration, Instant};
+use tracing::warn;
+
+use crate::outbox;
+
+/// Configuration for the latency SLO.
+#[derive(Clone, Debug, Deserialize)]
+pub struct LatencySloConfig {
+    /// room where the violations are posted.
+    pub room: OwnedRoomId,
+    /// latency the responses must be sent within, in milliseconds.
+    #[serde(default = "default_target_ms")]
+    pub target_ms: u64,
+    /// percentage of the responses that must be sent within the target.
+    #[serde(default = "default_percent")]
+    pub percent: f64,
+    /// rolling window the objective is checked over, in minutes.
+    #[serde(default = "default_window_minutes")]
+    pub window_minutes: u64,
+    /// fewest responses in the window for the objective to be checked, not to warn about a
+    /// couple of slow responses on a quiet day.
+    #[serde(default = "default_min_responses")]
+    pub min_responses: usize,
+}
+
+fn default_target_ms() -> u64 {
+    2000
+}
+
+fn default_percent() -> f64 {
+    95.0
+}
+
+fn default_window_minutes() -> u64 {
+    15
+}
+
+fn default_min_responses() -> usize {
+    20
+}
+
+/// Delay between two checks of the objective.
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// Most responses kept in the window; older ones are forgotten past that.
+const MAX_SAMPLES: usize = 100_000;
+
+/// The latency of a response of a module.
+struct Sample {
+    at: Instant,
+    module: String,
+    latency_ms: u64,
+}
+
+/// The latencies of a module's responses, in the window.
+struct ModuleLatencies {
+    module: String,
+    /// Sorted.
+    latencies_ms: Vec<u64>,
+}
+
+impl ModuleLatencies {
+    /// The latency at the given percentile, from 0 to 100.
+    fn percentile(&self, percent: f64) -> u64 {
+        let index = (self.latencies_ms.len() as f64 * percent / 100.0).ceil() as usize;
+        self.latencies_ms[index.clamp(1, self.latencies_ms.len()) - 1]
+    }
+}
+
+pub(crate) struct LatencySlo {
+    config: Option<LatencySloConfig>,
+    samples: Mutex<VecDeque<Sample>>,
+}
+
+impl LatencySlo {
+    pub fn new(config: Option<LatencySloConfig>) -> Self {
+        Self {
+            config,
+            samples: Default::default(),
+        }
+    }
+
+    /// Records the latency of a response of a module, just sent, to a message sent at
+    /// `origin_ts`.
+    pub fn record(&self, module: &str, origin_ts: MilliSecondsSinceUnixEpoch) {
+        if self.config.is_none() {
+            return;
+        }
+        let now = MilliSecondsSinceUnixEpoch::now();
+        // The origin timestamp comes from the sender's homeserver, whose clock may be ahead.
+        let latency_ms = u64::from(now.get()).saturating_sub(origin_ts.get().into());
+        let mut samples = self.samples.lock().unwrap();
+        if samples.len() >= MAX_SAMPLES {
+            samples.pop_front();
+        }
+        samples.push_back(Sample {
+            at: Instant::now(),
+            module: module.to_owned(),
+            latency_ms,
+        });
+    }
+
+    /// The latencies in the window, by module, forgetting the older ones.
+    fn window(&self, config: &LatencySloConfig) -> Vec<ModuleLatencies> {
+        let window = Duration::from_secs(config.window_minutes.max(1) * 60);
+        let mut samples = self.samples.lock().unwrap();
+        while samples.front().is_some_and(|s| s.at.elapsed() > window) {
+            samples.pop_front();
+        }
+
+        let mut modules = Vec::<ModuleLatencies>::new();
+        for sample in samples.iter() {
+            match modules.iter_mut().find(|m| m.module == sample.module) {
+                Some(module) => module.latencies_ms.push(sample.latency_ms),
+                None => modules.push(ModuleLatencies {
+                    module: sample.module.clone(),
+                    latencies_ms: vec![sample.latency_ms],
+                }),
+            }
+        }
+        for module in &mut modules {
+            module.latencies_ms.sort_unstable();
+        }
+        modules
+    }
+
+    /// Periodically checks the objective, and posts its violations.
+    pub async fn run(&self, client: Client) {
+        let Some(config) = &self.config else {
+            return;
+        };
+        let mut violated = false;
+
+        loop {
+            sleep(CHECK_INTERVAL).await;
+            let modules = self.window(config);
+            let total = modules.iter().map(|m| m.latencies_ms.len()).sum::<usize>();
+            if total < config.min_responses {
+                continue;
+            }
+            let within = modules
+                .iter()
+                .flat_map(|m| &m.latencies_ms)
+                .filter(|&&latency| latency <= config.target_ms)
+                .count();
+            let within_percent = 100.0 * within as f64 / total as f64;
+            let met = within_percent >= config.percent;
+
+            let mut msg = String::new();
+            if !met && !violated {
+                let _ = write!(
+                    msg,
+                    "⚠️ only {within_percent:.1}% of the {total} responses of the last {} \
+                     minutes were sent within {} ms, under the objective of {}%",
+                    config.window_minutes, config.target_ms, config.percent
+                );
+                // The slowest module is the one with the highest latency at the objective's
+                // percentile, e.g. the highest p95.
+                let slowest = modules.iter().max_by_key(|m| m.percentile(config.percent));
+                if let Some(slowest) = slowest {
+                    let _ = write!(
+                        msg,
+                        "; the slowest module is {}, with a p{} of {} ms over {} responses",
+                        slowest.module,
+                        config.percent,
+                        slowest.percentile(config.percent),
+                        slowest.latencies_ms.len()
+                    );
+                }
+            } else if met && violated {
+                let _ = write!(
+                    msg,
+                    "✅ {within_percent:.1}% of the responses of the last {} minutes were sent \
+                     within {} ms, the latency objective is met again",
+                    config.window_minutes, config.target_ms
+                );
+            }
+            violated = !met;
+            if msg.is_empty() {
+                continue;
+            }
+
+            let Some(room) = client.get_room(&config.room) else {
+                warn!("unknown latency SLO room {}", config.room);
+                continue;
+            };
+            let content = RoomMessageEventContent::text_plain(msg);
+            if let Err(err) = outbox::send(&room, content).await {
+                warn!("couldn't post a latency SLO violation: {err:#}");
+            }
+        }
+    }
+}
@@ -1,9 +1,89 @@
+mod activation;
+mod admin_dm;
+mod alerts;
+mod api_keys;
+mod auto_reactions;
+mod archive;
 mod admin_table;
+mod alertmanager;
+mod bus;
+mod compliance;
+mod content_filter;
+mod crash_loop;
+mod crash_reporter;
+mod cron;
+mod dashboard;
+mod decoration;
+mod devices;
+mod doctor;
+mod email;
+mod emoji;
+mod event_export;
+mod diagnostics;
+mod directory;
+mod gatekeeper;
+mod grafana;
+mod html_sanitizer;
+mod html_text;
+mod inspect;
+mod host_table;
+mod invites;
+mod latency_slo;
+mod link_hygiene;
+mod listener;
+mod live_events;
+mod maintenance;
+mod meetings;
+mod module_hooks;
+mod module_trace;
+mod mqtt;
+mod mute;
+mod notices;
+mod pagination;
+mod oncall;
+mod opt_out;
+mod outbox;
+mod polls;
+mod previews;
+mod progress;
+mod quiet_hours;
+mod quotes;
+mod reactions;
+mod repeats;
+mod replay;
+mod response_limits;
+mod reports;
+mod room_dump;
+mod room_modules;
+mod room_policies;
 mod room_resolver;
+mod roster;
+mod rsvp;
+mod safe_mode;
+mod schedule;
+mod scheduled_messages;
+mod self_report;
+mod sent_messages;
+mod server_acl;
+mod slowmode;
+mod smtp;
+mod standups;
+mod streams;
+mod threads;
+mod supervisor;
+mod temp_access;
+mod tickets;
+mod ticks;
+mod timers;
+mod trust;
+mod utils;
+mod votes;
 mod wasm;
+mod webhooks;
 
 use anyhow::{Context, bail};
 use matrix_sdk::{
+    attachment::AttachmentConfig,
     config::SyncSettings,
     event_handler::Ctx,
     matrix_auth::{MatrixAuth, MatrixSession, MatrixSessionTokens, LoginBuilder},
@@ -13,15 +93,26 @@ use matrix_sdk::{
         api::client::session::get_login_types::v3::{IdentityProvider, LoginType},
         events::{
             key::verification::{request::ToDeviceKeyVerificationRequestEvent, VerificationMethod},
-            reaction::ReactionEventContent,
-            relation::Annotation,
+            poll::{
+                unstable_end::OriginalSyncUnstablePollEndEvent,
+                unstable_response::OriginalSyncUnstablePollResponseEvent,
+            },
+            reaction::OriginalSyncReactionEvent,
             room::{
-                member::StrippedRoomMemberEvent,
-                message::{MessageType, RoomMessageEventContent, SyncRoomMessageEvent},
+                member::{OriginalSyncRoomMemberEvent, StrippedRoomMemberEvent},
+                message::{
+                    MessageType, OriginalRoomMessageEvent, RoomMessageEventContent,
+                    SyncRoomMessageEvent,
+                },
+                name::RoomNameEventContent,
+                redaction::OriginalSyncRoomRedactionEvent,
+                topic::RoomTopicEventContent,
             },
+            AnySyncStateEvent, StateEventType,
         },
         presence::PresenceState,
-        OwnedUserId, RoomId, UserId,
+        serde::Raw,
+        OwnedEventId, OwnedRoomId, OwnedUserId, RoomId, UserId,
     },
     encryption::verification::{Emoji, SasState, SasVerification, Verification, VerificationRequest, VerificationRequestState},
     Client,
@@ -29,18 +120,119 @@ use matrix_sdk::{
 use matrix_sdk_base::SessionMeta;
 use notify::{RecursiveMode, Watcher};
 use room_resolver::RoomResolver;
+
+pub use compliance::ComplianceConfig;
+pub use content_filter::ContentFilterConfig;
+pub use crash_reporter::CrashReporterConfig;
+pub use diagnostics::DiagnosticsConfig;
+pub use crash_loop::CrashLoopConfig;
+pub use quiet_hours::QuietHoursConfig;
+pub use room_modules::RoomModulesConfig;
+pub use event_export::EventExportConfig;
+pub use dashboard::DashboardConfig;
+pub use doctor::doctor;
+pub use emoji::EmojiConfig;
+pub use decoration::{DecorationConfig, DecorationStyle};
+pub use gatekeeper::GatekeeperConfig;
+pub use inspect::inspect;
+pub use invites::InvitesConfig;
+pub use latency_slo::LatencySloConfig;
+pub use link_hygiene::LinkHygieneConfig;
+pub use listener::ListenConfig;
+pub use email::{EmailConfig, EmailRule};
+pub use alertmanager::AlertmanagerConfig;
+pub use alerts::AlertsConfig;
+pub use grafana::GrafanaConfig;
+pub use self_report::SelfReportConfig;
+pub use roster::RosterConfig;
+pub use directory::DirectoryConfig;
+pub use archive::ArchiveConfig;
+pub use mqtt::{MqttConfig, MqttRoute};
+pub use smtp::SmtpConfig;
+pub use webhooks::WebhooksConfig;
+pub use outbox::enable_dry_run;
+pub use safe_mode::enable_safe_mode;
+pub use reports::ReportsConfig;
+pub use response_limits::ResponseLimitsConfig;
+pub use server_acl::ServerAclConfig;
+pub use standups::StandupConfig;
+pub use supervisor::supervise;
+pub use tickets::TicketsConfig;
+pub use trust::TrustConfig;
+pub use wasm::WasmEngineConfig;
 use serde::Deserialize;
-use std::{collections::HashMap, env, fs, net::SocketAddr, path::PathBuf, sync::Arc};
+use std::{
+    collections::HashMap, env, fs, net::SocketAddr, path::PathBuf, sync::Arc, time::Instant,
+};
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
-    net::TcpListener,
-    sync::Mutex,
+    sync::{Mutex, Notify},
     time::{sleep, Duration},
 };
 use tokio_stream::StreamExt;
 use tokio_util::codec::{FramedRead, LinesCodec};
 use tracing::{debug, error, info, trace, warn};
-use wasm::{GuestState, Module, WasmModules};
+use wasm::{GuestState, Module, ModuleCache, WasmModules};
+
+use crate::api_keys::ApiKeys;
+use crate::compliance::Compliance;
+use crate::content_filter::ContentFilter;
+use crate::devices::DeviceWatcher;
+use crate::diagnostics::{Diagnostics, APP_CTX_LOCK};
+use crate::directory::Directory;
+use crate::emoji::Emoji;
+use crate::event_export::EventExport;
+use crate::gatekeeper::Gatekeeper;
+use crate::invites::Invites;
+use crate::latency_slo::LatencySlo;
+use crate::link_hygiene::LinkHygiene;
+use crate::maintenance::Maintenance;
+use crate::meetings::Meetings;
+use crate::alertmanager::Alertmanager;
+use crate::alerts::{Alerts, NewAlert};
+use crate::activation::Activation;
+use crate::archive::Archive;
+use crate::auto_reactions::AutoReactions;
+use crate::grafana::Grafana;
+use crate::oncall::OnCall;
+use crate::opt_out::OptOut;
+use crate::polls::Polls;
+use crate::pagination::Pagination;
+use crate::previews::Previews;
+use crate::progress::Progress;
+use crate::self_report::SelfReport;
+use crate::roster::Roster;
+use crate::scheduled_messages::ScheduledMessages;
+use crate::temp_access::TempAccess;
+use crate::threads::Threads;
+use crate::reactions::SentReactions;
+use crate::sent_messages::SentMessages;
+use crate::bus::Bus;
+use crate::email::EmailIngest;
+use crate::mqtt::Mqtt;
+use crate::smtp::Smtp;
+use crate::mute::Mute;
+use crate::notices::Notices;
+use crate::reports::Reports;
+use crate::room_modules::RoomModules;
+use crate::room_policies::RoomPolicies;
+use crate::server_acl::ServerAcl;
+use crate::slowmode::SlowMode;
+use crate::standups::Standups;
+use crate::streams::Streams;
+use crate::crash_loop::CrashLoop;
+use crate::crash_reporter::CrashReporter;
+use crate::cron::CronScheduler;
+use crate::decoration::Decoration;
+use crate::quiet_hours::QuietHours;
+use crate::quotes::Quotes;
+use crate::repeats::RepeatFilter;
+use crate::response_limits::ResponseLimits;
+use crate::rsvp::Rsvps;
+use crate::tickets::{Ticket, Tickets};
+use crate::timers::TimerWheel;
+use crate::trust::Trust;
+use crate::votes::Votes;
 
 use crate::admin_table::DEVICE_ID_ENTRY;
 
@@ -68,9 +260,128 @@ pub struct BotConfig {
     pub modules_paths: Vec<PathBuf>,
     /// module specific configuration to forward to corresponding handler.
     pub modules_config: Option<HashMap<String, HashMap<String, String>>>,
+    /// modules trying to handle the messages first, in this order; the other ones come after,
+    /// by name.
+    pub module_priority: Option<Vec<String>>,
+    /// bind configuration for the listeners, indexed by listener name (e.g. `sso`).
+    pub listen: Option<HashMap<String, ListenConfig>>,
+    /// who may ask the bot for room invitations, and to which rooms.
+    pub invites: Option<InvitesConfig>,
+    /// entry gate for new members of some rooms.
+    pub gatekeeper: Option<GatekeeperConfig>,
+    /// word lists and regular expressions filtering messages, with their policies.
+    pub content_filter: Option<ContentFilterConfig>,
+    /// where to forward the `!report`s.
+    pub reports: Option<ReportsConfig>,
+    /// help-desk tickets configuration.
+    pub tickets: Option<TicketsConfig>,
+    /// scheduled stand-ups.
+    pub standups: Option<Vec<StandupConfig>>,
+    /// decoration of the modules' responses.
+    pub decoration: Option<DecorationConfig>,
+    /// where to report crashes and errors.
+    pub crash_reporter: Option<CrashReporterConfig>,
+    /// thresholds for the runtime diagnostics.
+    pub diagnostics: Option<DiagnosticsConfig>,
+    /// limits on the modules' responses.
+    pub response_limits: Option<ResponseLimitsConfig>,
+    /// additional emoji shortcodes.
+    pub emoji: Option<EmojiConfig>,
+    /// hygiene of the links in the bot's responses.
+    pub link_hygiene: Option<LinkHygieneConfig>,
+    /// how the trust levels of the senders are derived.
+    pub trust: Option<TrustConfig>,
+    /// homeservers whose events are ignored.
+    pub server_acl: Option<ServerAclConfig>,
+    /// tagging of the bot's messages, for deployments that must identify automated messages.
+    pub compliance: Option<ComplianceConfig>,
+    /// room where the actions intercepted in dry-run mode are reported.
+    pub dry_run_room: Option<OwnedRoomId>,
+    /// whether the modules' responses are sent as notices (`m.notice`), which other bots ignore.
+    pub send_notices: Option<bool>,
+    /// bridge to an MQTT broker.
+    pub mqtt: Option<MqttConfig>,
+    /// ingestion of the emails of an IMAP mailbox into rooms.
+    pub email: Option<EmailConfig>,
+    /// SMTP server the modules send emails through.
+    pub smtp: Option<SmtpConfig>,
+    /// listener for the webhooks of external services.
+    pub webhooks: Option<WebhooksConfig>,
+    /// alerting pipeline, with escalation of the unacknowledged alerts.
+    pub alerts: Option<AlertsConfig>,
+    /// receiver for the notifications of Prometheus Alertmanager, on the webhooks listener.
+    pub alertmanager: Option<AlertmanagerConfig>,
+    /// Grafana instance `!graph` renders panels of.
+    pub grafana: Option<GrafanaConfig>,
+    /// periodic self-reports of the bot's resource use, with warnings over thresholds.
+    pub self_report: Option<SelfReportConfig>,
+    /// latency objective of the responses, with warnings when it's violated.
+    pub latency_slo: Option<LatencySloConfig>,
+    /// external roster the memberships of some rooms are synced with.
+    pub roster: Option<RosterConfig>,
+    /// directory the group memberships are looked up in.
+    pub directory: Option<DirectoryConfig>,
+    /// compliance archive of the messages of some rooms.
+    pub archive: Option<ArchiveConfig>,
+    /// detection of the crash loops, entering the safe mode.
+    pub crash_loop: Option<CrashLoopConfig>,
+    /// quiet hours, during which the modules' messages that can wait are held.
+    pub quiet_hours: Option<QuietHoursConfig>,
+    /// modules enabled or disabled in some rooms.
+    pub room_modules: Option<HashMap<OwnedRoomId, RoomModulesConfig>>,
+    /// export of the bot's activity to a file or a NATS server, for analytics.
+    pub event_export: Option<EventExportConfig>,
+    /// web admin dashboard, with its REST API.
+    pub dashboard: Option<DashboardConfig>,
+    /// settings of the wasmtime engine running the modules.
+    pub wasm_engine: Option<WasmEngineConfig>,
+    /// file the configuration was read from, if any, read again on SIGHUP.
+    #[serde(skip)]
+    pub config_path: Option<PathBuf>,
+}
+
+/// Directory of the modules, when `MODULES_PATHS` isn't set.
+const DEFAULT_MODULES_PATH: &str = "./modules/target/wasm32-wasi/release";
+/// Directory of the modules before they were built for WASI, still used by older deployments.
+const LEGACY_MODULES_PATH: &str = "./modules/target/wasm32-unknown-unknown/release";
+
+/// Whether the directory contains modules.
+fn has_modules(path: &str) -> bool {
+    fs::read_dir(path).is_ok_and(|entries| {
+        entries
+            .flatten()
+            .any(|entry| entry.path().extension().is_some_and(|ext| ext == "wasm"))
+    })
+}
+
+/// The directory of the modules when `MODULES_PATHS` isn't set, falling back to the legacy one
+/// when the default one has no modules.
+fn default_modules_path() -> &'static str {
+    if has_modules(DEFAULT_MODULES_PATH) {
+        return DEFAULT_MODULES_PATH;
+    }
+    if has_modules(LEGACY_MODULES_PATH) {
+        warn!(
+            "no modules in {DEFAULT_MODULES_PATH}, loading the ones in {LEGACY_MODULES_PATH}; \
+             rebuild them with `--target=wasm32-wasi`, or set MODULES_PATHS"
+        );
+        return LEGACY_MODULES_PATH;
+    }
+    warn!("no modules in {DEFAULT_MODULES_PATH}, the bot won't load any; set MODULES_PATHS?");
+    DEFAULT_MODULES_PATH
 }
 
 impl BotConfig {
+    /// Returns the bind configuration for the listener with the given name, or a default one
+    /// if it's not been configured.
+    pub fn listen_config(&self, name: &str) -> ListenConfig {
+        self.listen
+            .as_ref()
+            .and_then(|listen| listen.get(name))
+            .cloned()
+            .unwrap_or_default()
+    }
+
     /// Generate a `BotConfig` from a TOML config file.
     ///
     /// If `path` matches `None`, will search for a file called `config.toml` in an XDG
@@ -86,7 +397,8 @@ impl BotConfig {
             }
         };
         let contents = fs::read_to_string(&config_path)?;
-        let config: BotConfig = toml::from_str(&contents)?;
+        let mut config: BotConfig = toml::from_str(&contents)?;
+        config.config_path = Some(PathBuf::from(&config_path));
 
         debug!("Using configuration from {config_path}");
         Ok(config)
@@ -113,9 +425,10 @@ impl BotConfig {
 
         // Read the module paths (separated by commas), check they exist, and return the whole
         // list.
-        let modules_paths = env::var("MODULES_PATHS")
+        let modules_paths = env::var("MODULES_PATHS");
+        let modules_paths = modules_paths
             .as_deref()
-            .unwrap_or("./modules/target/wasm32-unknown-unknown/release")
+            .unwrap_or_else(|_| default_modules_path())
             .split(',')
             .map(|path| {
                 let path = PathBuf::from(path);
@@ -141,12 +454,50 @@ impl BotConfig {
             redb_path,
             modules_paths,
             modules_config: None,
+            module_priority: None,
+            listen: None,
+            invites: None,
+            gatekeeper: None,
+            content_filter: None,
+            reports: None,
+            tickets: None,
+            standups: None,
+            decoration: None,
+            crash_reporter: None,
+            diagnostics: None,
+            response_limits: None,
+            emoji: None,
+            link_hygiene: None,
+            trust: None,
+            server_acl: None,
+            compliance: None,
+            dry_run_room: None,
+            send_notices: None,
+            mqtt: None,
+            email: None,
+            smtp: None,
+            webhooks: None,
+            alerts: None,
+            alertmanager: None,
+            grafana: None,
+            self_report: None,
+            latency_slo: None,
+            roster: None,
+            directory: None,
+            archive: None,
+            crash_loop: None,
+            quiet_hours: None,
+            room_modules: None,
+            event_export: None,
+            dashboard: None,
+            wasm_engine: None,
+            config_path: None,
         })
     }
 }
 
 struct AuthInfo<'a> {
-    _config: &'a BotConfig,
+    config: &'a BotConfig,
     /// used for SSO authentication
     login_token: String,
 }
@@ -157,10 +508,19 @@ struct AppCtx {
     modules: WasmModules,
     modules_paths: Vec<PathBuf>,
     modules_config: HashMap<String, HashMap<String, String>>,
+    module_priority: Vec<String>,
+    engine_config: WasmEngineConfig,
+    module_cache: ModuleCache,
     needs_recompile: bool,
     admin_user_id: OwnedUserId,
     db: ShareableDatabase,
+    directory: Arc<Directory>,
+    client: Client,
     room_resolver: RoomResolver,
+    timers: TimerWheel,
+    cron: CronScheduler,
+    /// Notified every time the modules are (re)loaded, to restart their ticks.
+    modules_loaded: Arc<Notify>,
 }
 
 impl AppCtx {
@@ -171,24 +531,79 @@ impl AppCtx {
         client: Client,
         modules_paths: Vec<PathBuf>,
         modules_config: HashMap<String, HashMap<String, String>>,
+        module_priority: Vec<String>,
+        engine_config: WasmEngineConfig,
+        module_cache: ModuleCache,
         db: ShareableDatabase,
+        directory: Arc<Directory>,
         admin_user_id: OwnedUserId,
     ) -> anyhow::Result<Self> {
-        let room_resolver = RoomResolver::new(client);
-        Ok(Self {
-            modules: WasmModules::new(db.clone(), &modules_paths, &modules_config)?,
+        let room_resolver = RoomResolver::new(client.clone());
+        let cron = CronScheduler::new(db.clone())?;
+        let modules = if safe_mode::is_enabled() {
+            WasmModules::default()
+        } else {
+            WasmModules::new(
+                db.clone(),
+                &directory,
+                &client,
+                &modules_paths,
+                &modules_config,
+                &module_priority,
+                &engine_config,
+                &module_cache,
+            )?
+        };
+        let mut ctx = Self {
+            modules,
             modules_paths,
             modules_config,
+            module_priority,
+            engine_config,
+            module_cache,
             needs_recompile: false,
             admin_user_id,
             db,
+            directory,
+            client,
             room_resolver,
-        })
+            timers: TimerWheel::default(),
+            cron,
+            modules_loaded: Default::default(),
+        };
+        ctx.on_modules_loaded();
+        Ok(ctx)
+    }
+
+    /// Called every time the modules are (re)loaded.
+    fn on_modules_loaded(&mut self) {
+        self.notify_refused_modules();
+        self.modules_loaded.notify_one();
+    }
+
+    /// Tells the admin about the modules that weren't loaded, e.g. because they need a newer
+    /// host, in the background.
+    fn notify_refused_modules(&mut self) {
+        let refused = self.modules.take_refused();
+        if refused.is_empty() {
+            return;
+        }
+        let client = self.client.clone();
+        let admin_user_id = self.admin_user_id.clone();
+        tokio::spawn(async move {
+            let text = refused.join("\n");
+            if let Err(err) = admin_dm::notify(&client, &admin_user_id, &text, None).await {
+                warn!("couldn't tell the admin about the refused modules: {err:#}");
+            }
+        });
     }
 
     pub async fn set_needs_recompile(ptr: Arc<Mutex<Self>>) {
+        if safe_mode::is_enabled() {
+            return;
+        }
         {
-            let need = &mut ptr.lock().await.needs_recompile;
+            let need = &mut APP_CTX_LOCK.lock(&ptr, "hot reload check").await.needs_recompile;
             if *need {
                 return;
             }
@@ -198,12 +613,22 @@ impl AppCtx {
         tokio::task::spawn_blocking(move || {
             let mut ptr = futures::executor::block_on(async {
                 tokio::time::sleep(Duration::new(1, 0)).await;
-                ptr.lock().await
+                APP_CTX_LOCK.lock(&ptr, "hot reload").await
             });
 
-            match WasmModules::new(ptr.db.clone(), &ptr.modules_paths, &ptr.modules_config) {
+            match WasmModules::new(
+                ptr.db.clone(),
+                &ptr.directory,
+                &ptr.client,
+                &ptr.modules_paths,
+                &ptr.modules_config,
+                &ptr.module_priority,
+                &ptr.engine_config,
+                &ptr.module_cache,
+            ) {
                 Ok(modules) => {
                     ptr.modules = modules;
+                    ptr.on_modules_loaded();
                     info!("successful hot reload!");
                 }
                 Err(err) => {
@@ -214,17 +639,249 @@ impl AppCtx {
             ptr.needs_recompile = false;
         });
     }
+
+    /// Rebuilds the engine with new settings, and reloads the modules with it in the background,
+    /// swapping them in once they're all ready; the current ones keep running meanwhile.
+    pub async fn reload_engine(ptr: Arc<Mutex<Self>>, engine_config: WasmEngineConfig) {
+        if safe_mode::is_enabled() {
+            return;
+        }
+        let (previous, db, directory, client, paths, modules_config, priority, cache) = {
+            let mut ctx = APP_CTX_LOCK.lock(&ptr, "engine reload").await;
+            if ctx.engine_config == engine_config {
+                info!("the engine settings didn't change");
+                return;
+            }
+            if ctx.engine_config.cache_dir != engine_config.cache_dir {
+                warn!("moving the cache of the compiled modules takes a restart");
+            }
+            // So that a hot reload meanwhile uses the new settings too.
+            let previous = std::mem::replace(&mut ctx.engine_config, engine_config.clone());
+            (
+                previous,
+                ctx.db.clone(),
+                ctx.directory.clone(),
+                ctx.client.clone(),
+                ctx.modules_paths.clone(),
+                ctx.modules_config.clone(),
+                ctx.module_priority.clone(),
+                ctx.module_cache.clone(),
+            )
+        };
+
+        info!("rebuilding the engine with the new settings...");
+        let new_config = engine_config.clone();
+        let modules = tokio::task::spawn_blocking(move || {
+            WasmModules::new(
+                db,
+                &directory,
+                &client,
+                &paths,
+                &modules_config,
+                &priority,
+                &new_config,
+                &cache,
+            )
+        })
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|modules| modules);
+
+        let mut ctx = APP_CTX_LOCK.lock(&ptr, "engine swap").await;
+        if ctx.engine_config != engine_config {
+            debug!("the engine settings changed again meanwhile, dropping the rebuilt modules");
+            return;
+        }
+        match modules {
+            Ok(modules) => {
+                ctx.modules = modules;
+                ctx.on_modules_loaded();
+                info!("swapped in the modules running on the new engine");
+            }
+            Err(err) => {
+                ctx.engine_config = previous;
+                error!("couldn't rebuild the engine, keeping the current one: {err:#}");
+            }
+        }
+    }
 }
 
 #[derive(Clone)]
 struct App {
     inner: Arc<Mutex<AppCtx>>,
+    admin_user_id: OwnedUserId,
+    devices: Arc<DeviceWatcher>,
+    invites: Arc<Invites>,
+    gatekeeper: Arc<Gatekeeper>,
+    content_filter: Arc<ContentFilter>,
+    slowmode: Arc<SlowMode>,
+    room_policies: Arc<RoomPolicies>,
+    reports: Arc<Reports>,
+    tickets: Arc<Tickets>,
+    standups: Arc<Standups>,
+    votes: Arc<Votes>,
+    rsvps: Arc<Rsvps>,
+    quotes: Arc<Quotes>,
+    decoration: Arc<Decoration>,
+    crash_reporter: Arc<CrashReporter>,
+    diagnostics: Arc<Diagnostics>,
+    response_limits: Arc<ResponseLimits>,
+    emoji: Arc<Emoji>,
+    link_hygiene: Arc<LinkHygiene>,
+    trust: Arc<Trust>,
+    server_acl: Arc<ServerAcl>,
+    compliance: Arc<Compliance>,
+    mute: Arc<Mute>,
+    repeats: Arc<RepeatFilter>,
+    streams: Arc<Streams>,
+    mqtt: Arc<Mqtt>,
+    email: Arc<EmailIngest>,
+    smtp: Arc<Smtp>,
+    alerts: Arc<Alerts>,
+    oncall: Arc<OnCall>,
+    sent_messages: Arc<SentMessages>,
+    sent_reactions: Arc<SentReactions>,
+    alertmanager: Arc<Alertmanager>,
+    grafana: Arc<Grafana>,
+    self_report: Arc<SelfReport>,
+    latency_slo: Arc<LatencySlo>,
+    threads: Arc<Threads>,
+    temp_access: Arc<TempAccess>,
+    roster: Arc<Roster>,
+    archive: Arc<Archive>,
+    scheduled_messages: Arc<ScheduledMessages>,
+    notices: Arc<Notices>,
+    polls: Arc<Polls>,
+    auto_reactions: Arc<AutoReactions>,
+    crash_loop: Arc<CrashLoop>,
+    activation: Arc<Activation>,
+    quiet_hours: Arc<QuietHours>,
+    room_modules: Arc<RoomModules>,
+    opt_out: Arc<OptOut>,
+    event_export: Arc<EventExport>,
+    api_keys: Arc<ApiKeys>,
+    previews: Arc<Previews>,
+    pagination: Arc<Pagination>,
+    bus: Arc<Bus>,
+    meetings: Arc<Meetings>,
+    maintenance: Arc<Maintenance>,
+    progress: Arc<Progress>,
 }
 
 impl App {
-    pub fn new(ctx: AppCtx) -> Self {
+    pub fn new(
+        ctx: AppCtx,
+        admin_user_id: OwnedUserId,
+        devices: DeviceWatcher,
+        invites: Invites,
+        gatekeeper: Gatekeeper,
+        content_filter: ContentFilter,
+        slowmode: SlowMode,
+        room_policies: RoomPolicies,
+        reports: Reports,
+        tickets: Tickets,
+        standups: Standups,
+        votes: Votes,
+        rsvps: Rsvps,
+        quotes: Quotes,
+        decoration: Decoration,
+        crash_reporter: CrashReporter,
+        diagnostics: Diagnostics,
+        response_limits: ResponseLimits,
+        emoji: Emoji,
+        link_hygiene: LinkHygiene,
+        trust: Trust,
+        server_acl: ServerAcl,
+        compliance: Compliance,
+        mute: Mute,
+        repeats: RepeatFilter,
+        streams: Streams,
+        mqtt: Mqtt,
+        email: EmailIngest,
+        smtp: Smtp,
+        alerts: Alerts,
+        oncall: OnCall,
+        sent_messages: SentMessages,
+        sent_reactions: SentReactions,
+        alertmanager: Alertmanager,
+        grafana: Grafana,
+        self_report: SelfReport,
+        latency_slo: LatencySlo,
+        threads: Threads,
+        temp_access: TempAccess,
+        roster: Roster,
+        archive: Archive,
+        scheduled_messages: ScheduledMessages,
+        notices: Notices,
+        polls: Polls,
+        auto_reactions: AutoReactions,
+        crash_loop: CrashLoop,
+        activation: Activation,
+        quiet_hours: QuietHours,
+        room_modules: RoomModules,
+        opt_out: OptOut,
+        event_export: EventExport,
+        api_keys: ApiKeys,
+    ) -> Self {
         Self {
             inner: Arc::new(Mutex::new(ctx)),
+            admin_user_id,
+            devices: Arc::new(devices),
+            invites: Arc::new(invites),
+            gatekeeper: Arc::new(gatekeeper),
+            content_filter: Arc::new(content_filter),
+            slowmode: Arc::new(slowmode),
+            room_policies: Arc::new(room_policies),
+            reports: Arc::new(reports),
+            tickets: Arc::new(tickets),
+            standups: Arc::new(standups),
+            votes: Arc::new(votes),
+            rsvps: Arc::new(rsvps),
+            quotes: Arc::new(quotes),
+            decoration: Arc::new(decoration),
+            crash_reporter: Arc::new(crash_reporter),
+            diagnostics: Arc::new(diagnostics),
+            response_limits: Arc::new(response_limits),
+            emoji: Arc::new(emoji),
+            link_hygiene: Arc::new(link_hygiene),
+            trust: Arc::new(trust),
+            server_acl: Arc::new(server_acl),
+            compliance: Arc::new(compliance),
+            mute: Arc::new(mute),
+            repeats: Arc::new(repeats),
+            streams: Arc::new(streams),
+            mqtt: Arc::new(mqtt),
+            email: Arc::new(email),
+            smtp: Arc::new(smtp),
+            alerts: Arc::new(alerts),
+            oncall: Arc::new(oncall),
+            sent_messages: Arc::new(sent_messages),
+            sent_reactions: Arc::new(sent_reactions),
+            alertmanager: Arc::new(alertmanager),
+            grafana: Arc::new(grafana),
+            self_report: Arc::new(self_report),
+            latency_slo: Arc::new(latency_slo),
+            threads: Arc::new(threads),
+            temp_access: Arc::new(temp_access),
+            roster: Arc::new(roster),
+            archive: Arc::new(archive),
+            scheduled_messages: Arc::new(scheduled_messages),
+            notices: Arc::new(notices),
+            polls: Arc::new(polls),
+            auto_reactions: Arc::new(auto_reactions),
+            crash_loop: Arc::new(crash_loop),
+            activation: Arc::new(activation),
+            quiet_hours: Arc::new(quiet_hours),
+            room_modules: Arc::new(room_modules),
+            opt_out: Arc::new(opt_out),
+            event_export: Arc::new(event_export),
+            api_keys: Arc::new(api_keys),
+            meetings: Default::default(),
+            maintenance: Default::default(),
+            bus: Default::default(),
+            progress: Default::default(),
+            previews: Default::default(),
+            pagination: Default::default(),
         }
     }
 }
@@ -263,6 +920,7 @@ fn try_handle_admin<'a>(
                         Ok(actions) => Some(actions),
                         Err(err) => {
                             error!("error when handling admin command: {err:#}");
+                            m.record_error(&err);
                             None
                         }
                     };
@@ -275,6 +933,8 @@ fn try_handle_admin<'a>(
                 text: "missing command".to_owned(),
                 html: None,
                 to: sender.to_string(),
+                notice: None,
+                urgency: None,
             })])
         }
     } else {
@@ -282,6 +942,8 @@ fn try_handle_admin<'a>(
             text: "missing module and command".to_owned(),
             html: None,
             to: sender.to_string(),
+            notice: None,
+            urgency: None,
         })])
     }
 }
@@ -303,17 +965,17 @@ fn try_handle_help<'a>(
                 Ok(msg) => Some(msg),
                 Err(err) => {
                     error!("error when handling help command: {err:#}");
+                    m.record_error(&err);
                     None
                 }
             }
             .unwrap_or("<missing>".to_string());
 
             msg.push_str(&format!("\n- {name}: {help}", name = m.name(), help = help));
-            // TODO lol sanitize html
             html.push_str(&format!(
                 "<li><b>{name}</b>: {help}</li>",
-                name = m.name(),
-                help = help
+                name = html_text::escape_html(m.name()),
+                help = html_text::escape_html(&help).replace('\n', "<br>")
             ));
         }
         html.push_str("</ul>");
@@ -337,7 +999,8 @@ fn try_handle_help<'a>(
         } else {
             format!("module {module} not found")
         };
-        (msg.clone(), msg)
+        let html = html_text::escape_html(&msg).replace('\n', "<br>");
+        (msg, html)
     } else {
         return None;
     };
@@ -346,24 +1009,11 @@ fn try_handle_help<'a>(
         text: msg,
         html: Some(html),
         to: sender.to_string(), // TODO rather room?
+        notice: None,
+        urgency: None,
     }))
 }
 
-enum AnyEvent {
-    RoomMessage(RoomMessageEventContent),
-    Reaction(ReactionEventContent),
-}
-
-impl AnyEvent {
-    async fn send(self, room: &mut Room) -> anyhow::Result<()> {
-        let _ = match self {
-            AnyEvent::RoomMessage(e) => room.send(e).await?,
-            AnyEvent::Reaction(e) => room.send(e).await?,
-        };
-        Ok(())
-    }
-}
-
 async fn on_verification_request(ev: ToDeviceKeyVerificationRequestEvent, client: Client) -> anyhow::Result<()> {
     let request = client
         .encryption()
@@ -454,9 +1104,501 @@ async fn wait_for_confirmation(sas: SasVerification, emoji: [Emoji; 7]) -> anyho
     Ok(())
 }
 
+/// Try to handle a message assuming it's an `!admin` command for one of the host features.
+///
+/// Must only be called for messages sent by the admin.
+async fn try_handle_host_admin(
+    ctx: &App,
+    client: &Client,
+    room: &Room,
+    content: &str,
+) -> Option<String> {
+    if let Some(response) = ctx.gatekeeper.try_handle_admin(content, room) {
+        return Some(response);
+    }
+    if let Some(response) = ctx.slowmode.try_handle_admin(client, content).await {
+        return Some(response);
+    }
+    if let Some(response) = ctx.room_policies.try_handle_admin(client, content).await {
+        return Some(response);
+    }
+    if let Some(response) = ctx
+        .diagnostics
+        .try_handle_admin(&ctx.inner, room, content)
+        .await
+    {
+        return Some(response);
+    }
+    if let Some(response) = ctx.trust.try_handle_admin(room, content).await {
+        return Some(response);
+    }
+    if let Some(response) = inspect::try_handle_admin(&ctx.inner, content).await {
+        return Some(response);
+    }
+    if let Some(response) = module_trace::try_handle_admin(&ctx.inner, content).await {
+        return Some(response);
+    }
+    if let Some(response) = supervisor::try_handle_admin(content) {
+        return Some(response);
+    }
+    if let Some(response) = room_dump::try_handle_admin(ctx, client, content).await {
+        return Some(response);
+    }
+    if let Some(response) = replay::try_handle_admin(ctx, client, content).await {
+        return Some(response);
+    }
+    if let Some(response) = ctx.maintenance.try_handle_admin(content) {
+        return Some(response);
+    }
+    if let Some(response) = ctx.temp_access.try_handle_admin(client, content).await {
+        return Some(response);
+    }
+    if let Some(response) = ctx.roster.try_handle_admin(client, content).await {
+        return Some(response);
+    }
+    if let Some(response) = ctx.auto_reactions.try_handle_admin(client, content).await {
+        return Some(response);
+    }
+    if let Some(response) = ctx.crash_loop.try_handle_admin(content) {
+        return Some(response);
+    }
+    if let Some(response) = ctx.room_modules.try_handle_admin(client, content).await {
+        return Some(response);
+    }
+    if let Some(response) = ctx
+        .api_keys
+        .try_handle_admin(client, &ctx.admin_user_id, content)
+        .await
+    {
+        return Some(response);
+    }
+    if let Some(response) = ctx
+        .archive
+        .try_handle_admin(client, &ctx.admin_user_id, content)
+        .await
+    {
+        return Some(response);
+    }
+    ctx.devices
+        .try_handle_admin(client, content, &ctx.admin_user_id)
+        .await
+}
+
+/// Converts a module's message into a room message. A missing text body is derived from the HTML
+/// one, and a missing HTML body from the text, read as Markdown.
+async fn message_content(
+    ctx: &App,
+    room: &Room,
+    module: &str,
+    mut msg: wasm::Message,
+) -> RoomMessageEventContent {
+    let notice = ctx.notices.is_notice(module, &msg);
+    html_text::fill_text(&mut msg);
+    ctx.emoji.expand(room, &mut msg).await;
+    let mut msg = ctx.decoration.apply(room.room_id(), module, msg);
+    ctx.link_hygiene.apply(&mut msg);
+    match (msg.html, notice) {
+        (Some(html), false) => RoomMessageEventContent::text_html(msg.text, html),
+        (None, false) => RoomMessageEventContent::text_markdown(msg.text),
+        (Some(html), true) => RoomMessageEventContent::notice_html(msg.text, html),
+        (None, true) => RoomMessageEventContent::notice_markdown(msg.text),
+    }
+}
+
+/// Posts a message of a module in another room, given by id or alias, where the bot must be.
+async fn send_to_room(
+    ctx: &App,
+    client: &Client,
+    module: &str,
+    target: wasm::RoomMessage,
+) -> anyhow::Result<()> {
+    let inner = ctx.inner.clone();
+    let room = target.room.clone();
+    let room_id = tokio::task::spawn_blocking(move || {
+        let ctx = &mut *futures::executor::block_on(APP_CTX_LOCK.lock(&inner, "room resolution"));
+        ctx.room_resolver.resolve_room(&room)
+    })
+    .await??;
+    let Some(room_id) = room_id else {
+        bail!("{} isn't a room id or alias", target.room);
+    };
+    let room = client
+        .get_room(&OwnedRoomId::try_from(room_id)?)
+        .filter(|room| room.state() == RoomState::Joined)
+        .with_context(|| format!("the bot isn't in {}", target.room))?;
+    deliver(ctx, &room, module, target.message).await
+}
+
+/// Sends a message of a module to a user privately, in the direct message room with them.
+async fn send_dm(
+    ctx: &App,
+    client: &Client,
+    module: &str,
+    dm: wasm::DirectMessage,
+) -> anyhow::Result<()> {
+    let user_id = OwnedUserId::try_from(dm.user.as_str())
+        .with_context(|| format!("{} isn't a user id", dm.user))?;
+    if client.user_id() == Some(&*user_id) {
+        bail!("{module} tried to send a direct message to the bot itself");
+    }
+    let room = utils::dm_room(client, &user_id).await?;
+    deliver(ctx, &room, module, dm.message).await
+}
+
+/// Redacts a message of the room on behalf of the module, if the bot has the power to.
+async fn redact_message(
+    room: &Room,
+    module: &str,
+    redaction: wasm::Redaction,
+) -> anyhow::Result<()> {
+    let event_id = OwnedEventId::try_from(redaction.event_id)?;
+    let client = room.client();
+    let bot = client.user_id().context("missing bot user id")?;
+    if !room.can_user_redact(bot).await? {
+        bail!("{module} can't redact {event_id}, the bot lacks the power level to");
+    }
+    outbox::redact(room, &event_id, redaction.reason.as_deref()).await
+}
+
+/// A change of the room's metadata asked by a module.
+enum RoomStateChange {
+    Topic(String),
+    Name(String),
+}
+
+/// Changes the room's metadata on behalf of the module, if the bot has the power to.
+async fn set_room_state(room: &Room, module: &str, change: RoomStateChange) -> anyhow::Result<()> {
+    let client = room.client();
+    let bot = client.user_id().context("missing bot user id")?;
+    let event_type = match change {
+        RoomStateChange::Topic(_) => StateEventType::RoomTopic,
+        RoomStateChange::Name(_) => StateEventType::RoomName,
+    };
+    if !room.can_user_send_state(bot, event_type.clone()).await? {
+        bail!("{module} can't set the {event_type} of the room, the bot lacks the power level to");
+    }
+    match change {
+        RoomStateChange::Topic(topic) => {
+            outbox::send_state(room, RoomTopicEventContent::new(topic)).await
+        }
+        RoomStateChange::Name(name) => {
+            outbox::send_state(room, RoomNameEventContent::new(name)).await
+        }
+    }
+}
+
+/// Uploads a file of the module to the content repository, and posts it in the room.
+async fn upload_media(
+    ctx: &App,
+    room: &Room,
+    module: &str,
+    upload: wasm::Upload,
+) -> anyhow::Result<()> {
+    let content_type: mime::Mime = upload
+        .mime_type
+        .parse()
+        .with_context(|| format!("{module} uploaded a file with an invalid MIME type"))?;
+    let event_id = outbox::send_attachment(
+        room,
+        &upload.filename,
+        &content_type,
+        upload.data,
+        AttachmentConfig::new(),
+    )
+    .await?;
+    ctx.sent_messages.record(module, room.room_id(), &event_id);
+    Ok(())
+}
+
+/// Posts or updates the progress message of a module's long task in the room.
+async fn report_progress(
+    ctx: &App,
+    room: &Room,
+    module: &str,
+    report: wasm::Progress,
+) -> anyhow::Result<()> {
+    let update = ctx.progress.update(module, room.room_id(), &report);
+    if matches!(update, progress::Update::Skip) {
+        return Ok(());
+    }
+    let msg = wasm::Message {
+        text: progress::render(&report),
+        html: None,
+        to: String::new(),
+        notice: None,
+        urgency: None,
+    };
+    let content = message_content(ctx, room, module, msg).await;
+    match update {
+        progress::Update::Post => {
+            let event_id = ctx.compliance.send(room, module, content).await?;
+            ctx.sent_messages.record(module, room.room_id(), &event_id);
+            if !report.done {
+                ctx.progress.posted(module, room.room_id(), &report.key, event_id);
+            }
+        }
+        progress::Update::Edit(event_id) => {
+            ctx.compliance.edit(room, module, event_id, content).await?;
+        }
+        progress::Update::Skip => {}
+    }
+    Ok(())
+}
+
+/// Replaces a message the module sent in the room with a new version.
+async fn edit_message(
+    ctx: &App,
+    room: &Room,
+    module: &str,
+    edit: wasm::Edit,
+) -> anyhow::Result<()> {
+    if !ctx.sent_messages.contains(module, room.room_id(), &edit.event_id)? {
+        bail!("{} isn't a recent message of {module} in this room", edit.event_id);
+    }
+    let event_id = OwnedEventId::try_from(edit.event_id)?;
+    let content = message_content(ctx, room, module, edit.message).await;
+    ctx.compliance.edit(room, module, event_id, content).await?;
+    Ok(())
+}
+
+/// Notifies the modules about a ticket change, and posts their responses in the ticket's room.
+async fn notify_ticket(ctx: &App, room: &Room, ticket: &Ticket) -> anyhow::Result<()> {
+    let inner = ctx.inner.clone();
+    let crash_reporter = ctx.crash_reporter.clone();
+    let response_limits = ctx.response_limits.clone();
+    let room_id = room.room_id().to_owned();
+    let ticket = ticket.to_wasm();
+
+    let actions = tokio::task::spawn_blocking(move || {
+        let ctx = &mut *futures::executor::block_on(APP_CTX_LOCK.lock(&inner, "ticket notification"));
+        let (store, modules) = ctx.modules.iter();
+
+        let mut actions = Vec::new();
+        for module in modules {
+            match module.on_ticket(&mut *store, &ticket) {
+                Ok(module_actions) => actions.push((
+                    module.name().to_owned(),
+                    response_limits.apply(module.name(), module_actions),
+                )),
+                Err(err) => {
+                    warn!("wasm module {} ran into an error: {err}", module.name());
+                    module.record_error(&err);
+                    crash_reporter.module_error(module.name(), Some(&room_id), None, &err);
+                }
+            }
+        }
+        actions
+    })
+    .await?;
+
+    for (module, actions) in actions {
+        handle_module_actions(ctx, room, &module, actions).await?;
+    }
+
+    Ok(())
+}
+
+/// Sends a message of a module in the room, unless it's a repeat or it's held for the quiet
+/// hours, in pages if it's too long.
+async fn deliver(ctx: &App, room: &Room, module: &str, msg: wasm::Message) -> anyhow::Result<()> {
+    respond(ctx, room, module, msg, None, false).await
+}
+
+/// Sends a message of a module in the room like [`deliver`], as a reply to the triggering message
+/// if `reply`, or in its thread.
+async fn respond(
+    ctx: &App,
+    room: &Room,
+    module: &str,
+    mut msg: wasm::Message,
+    trigger: Option<&OriginalRoomMessageEvent>,
+    reply: bool,
+) -> anyhow::Result<()> {
+    if ctx.repeats.is_repeat(module, room.room_id(), &msg) {
+        return Ok(());
+    }
+    let urgency = msg.urgency;
+    let pages = pagination::paginate(ctx, &mut msg);
+    let content = message_content(ctx, room, module, msg).await;
+    let event_id = match trigger {
+        Some(original) if reply => {
+            if ctx.quiet_hours.holds(room.room_id(), urgency) {
+                // Still delivered as a reply, without its pages, once the hours are over.
+                let content = compliance::reply_content(original, content);
+                ctx.quiet_hours
+                    .hold(room.room_id(), module, urgency, &content)?;
+                return Ok(());
+            }
+            ctx.compliance.reply(room, module, original, content).await?
+        }
+        _ => {
+            let content = match trigger {
+                Some(original) => ctx.threads.apply(module, original, content),
+                None => content,
+            };
+            if ctx.quiet_hours.hold(room.room_id(), module, urgency, &content)? {
+                return Ok(());
+            }
+            ctx.compliance.send(room, module, content).await?
+        }
+    };
+    ctx.sent_messages.record(module, room.room_id(), &event_id);
+    if let Some(pages) = pages {
+        ctx.pagination
+            .register(room, module, &event_id, pages)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Makes the empty event id of an action stand for the triggering message; returns whether the
+/// action has a message to target.
+fn target_trigger(event_id: &mut String, trigger: Option<&OriginalRoomMessageEvent>) -> bool {
+    if event_id.is_empty() {
+        let Some(original) = trigger else {
+            return false;
+        };
+        *event_id = original.event_id.to_string();
+    }
+    true
+}
+
+/// Carries out an action of a module in the room. `trigger` is the message the module responds
+/// to, if any; without one, e.g. for a ticket change, a timer, a recurring job or a stream event,
+/// reactions and redactions of the message are ignored, and replies are sent as plain responses.
+///
+/// Returns the errors of the events sent in the room; the other actions log theirs.
+async fn dispatch_action(
+    ctx: &App,
+    room: &Room,
+    module: &str,
+    action: wasm::Action,
+    trigger: Option<&OriginalRoomMessageEvent>,
+) -> anyhow::Result<()> {
+    ctx.event_export.action(room.room_id(), module, &action);
+    match action {
+        wasm::Action::Respond(msg) => respond(ctx, room, module, msg, trigger, false).await?,
+        wasm::Action::Reply(msg) => respond(ctx, room, module, msg, trigger, true).await?,
+        wasm::Action::React(mut reaction) => {
+            if target_trigger(&mut reaction.event_id, trigger) {
+                reactions::react(ctx, room, module, reaction).await?;
+            } else {
+                trace!("ignoring reactions from {module}, there's no message to react to");
+            }
+        }
+        wasm::Action::Unreact(mut reaction) => {
+            if target_trigger(&mut reaction.event_id, trigger) {
+                reactions::unreact(ctx, room, module, reaction).await?;
+            } else {
+                trace!("ignoring reactions from {module}, there's no message to react to");
+            }
+        }
+        wasm::Action::Redact(mut redaction) => {
+            if target_trigger(&mut redaction.event_id, trigger) {
+                redact_message(room, module, redaction).await?;
+            } else {
+                trace!("ignoring redaction from {module}, there's no message to redact");
+            }
+        }
+        wasm::Action::Upload(upload) => upload_media(ctx, room, module, upload).await?,
+        wasm::Action::SetTopic(topic) => {
+            set_room_state(room, module, RoomStateChange::Topic(topic)).await?;
+        }
+        wasm::Action::SetRoomName(name) => {
+            set_room_state(room, module, RoomStateChange::Name(name)).await?;
+        }
+        wasm::Action::Delayed(delayed) => {
+            timers::schedule(ctx, module, room.room_id(), delayed).await;
+        }
+        wasm::Action::Schedule(job) => {
+            cron::schedule(ctx, module, room.room_id(), job).await;
+        }
+        wasm::Action::Unschedule(name) => {
+            cron::unschedule(ctx, module, room.room_id(), &name).await;
+        }
+        wasm::Action::Subscribe(subscription) => {
+            ctx.streams.subscribe(module, room.room_id(), subscription);
+        }
+        wasm::Action::Unsubscribe(name) => {
+            ctx.streams.unsubscribe(module, room.room_id(), &name);
+        }
+        wasm::Action::Publish(message) => {
+            ctx.mqtt.publish(module, message).await;
+        }
+        wasm::Action::Emit(event) => {
+            ctx.bus.emit(module, room.room_id(), event);
+        }
+        wasm::Action::SendEmail(email) => {
+            ctx.smtp.send(module, email).await;
+        }
+        wasm::Action::RaiseAlert(alert) => {
+            let alert = NewAlert::from_wasm(alert);
+            if let Err(err) = ctx.alerts.raise(&room.client(), module, alert).await {
+                warn!("couldn't raise an alert from {module}: {err:#}");
+            }
+        }
+        wasm::Action::SendToRoom(target) => {
+            if let Err(err) = send_to_room(ctx, &room.client(), module, target).await {
+                warn!("couldn't post a message of {module} in another room: {err:#}");
+            }
+        }
+        wasm::Action::Dm(dm) => {
+            if let Err(err) = send_dm(ctx, &room.client(), module, dm).await {
+                warn!("couldn't send a direct message of {module}: {err:#}");
+            }
+        }
+        wasm::Action::Edit(edit) => {
+            if let Err(err) = edit_message(ctx, room, module, edit).await {
+                warn!("couldn't edit a message of {module}: {err:#}");
+            }
+        }
+        wasm::Action::Progress(report) => {
+            if let Err(err) = report_progress(ctx, room, module, report).await {
+                warn!("couldn't report the progress of a task of {module}: {err:#}");
+            }
+        }
+        wasm::Action::StartPoll(poll) => {
+            if let Err(err) = polls::start(ctx, room, module, poll).await {
+                warn!("couldn't start a poll of {module}: {err:#}");
+            }
+        }
+        wasm::Action::EndPoll(poll_id) => {
+            if let Err(err) = polls::end(ctx, room, module, &poll_id).await {
+                warn!("couldn't end a poll of {module}: {err:#}");
+            }
+        }
+        wasm::Action::Preview(preview) => {
+            let author = trigger.map(|original| &*original.sender);
+            if let Err(err) = previews::post(ctx, room, module, author, preview).await {
+                warn!("couldn't post a preview of {module}: {err:#}");
+            }
+        }
+        wasm::Action::FallThrough => {
+            if trigger.is_none() {
+                trace!("ignoring fall-through from {module}, there's no message to pass on");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Handles the actions a module emitted without a message to respond to, e.g. for a ticket change,
+/// a timer, a recurring job or a stream event.
+async fn handle_module_actions(
+    ctx: &App,
+    room: &Room,
+    module: &str,
+    actions: Vec<wasm::Action>,
+) -> anyhow::Result<()> {
+    for action in actions {
+        dispatch_action(ctx, room, module, action, None).await?;
+    }
+    Ok(())
+}
+
 async fn on_message(
     ev: SyncRoomMessageEvent,
-    mut room: Room,
+    room: Room,
     client: Client,
     Ctx(ctx): Ctx<App>,
 ) -> anyhow::Result<()> {
@@ -465,11 +1607,22 @@ async fn on_message(
         return Ok(());
     }
 
+    // Archive everything, including the bot's own messages.
+    if let Some(original) = ev.as_original() {
+        if let Err(err) = ctx.archive.record(&client, &room, original).await {
+            error!("couldn't archive {}: {err:#}", original.event_id);
+        }
+    }
+
     if ev.sender() == client.user_id().unwrap() {
         // Skip messages sent by the bot.
         return Ok(());
     }
 
+    if ctx.server_acl.is_blocked(ev.sender()) {
+        return Ok(());
+    }
+
     if ev.as_original().is_none() {
         trace!("redacted message");
         return Ok(());
@@ -477,10 +1630,21 @@ async fn on_message(
 
     let unredacted = ev.as_original().unwrap();
 
+    if ctx
+        .room_policies
+        .check(&client, &room, unredacted, &ctx.admin_user_id)
+        .await?
+    {
+        trace!("message violated a room policy, skipping modules");
+        return Ok(());
+    }
+
     let content = if let MessageType::Text(text) = &unredacted.content.msgtype {
         text.body.to_string()
     } else {
-        // Ignore other kinds of messages at the moment.
+        if !safe_mode::is_enabled() && !module_hooks::on_media(&ctx, &room, unredacted).await {
+            trace!("ignoring a message that's neither text nor media");
+        }
         return Ok(());
     };
 
@@ -492,24 +1656,188 @@ async fn on_message(
         content,
     );
 
-    if content.contains("you are a good boy") {
-        let reaction = ReactionEventContent::new(Annotation::new(ev.event_id().to_owned(), "👀".to_owned()));
-        room.send(reaction).await?;
-        let message = RoomMessageEventContent::text_html("thank you", "thank <a href='htts://aapx.org/'>you</a>");
-        room.send(message).await?;
+    // In safe mode, only the host admin commands are answered.
+    if safe_mode::is_enabled() {
+        if ev.sender() == ctx.admin_user_id {
+            if let Some(response) = try_handle_host_admin(&ctx, &client, &room, &content).await {
+                let content = RoomMessageEventContent::text_plain(response);
+                outbox::unmuted(ctx.compliance.send(&room, "host", content)).await?;
+            }
+        }
+        return Ok(());
+    }
+
+    if let Some(response) = ctx.opt_out.try_handle(&room, ev.sender(), &content) {
+        ctx.compliance
+            .send(&room, "host", RoomMessageEventContent::text_plain(response))
+            .await?;
+        return Ok(());
+    }
+    // Users who opted out only get through the moderation checks and the commands they send.
+    let opted_out = ctx.opt_out.is_opted_out(ev.sender(), room.room_id());
+    if !opted_out {
+        ctx.event_export.message(room.room_id(), ev.sender(), ev.event_id(), &content);
+    }
+
+    if !opted_out {
+        if let Err(err) = ctx
+            .auto_reactions
+            .on_message(&room, ev.event_id(), &content)
+            .await
+        {
+            warn!("couldn't react automatically to {}: {err:#}", ev.event_id());
+        }
+    }
+
+    ctx.standups.on_message(&room, ev.sender(), &content).await;
+    if !opted_out {
+        ctx.meetings.on_message(&room, ev.sender(), &content);
+    }
+
+    let from_admin = ev.sender() == ctx.admin_user_id;
+    if ctx
+        .mute
+        .try_handle(&room, ev.sender(), &content, from_admin)
+        .await?
+    {
+        trace!("handled by mute, skipping modules");
+        return Ok(());
+    }
+
+    if ctx.gatekeeper.on_message(&room, ev.sender(), &content).await? {
+        trace!("handled by the gatekeeper, skipping modules");
+        return Ok(());
+    }
+
+    if ctx
+        .content_filter
+        .check(&client, &room, ev.event_id(), ev.sender(), &content)
+        .await?
+    {
+        trace!("handled by the content filter, skipping modules");
+        return Ok(());
+    }
+
+    if ctx
+        .slowmode
+        .check(&client, &room, ev.event_id(), ev.sender())
+        .await?
+    {
+        trace!("handled by slow mode, skipping modules");
+        return Ok(());
+    }
+
+    if from_admin {
+        if let Some(response) = try_handle_host_admin(&ctx, &client, &room, &content).await {
+            // Responses to the admin get through mutes.
+            let content = RoomMessageEventContent::text_plain(response);
+            outbox::unmuted(ctx.compliance.send(&room, "host", content)).await?;
+            return Ok(());
+        }
+    }
+
+    if ctx
+        .reports
+        .try_handle(&client, &room, unredacted, &content)
+        .await?
+    {
+        trace!("handled by reports, skipping modules");
+        return Ok(());
+    }
+
+    if ctx.meetings.try_handle(&room, ev.sender(), &content).await? {
+        trace!("handled by meetings, skipping modules");
+        return Ok(());
+    }
+
+    if ctx.alerts.try_handle(&room, ev.sender(), &content).await? {
+        trace!("handled by alerts, skipping modules");
+        return Ok(());
+    }
+
+    if ctx.oncall.try_handle(&room, ev.sender(), &content).await? {
+        trace!("handled by oncall, skipping modules");
+        return Ok(());
+    }
+
+    if ctx.grafana.try_handle(&room, ev.sender(), &content).await? {
+        trace!("handled by grafana, skipping modules");
+        return Ok(());
+    }
+
+    if ctx.votes.try_handle(&room, ev.sender(), &content).await? {
+        trace!("handled by votes, skipping modules");
+        return Ok(());
+    }
+
+    if ctx.rsvps.try_handle(&room, ev.sender(), &content).await? {
+        trace!("handled by events, skipping modules");
+        return Ok(());
+    }
+
+    if ctx.quotes.try_handle(&room, unredacted, &content).await? {
+        trace!("handled by quotes, skipping modules");
+        return Ok(());
+    }
+
+    if let Some(changed) = ctx.tickets.try_handle(&room, ev.sender(), &content).await? {
+        if let Some(ticket) = changed.filter(|_| !ctx.maintenance.is_on()) {
+            notify_ticket(&ctx, &room, &ticket).await?;
+        }
+        trace!("handled by tickets, skipping modules");
+        return Ok(());
+    }
+
+    if let Some(response) = ctx
+        .invites
+        .try_handle(&client, &room, &content, ev.sender())
+        .await
+    {
+        ctx.compliance
+            .send(&room, "host", RoomMessageEventContent::text_plain(response))
+            .await?;
+        return Ok(());
+    }
+
+    if let Some(response) = ctx
+        .scheduled_messages
+        .try_handle(&client, &room, ev.sender(), &content)
+        .await
+    {
+        ctx.compliance
+            .send(&room, "host", RoomMessageEventContent::text_plain(response))
+            .await?;
+        return Ok(());
+    }
+
+    if ctx.maintenance.is_on() {
+        if content.starts_with('!') {
+            let response = RoomMessageEventContent::text_plain("in maintenance, try again later");
+            ctx.compliance.send(&room, "host", response).await?;
+        }
+        trace!("in maintenance, skipping modules");
+        return Ok(());
     }
 
     // TODO ohnoes, locking across other awaits is bad
     // TODO Use a lock-free data-structure for the list of modules + put locks in the module
     // internal implementation?
     // TODO or create a new wasm instance per message \o/
+    let app = ctx.clone();
     let ctx = ctx.inner.clone();
     let room_id = room.room_id().to_owned();
 
-    let event_id = ev.event_id().to_owned();
+    let original = Box::new(unredacted.clone().into_full_event(room_id.clone()));
+    let module_crash_reporter = app.crash_reporter.clone();
+    let activation = app.activation.clone();
+    let room_modules = app.room_modules.clone();
+    let event_export = app.event_export.clone();
+    let trust = app.trust.level(&room, ev.sender()).await;
 
-    let new_actions = tokio::task::spawn_blocking(move || {
-        let ctx = &mut *futures::executor::block_on(ctx.lock());
+    // The modules handling the message with their actions, and whether they're the host's own
+    // admin and help commands rather than wasm modules.
+    let (handled, builtin) = tokio::task::spawn_blocking(move || {
+        let ctx = &mut *futures::executor::block_on(APP_CTX_LOCK.lock(&ctx, "message handling"));
 
         let (store, modules) = ctx.modules.iter();
 
@@ -525,73 +1853,190 @@ async fn on_message(
                 None => {}
                 Some(actions) => {
                     trace!("handled by admin, skipping modules");
-                    return actions;
+                    return (vec![("admin".to_owned(), actions)], true);
                 }
             }
         }
 
         if let Some(actions) = try_handle_help(&content, ev.sender(), store, modules.clone()) {
             trace!("handled by help, skipping modules");
-            return vec![actions];
+            return (vec![("help".to_owned(), vec![actions])], true);
         }
 
+        // The modules handling the message, along with their actions.
+        let mut handled = Vec::new();
         for module in modules {
+            if !ctx.directory.allows(ctx.modules_config.get(module.name()), ev.sender()) {
+                trace!("{} is restricted to a group the sender isn't in", module.name());
+                continue;
+            }
+            if opted_out && !opt_out::is_moderation(ctx.modules_config.get(module.name())) {
+                trace!("the sender opted out of {}", module.name());
+                continue;
+            }
+            if !room_modules.is_enabled(&room_id, module.name()) {
+                trace!("{} is disabled in this room", module.name());
+                continue;
+            }
+            if !activation.is_active(module.name(), &room_id) {
+                trace!("{} is outside its activation window", module.name());
+                continue;
+            }
             trace!("trying to handle message with {}...", module.name());
-            match module.handle(&mut *store, &content, ev.sender(), &room_id) {
+            let started = Instant::now();
+            let result = module.handle(&mut *store, &content, ev.sender(), &room_id, trust);
+            event_export.record_call(&room_id, module.name(), started.elapsed(), &result);
+            match result {
                 Ok(actions) => {
                     if !actions.is_empty() {
-                        // TODO support handling the same message with several handlers.
                         trace!("{} returned a response!", module.name());
-                        return actions;
+                        let falls_through = actions
+                            .iter()
+                            .any(|action| matches!(action, wasm::Action::FallThrough));
+                        handled.push((module.name().to_owned(), actions));
+                        if !falls_through {
+                            break;
+                        }
                     }
                 }
                 Err(err) => {
                     warn!("wasm module {} ran into an error: {err}", module.name());
+                    module.record_error(&err);
+                    module_crash_reporter.module_error(
+                        module.name(),
+                        Some(&room_id),
+                        Some(ev.event_id()),
+                        &err,
+                    );
                 }
             }
         }
 
-        Vec::new()
+        (handled, false)
     })
     .await?;
 
-    let new_events = new_actions
-        .into_iter()
-        .map(|a| match a {
-            wasm::Action::Respond(msg) => {
-                let content = if let Some(html) = msg.html {
-                    RoomMessageEventContent::text_html(msg.text, html)
-                } else {
-                    RoomMessageEventContent::text_plain(msg.text)
-                };
-                AnyEvent::RoomMessage(content)
-            }
-            wasm::Action::React(reaction) => {
-                let reaction =
-                    ReactionEventContent::new(Annotation::new(event_id.clone(), reaction));
-                AnyEvent::Reaction(reaction)
-            }
-        })
-        .collect::<Vec<_>>();
+    for (module, new_actions) in handled {
+        let new_actions = app.response_limits.apply(&module, new_actions);
+
+        for action in new_actions {
+            let dispatch = dispatch_action(&app, &room, &module, action, Some(&*original));
+            let result = if from_admin {
+                outbox::unmuted(dispatch).await
+            } else {
+                dispatch.await
+            };
+            app.crash_reporter.send_result(room.room_id(), &module, &result);
+            result?;
+        }
+        if !builtin {
+            app.latency_slo.record(&module, original.origin_server_ts);
+        }
+    }
+    Ok(())
+}
+
+async fn on_room_member(
+    ev: OriginalSyncRoomMemberEvent,
+    room: Room,
+    client: Client,
+    Ctx(ctx): Ctx<App>,
+) -> anyhow::Result<()> {
+    if ctx.server_acl.is_blocked(&ev.sender) {
+        return Ok(());
+    }
+    ctx.room_policies.on_member(&ev, &room)?;
+    ctx.gatekeeper.clone().on_member(&ev, &room, &client).await?;
+    module_hooks::on_member(&ctx, &room, &ev);
+    Ok(())
+}
+
+async fn on_state_event(ev: Raw<AnySyncStateEvent>, room: Room, Ctx(ctx): Ctx<App>) {
+    module_hooks::on_state_event(&ctx, &room, &ev);
+}
+
+async fn on_redaction(
+    ev: OriginalSyncRoomRedactionEvent,
+    room: Room,
+    client: Client,
+    Ctx(ctx): Ctx<App>,
+) -> anyhow::Result<()> {
+    ctx.archive.on_redaction(&client, &room, &ev).await
+}
 
-    for event in new_events {
-        event.send(&mut room).await?;
+async fn on_reaction(
+    ev: OriginalSyncReactionEvent,
+    room: Room,
+    client: Client,
+    Ctx(ctx): Ctx<App>,
+) -> anyhow::Result<()> {
+    if ev.sender == client.user_id().unwrap() {
+        // Skip reactions sent by the bot.
+        return Ok(());
+    }
+
+    if ctx.server_acl.is_blocked(&ev.sender) {
+        return Ok(());
     }
 
+    let relates_to = &ev.content.relates_to;
+    ctx.gatekeeper
+        .on_reaction(&room, &ev.sender, &relates_to.event_id)
+        .await?;
+    ctx.votes
+        .on_reaction(&room, &ev.sender, &relates_to.event_id, &relates_to.key)
+        .await?;
+    ctx.alerts
+        .on_reaction(&room, &ev.sender, &relates_to.event_id)
+        .await?;
+    ctx.rsvps
+        .on_reaction(&room, &ev.sender, &relates_to.event_id, &relates_to.key)?;
+    previews::on_reaction(&ctx, &room, &ev.sender, &relates_to.event_id, &relates_to.key).await?;
+    pagination::on_reaction(&ctx, &room, &ev.sender, &relates_to.event_id, &relates_to.key).await?;
+    module_hooks::on_reaction(&ctx, &room, &ev.sender, &relates_to.event_id, &relates_to.key);
     Ok(())
 }
 
+async fn on_poll_response(
+    ev: OriginalSyncUnstablePollResponseEvent,
+    room: Room,
+    Ctx(ctx): Ctx<App>,
+) -> anyhow::Result<()> {
+    if ctx.server_acl.is_blocked(&ev.sender) {
+        return Ok(());
+    }
+    polls::on_response(&ctx, &room, &ev).await
+}
+
+async fn on_poll_end(
+    ev: OriginalSyncUnstablePollEndEvent,
+    room: Room,
+    client: Client,
+    Ctx(ctx): Ctx<App>,
+) -> anyhow::Result<()> {
+    if ev.sender == client.user_id().unwrap() {
+        // The bot's own poll ends are handled when sending them.
+        return Ok(());
+    }
+    polls::on_end(&ctx, &room, &ev).await
+}
+
 /// Autojoin mixin.
 async fn on_stripped_state_member(
     room_member: StrippedRoomMemberEvent,
     client: Client,
     room: Room,
+    Ctx(ctx): Ctx<App>,
 ) {
     if room_member.state_key != client.user_id().unwrap() {
         // the invite we've seen isn't for us, but for someone else. ignore
         return;
     }
 
+    if ctx.server_acl.is_blocked(&room_member.sender) {
+        return;
+    }
+
     // looks like the room is an invited room, let's attempt to join then
     if room.state() == RoomState::Invited {
         // The event handlers are called before the next sync begins, but
@@ -644,12 +2089,17 @@ async fn login_with_sso<'a>(
     idp: Option<&IdentityProvider>
 ) -> Result<LoginBuilder, anyhow::Error>
 {
-    let addr = SocketAddr::from(([0, 0, 0, 0], 43210));
-    let listener = TcpListener::bind(&addr).await?;
-    println!("Listening on: http://{}", addr);
+    let default_addr = SocketAddr::from(([0, 0, 0, 0], 43210));
+    let listeners = info.config.listen_config("sso").bind(default_addr).await?;
+    let mut port = default_addr.port();
+    for l in &listeners {
+        let addr = l.local_addr()?;
+        port = addr.port();
+        println!("Listening on: http://{}", addr);
+    }
 
     let sso_url = auth.get_sso_login_url(
-        &format!("http://localhost:{}/callback", addr.port()),
+        &format!("http://localhost:{}/callback", port),
         idp.map(|p| p.id.as_str())
     ).await;
 
@@ -662,7 +2112,7 @@ async fn login_with_sso<'a>(
     let mut token: Result<String, anyhow::Error>;
     loop {
         println!("accepting...");
-        let (mut stream, _) = listener.accept().await?;
+        let (mut stream, _) = listener::accept(&listeners).await?;
 
         token = tokio::task::spawn(async move {
             let mut buffer = [0; 1024];
@@ -694,18 +2144,39 @@ async fn login_with_sso<'a>(
     Ok(auth.login_token(&info.login_token))
 }
 
-/// Run the client for the given `BotConfig`.
-pub async fn run(config: BotConfig) -> anyhow::Result<()> {
-    let user_id = UserId::parse(config.user_id.clone())?;
-    let base_dir = if let Some(dir) = dirs::data_dir() {
+/// Returns the directory the store paths of the configuration are relative to.
+fn base_dir() -> PathBuf {
+    if let Some(dir) = dirs::data_dir() {
         dir
     } else if let Ok(dir) = std::env::current_dir() {
         dir
     } else {
         PathBuf::from(".")
-    };
+    }
+}
+
+/// Run the client for the given `BotConfig`.
+pub async fn run(config: BotConfig) -> anyhow::Result<()> {
+    // Set up first, so that panics are reported as early as possible.
+    let crash_reporter = CrashReporter::new(config.crash_reporter.clone())?;
+
+    let user_id = UserId::parse(config.user_id.clone())?;
+    if outbox::is_dry_run() {
+        info!("dry run: actions in rooms are logged, not carried out");
+        if let Some(room_id) = &config.dry_run_room {
+            outbox::set_debug_room(user_id.clone(), room_id.clone());
+        }
+    }
+    let base_dir = base_dir();
     let store_path = base_dir.join(&config.matrix_store_path);
     let redb_path = base_dir.join(&config.redb_path);
+    let engine_config = config.wasm_engine.clone().unwrap_or_default();
+    let module_cache = match &engine_config.cache_dir {
+        Some(dir) => ModuleCache::in_dir(base_dir.join(dir)),
+        None => ModuleCache::next_to(&redb_path),
+    };
+    let webhooks_listen = config.listen_config("webhooks");
+    let dashboard_listen = config.listen_config("dashboard");
 
     let store = matrix_sdk_sqlite::make_store_config(&store_path, None).await?;
     let client = Client::builder()
@@ -715,14 +2186,20 @@ pub async fn run(config: BotConfig) -> anyhow::Result<()> {
         .await?;
 
     // Create the database, and try to find a device id.
-    let db = Arc::new(unsafe { redb::Database::create(redb_path, 1024 * 1024)? });
+    let db = Arc::new(unsafe { redb::Database::create(&redb_path, 1024 * 1024)? });
+
+    // Before anything gets loaded, in case the bot is crash-looping.
+    let crash_loop = CrashLoop::startup(config.crash_loop.unwrap_or_default(), db.clone())?;
+    if crash_loop.detected() {
+        safe_mode::enable_safe_mode();
+    }
 
     // First we need to log in.
     debug!("logging in...");
     let login_types = client.matrix_auth().get_login_types().await?.flows;
     debug!("login types supported by server: {login_types:?}");
 
-    let mut info = AuthInfo { _config: &config, login_token: String::from("") };
+    let mut info = AuthInfo { config: &config, login_token: String::from("") };
     let mut login_builder = None;
     if config.access_token.is_none() {
         for login_type in login_types {
@@ -788,7 +2265,7 @@ pub async fn run(config: BotConfig) -> anyhow::Result<()> {
     if config.access_token.is_some() {
         let session = MatrixSession {
             meta: SessionMeta {
-                user_id,
+                user_id: user_id.clone(),
                 device_id: device_id.into(),
             },
             tokens: MatrixSessionTokens {
@@ -829,18 +2306,323 @@ pub async fn run(config: BotConfig) -> anyhow::Result<()> {
     }
 
     debug!("setting up app...");
+    let admin_user_id = config.admin_user_id.clone();
+    let devices = DeviceWatcher::new(db.clone(), admin_user_id.clone(), config.password.clone());
+    let invites = Invites::new(config.invites.unwrap_or_default());
+    let gatekeeper = Gatekeeper::new(config.gatekeeper)?;
+    let content_filter =
+        ContentFilter::new(config.content_filter, db.clone(), admin_user_id.clone())?;
+    let slowmode = SlowMode::new(db.clone());
+    let room_policies = RoomPolicies::new(db.clone());
+    let tickets = Tickets::new(config.tickets.unwrap_or_default(), db.clone());
+    let standups = Standups::new(config.standups.unwrap_or_default())?;
+    let votes = Votes::new(db.clone());
+    let rsvps = Rsvps::new(db.clone());
+    let quotes = Quotes::new(db.clone());
+    let decoration = Decoration::new(config.decoration.unwrap_or_default());
+    let diagnostics = Diagnostics::new(config.diagnostics.unwrap_or_default(), db.clone());
+    let response_limits = ResponseLimits::new(config.response_limits.unwrap_or_default());
+    let emoji = Emoji::new(config.emoji.unwrap_or_default());
+    let link_hygiene = LinkHygiene::new(config.link_hygiene.unwrap_or_default());
+    let trust = Trust::new(
+        config.trust.unwrap_or_default(),
+        db.clone(),
+        admin_user_id.clone(),
+    );
+    let server_acl = ServerAcl::new(config.server_acl.unwrap_or_default());
+    let compliance = Compliance::new(config.compliance.unwrap_or_default());
+    let mute = Mute::new(db.clone(), user_id.clone())?;
+    let repeats = RepeatFilter::new(&modules_config)?;
+    let threads = Threads::new(&modules_config)?;
+    let notices = Notices::new(config.send_notices.unwrap_or(false), &modules_config)?;
+    let activation = Activation::new(&modules_config)?;
+    let streams = Streams::new(db.clone(), &modules_config)?;
+    let mqtt = Mqtt::new(config.mqtt);
+    let email = EmailIngest::new(config.email)?;
+    let smtp = Smtp::new(config.smtp)?;
+    let alerts = Alerts::new(config.alerts, db.clone());
+    let oncall = OnCall::new(db.clone());
+    let sent_messages = SentMessages::new(db.clone());
+    let sent_reactions = SentReactions::new(db.clone());
+    let alertmanager = Alertmanager::new(config.alertmanager)?;
+    let grafana = Grafana::new(config.grafana)?;
+    let self_report = SelfReport::new(config.self_report, redb_path);
+    let latency_slo = LatencySlo::new(config.latency_slo);
+    let temp_access = TempAccess::new(db.clone());
+    let roster = Roster::new(config.roster, admin_user_id.clone())?;
+    let directory = Arc::new(Directory::new(config.directory)?);
+    let archive = Archive::new(config.archive, db.clone());
+    let scheduled_messages = ScheduledMessages::new(db.clone());
+    let polls = Polls::new(db.clone());
+    let auto_reactions = AutoReactions::new(db.clone());
+    let quiet_hours = QuietHours::new(config.quiet_hours.unwrap_or_default(), db.clone())?;
+    let room_modules = RoomModules::new(config.room_modules.unwrap_or_default(), db.clone());
+    let opt_out = OptOut::new(db.clone());
+    let event_export = EventExport::new(config.event_export);
+    let api_keys = ApiKeys::new(db.clone());
+    let reports = Reports::new(
+        config.reports.unwrap_or_default(),
+        db.clone(),
+        admin_user_id.clone(),
+    );
+
     let client_copy = client.clone();
     let app_ctx = tokio::task::spawn_blocking(|| {
         AppCtx::new(
             client_copy,
             config.modules_paths,
             modules_config,
+            config.module_priority.unwrap_or_default(),
+            engine_config,
+            module_cache,
             db,
+            directory,
             config.admin_user_id,
         )
     })
     .await??;
-    let app = App::new(app_ctx);
+    let app = App::new(
+        app_ctx,
+        admin_user_id,
+        devices,
+        invites,
+        gatekeeper,
+        content_filter,
+        slowmode,
+        room_policies,
+        reports,
+        tickets,
+        standups,
+        votes,
+        rsvps,
+        quotes,
+        decoration,
+        crash_reporter,
+        diagnostics,
+        response_limits,
+        emoji,
+        link_hygiene,
+        trust,
+        server_acl,
+        compliance,
+        mute,
+        repeats,
+        streams,
+        mqtt,
+        email,
+        smtp,
+        alerts,
+        oncall,
+        sent_messages,
+        sent_reactions,
+        alertmanager,
+        grafana,
+        self_report,
+        latency_slo,
+        threads,
+        temp_access,
+        roster,
+        archive,
+        scheduled_messages,
+        notices,
+        polls,
+        auto_reactions,
+        crash_loop,
+        activation,
+        quiet_hours,
+        room_modules,
+        opt_out,
+        event_export,
+        api_keys,
+    );
+
+    {
+        let devices = app.devices.clone();
+        let client = client.clone();
+        tokio::spawn(async move { devices.run(client).await });
+    }
+
+    {
+        let server_acl = app.server_acl.clone();
+        let client = client.clone();
+        tokio::spawn(async move { server_acl.run(client).await });
+    }
+
+    {
+        let app = app.clone();
+        let client = client.clone();
+        tokio::spawn(async move { temp_access::run(app, client).await });
+    }
+
+    {
+        let roster = app.roster.clone();
+        let client = client.clone();
+        tokio::spawn(async move { roster.run(client).await });
+    }
+
+    {
+        let archive = app.archive.clone();
+        tokio::spawn(async move { archive.run().await });
+    }
+
+    {
+        let crash_loop = app.crash_loop.clone();
+        let client = client.clone();
+        let admin_user_id = app.admin_user_id.clone();
+        tokio::spawn(async move { crash_loop.run(client, admin_user_id).await });
+    }
+
+    // The tasks posting on their own, or calling the modules, are paused in safe mode.
+    if safe_mode::is_enabled() {
+        info!("safe mode: modules, schedulers and bridges are disabled");
+    } else {
+        {
+            let tickets = app.tickets.clone();
+            let client = client.clone();
+            tokio::spawn(async move { tickets.run(client).await });
+        }
+
+        {
+            let standups = app.standups.clone();
+            let client = client.clone();
+            let maintenance = app.maintenance.clone();
+            tokio::spawn(async move { standups.run(client, &maintenance).await });
+        }
+
+        {
+            let votes = app.votes.clone();
+            let client = client.clone();
+            let maintenance = app.maintenance.clone();
+            tokio::spawn(async move { votes.run(client, &maintenance).await });
+        }
+
+        {
+            let rsvps = app.rsvps.clone();
+            let client = client.clone();
+            let maintenance = app.maintenance.clone();
+            tokio::spawn(async move { rsvps.run(client, &maintenance).await });
+        }
+
+        {
+            let app = app.clone();
+            let client = client.clone();
+            tokio::spawn(async move { timers::run(app, client).await });
+        }
+
+        {
+            let app = app.clone();
+            let client = client.clone();
+            tokio::spawn(async move { cron::run(app, client).await });
+        }
+
+        {
+            let app = app.clone();
+            let client = client.clone();
+            let modules_loaded = APP_CTX_LOCK
+                .lock(&app.inner, "ticks start")
+                .await
+                .modules_loaded
+                .clone();
+            tokio::spawn(async move { ticks::run(app, client, modules_loaded).await });
+        }
+
+        {
+            let app = app.clone();
+            let client = client.clone();
+            tokio::spawn(async move { quiet_hours::run(app, client).await });
+        }
+
+        {
+            let app = app.clone();
+            let client = client.clone();
+            tokio::spawn(async move { streams::run(app, client).await });
+        }
+
+        {
+            let mqtt = app.mqtt.clone();
+            let client = client.clone();
+            tokio::spawn(async move { mqtt.run(client).await });
+        }
+
+        {
+            let email = app.email.clone();
+            let client = client.clone();
+            tokio::spawn(async move { email.run(client).await });
+        }
+
+        {
+            let app = app.clone();
+            let client = client.clone();
+            tokio::spawn(async move { bus::run(app, client).await });
+        }
+
+        {
+            let alerts = app.alerts.clone();
+            let client = client.clone();
+            let maintenance = app.maintenance.clone();
+            tokio::spawn(async move { alerts.run(client, &maintenance).await });
+        }
+
+        {
+            let oncall = app.oncall.clone();
+            let client = client.clone();
+            let maintenance = app.maintenance.clone();
+            tokio::spawn(async move { oncall.run(client, &maintenance).await });
+        }
+
+        {
+            let scheduled_messages = app.scheduled_messages.clone();
+            let client = client.clone();
+            let maintenance = app.maintenance.clone();
+            tokio::spawn(async move { scheduled_messages.run(client, &maintenance).await });
+        }
+
+        {
+            let self_report = app.self_report.clone();
+            let client = client.clone();
+            tokio::spawn(async move { self_report.run(client).await });
+        }
+
+        {
+            let latency_slo = app.latency_slo.clone();
+            let client = client.clone();
+            tokio::spawn(async move { latency_slo.run(client).await });
+        }
+
+        {
+            let event_export = app.event_export.clone();
+            tokio::spawn(async move { event_export.run().await });
+        }
+
+        {
+            let app = app.clone();
+            let client = client.clone();
+            let webhooks = config.webhooks;
+            tokio::spawn(async move {
+                webhooks::run(app, client, webhooks, webhooks_listen).await
+            });
+        }
+    }
+
+    // Also in safe mode, to disable the faulty modules.
+    {
+        let app = app.clone();
+        let client = client.clone();
+        let dashboard = config.dashboard;
+        tokio::spawn(async move {
+            dashboard::run(app, client, dashboard, dashboard_listen).await
+        });
+    }
+
+    {
+        let inner = app.inner.clone();
+        let config_path = config.config_path.clone();
+        tokio::spawn(async move {
+            if let Err(err) = reload_on_sighup(inner, config_path).await {
+                error!("couldn't handle SIGHUP: {err:#}");
+            }
+        });
+    }
 
     let _watcher_guard = watcher(app.inner.clone()).await?;
 
@@ -850,6 +2632,12 @@ pub async fn run(config: BotConfig) -> anyhow::Result<()> {
     client.add_event_handler_context(app);
     client.add_event_handler(on_message);
     client.add_event_handler(on_stripped_state_member);
+    client.add_event_handler(on_room_member);
+    client.add_event_handler(on_state_event);
+    client.add_event_handler(on_reaction);
+    client.add_event_handler(on_redaction);
+    client.add_event_handler(on_poll_response);
+    client.add_event_handler(on_poll_end);
     client.add_event_handler(on_verification_request);
 
     // Note: this method will never return.
@@ -882,12 +2670,12 @@ async fn handle_signals() -> anyhow::Result<()> {
     use signal_hook::consts::signal::*;
     use signal_hook_tokio::*;
 
-    let mut signals = Signals::new([SIGINT, SIGHUP, SIGQUIT, SIGTERM])?;
+    let mut signals = Signals::new([SIGINT, SIGQUIT, SIGTERM])?;
     let handle = signals.handle();
 
     while let Some(signal) = signals.next().await {
         match signal {
-            SIGINT | SIGHUP | SIGQUIT | SIGTERM => {
+            SIGINT | SIGQUIT | SIGTERM => {
                 handle.close();
                 break;
             }
@@ -900,8 +2688,32 @@ async fn handle_signals() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Reads the configuration file again on SIGHUP, and applies the new settings of the wasmtime
+/// engine.
+async fn reload_on_sighup(
+    app: Arc<Mutex<AppCtx>>,
+    config_path: Option<PathBuf>,
+) -> anyhow::Result<()> {
+    let mut signals = signal_hook_tokio::Signals::new([signal_hook::consts::signal::SIGHUP])?;
+    while signals.next().await.is_some() {
+        let Some(path) = &config_path else {
+            warn!("SIGHUP: configured from the environment, there's no file to read again");
+            continue;
+        };
+        info!("SIGHUP: reading {} again...", path.display());
+        match BotConfig::from_config(Some(path.to_string_lossy().into_owned())) {
+            Ok(config) => {
+                let engine_config = config.wasm_engine.unwrap_or_default();
+                AppCtx::reload_engine(app.clone(), engine_config).await;
+            }
+            Err(err) => error!("couldn't read the configuration again: {err:#}"),
+        }
+    }
+    Ok(())
+}
+
 async fn watcher(app: Arc<Mutex<AppCtx>>) -> anyhow::Result<Vec<notify::RecommendedWatcher>> {
-    let modules_paths = { app.lock().await.modules_paths.clone() };
+    let modules_paths = { APP_CTX_LOCK.lock(&app, "watcher setup").await.modules_paths.clone() };
 
     let mut watchers = Vec::with_capacity(modules_paths.len());
     for modules_path in modules_paths {
@@ -0,0 +1,259 @@
+//! Hygiene of the links in the bot's responses: known tracking parameters are stripped from URLs,
+//! and links can be wrapped in code spans so that clients don't preview them.
+
+use std::sync::OnceLock;
+
+use matrix_sdk::ruma::events::room::message::FormattedBody;
+use regex::{Captures, Regex};
+use serde::Deserialize;
+
+use crate::wasm;
+
+/// Query parameters stripped from URLs, in addition to the `utm_*` ones.
+const TRACKING_PARAMS: &[&str] = &[
+    "fbclid", "gclid", "dclid", "gbraid", "wbraid", "msclkid", "yclid", "igshid", "mc_cid",
+    "mc_eid", "mkt_tok", "_hsenc", "_hsmi",
+];
+
+/// Configuration for the links hygiene.
+#[derive(Clone, Debug, Deserialize)]
+pub struct LinkHygieneConfig {
+    /// whether tracking parameters (`utm_*`, `fbclid`, etc.) are stripped from URLs.
+    #[serde(default = "default_strip_tracking")]
+    pub strip_tracking: bool,
+    /// additional query parameters to strip.
+    #[serde(default)]
+    pub extra_params: Vec<String>,
+    /// whether links are wrapped in code spans, so that clients don't preview them.
+    #[serde(default)]
+    pub disable_previews: bool,
+}
+
+impl Default for LinkHygieneConfig {
+    fn default() -> Self {
+        Self {
+            strip_tracking: default_strip_tracking(),
+            extra_params: Vec::new(),
+            disable_previews: false,
+        }
+    }
+}
+
+fn default_strip_tracking() -> bool {
+    true
+}
+
+fn url_regex() -> &'static Regex {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    REGEX.get_or_init(|| Regex::new(r#"https?://[^\s<>"']+"#).expect("valid regex"))
+}
+
+fn anchor_regex() -> &'static Regex {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    REGEX.get_or_init(|| {
+        Regex::new(r#"(?s)<a\s[^>]*href=["']([^"']*)["'][^>]*>(.*?)</a>"#).expect("valid regex")
+    })
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+pub(crate) struct LinkHygiene {
+    config: LinkHygieneConfig,
+}
+
+impl LinkHygiene {
+    pub fn new(config: LinkHygieneConfig) -> Self {
+        Self { config }
+    }
+
+    fn is_tracking_param(&self, key: &str) -> bool {
+        key.starts_with("utm_")
+            || TRACKING_PARAMS.contains(&key)
+            || self.config.extra_params.iter().any(|param| param == key)
+    }
+
+    /// Removes the tracking parameters from a URL, which may be HTML-escaped.
+    fn strip_url(&self, url: &str) -> String {
+        let (url, fragment) = match url.split_once('#') {
+            Some((url, fragment)) => (url, Some(fragment)),
+            None => (url, None),
+        };
+        let Some((base, query)) = url.split_once('?') else {
+            return match fragment {
+                Some(fragment) => format!("{url}#{fragment}"),
+                None => url.to_owned(),
+            };
+        };
+
+        let kept = query
+            .split('&')
+            .filter(|param| {
+                // In HTML, parameters are separated by `&amp;`.
+                let param = param.strip_prefix("amp;").unwrap_or(param);
+                let key = param.split('=').next().unwrap_or(param);
+                !param.is_empty() && !self.is_tracking_param(key)
+            })
+            .map(|param| param.strip_prefix("amp;").unwrap_or(param))
+            .collect::<Vec<_>>();
+
+        let separator = if query.contains("&amp;") {
+            "&amp;"
+        } else {
+            "&"
+        };
+        let mut stripped = base.to_owned();
+        if !kept.is_empty() {
+            stripped.push('?');
+            stripped.push_str(&kept.join(separator));
+        }
+        if let Some(fragment) = fragment {
+            stripped.push('#');
+            stripped.push_str(fragment);
+        }
+        stripped
+    }
+
+    fn strip_tracking(&self, body: &str) -> String {
+        url_regex()
+            .replace_all(body, |caps: &Captures<'_>| self.strip_url(&caps[0]))
+            .into_owned()
+    }
+
+    /// Replaces the links of an HTML body with code spans.
+    fn wrap_links(html: &str) -> String {
+        let html = anchor_regex().replace_all(html, |caps: &Captures<'_>| {
+            let (href, text) = (&caps[1], &caps[2]);
+            if text == href {
+                format!("<code>{href}</code>")
+            } else {
+                format!("{text} (<code>{href}</code>)")
+            }
+        });
+
+        // Then the bare URLs, skipping the ones in attributes or already in code spans.
+        let mut wrapped = String::with_capacity(html.len());
+        let mut last = 0;
+        for url in url_regex().find_iter(&html) {
+            let before = &html[..url.start()];
+            wrapped.push_str(&html[last..url.start()]);
+            if before.ends_with(['"', '\'']) || before.ends_with("<code>") {
+                wrapped.push_str(url.as_str());
+            } else {
+                wrapped.push_str(&format!("<code>{}</code>", url.as_str()));
+            }
+            last = url.end();
+        }
+        wrapped.push_str(&html[last..]);
+        wrapped
+    }
+
+    /// Cleans up the links of a message.
+    pub fn apply(&self, msg: &mut wasm::Message) {
+        if !url_regex().is_match(&msg.text)
+            && !msg
+                .html
+                .as_ref()
+                .map_or(false, |html| url_regex().is_match(html))
+        {
+            return;
+        }
+
+        if self.config.strip_tracking {
+            msg.text = self.strip_tracking(&msg.text);
+            if let Some(html) = &msg.html {
+                msg.html = Some(self.strip_tracking(html));
+            }
+        }
+
+        if self.config.disable_previews {
+            let html = msg.html.take().unwrap_or_else(|| {
+                FormattedBody::markdown(&msg.text)
+                    .map(|formatted| formatted.body)
+                    .unwrap_or_else(|| escape_html(&msg.text).replace('\n', "<br>"))
+            });
+            msg.html = Some(Self::wrap_links(&html));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hygiene(extra_params: &[&str]) -> LinkHygiene {
+        LinkHygiene::new(LinkHygieneConfig {
+            extra_params: extra_params.iter().map(|param| param.to_string()).collect(),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn strip_tracking_params() {
+        let hygiene = hygiene(&["ref"]);
+        assert_eq!(
+            hygiene.strip_url("https://example.com/?utm_source=x&id=1&fbclid=y"),
+            "https://example.com/?id=1"
+        );
+        assert_eq!(
+            hygiene.strip_url("https://example.com/a?gclid=1#top"),
+            "https://example.com/a#top"
+        );
+        assert_eq!(
+            hygiene.strip_url("https://example.com/a?ref=x"),
+            "https://example.com/a"
+        );
+        assert_eq!(
+            hygiene.strip_url("https://example.com/a?id=1#b?utm_source=x"),
+            "https://example.com/a?id=1#b?utm_source=x"
+        );
+    }
+
+    #[test]
+    fn strip_escaped_urls() {
+        assert_eq!(
+            hygiene(&[]).strip_url("https://example.com/?id=1&amp;utm_medium=x&amp;page=2"),
+            "https://example.com/?id=1&amp;page=2"
+        );
+    }
+
+    #[test]
+    fn keep_clean_urls() {
+        let hygiene = hygiene(&[]);
+        for url in [
+            "https://example.com",
+            "https://example.com/?id=1&page=2",
+            "https://example.com/#utm_source",
+        ] {
+            assert_eq!(hygiene.strip_url(url), url);
+        }
+        assert_eq!(hygiene.strip_tracking(""), "");
+        assert_eq!(
+            hygiene.strip_tracking("see https://example.com/?utm_campaign=x now"),
+            "see https://example.com/ now"
+        );
+    }
+
+    #[test]
+    fn wrap_links() {
+        assert_eq!(
+            LinkHygiene::wrap_links(r#"<a href="https://example.com">site</a>"#),
+            "site (<code>https://example.com</code>)"
+        );
+        assert_eq!(
+            LinkHygiene::wrap_links(r#"<a href="https://example.com">https://example.com</a>"#),
+            "<code>https://example.com</code>"
+        );
+        assert_eq!(
+            LinkHygiene::wrap_links("go to https://example.com<br>now"),
+            "go to <code>https://example.com</code><br>now"
+        );
+        assert_eq!(
+            LinkHygiene::wrap_links(r#"<img src="https://example.com/a.png">"#),
+            r#"<img src="https://example.com/a.png">"#
+        );
+    }
+}
@@ -0,0 +1,382 @@
+//! Bind configuration shared by every TCP listener the bot runs (SSO callback, webhooks, ...),
+//! and the serving loop of the HTTP ones, with their security controls: an allowlist of the
+//! clients' addresses, TLS, and request logging.
+
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    fs::File,
+    future::Future,
+    io::BufReader,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock},
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::Context as _;
+use hyper::{server::conn::Http, service::service_fn, Body, Request, Response};
+use serde::Deserialize;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::{rustls, TlsAcceptor};
+use tokio_util::either::Either;
+use tracing::{debug, info, warn};
+
+/// Where and how a given listener should accept connections.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ListenConfig {
+    /// Addresses to bind to. IPv6 addresses are accepted, e.g. `[::1]:43210`.
+    ///
+    /// Several addresses may be given to listen on several interfaces/families at once.
+    #[serde(default)]
+    pub addresses: Vec<SocketAddr>,
+
+    /// Name of the systemd socket (`FileDescriptorName=`) to take listening sockets from, when
+    /// the bot is started through systemd socket activation.
+    ///
+    /// Takes precedence over `addresses` when sockets with this name have been passed to us.
+    pub systemd_name: Option<String>,
+
+    /// Addresses or CIDR ranges of the clients allowed to connect, e.g. `10.0.0.0/8`; everyone
+    /// if empty. Only applies to the HTTP listeners served by the bot, not the SSO callback.
+    #[serde(default)]
+    pub allow: Vec<String>,
+
+    /// Serve HTTPS rather than HTTP, with this certificate and key.
+    pub tls: Option<TlsConfig>,
+
+    /// Log every request, with its client, status and duration.
+    #[serde(default)]
+    pub log_requests: bool,
+}
+
+/// TLS configuration of a listener.
+#[derive(Clone, Debug, Deserialize)]
+pub struct TlsConfig {
+    /// PEM file with the certificate chain, e.g. the `fullchain.pem` of an ACME client.
+    pub cert_path: PathBuf,
+    /// PEM file with the private key, in PKCS#8, PKCS#1 (RSA) or SEC1 (EC) format.
+    pub key_path: PathBuf,
+}
+
+/// How long a client has to complete the TLS handshake.
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Sockets passed by systemd, indexed by their file descriptor name. Each socket can only be
+/// taken once.
+#[cfg(unix)]
+static SYSTEMD_SOCKETS: OnceLock<Mutex<HashMap<String, Vec<std::net::TcpListener>>>> =
+    OnceLock::new();
+
+/// Reads the sockets passed through the `LISTEN_PID`/`LISTEN_FDS`/`LISTEN_FDNAMES` protocol.
+///
+/// See sd_listen_fds(3) for details.
+#[cfg(unix)]
+fn read_systemd_sockets() -> HashMap<String, Vec<std::net::TcpListener>> {
+    use std::os::fd::FromRawFd as _;
+
+    /// First file descriptor passed by systemd.
+    const SD_LISTEN_FDS_START: i32 = 3;
+
+    let mut sockets: HashMap<String, Vec<std::net::TcpListener>> = HashMap::new();
+
+    let for_us = std::env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        .map_or(false, |pid| pid == std::process::id());
+    if !for_us {
+        return sockets;
+    }
+
+    let num_fds = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|n| n.parse::<i32>().ok())
+        .unwrap_or(0);
+    let names = std::env::var("LISTEN_FDNAMES").unwrap_or_default();
+    let mut names = names.split(':');
+
+    for fd in SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + num_fds {
+        let name = names.next().unwrap_or("unknown").to_owned();
+        // SAFETY: systemd guarantees these file descriptors are open and handed to this process,
+        // and we only ever wrap each of them once.
+        let listener = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+        debug!("received systemd socket {name} (fd {fd})");
+        sockets.entry(name).or_default().push(listener);
+    }
+
+    // The variables are left in the environment: changing it while the runtime's threads may be
+    // reading it is unsound, and `LISTEN_PID` already tells child processes the sockets aren't
+    // theirs.
+    sockets
+}
+
+/// Takes the systemd sockets with the given name, if there are any.
+#[cfg(unix)]
+fn take_systemd_sockets(name: &str) -> anyhow::Result<Vec<TcpListener>> {
+    let sockets = SYSTEMD_SOCKETS.get_or_init(|| Mutex::new(read_systemd_sockets()));
+    let taken = sockets
+        .lock()
+        .map_err(|_| anyhow::anyhow!("systemd sockets lock poisoned"))?
+        .remove(name)
+        .unwrap_or_default();
+    taken
+        .into_iter()
+        .map(|listener| {
+            listener.set_nonblocking(true)?;
+            Ok(TcpListener::from_std(listener)?)
+        })
+        .collect()
+}
+
+#[cfg(not(unix))]
+fn take_systemd_sockets(_name: &str) -> anyhow::Result<Vec<TcpListener>> {
+    Ok(Vec::new())
+}
+
+impl ListenConfig {
+    /// Binds all the configured addresses, or the `default` one if none has been configured.
+    pub async fn bind(&self, default: SocketAddr) -> anyhow::Result<Vec<TcpListener>> {
+        if let Some(name) = &self.systemd_name {
+            let listeners = take_systemd_sockets(name)?;
+            if !listeners.is_empty() {
+                return Ok(listeners);
+            }
+            debug!("no systemd socket named {name}, binding configured addresses instead");
+        }
+
+        let addresses = if self.addresses.is_empty() {
+            vec![default]
+        } else {
+            self.addresses.clone()
+        };
+
+        let mut listeners = Vec::with_capacity(addresses.len());
+        for addr in addresses {
+            listeners.push(TcpListener::bind(addr).await?);
+        }
+        anyhow::ensure!(!listeners.is_empty(), "no address to listen on");
+        Ok(listeners)
+    }
+}
+
+/// Accepts the next connection coming on any of the given listeners.
+pub async fn accept(listeners: &[TcpListener]) -> std::io::Result<(TcpStream, SocketAddr)> {
+    if listeners.is_empty() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "no listener to accept connections on",
+        ));
+    }
+    let accepts = listeners.iter().map(|l| Box::pin(l.accept()));
+    let (res, _index, _rest) = futures::future::select_all(accepts).await;
+    res
+}
+
+/// A range of IP addresses, e.g. `192.168.0.0/16`.
+#[derive(Debug)]
+struct Cidr {
+    addr: IpAddr,
+    prefix: u32,
+}
+
+impl Cidr {
+    fn parse(text: &str) -> anyhow::Result<Self> {
+        let (addr, prefix) = match text.split_once('/') {
+            Some((addr, prefix)) => (addr.parse::<IpAddr>()?, Some(prefix.parse::<u32>()?)),
+            None => (text.parse::<IpAddr>()?, None),
+        };
+        let bits = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(bits);
+        anyhow::ensure!(prefix <= bits, "prefix longer than the address");
+        Ok(Self { addr, prefix })
+    }
+
+    fn contains(&self, addr: IpAddr) -> bool {
+        // Clients connecting over IPv4 to a socket bound to `[::]` show up as mapped addresses.
+        let addr = match addr {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(addr, IpAddr::V4),
+            IpAddr::V4(_) => addr,
+        };
+        match (self.addr, addr) {
+            (IpAddr::V4(net), IpAddr::V4(addr)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix).unwrap_or(0);
+                u32::from(net) & mask == u32::from(addr) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(addr)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix).unwrap_or(0);
+                u128::from(net) & mask == u128::from(addr) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The TLS acceptor of a listener, reloaded when its certificate or key files change, e.g. when
+/// an ACME client renews them.
+struct Tls {
+    config: TlsConfig,
+    current: Mutex<(Option<SystemTime>, TlsAcceptor)>,
+}
+
+fn modified(config: &TlsConfig) -> Option<SystemTime> {
+    let cert = std::fs::metadata(&config.cert_path).and_then(|m| m.modified());
+    let key = std::fs::metadata(&config.key_path).and_then(|m| m.modified());
+    Some(cert.ok()?.max(key.ok()?))
+}
+
+fn load_tls(config: &TlsConfig) -> anyhow::Result<TlsAcceptor> {
+    let read = |path: &Path| {
+        let file = File::open(path).with_context(|| format!("couldn't open {}", path.display()))?;
+        rustls_pemfile::read_all(&mut BufReader::new(file))
+            .with_context(|| format!("couldn't read {}", path.display()))
+    };
+    let certs = read(&config.cert_path)?
+        .into_iter()
+        .filter_map(|item| match item {
+            rustls_pemfile::Item::X509Certificate(cert) => Some(rustls::Certificate(cert)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    anyhow::ensure!(
+        !certs.is_empty(),
+        "no certificate in {}",
+        config.cert_path.display()
+    );
+    let key = read(&config.key_path)?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(rustls::PrivateKey(key)),
+            _ => None,
+        })
+        .with_context(|| format!("no private key in {}", config.key_path.display()))?;
+    let server_config = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+impl Tls {
+    fn new(config: TlsConfig) -> anyhow::Result<Self> {
+        let acceptor = load_tls(&config)?;
+        Ok(Self {
+            current: Mutex::new((modified(&config), acceptor)),
+            config,
+        })
+    }
+
+    /// The acceptor for a new connection, with the certificate reloaded if it changed.
+    fn acceptor(&self) -> TlsAcceptor {
+        let mut current = self.current.lock().unwrap();
+        let modified = modified(&self.config);
+        if modified != current.0 {
+            match load_tls(&self.config) {
+                Ok(acceptor) => {
+                    info!(
+                        "reloaded the TLS certificate {}",
+                        self.config.cert_path.display()
+                    );
+                    *current = (modified, acceptor);
+                }
+                Err(err) => {
+                    // Keep the previous certificate; the new one may be half-written.
+                    warn!("couldn't reload the TLS certificate: {err:#}");
+                }
+            }
+        }
+        current.1.clone()
+    }
+}
+
+impl ListenConfig {
+    /// Serves HTTP on the configured addresses, or the `default` one, with `handle`; `name`
+    /// identifies the listener in the logs.
+    pub(crate) async fn serve<H, F>(
+        &self,
+        name: &'static str,
+        default: SocketAddr,
+        handle: H,
+    ) -> anyhow::Result<()>
+    where
+        H: Fn(Request<Body>) -> F + Clone + Send + Sync + 'static,
+        F: Future<Output = Response<Body>> + Send + 'static,
+    {
+        let allow = self
+            .allow
+            .iter()
+            .map(|cidr| {
+                Cidr::parse(cidr).with_context(|| format!("invalid allowed address {cidr}"))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let tls = self.tls.clone().map(Tls::new).transpose()?.map(Arc::new);
+        let listeners = self.bind(default).await?;
+        let scheme = if tls.is_some() { "https" } else { "http" };
+        for l in &listeners {
+            if let Ok(addr) = l.local_addr() {
+                info!("serving {name} on {scheme}://{addr}/");
+            }
+        }
+
+        loop {
+            let (stream, peer) = match accept(&listeners).await {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    warn!("couldn't accept a {name} connection: {err}");
+                    continue;
+                }
+            };
+            if !allow.is_empty() && !allow.iter().any(|cidr| cidr.contains(peer.ip())) {
+                debug!("refused a {name} connection from {peer}, not in the allowlist");
+                continue;
+            }
+
+            let (handle, tls, log_requests) = (handle.clone(), tls.clone(), self.log_requests);
+            tokio::spawn(async move {
+                let stream = match tls {
+                    Some(tls) => {
+                        let handshake = tls.acceptor().accept(stream);
+                        match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, handshake).await {
+                            Ok(Ok(stream)) => Either::Right(stream),
+                            Ok(Err(err)) => {
+                                debug!("TLS handshake with {peer} failed: {err}");
+                                return;
+                            }
+                            Err(_) => {
+                                debug!("TLS handshake with {peer} timed out");
+                                return;
+                            }
+                        }
+                    }
+                    None => Either::Left(stream),
+                };
+
+                let service = service_fn(move |req: Request<Body>| {
+                    let (method, path) = (req.method().clone(), req.uri().path().to_owned());
+                    let started = Instant::now();
+                    let response = handle(req);
+                    async move {
+                        let response = response.await;
+                        if log_requests {
+                            info!(
+                                "{name}: {peer} {method} {path} {} in {}ms",
+                                response.status().as_u16(),
+                                started.elapsed().as_millis()
+                            );
+                        }
+                        Ok::<_, Infallible>(response)
+                    }
+                });
+                if let Err(err) = Http::new()
+                    .http1_only(true)
+                    .serve_connection(stream, service)
+                    .with_upgrades()
+                    .await
+                {
+                    debug!("{name} connection from {peer} failed: {err}");
+                }
+            });
+        }
+    }
+}
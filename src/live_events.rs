@@ -0,0 +1,109 @@
+//! Live stream of the bot's activity for dashboards: the `/ws` endpoint of the webhooks listener
+//! upgrades to a WebSocket, over which every event (messages passed on to the modules, actions
+//! they took, and calls to them, with their latency and errors) is sent as a JSON text message.
+//!
+//! The stream is read-only, and authenticated with the webhooks token or an API key with the
+//! `metrics` scope, like the webhooks.
+
+use futures::{SinkExt as _, StreamExt as _};
+use hyper::{
+    header::{self, HeaderValue},
+    upgrade::Upgraded,
+    Body, Request, Response, StatusCode,
+};
+use tokio::sync::broadcast::error::RecvError;
+use tokio_tungstenite::{
+    tungstenite::{handshake::derive_accept_key, protocol::Role, Message},
+    WebSocketStream,
+};
+use tracing::{debug, warn};
+
+use crate::{event_export, webhooks::respond, App};
+
+/// Whether the header contains the token, case-insensitively, in a comma-separated list.
+fn header_contains(req: &Request<Body>, name: header::HeaderName, token: &str) -> bool {
+    req.headers()
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|value| value.trim().eq_ignore_ascii_case(token))
+}
+
+/// Upgrades the request to a WebSocket streaming the events; the request must be authorized.
+pub(crate) fn upgrade(app: &App, mut req: Request<Body>) -> Response<Body> {
+    if !header_contains(&req, header::CONNECTION, "upgrade")
+        || !header_contains(&req, header::UPGRADE, "websocket")
+    {
+        return respond(StatusCode::BAD_REQUEST, "expected a WebSocket upgrade");
+    }
+    let Some(key) = req.headers().get(header::SEC_WEBSOCKET_KEY) else {
+        return respond(StatusCode::BAD_REQUEST, "missing Sec-WebSocket-Key");
+    };
+    let accept = derive_accept_key(key.as_bytes());
+
+    let app = app.clone();
+    let upgrade = hyper::upgrade::on(&mut req);
+    tokio::spawn(async move {
+        match upgrade.await {
+            Ok(upgraded) => {
+                let socket = WebSocketStream::from_raw_socket(upgraded, Role::Server, None).await;
+                stream(&app, socket).await;
+            }
+            Err(err) => warn!("couldn't upgrade to a WebSocket: {err}"),
+        }
+    });
+
+    let mut response = respond(StatusCode::SWITCHING_PROTOCOLS, "");
+    let headers = response.headers_mut();
+    headers.insert(header::CONNECTION, HeaderValue::from_static("Upgrade"));
+    headers.insert(header::UPGRADE, HeaderValue::from_static("websocket"));
+    match HeaderValue::from_str(&accept) {
+        Ok(accept) => {
+            headers.insert(header::SEC_WEBSOCKET_ACCEPT, accept);
+        }
+        Err(err) => return respond(StatusCode::INTERNAL_SERVER_ERROR, &format!("{err}")),
+    }
+    response
+}
+
+/// Sends the events over the WebSocket, until the client leaves.
+async fn stream(app: &App, mut socket: WebSocketStream<Upgraded>) {
+    debug!("a dashboard connected to the live stream");
+    let mut events = app.event_export.subscribe();
+    loop {
+        tokio::select! {
+            event = events.recv() => {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(count)) => {
+                        debug!("a dashboard can't keep up, dropped {count} events");
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                let json = match event_export::to_json(&event) {
+                    Ok(json) => json,
+                    Err(err) => {
+                        warn!("couldn't serialize a live event: {err}");
+                        continue;
+                    }
+                };
+                if let Err(err) = socket.send(Message::Text(json)).await {
+                    debug!("a dashboard went away: {err}");
+                    break;
+                }
+            }
+            // Reading answers the pings; the clients have nothing to say otherwise.
+            message = socket.next() => match message {
+                Some(Ok(Message::Close(_))) | None => break,
+                Some(Ok(_)) => {}
+                Some(Err(err)) => {
+                    debug!("a dashboard went away: {err}");
+                    break;
+                }
+            },
+        }
+    }
+    debug!("a dashboard disconnected from the live stream");
+}
@@ -0,0 +1,54 @@
+//! Maintenance mode: `!admin host maintenance on` suspends the modules, the timers and the
+//! recurring jobs while the bot keeps syncing, so that modules or the database can be worked on in
+//! a live deployment. `!admin host maintenance off` resumes them.
+//!
+//! Commands that would have gone to the modules are acknowledged with "in maintenance" meanwhile.
+//! Timers that fire during maintenance are delayed until it ends, and recurring jobs are skipped.
+//! The host's own schedulers (votes, events, alerts, on-call, scheduled messages) wait for it to
+//! end too, and stand-ups are skipped.
+//! Maintenance isn't persisted: a restart ends it.
+
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use tracing::info;
+
+#[derive(Default)]
+pub(crate) struct Maintenance {
+    /// When maintenance started, if it's on.
+    since: Mutex<Option<DateTime<Utc>>>,
+}
+
+impl Maintenance {
+    pub fn is_on(&self) -> bool {
+        self.since.lock().unwrap().is_some()
+    }
+
+    /// Try to handle a message assuming it's an `!admin host maintenance [on|off]` command.
+    pub fn try_handle_admin(&self, content: &str) -> Option<String> {
+        let rest = content.strip_prefix("!admin host maintenance")?;
+        if !rest.is_empty() && !rest.starts_with(' ') {
+            return None;
+        }
+
+        let mut since = self.since.lock().unwrap();
+        Some(match (rest.trim(), *since) {
+            ("", Some(time)) | ("on", Some(time)) => format!(
+                "in maintenance since {}",
+                time.format("%Y-%m-%d %H:%M:%S UTC")
+            ),
+            ("", None) | ("off", None) => "not in maintenance".to_owned(),
+            ("on", None) => {
+                info!("entering maintenance");
+                *since = Some(Utc::now());
+                "in maintenance: modules, timers and recurring jobs are suspended".to_owned()
+            }
+            ("off", Some(_)) => {
+                info!("leaving maintenance");
+                *since = None;
+                "maintenance over: modules, timers and recurring jobs are resumed".to_owned()
+            }
+            _ => "usage: !admin host maintenance [on|off]".to_owned(),
+        })
+    }
+}
@@ -0,0 +1,194 @@
+//! MeetBot-style meeting minutes: between `!meeting start` and `!meeting stop`, the messages of the
+//! room are recorded, then a summary of the `#info`, `#action` and `#agreed` items is posted along
+//! with the full log.
+
+use std::{collections::HashMap, sync::Mutex};
+
+use chrono::{DateTime, Utc};
+use matrix_sdk::{
+    attachment::AttachmentConfig,
+    room::Room,
+    ruma::{events::room::message::RoomMessageEventContent, OwnedRoomId, OwnedUserId, UserId},
+};
+
+use crate::{html_text::escape_html, outbox, utils::is_moderator};
+
+struct Meeting {
+    title: String,
+    chair: OwnedUserId,
+    started: DateTime<Utc>,
+    log: Vec<(DateTime<Utc>, OwnedUserId, String)>,
+    infos: Vec<(OwnedUserId, String)>,
+    actions: Vec<(OwnedUserId, String)>,
+    agreed: Vec<(OwnedUserId, String)>,
+}
+
+impl Meeting {
+    fn record(&mut self, sender: &UserId, content: &str) {
+        self.log.push((Utc::now(), sender.to_owned(), content.to_owned()));
+
+        let items = if let Some(text) = content.strip_prefix("#info ") {
+            Some((&mut self.infos, text))
+        } else if let Some(text) = content.strip_prefix("#action ") {
+            Some((&mut self.actions, text))
+        } else {
+            content
+                .strip_prefix("#agreed ")
+                .map(|text| (&mut self.agreed, text))
+        };
+        if let Some((items, text)) = items {
+            items.push((sender.to_owned(), text.trim().to_owned()));
+        }
+    }
+
+    fn summary(&self) -> (String, String) {
+        let mut text = format!("Meeting summary: {}", self.title);
+        let mut html = format!("<h3>Meeting summary: {}</h3>", escape_html(&self.title));
+        for (name, items) in [
+            ("Info", &self.infos),
+            ("Action items", &self.actions),
+            ("Agreed", &self.agreed),
+        ] {
+            if items.is_empty() {
+                continue;
+            }
+            text.push_str(&format!("\n{name}:"));
+            html.push_str(&format!("<p>{name}:</p><ul>"));
+            for (author, item) in items {
+                text.push_str(&format!("\n- {item} ({author})"));
+                let (item, author) = (escape_html(item), escape_html(author.as_str()));
+                html.push_str(&format!("<li>{item} ({author})</li>"));
+            }
+            html.push_str("</ul>");
+        }
+        (text, html)
+    }
+
+    fn full_log(&self) -> String {
+        let mut log = format!(
+            "Meeting: {}\nChair: {}\nStarted: {}\n\n",
+            self.title,
+            self.chair,
+            self.started.to_rfc3339()
+        );
+        for (time, sender, content) in &self.log {
+            log.push_str(&format!("[{}] <{sender}> {content}\n", time.format("%H:%M:%S")));
+        }
+        log
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Meetings {
+    running: Mutex<HashMap<OwnedRoomId, Meeting>>,
+}
+
+impl Meetings {
+    /// Records the message, if a meeting is running in this room.
+    pub fn on_message(&self, room: &Room, sender: &UserId, content: &str) {
+        if let Some(meeting) = self.running.lock().unwrap().get_mut(room.room_id()) {
+            meeting.record(sender, content);
+        }
+    }
+
+    async fn stop(&self, room: &Room, sender: &UserId) -> anyhow::Result<()> {
+        let is_chair = self
+            .running
+            .lock()
+            .unwrap()
+            .get(room.room_id())
+            .map(|meeting| &*meeting.chair == sender);
+        let allowed = match is_chair {
+            None => {
+                let text = "no meeting is running in this room";
+                outbox::send(room, RoomMessageEventContent::text_plain(text)).await?;
+                return Ok(());
+            }
+            Some(is_chair) => is_chair || is_moderator(room, sender).await?,
+        };
+        if !allowed {
+            let text = "only the chair or a moderator can stop the meeting";
+            outbox::send(room, RoomMessageEventContent::text_plain(text)).await?;
+            return Ok(());
+        }
+
+        let Some(meeting) = self.running.lock().unwrap().remove(room.room_id()) else {
+            return Ok(());
+        };
+
+        let (text, html) = meeting.summary();
+        outbox::send(room, RoomMessageEventContent::text_html(text, html)).await?;
+
+        let filename = format!("meeting-{}.log", meeting.started.format("%Y-%m-%d-%H%M"));
+        outbox::send_attachment(
+            room,
+            &filename,
+            &mime::TEXT_PLAIN_UTF_8,
+            meeting.full_log().into_bytes(),
+            AttachmentConfig::new(),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Try to handle a message assuming it's a `!meeting` command.
+    ///
+    /// Returns whether the message was such a command, in which case it mustn't be handled any
+    /// further.
+    pub async fn try_handle(
+        &self,
+        room: &Room,
+        sender: &UserId,
+        content: &str,
+    ) -> anyhow::Result<bool> {
+        let Some(rest) = content.strip_prefix("!meeting") else {
+            return Ok(false);
+        };
+        if !rest.is_empty() && !rest.starts_with(' ') {
+            return Ok(false);
+        }
+
+        let rest = rest.trim();
+        let (cmd, arg) = rest.split_once(' ').unwrap_or((rest, ""));
+        match cmd {
+            "start" => {
+                let text = {
+                    let mut running = self.running.lock().unwrap();
+                    if running.contains_key(room.room_id()) {
+                        "a meeting is already running in this room".to_owned()
+                    } else {
+                        let title = if arg.trim().is_empty() {
+                            "untitled meeting".to_owned()
+                        } else {
+                            arg.trim().to_owned()
+                        };
+                        let text = format!(
+                            "Meeting started: {title}. Chair: {sender}. Use #info, #action and \
+                            #agreed to add items to the minutes, and !meeting stop to end it."
+                        );
+                        running.insert(
+                            room.room_id().to_owned(),
+                            Meeting {
+                                title,
+                                chair: sender.to_owned(),
+                                started: Utc::now(),
+                                log: Vec::new(),
+                                infos: Vec::new(),
+                                actions: Vec::new(),
+                                agreed: Vec::new(),
+                            },
+                        );
+                        text
+                    }
+                };
+                outbox::send(room, RoomMessageEventContent::text_plain(text)).await?;
+            }
+            "stop" => self.stop(room, sender).await?,
+            _ => {
+                let text = "usage: !meeting (start [TITLE]|stop)";
+                outbox::send(room, RoomMessageEventContent::text_plain(text)).await?;
+            }
+        }
+        Ok(true)
+    }
+}
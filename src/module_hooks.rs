@@ -0,0 +1,238 @@
+//! Events other than text messages, passed on to the modules: the users' reactions, to the
+//! `on-reaction` export, for reaction-driven workflows, e.g. approving a request by reacting ✅ to
+//! it, or counting the attendees of an event; and the users joining and leaving the rooms, to the
+//! `on-join` and `on-leave` exports, for greeters, membership logs or auto-roles; and the state
+//! events of the types the modules declared an interest in, e.g. topic or power level changes, to
+//! the `on-state-event` export, as JSON; and the media messages, to the `on-media` export, for
+//! OCR, virus scanning or archival modules, which download the content with the `media` API.
+//!
+//! The modules see the events of the users they'd see the messages of: the same group
+//! restrictions, opt-outs, per-room toggles and activation windows apply. All the modules get each
+//! event, in the background, after the host's own features (votes, previews, gatekeeper...)
+//! handled it.
+
+use matrix_sdk::{
+    room::Room,
+    ruma::{
+        events::{
+            room::{
+                member::{MembershipChange, OriginalSyncRoomMemberEvent},
+                message::OriginalSyncRoomMessageEvent,
+            },
+            AnySyncStateEvent,
+        },
+        serde::Raw,
+        EventId, OwnedEventId, OwnedUserId, UserId,
+    },
+};
+use serde::Deserialize;
+use tracing::{error, trace, warn};
+
+use crate::{
+    diagnostics::APP_CTX_LOCK,
+    handle_module_actions, opt_out,
+    wasm::{self, Module, WasmStore},
+    App,
+};
+
+/// Calls the modules back with the reaction, in the background, and handles their responses.
+pub(crate) fn on_reaction(app: &App, room: &Room, sender: &UserId, event_id: &EventId, key: &str) {
+    let (event_id, key) = (event_id.to_owned(), key.to_owned());
+    let room_id = room.room_id().to_owned();
+    let author = sender.to_owned();
+    dispatch(
+        app,
+        room,
+        sender,
+        Some(event_id.clone()),
+        "reaction",
+        move |module, store| module.on_reaction(store, &event_id, &key, &author, &room_id),
+    );
+}
+
+/// Calls the modules back with the user joining or leaving the room, if that's what the event
+/// is, in the background, and handles their responses.
+pub(crate) fn on_member(app: &App, room: &Room, ev: &OriginalSyncRoomMemberEvent) {
+    let member = ev.state_key.clone();
+    if room.client().user_id() == Some(&*member) {
+        return;
+    }
+    let room_id = room.room_id().to_owned();
+    let event_id = Some(ev.event_id.clone());
+    match ev.membership_change() {
+        MembershipChange::Joined => {
+            let display_name = ev.content.displayname.clone();
+            dispatch(
+                app,
+                room,
+                &ev.state_key,
+                event_id,
+                "join",
+                move |module, store| {
+                    module.on_join(store, &member, display_name.as_deref(), &room_id)
+                },
+            );
+        }
+        MembershipChange::Left
+        | MembershipChange::Kicked
+        | MembershipChange::Banned
+        | MembershipChange::KickedAndBanned => {
+            dispatch(
+                app,
+                room,
+                &ev.state_key,
+                event_id,
+                "leave",
+                move |module, store| module.on_leave(store, &member, &room_id),
+            );
+        }
+        _ => {}
+    }
+}
+
+/// Calls the modules back with the message, if it's a media message, in the background, and
+/// handles their responses; returns whether it was one.
+pub(crate) async fn on_media(app: &App, room: &Room, ev: &OriginalSyncRoomMessageEvent) -> bool {
+    let Some((_, media)) = wasm::describe_media(&ev.event_id, &ev.content.msgtype) else {
+        return false;
+    };
+    let trust = app.trust.level(room, &ev.sender).await;
+    let room_id = room.room_id().to_owned();
+    let sender = ev.sender.clone();
+    dispatch(
+        app,
+        room,
+        &ev.sender,
+        Some(ev.event_id.clone()),
+        "media message",
+        move |module, store| module.on_media(store, &media, &sender, &room_id, trust),
+    );
+    true
+}
+
+/// The parts of a state event passed on to the modules.
+#[derive(Deserialize)]
+struct StateEvent {
+    #[serde(rename = "type")]
+    event_type: String,
+    state_key: String,
+    sender: OwnedUserId,
+    event_id: OwnedEventId,
+    content: serde_json::Value,
+}
+
+/// Calls the modules interested in the type of the state event back with it, in the background,
+/// and handles their responses.
+pub(crate) fn on_state_event(app: &App, room: &Room, ev: &Raw<AnySyncStateEvent>) {
+    let ev = match ev.deserialize_as::<StateEvent>() {
+        Ok(ev) => ev,
+        Err(err) => {
+            warn!("couldn't parse a state event: {err}");
+            return;
+        }
+    };
+    if room.client().user_id() == Some(&*ev.sender) || app.server_acl.is_blocked(&ev.sender) {
+        return;
+    }
+    let content = ev.content.to_string();
+    let room_id = room.room_id().to_owned();
+    let sender = ev.sender.clone();
+    dispatch(
+        app,
+        room,
+        &ev.sender,
+        Some(ev.event_id),
+        "state event",
+        move |module, store| {
+            if !module.wants_state_event(&ev.event_type) {
+                return Ok(Vec::new());
+            }
+            module.on_state_event(
+                store,
+                &ev.event_type,
+                &ev.state_key,
+                &sender,
+                &room_id,
+                &content,
+            )
+        },
+    );
+}
+
+/// Calls `call` for each module that sees the events of `user` in the room, in the background,
+/// and handles their responses; `what` describes the event in the logs.
+fn dispatch<F>(
+    app: &App,
+    room: &Room,
+    user: &UserId,
+    event_id: Option<OwnedEventId>,
+    what: &'static str,
+    call: F,
+) where
+    F: Fn(&Module, &mut WasmStore) -> anyhow::Result<Vec<wasm::Action>> + Send + 'static,
+{
+    if app.maintenance.is_on() {
+        trace!("in maintenance, not passing a {what} to the modules");
+        return;
+    }
+    let opted_out = app.opt_out.is_opted_out(user, room.room_id());
+
+    let app = app.clone();
+    let room = room.clone();
+    let user = user.to_owned();
+    tokio::spawn(async move {
+        let inner = app.inner.clone();
+        let crash_reporter = app.crash_reporter.clone();
+        let response_limits = app.response_limits.clone();
+        let room_modules = app.room_modules.clone();
+        let activation = app.activation.clone();
+        let room_id = room.room_id().to_owned();
+        let responses = tokio::task::spawn_blocking(move || {
+            let ctx = &mut *futures::executor::block_on(APP_CTX_LOCK.lock(&inner, what));
+            let (store, modules) = ctx.modules.iter();
+            let mut responses = Vec::new();
+            for module in modules {
+                let config = ctx.modules_config.get(module.name());
+                if !ctx.directory.allows(config, &user)
+                    || (opted_out && !opt_out::is_moderation(config))
+                    || !room_modules.is_enabled(&room_id, module.name())
+                    || !activation.is_active(module.name(), &room_id)
+                {
+                    continue;
+                }
+                match call(module, &mut *store) {
+                    Ok(actions) if actions.is_empty() => {}
+                    Ok(actions) => responses.push((
+                        module.name().to_owned(),
+                        response_limits.apply(module.name(), actions),
+                    )),
+                    Err(err) => {
+                        warn!("wasm module {} ran into an error: {err}", module.name());
+                        module.record_error(&err);
+                        crash_reporter.module_error(
+                            module.name(),
+                            Some(&room_id),
+                            event_id.as_deref(),
+                            &err,
+                        );
+                    }
+                }
+            }
+            responses
+        })
+        .await;
+
+        let responses = match responses {
+            Ok(responses) => responses,
+            Err(err) => {
+                error!("delivering a {what} failed: {err}");
+                return;
+            }
+        };
+        for (module, actions) in responses {
+            if let Err(err) = handle_module_actions(&app, &room, &module, actions).await {
+                warn!("couldn't handle the actions of {module} for a {what}: {err:#}");
+            }
+        }
+    });
+}
@@ -0,0 +1,98 @@
+//! `!admin host trace MODULE on [DURATION]`: logs every host call of a module, with its arguments
+//! and results, at debug level, for a while, to see what it does in production without reloading
+//! it. The trace ends by itself after the duration, or with `!admin host trace MODULE off`.
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use tokio::sync::Mutex;
+
+use crate::{
+    diagnostics::APP_CTX_LOCK,
+    utils::{parse_duration, split_args},
+    AppCtx,
+};
+
+const USAGE: &str = "usage: !admin host trace MODULE [on [DURATION] | off]";
+
+/// How long a trace lasts, unless given a duration.
+const DEFAULT_DURATION: Duration = Duration::from_secs(10 * 60);
+
+/// Longest a trace may last, not to leave the logs flooded by a forgotten one.
+const MAX_DURATION: Duration = Duration::from_secs(60 * 60);
+
+enum Toggle {
+    Status,
+    On(Duration),
+    Off,
+}
+
+fn parse_trace_duration(duration: &str) -> anyhow::Result<Duration> {
+    let duration =
+        parse_duration(duration).ok_or_else(|| anyhow::anyhow!("invalid duration {duration}"))?;
+    if duration.is_zero() || duration > MAX_DURATION {
+        anyhow::bail!(
+            "a trace lasts at most {} minutes",
+            MAX_DURATION.as_secs() / 60
+        );
+    }
+    Ok(duration)
+}
+
+async fn trace(app_ctx: &Arc<Mutex<AppCtx>>, name: &str, toggle: Toggle) -> anyhow::Result<String> {
+    let mut ctx = APP_CTX_LOCK.lock(app_ctx, "module trace").await;
+    let (_, mut modules) = ctx.modules.iter();
+    let Some(module) = modules.find(|module| module.name() == name) else {
+        anyhow::bail!("module {name} not found");
+    };
+    Ok(match toggle {
+        Toggle::Status => match module.traced_until() {
+            Some(until) => format!(
+                "the host calls of {name} are traced for {} more s",
+                until.saturating_duration_since(Instant::now()).as_secs()
+            ),
+            None => format!("the host calls of {name} aren't traced"),
+        },
+        Toggle::On(duration) => {
+            module.trace_for(duration);
+            format!(
+                "tracing the host calls of {name} at debug level for {} s",
+                duration.as_secs()
+            )
+        }
+        Toggle::Off => {
+            if module.stop_trace() {
+                format!("stopped tracing the host calls of {name}")
+            } else {
+                format!("the host calls of {name} weren't traced")
+            }
+        }
+    })
+}
+
+/// Try to handle an `!admin host trace` command.
+pub(crate) async fn try_handle_admin(
+    app_ctx: &Arc<Mutex<AppCtx>>,
+    content: &str,
+) -> Option<String> {
+    let rest = content.strip_prefix("!admin host trace")?;
+    if !rest.is_empty() && !rest.starts_with(' ') {
+        return None;
+    }
+
+    let args = split_args(rest);
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+    let result = match args.as_slice() {
+        [module] => trace(app_ctx, module, Toggle::Status).await,
+        [module, "on"] => trace(app_ctx, module, Toggle::On(DEFAULT_DURATION)).await,
+        [module, "on", duration] => match parse_trace_duration(duration) {
+            Ok(duration) => trace(app_ctx, module, Toggle::On(duration)).await,
+            Err(err) => Err(err),
+        },
+        [module, "off"] => trace(app_ctx, module, Toggle::Off).await,
+        _ => Ok(USAGE.to_owned()),
+    };
+    Some(result.unwrap_or_else(|err| format!("error: {err:#}")))
+}
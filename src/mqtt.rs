@@ -0,0 +1,197 @@
+//! Bridge to an MQTT broker, e.g. for home automation: the messages published on some topics are
+//! posted in rooms, according to routing rules, and modules can publish messages with `publish`
+//! actions, e.g. in response to commands.
+
+use std::time::Duration;
+
+use matrix_sdk::{
+    ruma::{events::room::message::RoomMessageEventContent, OwnedRoomId},
+    Client,
+};
+use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Packet, QoS};
+use serde::Deserialize;
+use tokio::sync::Mutex;
+use tracing::{debug, info, warn};
+
+use crate::{outbox, wasm};
+
+/// Configuration for the MQTT bridge.
+#[derive(Clone, Debug, Deserialize)]
+pub struct MqttConfig {
+    /// host name of the broker.
+    pub host: String,
+    /// port of the broker.
+    #[serde(default = "default_port")]
+    pub port: u16,
+    /// client id of the bot on the broker.
+    #[serde(default = "default_client_id")]
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// which topics are posted in which rooms.
+    #[serde(default)]
+    pub routes: Vec<MqttRoute>,
+    /// topic filters the modules may publish to; they may not publish anywhere if empty.
+    #[serde(default)]
+    pub publish_topics: Vec<String>,
+}
+
+/// A routing rule, posting the messages published on the matching topics in a room.
+#[derive(Clone, Debug, Deserialize)]
+pub struct MqttRoute {
+    /// topic filter, where `+` matches a level and a final `#` any number of levels.
+    pub topic: String,
+    pub room: OwnedRoomId,
+    /// format of the messages, where `{topic}` is replaced with the topic and `{payload}` with
+    /// the message.
+    #[serde(default = "default_format")]
+    pub format: String,
+}
+
+fn default_port() -> u16 {
+    1883
+}
+
+fn default_client_id() -> String {
+    "tritongue".to_owned()
+}
+
+fn default_format() -> String {
+    "{topic}: {payload}".to_owned()
+}
+
+/// Delay before polling the broker again after an error; the connection is then reopened.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// Largest payload posted in a room.
+const MAX_PAYLOAD_BYTES: usize = 4096;
+
+/// Whether the topic matches the topic filter.
+fn topic_matches(filter: &str, topic: &str) -> bool {
+    let mut topic_levels = topic.split('/');
+    for level in filter.split('/') {
+        match (level, topic_levels.next()) {
+            ("#", _) => return true,
+            ("+", Some(_)) => {}
+            (level, Some(topic_level)) if level == topic_level => {}
+            _ => return false,
+        }
+    }
+    topic_levels.next().is_none()
+}
+
+pub(crate) struct Mqtt {
+    config: Option<MqttConfig>,
+    client: Option<AsyncClient>,
+    event_loop: Mutex<Option<EventLoop>>,
+}
+
+impl Mqtt {
+    pub fn new(config: Option<MqttConfig>) -> Self {
+        let (client, event_loop) = match &config {
+            Some(config) => {
+                let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+                options.set_keep_alive(Duration::from_secs(30));
+                if let Some(username) = &config.username {
+                    let password = config.password.clone().unwrap_or_default();
+                    options.set_credentials(username, password);
+                }
+                let (client, event_loop) = AsyncClient::new(options, 16);
+                (Some(client), Some(event_loop))
+            }
+            None => (None, None),
+        };
+        Self {
+            config,
+            client,
+            event_loop: Mutex::new(event_loop),
+        }
+    }
+
+    /// Subscribes to the routed topics, and posts their messages in the rooms.
+    pub async fn run(&self, client: Client) {
+        let (Some(config), Some(mqtt)) = (&self.config, &self.client) else {
+            return;
+        };
+        let Some(mut event_loop) = self.event_loop.lock().await.take() else {
+            return;
+        };
+
+        for route in &config.routes {
+            if let Err(err) = mqtt.subscribe(&route.topic, QoS::AtLeastOnce).await {
+                warn!("couldn't subscribe to MQTT topic {}: {err}", route.topic);
+            }
+        }
+
+        loop {
+            let publish = match event_loop.poll().await {
+                Ok(Event::Incoming(Packet::Publish(publish))) => publish,
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    info!("connected to the MQTT broker {}", config.host);
+                    continue;
+                }
+                Ok(_) => continue,
+                Err(err) => {
+                    // Polling again reconnects, and the subscriptions are restored.
+                    warn!("MQTT connection error: {err}");
+                    tokio::time::sleep(RECONNECT_DELAY).await;
+                    continue;
+                }
+            };
+
+            let payload = String::from_utf8_lossy(&publish.payload);
+            if payload.len() > MAX_PAYLOAD_BYTES {
+                debug!("ignoring a large MQTT message on {}", publish.topic);
+                continue;
+            }
+            for route in &config.routes {
+                if !topic_matches(&route.topic, &publish.topic) {
+                    continue;
+                }
+                let Some(room) = client.get_room(&route.room) else {
+                    debug!("MQTT route to unknown room {}", route.room);
+                    continue;
+                };
+                let text = route
+                    .format
+                    .replace("{topic}", &publish.topic)
+                    .replace("{payload}", &payload);
+                let content = RoomMessageEventContent::text_plain(text);
+                if let Err(err) = outbox::send(&room, content).await {
+                    warn!("couldn't post an MQTT message in {}: {err:#}", route.room);
+                }
+            }
+        }
+    }
+
+    /// Publishes a message requested by a module, if the topic is allowed.
+    pub async fn publish(&self, module: &str, message: wasm::MqttMessage) {
+        let (Some(config), Some(mqtt)) = (&self.config, &self.client) else {
+            warn!("{module} can't publish to MQTT, no broker is configured");
+            return;
+        };
+        let topic = message.topic;
+        if !config
+            .publish_topics
+            .iter()
+            .any(|filter| topic_matches(filter, &topic))
+        {
+            warn!("{module} can't publish to MQTT topic {topic}, it's not in publish_topics");
+            return;
+        }
+        if outbox::is_dry_run() {
+            info!("dry run: {module} would publish to MQTT topic {topic}");
+            return;
+        }
+        let result = mqtt
+            .publish(
+                &topic,
+                QoS::AtLeastOnce,
+                false,
+                message.payload.into_bytes(),
+            )
+            .await;
+        if let Err(err) = result {
+            warn!("couldn't publish to MQTT topic {topic} for {module}: {err}");
+        }
+    }
+}
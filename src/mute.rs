@@ -0,0 +1,160 @@
+//! Soft-mute of the bot in a room: `!mute [DURATION]` silences the bot's messages and reactions
+//! there for a while (an hour by default), except for its responses to the admin, for when it
+//! misbehaves during an incident. `!unmute` lifts it early.
+//!
+//! The mute is enforced when sending, by [`crate::outbox`], so it applies to every feature and
+//! module. It's persisted, and expires by itself.
+
+use std::{collections::HashMap, sync::Mutex};
+
+use matrix_sdk::{
+    room::Room,
+    ruma::{
+        events::room::message::RoomMessageEventContent, OwnedRoomId, OwnedUserId, RoomId, UserId,
+    },
+};
+use tokio::time::Duration;
+use tracing::{debug, warn};
+
+use crate::{
+    host_table, outbox,
+    utils::{is_moderator, now_secs, parse_duration},
+    ShareableDatabase,
+};
+
+/// Name of the host table keeping the mutes.
+const TABLE: &str = "mute";
+/// Key of the map of the muted rooms to when their mute expires, in seconds since the epoch.
+const ROOMS_KEY: &str = "rooms";
+/// Duration of a mute, unless specified otherwise.
+const DEFAULT_DURATION: Duration = Duration::from_secs(60 * 60);
+
+/// When the mute of each room expires, per bot (there may be several of them in supervisor mode).
+static MUTED: Mutex<Option<HashMap<(OwnedUserId, OwnedRoomId), u64>>> = Mutex::new(None);
+
+/// Whether the bot is muted in the room.
+pub(crate) fn is_muted(bot: &UserId, room_id: &RoomId) -> bool {
+    let muted = MUTED.lock().unwrap();
+    let until = muted
+        .as_ref()
+        .and_then(|muted| muted.get(&(bot.to_owned(), room_id.to_owned())));
+    until.is_some_and(|until| *until > now_secs())
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        _ if secs % (24 * 60 * 60) == 0 => format!("{}d", secs / (24 * 60 * 60)),
+        _ if secs % (60 * 60) == 0 => format!("{}h", secs / (60 * 60)),
+        _ if secs % 60 == 0 => format!("{}m", secs / 60),
+        _ => format!("{secs}s"),
+    }
+}
+
+pub(crate) struct Mute {
+    db: ShareableDatabase,
+    bot: OwnedUserId,
+}
+
+impl Mute {
+    /// Creates the mute feature for the given bot, restoring the mutes that haven't expired.
+    pub fn new(db: ShareableDatabase, bot: OwnedUserId) -> anyhow::Result<Self> {
+        let this = Self { db, bot };
+        let now = now_secs();
+        let rooms = this
+            .read_rooms()?
+            .into_iter()
+            .filter(|(_, until)| *until > now);
+        let mut muted = MUTED.lock().unwrap();
+        let muted = muted.get_or_insert_with(HashMap::new);
+        muted.retain(|(bot, _), _| *bot != this.bot);
+        muted.extend(rooms.map(|(room_id, until)| ((this.bot.clone(), room_id), until)));
+        Ok(this)
+    }
+
+    fn read_rooms(&self) -> anyhow::Result<HashMap<OwnedRoomId, u64>> {
+        Ok(host_table::read_json(&self.db, TABLE, ROOMS_KEY)?.unwrap_or_default())
+    }
+
+    /// Mutes the room until the given time, or unmutes it.
+    fn set(&self, room_id: &RoomId, until: Option<u64>) -> anyhow::Result<()> {
+        let now = now_secs();
+        let mut rooms = self.read_rooms()?;
+        rooms.retain(|_, until| *until > now);
+        match until {
+            Some(until) => rooms.insert(room_id.to_owned(), until),
+            None => rooms.remove(room_id),
+        };
+        host_table::write_json(&self.db, TABLE, ROOMS_KEY, &rooms)?;
+
+        let mut muted = MUTED.lock().unwrap();
+        let muted = muted.get_or_insert_with(HashMap::new);
+        let key = (self.bot.clone(), room_id.to_owned());
+        match until {
+            Some(until) => muted.insert(key, until),
+            None => muted.remove(&key),
+        };
+        Ok(())
+    }
+
+    /// Try to handle a message assuming it's a `!mute` or `!unmute` command.
+    ///
+    /// Returns whether it was one.
+    pub async fn try_handle(
+        &self,
+        room: &Room,
+        sender: &UserId,
+        content: &str,
+        is_admin: bool,
+    ) -> anyhow::Result<bool> {
+        let (mute, rest) = if let Some(rest) = content.strip_prefix("!mute") {
+            (true, rest)
+        } else if let Some(rest) = content.strip_prefix("!unmute") {
+            (false, rest)
+        } else {
+            return Ok(false);
+        };
+        if !rest.is_empty() && !rest.starts_with(' ') {
+            return Ok(false);
+        }
+
+        let response = if !is_admin && !is_moderator(room, sender).await? {
+            "only moderators can mute the bot".to_owned()
+        } else if mute {
+            let duration = match rest.trim() {
+                "" => Some(DEFAULT_DURATION),
+                duration => parse_duration(duration),
+            };
+            match duration {
+                Some(duration) => {
+                    let until = now_secs() + duration.as_secs();
+                    match self.set(room.room_id(), Some(until)) {
+                        Ok(()) => {
+                            debug!("{sender} muted the bot in {}", room.room_id());
+                            let duration = format_duration(duration);
+                            format!("muted for {duration}, !unmute to lift it")
+                        }
+                        Err(err) => {
+                            warn!("couldn't mute {}: {err:#}", room.room_id());
+                            format!("error when muting: {err:#}")
+                        }
+                    }
+                }
+                None => "usage: !mute [DURATION], e.g. 30m".to_owned(),
+            }
+        } else {
+            match self.set(room.room_id(), None) {
+                Ok(()) => "unmuted".to_owned(),
+                Err(err) => format!("error when unmuting: {err:#}"),
+            }
+        };
+
+        // The mute command's own response gets through.
+        outbox::unmuted(outbox::send(
+            room,
+            RoomMessageEventContent::text_plain(response),
+        ))
+        .await?;
+        Ok(true)
+    }
+}
@@ -0,0 +1,43 @@
+//! Responses sent as notices (`m.notice`), which by convention bots never respond to, so that
+//! several bots in a room can't get into a loop. The global `send_notices` setting chooses the
+//! message type of all the modules' responses, a module's `send_notices` configuration key
+//! overrides it for the module, and the `notice` field of a message overrides both.
+
+use std::collections::HashMap;
+
+use crate::wasm;
+
+/// Module configuration key choosing whether the module's responses are sent as notices.
+const SEND_NOTICES_KEY: &str = "send_notices";
+
+pub(crate) struct Notices {
+    /// Whether the responses are sent as notices, for the modules not configuring it.
+    default: bool,
+    /// Whether the responses are sent as notices, for the modules configuring it.
+    modules: HashMap<String, bool>,
+}
+
+impl Notices {
+    pub fn new(
+        default: bool,
+        modules_config: &HashMap<String, HashMap<String, String>>,
+    ) -> anyhow::Result<Self> {
+        let mut modules = HashMap::new();
+        for (module, config) in modules_config {
+            let Some(value) = config.get(SEND_NOTICES_KEY) else {
+                continue;
+            };
+            let send_notices: bool = value.trim().parse().map_err(|err| {
+                anyhow::anyhow!("invalid {SEND_NOTICES_KEY} for module {module}: {err}")
+            })?;
+            modules.insert(module.clone(), send_notices);
+        }
+        Ok(Self { default, modules })
+    }
+
+    /// Whether the module's message is sent as a notice.
+    pub fn is_notice(&self, module: &str, msg: &wasm::Message) -> bool {
+        msg.notice
+            .unwrap_or_else(|| self.modules.get(module).copied().unwrap_or(self.default))
+    }
+}
@@ -0,0 +1,366 @@
+//! On-call rotations, managed with `!oncall`: users take turns for shifts of a given length,
+//! starting at midnight in the rotation's timezone, and can be replaced temporarily with
+//! overrides. The handovers are announced in the room where the rotation was created, and the
+//! alerts escalate to the on-call user of their rotation.
+
+use chrono::{DateTime, Duration as ChronoDuration, NaiveDateTime, TimeZone as _, Utc};
+use chrono_tz::Tz;
+use matrix_sdk::{
+    room::Room,
+    ruma::{events::room::message::RoomMessageEventContent, OwnedRoomId, OwnedUserId, UserId},
+    Client,
+};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::Mutex,
+    time::{sleep, Duration},
+};
+use tracing::error;
+
+use crate::{
+    host_table,
+    maintenance::Maintenance,
+    outbox,
+    utils::{is_moderator, parse_duration, split_args},
+    ShareableDatabase,
+};
+
+/// Name of the host table keeping the rotations.
+const TABLE: &str = "oncall";
+/// Key of the list of all the rotations.
+const ROTATIONS_KEY: &str = "rotations";
+/// Delay between two checks of the handovers to announce.
+const HANDOVER_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// Shortest shift.
+const MIN_SHIFT: Duration = Duration::from_secs(60 * 60);
+
+const USAGE: &str = "usage: !oncall [NAME | create NAME PERIOD TIMEZONE USER... | delete NAME | \
+                     override NAME USER DURATION | clear NAME]";
+
+/// A user replacing the on-call user of a rotation for a while.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Override {
+    user: OwnedUserId,
+    until: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Rotation {
+    name: String,
+    /// Room where the rotation was created, and its handovers are announced.
+    room: OwnedRoomId,
+    users: Vec<OwnedUserId>,
+    shift_secs: u64,
+    timezone: String,
+    /// Start of the first user's first shift, in the rotation's timezone.
+    start: NaiveDateTime,
+    #[serde(default)]
+    overrides: Vec<Override>,
+    /// Last shift whose handover was announced.
+    #[serde(default)]
+    announced_shift: Option<i64>,
+}
+
+impl Rotation {
+    fn tz(&self) -> Tz {
+        self.timezone.parse().unwrap_or(Tz::UTC)
+    }
+
+    /// Index of the shift at the given time.
+    fn shift(&self, now: DateTime<Utc>) -> i64 {
+        let local = now.with_timezone(&self.tz()).naive_local();
+        (local - self.start).num_seconds().max(0) / self.shift_secs as i64
+    }
+
+    /// User on call during the given shift, ignoring the overrides.
+    fn scheduled(&self, shift: i64) -> &OwnedUserId {
+        &self.users[shift.rem_euclid(self.users.len() as i64) as usize]
+    }
+
+    /// User on call at the given time, taking the overrides into account.
+    fn on_call(&self, now: DateTime<Utc>) -> &OwnedUserId {
+        match self.overrides.iter().rev().find(|o| o.until > now) {
+            Some(o) => &o.user,
+            None => self.scheduled(self.shift(now)),
+        }
+    }
+
+    /// End of the given shift.
+    fn shift_end(&self, shift: i64) -> Option<DateTime<Utc>> {
+        let end = self.start + ChronoDuration::seconds((shift + 1) * self.shift_secs as i64);
+        self.tz()
+            .from_local_datetime(&end)
+            .earliest()
+            .map(|end| end.with_timezone(&Utc))
+    }
+
+    fn describe(&self, now: DateTime<Utc>) -> String {
+        let mut msg = format!("{}: {} is on call", self.name, self.on_call(now));
+        let shift = self.shift(now);
+        if let Some(end) = self.shift_end(shift) {
+            let end = end.with_timezone(&self.tz());
+            msg.push_str(&format!(
+                ", {} takes over on {}",
+                self.scheduled(shift + 1),
+                end.format("%Y-%m-%d %H:%M %Z")
+            ));
+        }
+        msg
+    }
+}
+
+fn read_rotations(db: &ShareableDatabase) -> anyhow::Result<Vec<Rotation>> {
+    Ok(host_table::read_json(db, TABLE, ROTATIONS_KEY)?.unwrap_or_default())
+}
+
+/// User currently on call for the rotation with the given name, if it exists.
+pub(crate) fn on_call(
+    db: &ShareableDatabase,
+    rotation: &str,
+) -> anyhow::Result<Option<OwnedUserId>> {
+    Ok(read_rotations(db)?
+        .iter()
+        .find(|r| r.name == rotation)
+        .map(|r| r.on_call(Utc::now()).clone()))
+}
+
+pub(crate) struct OnCall {
+    db: ShareableDatabase,
+    /// Serializes the read-modify-write cycles on the rotations.
+    lock: Mutex<()>,
+}
+
+impl OnCall {
+    pub fn new(db: ShareableDatabase) -> Self {
+        Self {
+            db,
+            lock: Mutex::new(()),
+        }
+    }
+
+    fn write_rotations(&self, rotations: &[Rotation]) -> anyhow::Result<()> {
+        host_table::write_json(&self.db, TABLE, ROTATIONS_KEY, &rotations)
+    }
+
+    /// Announces the handovers of the rotations whose shift changed.
+    async fn announce_handovers(&self, client: &Client) -> anyhow::Result<()> {
+        let _guard = self.lock.lock().await;
+        let mut rotations = read_rotations(&self.db)?;
+        let now = Utc::now();
+
+        let mut changed = false;
+        for rotation in rotations.iter_mut() {
+            let shift = rotation.shift(now);
+            if rotation.announced_shift == Some(shift) {
+                continue;
+            }
+            let first = rotation.announced_shift.is_none();
+            rotation.announced_shift = Some(shift);
+            rotation.overrides.retain(|o| o.until > now);
+            changed = true;
+            if first {
+                continue;
+            }
+
+            let Some(room) = client.get_room(&rotation.room) else {
+                continue;
+            };
+            let text = format!(
+                "🔁 {} handover: {} is now on call, taking over from {}",
+                rotation.name,
+                rotation.scheduled(shift),
+                rotation.scheduled(shift - 1)
+            );
+            if let Err(err) = outbox::send(&room, RoomMessageEventContent::text_plain(text)).await {
+                error!(
+                    "couldn't announce the handover of {}: {err:#}",
+                    rotation.name
+                );
+            }
+        }
+
+        if changed {
+            self.write_rotations(&rotations)?;
+        }
+        Ok(())
+    }
+
+    /// Periodically announces the handovers.
+    pub async fn run(&self, client: Client, maintenance: &Maintenance) {
+        loop {
+            sleep(HANDOVER_CHECK_INTERVAL).await;
+            if maintenance.is_on() {
+                // The handovers are announced once maintenance is over.
+                continue;
+            }
+            if let Err(err) = self.announce_handovers(&client).await {
+                error!("error when announcing on-call handovers: {err:#}");
+            }
+        }
+    }
+
+    async fn create(&self, room: &Room, args: &[String]) -> anyhow::Result<String> {
+        let [name, period, timezone, users @ ..] = args else {
+            return Ok(USAGE.to_owned());
+        };
+        let Some(shift) = parse_duration(period).filter(|shift| *shift >= MIN_SHIFT) else {
+            return Ok(format!(
+                "invalid period {period}, e.g. 12h or 7d, at least an hour"
+            ));
+        };
+        let Ok(tz) = timezone.parse::<Tz>() else {
+            return Ok(format!("unknown timezone {timezone}, e.g. Europe/Paris"));
+        };
+        let users = match users
+            .iter()
+            .map(|u| OwnedUserId::try_from(u.as_str()))
+            .collect::<Result<Vec<_>, _>>()
+        {
+            Ok(users) if !users.is_empty() => users,
+            Ok(_) => return Ok(USAGE.to_owned()),
+            Err(err) => return Ok(format!("invalid user id: {err}")),
+        };
+
+        let _guard = self.lock.lock().await;
+        let mut rotations = read_rotations(&self.db)?;
+        let now = Utc::now();
+        // Shifts start at midnight, in the rotation's timezone.
+        let start = now
+            .with_timezone(&tz)
+            .date_naive()
+            .and_hms_opt(0, 0, 0)
+            .expect("midnight exists");
+        let rotation = Rotation {
+            name: name.clone(),
+            room: room.room_id().to_owned(),
+            users,
+            shift_secs: shift.as_secs(),
+            timezone: tz.name().to_owned(),
+            start,
+            overrides: Vec::new(),
+            announced_shift: None,
+        };
+        let msg = format!("rotation created; {}", rotation.describe(now));
+        rotations.retain(|r| r.name != *name);
+        rotations.push(rotation);
+        self.write_rotations(&rotations)?;
+        Ok(msg)
+    }
+
+    /// Applies a change to the rotation with the given name, returning a message describing the
+    /// outcome.
+    async fn update(
+        &self,
+        name: &str,
+        change: impl FnOnce(&mut Rotation) -> String,
+    ) -> anyhow::Result<String> {
+        let _guard = self.lock.lock().await;
+        let mut rotations = read_rotations(&self.db)?;
+        let Some(rotation) = rotations.iter_mut().find(|r| r.name == name) else {
+            return Ok(format!("no rotation named {name}"));
+        };
+        let msg = change(rotation);
+        self.write_rotations(&rotations)?;
+        Ok(msg)
+    }
+
+    async fn handle(&self, room: &Room, sender: &UserId, rest: &str) -> anyhow::Result<String> {
+        let args = split_args(rest);
+        let now = Utc::now();
+
+        let Some(cmd) = args.first() else {
+            let rotations = read_rotations(&self.db)?;
+            let here = rotations
+                .iter()
+                .filter(|r| r.room == room.room_id())
+                .map(|r| r.describe(now))
+                .collect::<Vec<_>>();
+            return Ok(if here.is_empty() {
+                "no rotations in this room".to_owned()
+            } else {
+                here.join("\n")
+            });
+        };
+
+        let is_change = matches!(cmd.as_str(), "create" | "delete" | "override" | "clear");
+        if is_change && !is_moderator(room, sender).await? {
+            return Ok("only moderators can do that".to_owned());
+        }
+
+        Ok(match (cmd.as_str(), &args[1..]) {
+            ("create", args) => self.create(room, args).await?,
+
+            ("delete", [name]) => {
+                let _guard = self.lock.lock().await;
+                let mut rotations = read_rotations(&self.db)?;
+                let before = rotations.len();
+                rotations.retain(|r| r.name != *name);
+                if rotations.len() == before {
+                    format!("no rotation named {name}")
+                } else {
+                    self.write_rotations(&rotations)?;
+                    format!("rotation {name} deleted")
+                }
+            }
+
+            ("override", [name, user, duration]) => {
+                let Ok(user) = OwnedUserId::try_from(user.as_str()) else {
+                    return Ok(format!("invalid user id: {user}"));
+                };
+                let Some(duration) = parse_duration(duration) else {
+                    return Ok(format!("invalid duration {duration}, e.g. 4h or 2d"));
+                };
+                let until = now + ChronoDuration::seconds(duration.as_secs() as i64);
+                self.update(name, |rotation| {
+                    rotation.overrides.push(Override {
+                        user: user.clone(),
+                        until,
+                    });
+                    format!(
+                        "{user} is on call for {name} until {}",
+                        until
+                            .with_timezone(&rotation.tz())
+                            .format("%Y-%m-%d %H:%M %Z")
+                    )
+                })
+                .await?
+            }
+
+            ("clear", [name]) => {
+                self.update(name, |rotation| {
+                    rotation.overrides.clear();
+                    format!("overrides cleared; {}", rotation.describe(now))
+                })
+                .await?
+            }
+
+            (name, []) => match read_rotations(&self.db)?.iter().find(|r| r.name == name) {
+                Some(rotation) => rotation.describe(now),
+                None => format!("no rotation named {name}"),
+            },
+
+            _ => USAGE.to_owned(),
+        })
+    }
+
+    /// Try to handle a message assuming it's an `!oncall` command.
+    ///
+    /// Returns whether the message was such a command.
+    pub async fn try_handle(
+        &self,
+        room: &Room,
+        sender: &UserId,
+        content: &str,
+    ) -> anyhow::Result<bool> {
+        let Some(rest) = content.strip_prefix("!oncall") else {
+            return Ok(false);
+        };
+        if !rest.is_empty() && !rest.starts_with(' ') {
+            return Ok(false);
+        }
+
+        let response = self.handle(room, sender, rest.trim()).await?;
+        outbox::send(room, RoomMessageEventContent::text_plain(response)).await?;
+        Ok(true)
+    }
+}
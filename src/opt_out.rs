@@ -0,0 +1,161 @@
+//! Opt-outs: users can `!optout` of a room, or of all of them, and the host then neither passes
+//! their messages to the modules, except the moderation ones, nor records them in meeting minutes
+//! or reacts to them automatically. Modules handling users through other means, e.g. moderation
+//! modules or ones keeping statistics, can check the opt-outs with the `is-opted-out` function of
+//! the `sys` API.
+
+use std::collections::{BTreeSet, HashMap};
+
+use matrix_sdk::{
+    room::Room,
+    ruma::{OwnedRoomId, RoomId, UserId},
+};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::{host_table, ShareableDatabase};
+
+/// Name of the host table keeping the opt-outs, by user id.
+const TABLE: &str = "opt_out";
+
+/// Module configuration key marking a module as a moderation one, still passed the messages of
+/// the users who opted out.
+const MODERATION_KEY: &str = "moderation";
+
+const USAGE: &str = "usage: !optout [everywhere | status], !optin [everywhere]";
+
+/// The opt-outs of a user.
+#[derive(Default, Serialize, Deserialize)]
+struct Entry {
+    /// Whether the user opted out of all the rooms.
+    everywhere: bool,
+    /// Rooms the user opted out of.
+    rooms: BTreeSet<OwnedRoomId>,
+}
+
+fn read(db: &ShareableDatabase, user_id: &str) -> anyhow::Result<Entry> {
+    Ok(host_table::read_json(db, TABLE, user_id)?.unwrap_or_default())
+}
+
+/// Whether the user, given by id, opted out of the room, given by id.
+pub(crate) fn is_opted_out(db: &ShareableDatabase, user_id: &str, room_id: &str) -> bool {
+    match read(db, user_id) {
+        Ok(entry) => entry.everywhere || entry.rooms.iter().any(|room| room.as_str() == room_id),
+        Err(err) => {
+            // Failing closed: better miss a message than process one against the user's will.
+            warn!("couldn't read the opt-outs of {user_id}: {err:#}");
+            true
+        }
+    }
+}
+
+/// Whether the module's configuration marks it as a moderation one.
+pub(crate) fn is_moderation(module_config: Option<&HashMap<String, String>>) -> bool {
+    module_config
+        .and_then(|config| config.get(MODERATION_KEY))
+        .is_some_and(|value| value.trim() == "true")
+}
+
+pub(crate) struct OptOut {
+    db: ShareableDatabase,
+}
+
+impl OptOut {
+    pub fn new(db: ShareableDatabase) -> Self {
+        Self { db }
+    }
+
+    /// Whether the user opted out of the room.
+    pub fn is_opted_out(&self, user_id: &UserId, room_id: &RoomId) -> bool {
+        is_opted_out(&self.db, user_id.as_str(), room_id.as_str())
+    }
+
+    fn write(&self, user_id: &UserId, entry: &Entry) -> anyhow::Result<()> {
+        if !entry.everywhere && entry.rooms.is_empty() {
+            host_table::remove(&self.db, TABLE, user_id.as_str())
+        } else {
+            host_table::write_json(&self.db, TABLE, user_id.as_str(), entry)
+        }
+    }
+
+    fn status(&self, user_id: &UserId, room_id: &RoomId) -> anyhow::Result<String> {
+        let entry = read(&self.db, user_id.as_str())?;
+        Ok(if entry.everywhere {
+            "you're opted out of all the rooms".to_owned()
+        } else if entry.rooms.contains(room_id) {
+            "you're opted out of this room".to_owned()
+        } else {
+            "you're not opted out of this room".to_owned()
+        })
+    }
+
+    fn set(
+        &self,
+        user_id: &UserId,
+        room_id: &RoomId,
+        opt_out: bool,
+        everywhere: bool,
+    ) -> anyhow::Result<String> {
+        let mut entry = read(&self.db, user_id.as_str())?;
+        if everywhere {
+            entry.everywhere = opt_out;
+            if !opt_out {
+                entry.rooms.clear();
+            }
+        } else if opt_out {
+            entry.rooms.insert(room_id.to_owned());
+        } else {
+            entry.rooms.remove(room_id);
+        }
+        self.write(user_id, &entry)?;
+        let scope = if everywhere {
+            "all the rooms"
+        } else {
+            room_id.as_str()
+        };
+        debug!(
+            "{user_id} opted {} {scope}",
+            if opt_out { "out of" } else { "back in" }
+        );
+
+        Ok(match (opt_out, everywhere) {
+            (true, true) => {
+                "opted out of all the rooms: the modules won't see your messages anymore, \
+                 !optin everywhere to undo"
+            }
+            (true, false) => {
+                "opted out of this room: the modules won't see your messages here anymore, \
+                 !optin to undo"
+            }
+            (false, true) => "opted back in everywhere",
+            (false, false) if entry.everywhere => {
+                "you're still opted out of all the rooms, !optin everywhere to opt back in"
+            }
+            (false, false) => "opted back in this room",
+        }
+        .to_owned())
+    }
+
+    /// Try to handle a message assuming it's an `!optout` or `!optin` command.
+    pub fn try_handle(&self, room: &Room, sender: &UserId, content: &str) -> Option<String> {
+        let (opt_out, rest) = if let Some(rest) = content.strip_prefix("!optout") {
+            (true, rest)
+        } else if let Some(rest) = content.strip_prefix("!optin") {
+            (false, rest)
+        } else {
+            return None;
+        };
+        if !rest.is_empty() && !rest.starts_with(' ') {
+            return None;
+        }
+
+        let room_id = room.room_id();
+        let result = match (opt_out, rest.trim()) {
+            (true, "status") => self.status(sender, room_id),
+            (_, "") => self.set(sender, room_id, opt_out, false),
+            (_, "everywhere") => self.set(sender, room_id, opt_out, true),
+            _ => Ok(USAGE.to_owned()),
+        };
+        Some(result.unwrap_or_else(|err| format!("error: {err:#}")))
+    }
+}
@@ -0,0 +1,252 @@
+//! The single way out for the events and actions of the bot in rooms, so that a dry run can
+//! intercept them: when enabled, every action is logged and reported to the debug room instead of
+//! being carried out. The messages and reactions to rooms where the bot is muted are dropped here
+//! too, and the HTML bodies of the messages are sanitized.
+
+use std::{
+    any::Any,
+    collections::HashMap,
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+use matrix_sdk::{
+    attachment::AttachmentConfig,
+    room::Room,
+    ruma::{
+        events::{
+            room::message::{MessageFormat, MessageType, Relation, RoomMessageEventContent},
+            EmptyStateKey, MessageLikeEventContent, StateEventContent,
+        },
+        EventId, OwnedEventId, OwnedRoomId, OwnedUserId, UserId,
+    },
+};
+use mime::Mime;
+use tracing::{debug, info, warn};
+
+use crate::{html_sanitizer, mute};
+
+/// Whether the process runs in dry-run mode.
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+/// Room where the actions intercepted in dry-run mode are reported, per bot (there may be
+/// several of them in supervisor mode).
+static DEBUG_ROOMS: Mutex<Option<HashMap<OwnedUserId, OwnedRoomId>>> = Mutex::new(None);
+
+/// Enables the dry-run mode, for all the bots of the process.
+pub fn enable_dry_run() {
+    DRY_RUN.store(true, Ordering::Relaxed);
+}
+
+pub(crate) fn is_dry_run() -> bool {
+    DRY_RUN.load(Ordering::Relaxed)
+}
+
+/// Sets where the given bot reports the actions it would have taken in dry-run mode.
+pub(crate) fn set_debug_room(bot: OwnedUserId, room: OwnedRoomId) {
+    DEBUG_ROOMS
+        .lock()
+        .unwrap()
+        .get_or_insert_with(HashMap::new)
+        .insert(bot, room);
+}
+
+tokio::task_local! {
+    /// Set for the output that gets through mutes.
+    static UNMUTED: bool;
+}
+
+/// Runs the future, letting its messages through even if the room is muted.
+pub(crate) async fn unmuted<F: Future>(future: F) -> F::Output {
+    UNMUTED.scope(true, future).await
+}
+
+/// Whether the bot's messages to the room are silenced, in which case they're dropped.
+fn is_muted(room: &Room) -> bool {
+    if UNMUTED.try_with(|unmuted| *unmuted).unwrap_or(false) {
+        return false;
+    }
+    let client = room.client();
+    let muted = client
+        .user_id()
+        .is_some_and(|bot| mute::is_muted(bot, room.room_id()));
+    if muted {
+        debug!("muted in {}, dropping a message", room.room_id());
+    }
+    muted
+}
+
+/// In dry-run mode, reports the action that would have been taken in the room, and returns true
+/// so that the caller skips it.
+async fn intercept(room: &Room, action: impl FnOnce() -> String) -> bool {
+    if !is_dry_run() {
+        return false;
+    }
+
+    let action = action();
+    info!("dry run, in {}: would {action}", room.room_id());
+
+    let client = room.client();
+    let debug_room = client.user_id().and_then(|bot| {
+        DEBUG_ROOMS
+            .lock()
+            .unwrap()
+            .as_ref()
+            .and_then(|rooms| rooms.get(bot).cloned())
+    });
+    // Reports to the debug room itself go through, of course.
+    if debug_room.as_deref() == Some(room.room_id()) {
+        return false;
+    }
+    if let Some(debug_room) = debug_room.and_then(|room_id| client.get_room(&room_id)) {
+        let report = format!("dry run, in {}: would {action}", room.room_id());
+        if let Err(err) = debug_room
+            .send(RoomMessageEventContent::text_plain(report))
+            .await
+        {
+            warn!("couldn't report to the dry-run debug room: {err}");
+        }
+    }
+    true
+}
+
+/// Returns an event id standing for an event that hasn't been sent.
+fn fake_event_id(room: &Room) -> OwnedEventId {
+    match room.room_id().server_name() {
+        Some(server) => EventId::new(server),
+        None => EventId::parse("$dry-run").expect("valid event id"),
+    }
+}
+
+/// Sanitizes the HTML body of a message, if it has one.
+fn sanitize_formatted(msgtype: &mut MessageType) {
+    let formatted = match msgtype {
+        MessageType::Text(content) => &mut content.formatted,
+        MessageType::Notice(content) => &mut content.formatted,
+        MessageType::Emote(content) => &mut content.formatted,
+        _ => return,
+    };
+    if let Some(formatted) = formatted {
+        if matches!(formatted.format, MessageFormat::Html) {
+            formatted.body = html_sanitizer::sanitize(&formatted.body);
+        }
+    }
+}
+
+/// Sanitizes the HTML body of a message given as JSON, and of its new version if it's an edit.
+fn sanitize_raw_formatted(content: &mut serde_json::Value) {
+    if content.get("format").and_then(|format| format.as_str()) == Some("org.matrix.custom.html") {
+        if let Some(body) = content.get_mut("formatted_body") {
+            if let Some(html) = body.as_str() {
+                *body = html_sanitizer::sanitize(html).into();
+            }
+        }
+    }
+    if let Some(new_content) = content.get_mut("m.new_content") {
+        sanitize_raw_formatted(new_content);
+    }
+}
+
+pub(crate) async fn send(
+    room: &Room,
+    mut content: impl MessageLikeEventContent + 'static,
+) -> anyhow::Result<OwnedEventId> {
+    if let Some(message) = (&mut content as &mut dyn Any).downcast_mut::<RoomMessageEventContent>()
+    {
+        sanitize_formatted(&mut message.msgtype);
+        if let Some(Relation::Replacement(replacement)) = &mut message.relates_to {
+            sanitize_formatted(&mut replacement.new_content.msgtype);
+        }
+    }
+    let describe = || {
+        format!(
+            "send a {} event: {}",
+            content.event_type(),
+            serde_json::to_string(&content).unwrap_or_default()
+        )
+    };
+    if is_muted(room) || intercept(room, describe).await {
+        return Ok(fake_event_id(room));
+    }
+    Ok(room.send(content).await?.event_id)
+}
+
+pub(crate) async fn send_raw(
+    room: &Room,
+    event_type: &str,
+    mut content: serde_json::Value,
+) -> anyhow::Result<OwnedEventId> {
+    if event_type == "m.room.message" {
+        sanitize_raw_formatted(&mut content);
+    }
+    if is_muted(room) || intercept(room, || format!("send a {event_type} event: {content}")).await {
+        return Ok(fake_event_id(room));
+    }
+    Ok(room.send_raw(event_type, content).await?.event_id)
+}
+
+pub(crate) async fn send_attachment(
+    room: &Room,
+    filename: &str,
+    content_type: &Mime,
+    data: Vec<u8>,
+    config: AttachmentConfig,
+) -> anyhow::Result<OwnedEventId> {
+    let describe = || format!("upload {filename} ({content_type}, {} bytes)", data.len());
+    if is_muted(room) || intercept(room, describe).await {
+        return Ok(fake_event_id(room));
+    }
+    Ok(room
+        .send_attachment(filename, content_type, data, config)
+        .await?
+        .event_id)
+}
+
+pub(crate) async fn send_state(
+    room: &Room,
+    content: impl StateEventContent<StateKey = EmptyStateKey>,
+) -> anyhow::Result<()> {
+    let describe = || {
+        format!(
+            "set the {} state: {}",
+            content.event_type(),
+            serde_json::to_string(&content).unwrap_or_default()
+        )
+    };
+    if !intercept(room, describe).await {
+        room.send_state_event(content).await?;
+    }
+    Ok(())
+}
+
+pub(crate) async fn redact(
+    room: &Room,
+    event_id: &EventId,
+    reason: Option<&str>,
+) -> anyhow::Result<()> {
+    if !intercept(room, || format!("redact {event_id} ({reason:?})")).await {
+        room.redact(event_id, reason, None).await?;
+    }
+    Ok(())
+}
+
+pub(crate) async fn kick(
+    room: &Room,
+    user_id: &UserId,
+    reason: Option<&str>,
+) -> anyhow::Result<()> {
+    if !intercept(room, || format!("kick {user_id} ({reason:?})")).await {
+        room.kick_user(user_id, reason).await?;
+    }
+    Ok(())
+}
+
+pub(crate) async fn invite(room: &Room, user_id: &UserId) -> anyhow::Result<()> {
+    if !intercept(room, || format!("invite {user_id}")).await {
+        room.invite_user_by_id(user_id).await?;
+    }
+    Ok(())
+}
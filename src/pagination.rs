@@ -0,0 +1,290 @@
+//! Pagination of the long responses of the modules, e.g. search results or logs, which would
+//! otherwise hit the homeserver's event size limit: the host posts the first page, with ◀️ and ▶️
+//! reactions, and edits the message to show the previous or next page when someone reacts with
+//! them.
+//!
+//! The pages are split on line boundaries, keeping the code blocks balanced, and only the text
+//! body is kept, since cutting the HTML one would leave unbalanced tags. They're kept in memory for
+//! a while, so they're lost on restart.
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use matrix_sdk::{
+    room::Room,
+    ruma::{
+        events::{reaction::ReactionEventContent, relation::Annotation},
+        EventId, OwnedEventId, OwnedRoomId, UserId,
+    },
+};
+use tracing::{debug, warn};
+
+use crate::{html_text, message_content, outbox, wasm, App};
+
+/// Reaction showing the previous page.
+const PREVIOUS_KEY: &str = "◀️";
+/// Reaction showing the next page.
+const NEXT_KEY: &str = "▶️";
+/// How long the pages of a message can be turned.
+const PAGES_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+/// Most paginated messages whose pages can be turned at once.
+const MAX_PAGED_MESSAGES: usize = 500;
+
+/// The pages of a long message, the first one being the message to post.
+pub(crate) struct Pages {
+    /// The message, without its text.
+    message: wasm::Message,
+    pages: Vec<String>,
+}
+
+impl Pages {
+    /// The message showing the page at `index`, with the navigation hint.
+    fn page(&self, index: usize) -> wasm::Message {
+        let mut message = self.message.clone();
+        message.text = format!(
+            "{}\n\n(page {}/{}, react with {PREVIOUS_KEY} or {NEXT_KEY} to turn the pages)",
+            self.pages[index],
+            index + 1,
+            self.pages.len()
+        );
+        message
+    }
+}
+
+/// A posted message whose pages can be turned.
+struct Paged {
+    module: String,
+    room: OwnedRoomId,
+    pages: Pages,
+    current: usize,
+    posted_at: Instant,
+}
+
+#[derive(Default)]
+pub(crate) struct Pagination {
+    /// The paginated messages, by event id.
+    paged: Mutex<HashMap<OwnedEventId, Paged>>,
+}
+
+/// The largest index at most `index` that is on a char boundary of `text`.
+fn floor_char_boundary(text: &str, index: usize) -> usize {
+    let mut index = index.min(text.len());
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+/// Splits a Markdown text into pages of at most about `page_bytes`, on line boundaries; lines
+/// longer than half a page are cut. A code block spanning several pages is closed at the end of
+/// each and reopened at the start of the next.
+fn split_pages(text: &str, page_bytes: usize) -> Vec<String> {
+    // Leaves room for closing and reopening a code block.
+    let page_bytes = page_bytes.max(64);
+    let mut lines = Vec::new();
+    for line in text.split_inclusive('\n') {
+        let mut line = line;
+        while line.len() > page_bytes / 2 {
+            let (head, tail) = line.split_at(floor_char_boundary(line, page_bytes / 2));
+            lines.push(head);
+            line = tail;
+        }
+        lines.push(line);
+    }
+
+    let mut pages = Vec::new();
+    let mut page = String::new();
+    // The line opening the code block the page is in, if any.
+    let mut fence: Option<String> = None;
+    for line in lines {
+        if !page.trim().is_empty() && page.len() + line.len() > page_bytes {
+            if fence.is_some() {
+                if !page.ends_with('\n') {
+                    page.push('\n');
+                }
+                page.push_str("```");
+            }
+            pages.push(std::mem::take(&mut page));
+            if let Some(fence) = &fence {
+                page.push_str(fence);
+            }
+        }
+        page.push_str(line);
+        if line.trim_start().starts_with("```") {
+            fence = match fence {
+                Some(_) => None,
+                None => Some(format!("{}\n", line.trim_end())),
+            };
+        }
+    }
+    if !page.trim().is_empty() {
+        pages.push(page);
+    }
+    pages
+}
+
+/// Splits the message into pages if it's longer than a page, leaving the first one in the
+/// message; returns the pages to register once the message is posted.
+pub(crate) fn paginate(app: &App, msg: &mut wasm::Message) -> Option<Pages> {
+    let page_bytes = app.response_limits.page_bytes();
+    let html_too_long = msg
+        .html
+        .as_ref()
+        .is_some_and(|html| html.len() > page_bytes);
+    if msg.text.len() <= page_bytes && !html_too_long {
+        return None;
+    }
+
+    html_text::fill_text(msg);
+    msg.html = None;
+    let pages = split_pages(&msg.text, page_bytes);
+    if pages.len() < 2 {
+        return None;
+    }
+    let pages = Pages {
+        message: wasm::Message {
+            text: String::new(),
+            ..msg.clone()
+        },
+        pages,
+    };
+    *msg = pages.page(0);
+    Some(pages)
+}
+
+impl Pagination {
+    /// Adds the navigation reactions to a posted paginated message, and remembers its pages.
+    pub async fn register(
+        &self,
+        room: &Room,
+        module: &str,
+        event_id: &EventId,
+        pages: Pages,
+    ) -> anyhow::Result<()> {
+        for key in [PREVIOUS_KEY, NEXT_KEY] {
+            let reaction =
+                ReactionEventContent::new(Annotation::new(event_id.to_owned(), key.to_owned()));
+            outbox::send(room, reaction).await?;
+        }
+
+        let mut paged = self.paged.lock().unwrap();
+        paged.retain(|_, p| p.posted_at.elapsed() < PAGES_TIMEOUT);
+        if paged.len() >= MAX_PAGED_MESSAGES {
+            // Forget the oldest one, whose pages are the least likely to be turned still.
+            let oldest = paged
+                .iter()
+                .min_by_key(|(_, p)| p.posted_at)
+                .map(|(event_id, _)| event_id.clone());
+            if let Some(oldest) = oldest {
+                paged.remove(&oldest);
+            }
+        }
+        paged.insert(
+            event_id.to_owned(),
+            Paged {
+                module: module.to_owned(),
+                room: room.room_id().to_owned(),
+                pages,
+                current: 0,
+                posted_at: Instant::now(),
+            },
+        );
+        Ok(())
+    }
+}
+
+/// Handles a reaction to a paginated message, showing the previous or next page.
+pub(crate) async fn on_reaction(
+    app: &App,
+    room: &Room,
+    sender: &UserId,
+    event_id: &EventId,
+    key: &str,
+) -> anyhow::Result<()> {
+    let forward = match key {
+        NEXT_KEY => true,
+        PREVIOUS_KEY => false,
+        _ => return Ok(()),
+    };
+    let (module, msg) = {
+        let mut paged = app.pagination.paged.lock().unwrap();
+        let Some(message) = paged
+            .get_mut(event_id)
+            .filter(|p| p.room == room.room_id() && p.posted_at.elapsed() < PAGES_TIMEOUT)
+        else {
+            return Ok(());
+        };
+        let page = if forward {
+            message.current + 1
+        } else {
+            message.current.wrapping_sub(1)
+        };
+        if page >= message.pages.pages.len() {
+            return Ok(());
+        }
+        message.current = page;
+        (message.module.clone(), message.pages.page(page))
+    };
+
+    debug!("{sender} turned a page of a message of {module}");
+    let content = message_content(app, room, &module, msg).await;
+    if let Err(err) = app
+        .compliance
+        .edit(room, &module, event_id.to_owned(), content)
+        .await
+    {
+        warn!("couldn't turn a page of a message of {module}: {err:#}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_text() {
+        assert_eq!(split_pages("hello\nworld", 100), ["hello\nworld"]);
+        assert!(split_pages("", 100).is_empty());
+        assert!(split_pages(" \n\n ", 100).is_empty());
+    }
+
+    #[test]
+    fn split_on_lines() {
+        let text = (0..100).map(|i| format!("line {i}\n")).collect::<String>();
+        let pages = split_pages(&text, 100);
+        assert!(pages.len() > 1);
+        assert!(pages.iter().all(|page| page.len() <= 100));
+        assert!(pages.iter().all(|page| page.ends_with('\n')));
+        assert_eq!(pages.concat(), text);
+    }
+
+    #[test]
+    fn long_lines_cut_on_char_boundaries() {
+        let text = "€".repeat(100);
+        let pages = split_pages(&text, 64);
+        assert!(pages.len() > 1);
+        assert_eq!(pages.concat(), text);
+    }
+
+    #[test]
+    fn code_blocks_stay_balanced() {
+        let code = (0..50)
+            .map(|i| format!("let x{i} = {i};\n"))
+            .collect::<String>();
+        let text = format!("before\n```rust\n{code}```\nafter\n");
+        let pages = split_pages(&text, 128);
+        assert!(pages.len() > 2);
+        for page in &pages {
+            assert_eq!(
+                page.matches("```").count() % 2,
+                0,
+                "unbalanced page {page:?}"
+            );
+        }
+        assert!(pages[1].starts_with("```rust\n"));
+    }
+}
@@ -0,0 +1,307 @@
+//! Polls (MSC3381) started by the modules with `start-poll` actions: the host posts the poll,
+//! tallies the responses, and calls the module's `on-poll-update` export back with the tally after
+//! each response, and once more when the poll ends, with an `end-poll` action or by a moderator.
+//!
+//! The polls use the unstable `org.matrix.msc3381` event types, which the current clients send and
+//! display. They're persisted, so the responses keep being counted after restarts.
+
+use std::collections::HashMap;
+
+use matrix_sdk::{
+    room::Room,
+    ruma::{
+        events::poll::{
+            unstable_end::OriginalSyncUnstablePollEndEvent,
+            unstable_response::OriginalSyncUnstablePollResponseEvent,
+        },
+        EventId, OwnedEventId, OwnedRoomId, OwnedUserId,
+    },
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::Mutex;
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    diagnostics::APP_CTX_LOCK, handle_module_actions, host_table, outbox, utils::is_moderator,
+    wasm, App, ShareableDatabase,
+};
+
+/// Name of the host table keeping the polls, by event id.
+const TABLE: &str = "polls";
+const START_EVENT_TYPE: &str = "org.matrix.msc3381.poll.start";
+const END_EVENT_TYPE: &str = "org.matrix.msc3381.poll.end";
+/// Key of the text fallbacks, in the poll events.
+const TEXT_KEY: &str = "org.matrix.msc1767.text";
+/// Most answers a poll may have, as per MSC3381.
+const MAX_ANSWERS: usize = 20;
+
+/// A poll started by a module.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Poll {
+    module: String,
+    room: OwnedRoomId,
+    question: String,
+    answers: Vec<String>,
+    max_selections: u8,
+    /// The latest response of each voter: when it was sent, in milliseconds since the epoch, and
+    /// the indexes of the answers selected, none meaning the vote was withdrawn.
+    responses: HashMap<OwnedUserId, (u64, Vec<usize>)>,
+    ended: bool,
+}
+
+impl Poll {
+    fn tally(&self, event_id: &EventId) -> wasm::PollTally {
+        let mut counts = vec![0; self.answers.len()];
+        let mut voters = 0;
+        for (_, selections) in self.responses.values() {
+            if selections.is_empty() {
+                continue;
+            }
+            voters += 1;
+            for &answer in selections {
+                counts[answer] += 1;
+            }
+        }
+        wasm::PollTally {
+            poll_id: event_id.to_string(),
+            question: self.question.clone(),
+            counts,
+            voters,
+            ended: self.ended,
+        }
+    }
+
+    /// The text of the end event: the results, most voted answers first.
+    fn results(&self, tally: &wasm::PollTally) -> String {
+        let mut results = self.answers.iter().zip(&tally.counts).collect::<Vec<_>>();
+        results.sort_by(|a, b| b.1.cmp(a.1));
+        let results = results
+            .iter()
+            .map(|(answer, count)| format!("{answer}: {count}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!("The poll has ended. Results: {results}")
+    }
+}
+
+pub(crate) struct Polls {
+    db: ShareableDatabase,
+    /// Serializes the read-modify-write cycles on the polls.
+    lock: Mutex<()>,
+}
+
+impl Polls {
+    pub fn new(db: ShareableDatabase) -> Self {
+        Self {
+            db,
+            lock: Mutex::new(()),
+        }
+    }
+
+    fn read(&self, event_id: &EventId) -> anyhow::Result<Option<Poll>> {
+        host_table::read_json(&self.db, TABLE, event_id.as_str())
+    }
+
+    fn write(&self, event_id: &EventId, poll: &Poll) -> anyhow::Result<()> {
+        host_table::write_json(&self.db, TABLE, event_id.as_str(), poll)
+    }
+}
+
+/// Posts a poll of the module in the room.
+pub(crate) async fn start(
+    app: &App,
+    room: &Room,
+    module: &str,
+    poll: wasm::Poll,
+) -> anyhow::Result<()> {
+    anyhow::ensure!(!poll.question.trim().is_empty(), "the poll has no question");
+    anyhow::ensure!(
+        (1..=MAX_ANSWERS).contains(&poll.answers.len()),
+        "a poll needs between 1 and {MAX_ANSWERS} answers, not {}",
+        poll.answers.len()
+    );
+    let max_selections = poll.max_selections.max(1);
+
+    let kind = if poll.disclosed {
+        "org.matrix.msc3381.poll.disclosed"
+    } else {
+        "org.matrix.msc3381.poll.undisclosed"
+    };
+    let answers = poll
+        .answers
+        .iter()
+        .enumerate()
+        .map(|(i, answer)| json!({ "id": i.to_string(), TEXT_KEY: answer }))
+        .collect::<Vec<_>>();
+    let fallback = poll
+        .answers
+        .iter()
+        .enumerate()
+        .fold(poll.question.clone(), |text, (i, answer)| {
+            format!("{text}\n{}. {answer}", i + 1)
+        });
+    let content = json!({
+        START_EVENT_TYPE: {
+            "kind": kind,
+            "max_selections": max_selections,
+            "question": { TEXT_KEY: poll.question },
+            "answers": answers,
+        },
+        TEXT_KEY: fallback,
+    });
+
+    let event_id = outbox::send_raw(room, START_EVENT_TYPE, content).await?;
+    app.sent_messages.record(module, room.room_id(), &event_id);
+    let poll = Poll {
+        module: module.to_owned(),
+        room: room.room_id().to_owned(),
+        question: poll.question,
+        answers: poll.answers,
+        max_selections,
+        responses: HashMap::new(),
+        ended: false,
+    };
+    let _guard = app.polls.lock.lock().await;
+    app.polls.write(&event_id, &poll)
+}
+
+/// Ends a poll, posting its results and calling its module back with the final tally.
+async fn close(app: &App, room: &Room, event_id: &EventId, mut poll: Poll) -> anyhow::Result<()> {
+    poll.ended = true;
+    app.polls.write(event_id, &poll)?;
+    let tally = poll.tally(event_id);
+    info!("poll {event_id} of {} ended", poll.module);
+
+    let content = json!({
+        "m.relates_to": { "rel_type": "m.reference", "event_id": event_id },
+        END_EVENT_TYPE: {},
+        TEXT_KEY: poll.results(&tally),
+    });
+    outbox::send_raw(room, END_EVENT_TYPE, content).await?;
+    notify(app, room, poll.module, tally);
+    Ok(())
+}
+
+/// Ends a poll the module started in the room.
+pub(crate) async fn end(app: &App, room: &Room, module: &str, poll_id: &str) -> anyhow::Result<()> {
+    let event_id = OwnedEventId::try_from(poll_id)?;
+    let _guard = app.polls.lock.lock().await;
+    let Some(poll) = app.polls.read(&event_id)? else {
+        anyhow::bail!("{poll_id} isn't a poll");
+    };
+    if poll.module != module || poll.room != room.room_id() {
+        anyhow::bail!("{poll_id} isn't a poll of {module} in this room");
+    }
+    if poll.ended {
+        return Ok(());
+    }
+    close(app, room, &event_id, poll).await
+}
+
+/// Counts a response to a poll of a module, and calls the module back with the new tally.
+pub(crate) async fn on_response(
+    app: &App,
+    room: &Room,
+    ev: &OriginalSyncUnstablePollResponseEvent,
+) -> anyhow::Result<()> {
+    let event_id = &ev.content.relates_to.event_id;
+    let _guard = app.polls.lock.lock().await;
+    let Some(mut poll) = app.polls.read(event_id)? else {
+        return Ok(());
+    };
+    if poll.ended || poll.room != room.room_id() {
+        return Ok(());
+    }
+
+    // Only the latest response of each voter counts, even if they arrive out of order.
+    let ts = u64::from(ev.origin_server_ts.0);
+    if poll
+        .responses
+        .get(&ev.sender)
+        .is_some_and(|(previous, _)| *previous > ts)
+    {
+        return Ok(());
+    }
+    let mut selections = Vec::new();
+    for answer in &ev.content.poll_response.answers {
+        let Some(index) = answer.parse::<usize>().ok().filter(|i| *i < poll.answers.len()) else {
+            continue;
+        };
+        if !selections.contains(&index) {
+            selections.push(index);
+        }
+    }
+    selections.truncate(poll.max_selections as usize);
+    debug!("{} responded to the poll {event_id}", ev.sender);
+    poll.responses.insert(ev.sender.clone(), (ts, selections));
+    app.polls.write(event_id, &poll)?;
+
+    notify(app, room, poll.module.clone(), poll.tally(event_id));
+    Ok(())
+}
+
+/// Ends a poll of a module ended by a moderator from their client.
+pub(crate) async fn on_end(
+    app: &App,
+    room: &Room,
+    ev: &OriginalSyncUnstablePollEndEvent,
+) -> anyhow::Result<()> {
+    let event_id = &ev.content.relates_to.event_id;
+    let _guard = app.polls.lock.lock().await;
+    let Some(poll) = app.polls.read(event_id)? else {
+        return Ok(());
+    };
+    if poll.ended || poll.room != room.room_id() {
+        return Ok(());
+    }
+    if !is_moderator(room, &ev.sender).await? {
+        debug!(
+            "ignoring the end of the poll {event_id} by {}, who isn't a moderator",
+            ev.sender
+        );
+        return Ok(());
+    }
+    close(app, room, event_id, poll).await
+}
+
+/// Calls the module back with the tally of its poll, in the background, and handles its response.
+fn notify(app: &App, room: &Room, module: String, tally: wasm::PollTally) {
+    let app = app.clone();
+    let room = room.clone();
+    tokio::spawn(async move {
+        let inner = app.inner.clone();
+        let crash_reporter = app.crash_reporter.clone();
+        let response_limits = app.response_limits.clone();
+        let room_id = room.room_id().to_owned();
+        let name = module.clone();
+        let actions = tokio::task::spawn_blocking(move || {
+            let ctx = &mut *futures::executor::block_on(APP_CTX_LOCK.lock(&inner, "poll update"));
+            let (store, mut modules) = ctx.modules.iter();
+            let Some(module) = modules.find(|m| m.name() == name) else {
+                debug!("dropping a poll update of {name}, which isn't loaded");
+                return None;
+            };
+            match module.on_poll_update(&mut *store, &room_id, &tally) {
+                Ok(actions) => Some(response_limits.apply(module.name(), actions)),
+                Err(err) => {
+                    warn!("wasm module {} ran into an error: {err}", module.name());
+                    module.record_error(&err);
+                    crash_reporter.module_error(module.name(), Some(&room_id), None, &err);
+                    None
+                }
+            }
+        })
+        .await;
+
+        match actions {
+            Ok(Some(actions)) => {
+                if let Err(err) = handle_module_actions(&app, &room, &module, actions).await {
+                    warn!("couldn't handle the actions of {module} for a poll update: {err:#}");
+                }
+            }
+            Ok(None) => {}
+            Err(err) => error!("delivering a poll update failed: {err}"),
+        }
+    });
+}
@@ -0,0 +1,200 @@
+//! Two-phase commands: a module about to do something destructive can return a `preview` action
+//! instead, describing what it would do. The host posts the preview with ✅ and ❌ reactions, and
+//! once the author of the command reacts with ✅, calls the module's `on-confirm` export back with
+//! the preview's payload, for it to do it for real. By convention, modules also return a preview
+//! when a command ends with `--preview`, without ever doing it.
+//!
+//! The previews expire after a while, and are kept in memory only, so they're lost on restart.
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use matrix_sdk::{
+    room::Room,
+    ruma::{
+        events::{
+            reaction::ReactionEventContent, relation::Annotation,
+            room::message::RoomMessageEventContent,
+        },
+        EventId, OwnedEventId, OwnedRoomId, OwnedUserId, UserId,
+    },
+};
+use tracing::{debug, error, warn};
+
+use crate::{
+    diagnostics::APP_CTX_LOCK, handle_module_actions, message_content, outbox, utils::is_moderator,
+    wasm, App,
+};
+
+/// Reaction confirming a preview.
+const CONFIRM_KEY: &str = "✅";
+/// Reaction cancelling a preview.
+const CANCEL_KEY: &str = "❌";
+/// How long a preview can be confirmed.
+const PREVIEW_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// A preview waiting for a confirmation.
+struct Pending {
+    module: String,
+    room: OwnedRoomId,
+    /// Who may confirm it: the author of the command, or the moderators if there's none, e.g.
+    /// for a preview posted by a timer.
+    author: Option<OwnedUserId>,
+    payload: String,
+    /// The text of the preview, to edit it once answered.
+    text: String,
+    posted_at: Instant,
+}
+
+#[derive(Default)]
+pub(crate) struct Previews {
+    /// The previews waiting for a confirmation, by event id.
+    pending: Mutex<HashMap<OwnedEventId, Pending>>,
+}
+
+impl Previews {
+    /// Removes the pending preview, if the user may answer it.
+    async fn take(&self, room: &Room, event_id: &EventId, sender: &UserId) -> Option<Pending> {
+        let author = {
+            let mut pending = self.pending.lock().unwrap();
+            pending.retain(|_, p| p.posted_at.elapsed() < PREVIEW_TIMEOUT);
+            let preview = pending.get(event_id)?;
+            if preview.room != room.room_id() {
+                return None;
+            }
+            preview.author.clone()
+        };
+        let allowed = match author {
+            Some(author) => author == sender,
+            None => is_moderator(room, sender).await.unwrap_or(false),
+        };
+        if !allowed {
+            debug!("ignoring the answer of {sender} to a preview they can't answer");
+            return None;
+        }
+        self.pending.lock().unwrap().remove(event_id)
+    }
+}
+
+/// Posts a module's preview in the room, with the reactions to confirm or cancel it.
+pub(crate) async fn post(
+    app: &App,
+    room: &Room,
+    module: &str,
+    author: Option<&UserId>,
+    preview: wasm::Preview,
+) -> anyhow::Result<()> {
+    let mut msg = preview.message;
+    msg.text = format!(
+        "{}\n\nReact with {CONFIRM_KEY} to confirm, or {CANCEL_KEY} to cancel.",
+        msg.text
+    );
+    msg.html = msg.html.map(|html| {
+        format!("{html}<p>React with {CONFIRM_KEY} to confirm, or {CANCEL_KEY} to cancel.</p>")
+    });
+    let text = msg.text.clone();
+    let content = message_content(app, room, module, msg).await;
+    let event_id = app.compliance.send(room, module, content).await?;
+    app.sent_messages.record(module, room.room_id(), &event_id);
+
+    for key in [CONFIRM_KEY, CANCEL_KEY] {
+        let reaction = ReactionEventContent::new(Annotation::new(event_id.clone(), key.to_owned()));
+        outbox::send(room, reaction).await?;
+    }
+
+    let pending = Pending {
+        module: module.to_owned(),
+        room: room.room_id().to_owned(),
+        author: author.map(ToOwned::to_owned),
+        payload: preview.payload,
+        text,
+        posted_at: Instant::now(),
+    };
+    app.previews
+        .pending
+        .lock()
+        .unwrap()
+        .insert(event_id, pending);
+    Ok(())
+}
+
+/// Handles a reaction to a preview: a confirmation calls its module back, and a cancellation
+/// forgets it.
+pub(crate) async fn on_reaction(
+    app: &App,
+    room: &Room,
+    sender: &UserId,
+    event_id: &OwnedEventId,
+    key: &str,
+) -> anyhow::Result<()> {
+    let confirmed = match key {
+        CONFIRM_KEY => true,
+        CANCEL_KEY => false,
+        _ => return Ok(()),
+    };
+    let Some(preview) = app.previews.take(room, event_id, sender).await else {
+        return Ok(());
+    };
+
+    let outcome = if confirmed { "confirmed" } else { "cancelled" };
+    let text = format!("{}\n\n({outcome} by {sender})", preview.text);
+    let content = RoomMessageEventContent::text_plain(text);
+    if let Err(err) = app
+        .compliance
+        .edit(room, &preview.module, event_id.clone(), content)
+        .await
+    {
+        warn!(
+            "couldn't mark a preview of {} as {outcome}: {err:#}",
+            preview.module
+        );
+    }
+
+    if confirmed {
+        confirm(app, room, preview, sender).await;
+    }
+    Ok(())
+}
+
+/// Calls the module back with the payload of its confirmed preview, and handles its response.
+async fn confirm(app: &App, room: &Room, preview: Pending, sender: &UserId) {
+    let inner = app.inner.clone();
+    let crash_reporter = app.crash_reporter.clone();
+    let response_limits = app.response_limits.clone();
+    let room_id = room.room_id().to_owned();
+    let name = preview.module.clone();
+    let payload = preview.payload;
+    let sender = sender.to_owned();
+    let actions = tokio::task::spawn_blocking(move || {
+        let ctx = &mut *futures::executor::block_on(APP_CTX_LOCK.lock(&inner, "confirmation"));
+        let (store, mut modules) = ctx.modules.iter();
+        let Some(module) = modules.find(|m| m.name() == name) else {
+            debug!("dropping a confirmation for {name}, which isn't loaded");
+            return None;
+        };
+        match module.on_confirm(&mut *store, &payload, &sender, &room_id) {
+            Ok(actions) => Some(response_limits.apply(module.name(), actions)),
+            Err(err) => {
+                warn!("wasm module {} ran into an error: {err}", module.name());
+                module.record_error(&err);
+                crash_reporter.module_error(module.name(), Some(&room_id), None, &err);
+                None
+            }
+        }
+    })
+    .await;
+
+    let module = &preview.module;
+    match actions {
+        Ok(Some(actions)) => {
+            if let Err(err) = handle_module_actions(app, room, module, actions).await {
+                warn!("couldn't handle the actions of {module} for a confirmation: {err:#}");
+            }
+        }
+        Ok(None) => {}
+        Err(err) => error!("delivering a confirmation failed: {err}"),
+    }
+}
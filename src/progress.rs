@@ -0,0 +1,83 @@
+//! Progress messages of the modules' long tasks: the first progress report of a task posts a
+//! message, and the next ones edit it in place, e.g. "Deploying… 40%", instead of flooding the
+//! room. The edits are rate-limited per task: the reports coming too soon after the last edit are
+//! dropped, except the final one, so the message always ends up up to date.
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use matrix_sdk::ruma::{OwnedEventId, OwnedRoomId, RoomId};
+
+use crate::wasm;
+
+/// Shortest delay between two edits of a progress message.
+const MIN_EDIT_INTERVAL: Duration = Duration::from_secs(3);
+/// How long a task may go without reports before it's forgotten, and its next report posts a new
+/// message.
+const TASK_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// What to do with a progress report.
+pub(crate) enum Update {
+    /// Post a new progress message.
+    Post,
+    /// Edit the task's progress message.
+    Edit(OwnedEventId),
+    /// Drop the report, the message was edited too recently.
+    Skip,
+}
+
+/// A task whose progress message was posted.
+struct Task {
+    event_id: OwnedEventId,
+    last_edit: Instant,
+}
+
+/// The text of the progress message for a report: its text, followed by the percentage if any.
+pub(crate) fn render(report: &wasm::Progress) -> String {
+    match report.percent {
+        Some(percent) => format!("{} {}%", report.text, percent.min(100)),
+        None => report.text.clone(),
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Progress {
+    /// The tasks in progress, by module, room and key.
+    tasks: Mutex<HashMap<(String, OwnedRoomId, String), Task>>,
+}
+
+impl Progress {
+    /// Decides what to do with a progress report of the module's task in the room.
+    pub fn update(&self, module: &str, room_id: &RoomId, report: &wasm::Progress) -> Update {
+        let mut tasks = self.tasks.lock().unwrap();
+        tasks.retain(|_, task| task.last_edit.elapsed() < TASK_TIMEOUT);
+
+        let key = (module.to_owned(), room_id.to_owned(), report.key.clone());
+        let Some(task) = tasks.get_mut(&key) else {
+            return Update::Post;
+        };
+        if report.done {
+            let task = tasks.remove(&key).expect("the task was just found");
+            return Update::Edit(task.event_id);
+        }
+        if task.last_edit.elapsed() < MIN_EDIT_INTERVAL {
+            return Update::Skip;
+        }
+        task.last_edit = Instant::now();
+        Update::Edit(task.event_id.clone())
+    }
+
+    /// Remembers the progress message posted for the module's task in the room, so the next
+    /// reports edit it.
+    pub fn posted(&self, module: &str, room_id: &RoomId, key: &str, event_id: OwnedEventId) {
+        let key = (module.to_owned(), room_id.to_owned(), key.to_owned());
+        let task = Task {
+            event_id,
+            last_edit: Instant::now(),
+        };
+        self.tasks.lock().unwrap().insert(key, task);
+    }
+}
@@ -0,0 +1,234 @@
+//! Quiet hours: during the configured hours, and on holidays, the modules' messages that aren't
+//! urgent enough, e.g. digests, feeds or reminders with a `low` urgency, are held, and delivered
+//! once the quiet hours of their room are over. Alerts, with a `high` urgency, always get through.
+//!
+//! The held messages are persisted, so they're delivered even if the bot restarts in between.
+
+use std::{collections::HashMap, sync::Mutex};
+
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use chrono_tz::Tz;
+use matrix_sdk::{
+    ruma::{events::room::message::RoomMessageEventContent, OwnedRoomId, RoomId},
+    Client,
+};
+use serde::{Deserialize, Serialize};
+use tokio::time::Duration;
+use tracing::{debug, warn};
+
+use crate::{
+    host_table,
+    schedule::{hours_include, parse_hours, parse_timezone},
+    wasm, App, ShareableDatabase,
+};
+
+/// Name of the host table keeping the held messages.
+const TABLE: &str = "quiet_hours";
+const HELD_KEY: &str = "held";
+/// How often the end of the quiet hours is checked.
+const TICK: Duration = Duration::from_secs(60);
+/// How many times the delivery of a held message is tried, once per tick, before dropping it.
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Configuration for the quiet hours.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct QuietHoursConfig {
+    /// quiet hours of all the rooms, e.g. `22:00-08:00`.
+    pub hours: Option<String>,
+    /// quiet hours of some rooms, overriding the ones of all the rooms; empty for none.
+    #[serde(default)]
+    pub rooms: HashMap<OwnedRoomId, String>,
+    /// days entirely quiet, as `YYYY-MM-DD` dates.
+    #[serde(default)]
+    pub holidays: Vec<String>,
+    /// IANA timezone of the hours and holidays, UTC by default.
+    pub timezone: Option<String>,
+    /// highest urgency held: `low` (the default), or `normal` to let only the `high` ones through.
+    pub hold: Option<String>,
+}
+
+/// A message held until the end of the quiet hours.
+#[derive(Serialize, Deserialize)]
+struct Held {
+    room: OwnedRoomId,
+    module: String,
+    content: RoomMessageEventContent,
+    /// failed deliveries so far.
+    #[serde(default)]
+    attempts: u32,
+}
+
+/// The urgency of a message, `normal` if it's not given.
+fn urgency_level(urgency: Option<wasm::Urgency>) -> u8 {
+    match urgency {
+        Some(wasm::Urgency::Low) => 0,
+        None | Some(wasm::Urgency::Normal) => 1,
+        Some(wasm::Urgency::High) => 2,
+    }
+}
+
+pub(crate) struct QuietHours {
+    db: ShareableDatabase,
+    hours: Option<(NaiveTime, NaiveTime)>,
+    rooms: HashMap<OwnedRoomId, Option<(NaiveTime, NaiveTime)>>,
+    holidays: Vec<NaiveDate>,
+    timezone: Tz,
+    /// Highest urgency level held.
+    hold: u8,
+    /// Serializes the read-modify-write cycles on the held messages.
+    lock: Mutex<()>,
+}
+
+impl QuietHours {
+    pub fn new(config: QuietHoursConfig, db: ShareableDatabase) -> anyhow::Result<Self> {
+        let hours = config.hours.as_deref().map(parse_hours).transpose()?;
+        let rooms = config
+            .rooms
+            .into_iter()
+            .map(|(room, hours)| {
+                let hours = match hours.trim() {
+                    "" => None,
+                    hours => Some(parse_hours(hours)?),
+                };
+                Ok((room, hours))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        let holidays = config
+            .holidays
+            .iter()
+            .map(|day| {
+                NaiveDate::parse_from_str(day, "%Y-%m-%d")
+                    .map_err(|err| anyhow::anyhow!("invalid holiday {day}: {err}"))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        let hold = match config.hold.as_deref() {
+            None | Some("low") => 0,
+            Some("normal") => 1,
+            Some(hold) => anyhow::bail!("invalid quiet hours hold level {hold}"),
+        };
+        Ok(Self {
+            db,
+            hours,
+            rooms,
+            holidays,
+            timezone: parse_timezone(config.timezone.as_deref())?,
+            hold,
+            lock: Mutex::new(()),
+        })
+    }
+
+    /// Whether the room is in its quiet hours at the given time.
+    fn is_quiet(&self, room_id: &RoomId, now: DateTime<Utc>) -> bool {
+        let local = now.with_timezone(&self.timezone);
+        if self.holidays.contains(&local.date_naive()) {
+            return true;
+        }
+        let hours = match self.rooms.get(room_id) {
+            Some(hours) => *hours,
+            None => self.hours,
+        };
+        hours.is_some_and(|hours| hours_include(hours, local.time()))
+    }
+
+    fn read(&self) -> anyhow::Result<Vec<Held>> {
+        Ok(host_table::read_json(&self.db, TABLE, HELD_KEY)?.unwrap_or_default())
+    }
+
+    /// Whether a message with the given urgency would be held in the room now.
+    pub fn holds(&self, room_id: &RoomId, urgency: Option<wasm::Urgency>) -> bool {
+        urgency_level(urgency) <= self.hold && self.is_quiet(room_id, Utc::now())
+    }
+
+    /// Holds the module's message if the room is in its quiet hours, and the message isn't
+    /// urgent enough; returns whether it was held.
+    pub fn hold(
+        &self,
+        room_id: &RoomId,
+        module: &str,
+        urgency: Option<wasm::Urgency>,
+        content: &RoomMessageEventContent,
+    ) -> anyhow::Result<bool> {
+        if !self.holds(room_id, urgency) {
+            return Ok(false);
+        }
+        debug!("holding a message of {module} in {room_id} until the end of the quiet hours");
+        let _guard = self.lock.lock().unwrap();
+        let mut held = self.read()?;
+        held.push(Held {
+            room: room_id.to_owned(),
+            module: module.to_owned(),
+            content: content.clone(),
+            attempts: 0,
+        });
+        host_table::write_json(&self.db, TABLE, HELD_KEY, &held)?;
+        Ok(true)
+    }
+
+    /// Removes the held messages of the rooms whose quiet hours are over.
+    fn take_due(&self) -> anyhow::Result<Vec<Held>> {
+        let _guard = self.lock.lock().unwrap();
+        let now = Utc::now();
+        let (due, held): (Vec<_>, Vec<_>) = self
+            .read()?
+            .into_iter()
+            .partition(|held| !self.is_quiet(&held.room, now));
+        if !due.is_empty() {
+            host_table::write_json(&self.db, TABLE, HELD_KEY, &held)?;
+        }
+        Ok(due)
+    }
+
+    /// Holds again messages which couldn't be delivered, to retry on the next tick.
+    fn put_back(&self, failed: Vec<Held>) -> anyhow::Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        let mut held = self.read()?;
+        held.extend(failed);
+        host_table::write_json(&self.db, TABLE, HELD_KEY, &held)
+    }
+}
+
+/// Delivers the held messages once the quiet hours of their rooms are over.
+pub(crate) async fn run(app: App, client: Client) {
+    let mut interval = tokio::time::interval(TICK);
+    loop {
+        interval.tick().await;
+        let due = match app.quiet_hours.take_due() {
+            Ok(due) => due,
+            Err(err) => {
+                warn!("couldn't read the messages held for the quiet hours: {err:#}");
+                continue;
+            }
+        };
+        let mut failed = Vec::new();
+        for mut held in due {
+            let Some(room) = client.get_room(&held.room) else {
+                debug!(
+                    "dropping a held message for {}, the bot isn't there",
+                    held.room
+                );
+                continue;
+            };
+            let content = held.content.clone();
+            match app.compliance.send(&room, &held.module, content).await {
+                Ok(event_id) => app
+                    .sent_messages
+                    .record(&held.module, &held.room, &event_id),
+                Err(err) => {
+                    warn!(
+                        "couldn't deliver a held message of {} in {}: {err:#}",
+                        held.module, held.room
+                    );
+                    held.attempts += 1;
+                    if held.attempts < MAX_ATTEMPTS {
+                        failed.push(held);
+                    }
+                }
+            }
+        }
+        if !failed.is_empty() {
+            if let Err(err) = app.quiet_hours.put_back(failed) {
+                warn!("couldn't hold the undelivered messages again: {err:#}");
+            }
+        }
+    }
+}
@@ -0,0 +1,264 @@
+//! Quote database: `!quote add` (as a reply) saves a message with its attribution, and quotes can
+//! then be recalled at random, searched and exported.
+
+use std::sync::Mutex;
+
+use chrono::{TimeZone as _, Utc};
+use matrix_sdk::{
+    attachment::AttachmentConfig,
+    room::Room,
+    ruma::{
+        events::room::message::{OriginalSyncRoomMessageEvent, Relation, RoomMessageEventContent},
+        MilliSecondsSinceUnixEpoch, OwnedRoomId, OwnedUserId,
+    },
+};
+use rand::seq::SliceRandom as _;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    host_table, outbox,
+    utils::{is_moderator, now_secs, strip_reply_fallback},
+    ShareableDatabase,
+};
+
+/// Name of the host table keeping the quotes of each room.
+const TABLE: &str = "quotes";
+
+/// Maximum number of results shown by `!quote search`.
+const MAX_SEARCH_RESULTS: usize = 5;
+
+const USAGE: &str =
+    "usage: !quote (add, as a reply|random|search TEXT|show NUMBER|remove NUMBER|export)";
+
+/// The parts of the quoted event we're interested in.
+#[derive(Deserialize)]
+struct QuotedEvent {
+    sender: OwnedUserId,
+    origin_server_ts: MilliSecondsSinceUnixEpoch,
+    content: QuotedContent,
+}
+
+#[derive(Deserialize)]
+struct QuotedContent {
+    body: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Quote {
+    id: u64,
+    text: String,
+    /// who said it.
+    author: OwnedUserId,
+    /// when it was said, in seconds since the Unix epoch.
+    said_at: u64,
+    added_by: OwnedUserId,
+    added_at: u64,
+}
+
+impl Quote {
+    fn format(&self) -> String {
+        let date = Utc
+            .timestamp_opt(self.said_at as i64, 0)
+            .single()
+            .map(|date| date.format("%Y-%m-%d").to_string())
+            .unwrap_or_default();
+        format!("#{}: \"{}\" — {}, {date}", self.id, self.text, self.author)
+    }
+}
+
+/// All the quotes of a single room.
+#[derive(Default, Serialize, Deserialize)]
+struct RoomQuotes {
+    next_id: u64,
+    quotes: Vec<Quote>,
+}
+
+pub(crate) struct Quotes {
+    db: ShareableDatabase,
+    /// Serializes the read-modify-write cycles on the quotes.
+    lock: Mutex<()>,
+}
+
+impl Quotes {
+    pub fn new(db: ShareableDatabase) -> Self {
+        Self {
+            db,
+            lock: Mutex::new(()),
+        }
+    }
+
+    fn read_room(&self, room_id: &OwnedRoomId) -> anyhow::Result<RoomQuotes> {
+        Ok(host_table::read_json(&self.db, TABLE, room_id.as_str())?.unwrap_or_default())
+    }
+
+    /// Describes what's stored for the room, if anything, for `!admin host dump-room`.
+    pub fn room_summary(&self, room_id: &OwnedRoomId) -> anyhow::Result<Option<String>> {
+        let count = self.read_room(room_id)?.quotes.len();
+        Ok((count > 0).then(|| format!("quotes: {count}")))
+    }
+
+    /// Applies `f` to the quotes of the room, and saves them.
+    fn update_room<T>(
+        &self,
+        room_id: &OwnedRoomId,
+        f: impl FnOnce(&mut RoomQuotes) -> T,
+    ) -> anyhow::Result<T> {
+        let _guard = self.lock.lock().unwrap();
+        let mut room_quotes = self.read_room(room_id)?;
+        let result = f(&mut room_quotes);
+        host_table::write_json(&self.db, TABLE, room_id.as_str(), &room_quotes)?;
+        Ok(result)
+    }
+
+    async fn add(&self, room: &Room, ev: &OriginalSyncRoomMessageEvent) -> anyhow::Result<String> {
+        let Some(Relation::Reply { in_reply_to }) = &ev.content.relates_to else {
+            return Ok("use !quote add as a reply to the message to quote".to_owned());
+        };
+
+        let quoted = room.event(&in_reply_to.event_id).await?;
+        let quoted = quoted.event.deserialize_as::<QuotedEvent>()?;
+        let Some(body) = quoted.content.body else {
+            return Ok("only text messages can be quoted".to_owned());
+        };
+        let text = strip_reply_fallback(&body).trim().to_owned();
+        if text.is_empty() {
+            return Ok("only text messages can be quoted".to_owned());
+        }
+
+        let id = self.update_room(&room.room_id().to_owned(), |room_quotes| {
+            room_quotes.next_id += 1;
+            room_quotes.quotes.push(Quote {
+                id: room_quotes.next_id,
+                text,
+                author: quoted.sender,
+                said_at: u64::from(quoted.origin_server_ts.as_secs()),
+                added_by: ev.sender.clone(),
+                added_at: now_secs(),
+            });
+            room_quotes.next_id
+        })?;
+        Ok(format!("quote #{id} added"))
+    }
+
+    async fn handle(
+        &self,
+        room: &Room,
+        ev: &OriginalSyncRoomMessageEvent,
+        rest: &str,
+    ) -> anyhow::Result<String> {
+        let room_id = room.room_id().to_owned();
+        let (cmd, arg) = rest.split_once(' ').unwrap_or((rest, ""));
+        let arg = arg.trim();
+        let id = arg.trim_start_matches('#').parse::<u64>().ok();
+
+        Ok(match cmd {
+            "add" => self.add(room, ev).await?,
+
+            "" | "random" => {
+                let quotes = self.read_room(&room_id)?.quotes;
+                quotes
+                    .choose(&mut rand::thread_rng())
+                    .map_or_else(|| "no quotes in this room yet".to_owned(), Quote::format)
+            }
+
+            "search" if !arg.is_empty() => {
+                let needle = arg.to_lowercase();
+                let found = self
+                    .read_room(&room_id)?
+                    .quotes
+                    .into_iter()
+                    .filter(|q| {
+                        q.text.to_lowercase().contains(&needle)
+                            || q.author.as_str().to_lowercase().contains(&needle)
+                    })
+                    .collect::<Vec<_>>();
+                if found.is_empty() {
+                    "no matching quotes".to_owned()
+                } else {
+                    let mut text = format!("{} matching quote(s):", found.len());
+                    for quote in found.iter().rev().take(MAX_SEARCH_RESULTS) {
+                        text.push_str(&format!("\n{}", quote.format()));
+                    }
+                    text
+                }
+            }
+
+            "show" => {
+                let Some(id) = id else {
+                    return Ok(USAGE.to_owned());
+                };
+                self.read_room(&room_id)?
+                    .quotes
+                    .iter()
+                    .find(|q| q.id == id)
+                    .map_or_else(|| format!("no quote #{id} in this room"), Quote::format)
+            }
+
+            "remove" => {
+                let Some(id) = id else {
+                    return Ok(USAGE.to_owned());
+                };
+                let added_it = self
+                    .read_room(&room_id)?
+                    .quotes
+                    .iter()
+                    .any(|q| q.id == id && q.added_by == ev.sender);
+                if !added_it && !is_moderator(room, &ev.sender).await? {
+                    return Ok("only who added the quote or a moderator can remove it".to_owned());
+                }
+                let removed = self.update_room(&room_id, |room_quotes| {
+                    let len = room_quotes.quotes.len();
+                    room_quotes.quotes.retain(|q| q.id != id);
+                    room_quotes.quotes.len() != len
+                })?;
+                if removed {
+                    format!("quote #{id} removed")
+                } else {
+                    format!("no quote #{id} in this room")
+                }
+            }
+
+            "export" => {
+                let quotes = self.read_room(&room_id)?.quotes;
+                if quotes.is_empty() {
+                    return Ok("no quotes in this room yet".to_owned());
+                }
+                outbox::send_attachment(
+                    room,
+                    "quotes.json",
+                    &mime::APPLICATION_JSON,
+                    serde_json::to_vec_pretty(&quotes)?,
+                    AttachmentConfig::new(),
+                )
+                .await?;
+                String::new()
+            }
+
+            _ => USAGE.to_owned(),
+        })
+    }
+
+    /// Try to handle a message assuming it's a `!quote` command.
+    ///
+    /// Returns whether the message was such a command, in which case it mustn't be handled any
+    /// further.
+    pub async fn try_handle(
+        &self,
+        room: &Room,
+        ev: &OriginalSyncRoomMessageEvent,
+        content: &str,
+    ) -> anyhow::Result<bool> {
+        let Some(rest) = strip_reply_fallback(content).strip_prefix("!quote") else {
+            return Ok(false);
+        };
+        if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+            return Ok(false);
+        }
+
+        let response = self.handle(room, ev, rest.trim()).await?;
+        if !response.is_empty() {
+            outbox::send(room, RoomMessageEventContent::text_plain(response)).await?;
+        }
+        Ok(true)
+    }
+}
@@ -0,0 +1,158 @@
+//! Reactions of the modules: a `react` action adds one or several reactions of the bot to a
+//! message of the room, the one being handled by default, e.g. reaction buttons, and an `unreact`
+//! action removes them again, by redacting them.
+//!
+//! The reactions sent for the modules are remembered per room, to find the ones to redact, and
+//! not to send the same reaction twice, which homeservers refuse.
+
+use matrix_sdk::{
+    room::Room,
+    ruma::{
+        events::{reaction::ReactionEventContent, relation::Annotation},
+        EventId, OwnedEventId, RoomId,
+    },
+};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::{host_table, outbox, wasm, App, ShareableDatabase};
+
+/// Name of the host table keeping the reactions sent, per room.
+const TABLE: &str = "sent_reactions";
+/// Most reactions remembered per room; the oldest ones can't be removed past that.
+const MAX_PER_ROOM: usize = 500;
+/// Most reactions of a single action.
+const MAX_KEYS: usize = 20;
+
+#[derive(Serialize, Deserialize)]
+struct SentReaction {
+    /// The message reacted to.
+    target: String,
+    key: String,
+    /// The reaction's own event id, to redact it.
+    reaction: String,
+}
+
+pub(crate) struct SentReactions {
+    db: ShareableDatabase,
+}
+
+impl SentReactions {
+    pub fn new(db: ShareableDatabase) -> Self {
+        Self { db }
+    }
+
+    fn list(&self, room_id: &RoomId) -> anyhow::Result<Vec<SentReaction>> {
+        Ok(host_table::read_json(&self.db, TABLE, room_id.as_str())?.unwrap_or_default())
+    }
+
+    fn write(&self, room_id: &RoomId, reactions: &[SentReaction]) -> anyhow::Result<()> {
+        host_table::write_json(&self.db, TABLE, room_id.as_str(), &reactions)
+    }
+
+    fn contains(&self, room_id: &RoomId, target: &EventId, key: &str) -> anyhow::Result<bool> {
+        Ok(self
+            .list(room_id)?
+            .iter()
+            .any(|r| r.target == target.as_str() && r.key == key))
+    }
+
+    fn record(
+        &self,
+        room_id: &RoomId,
+        target: &EventId,
+        key: &str,
+        reaction: &EventId,
+    ) -> anyhow::Result<()> {
+        let mut reactions = self.list(room_id)?;
+        reactions.push(SentReaction {
+            target: target.to_string(),
+            key: key.to_owned(),
+            reaction: reaction.to_string(),
+        });
+        let excess = reactions.len().saturating_sub(MAX_PER_ROOM);
+        reactions.drain(..excess);
+        self.write(room_id, &reactions)
+    }
+
+    /// Forgets the reactions to the message with the given keys, or all of them if there are
+    /// none; returns their event ids.
+    fn take(
+        &self,
+        room_id: &RoomId,
+        target: &EventId,
+        keys: &[String],
+    ) -> anyhow::Result<Vec<OwnedEventId>> {
+        let (taken, kept): (Vec<_>, Vec<_>) = self.list(room_id)?.into_iter().partition(|r| {
+            r.target == target.as_str() && (keys.is_empty() || keys.contains(&r.key))
+        });
+        if !taken.is_empty() {
+            self.write(room_id, &kept)?;
+        }
+        Ok(taken
+            .into_iter()
+            .filter_map(|r| OwnedEventId::try_from(r.reaction).ok())
+            .collect())
+    }
+}
+
+/// The reactions of the action, with their shortcodes expanded, each at most once.
+fn keys(app: &App, module: &str, reaction: &wasm::Reaction) -> Vec<String> {
+    let mut keys = Vec::new();
+    for key in &reaction.keys {
+        let key = app.emoji.expand_text(key);
+        if !key.is_empty() && !keys.contains(&key) {
+            keys.push(key);
+        }
+    }
+    if keys.len() > MAX_KEYS {
+        warn!(
+            "module {module} reacted with {} reactions at once, keeping {MAX_KEYS}",
+            keys.len()
+        );
+        keys.truncate(MAX_KEYS);
+    }
+    keys
+}
+
+/// Adds the bot's reactions to the message of a `react` action, skipping the ones it already
+/// added.
+pub(crate) async fn react(
+    app: &App,
+    room: &Room,
+    module: &str,
+    reaction: wasm::Reaction,
+) -> anyhow::Result<()> {
+    let target = OwnedEventId::try_from(reaction.event_id.as_str())?;
+    for key in keys(app, module, &reaction) {
+        if app.sent_reactions.contains(room.room_id(), &target, &key)? {
+            debug!("{module} already reacted with {key} to {target}");
+            continue;
+        }
+        let content = ReactionEventContent::new(Annotation::new(target.clone(), key.clone()));
+        let event_id = outbox::send(room, content).await?;
+        app.sent_reactions
+            .record(room.room_id(), &target, &key, &event_id)?;
+    }
+    Ok(())
+}
+
+/// Removes the bot's reactions to the message of an `unreact` action, all of them if it lists
+/// none.
+pub(crate) async fn unreact(
+    app: &App,
+    room: &Room,
+    module: &str,
+    reaction: wasm::Reaction,
+) -> anyhow::Result<()> {
+    let target = OwnedEventId::try_from(reaction.event_id.as_str())?;
+    let keys = keys(app, module, &reaction);
+    let reactions = app.sent_reactions.take(room.room_id(), &target, &keys)?;
+    if reactions.is_empty() {
+        debug!("{module} removed reactions to {target} the bot didn't add, or forgot");
+    }
+    for event_id in reactions {
+        outbox::redact(room, &event_id, None).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,82 @@
+//! Suppression of repeated module responses: modules like karma or link titles tend to respond
+//! the same thing each time users paste the same link. A module's `suppress_repeats_minutes`
+//! configuration key makes the host drop its responses identical to one it sent in the same room
+//! within that many minutes.
+
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash as _, Hasher as _},
+    sync::Mutex,
+};
+
+use matrix_sdk::ruma::{OwnedRoomId, RoomId};
+use tokio::time::{Duration, Instant};
+use tracing::debug;
+
+use crate::wasm;
+
+/// Module configuration key setting the window during which repeated responses are dropped.
+const WINDOW_KEY: &str = "suppress_repeats_minutes";
+/// Most recent responses remembered per module and room.
+const MAX_REMEMBERED: usize = 100;
+
+fn fingerprint(msg: &wasm::Message) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    msg.text.hash(&mut hasher);
+    msg.html.hash(&mut hasher);
+    hasher.finish()
+}
+
+pub(crate) struct RepeatFilter {
+    /// Suppression window of each module that has one.
+    windows: HashMap<String, Duration>,
+    /// Fingerprints of the recent responses, with when they were sent, per module and room.
+    sent: Mutex<HashMap<(String, OwnedRoomId), Vec<(u64, Instant)>>>,
+}
+
+impl RepeatFilter {
+    pub fn new(modules_config: &HashMap<String, HashMap<String, String>>) -> anyhow::Result<Self> {
+        let mut windows = HashMap::new();
+        for (module, config) in modules_config {
+            let Some(minutes) = config.get(WINDOW_KEY) else {
+                continue;
+            };
+            let minutes: u64 = minutes.trim().parse().map_err(|err| {
+                anyhow::anyhow!("invalid {WINDOW_KEY} for module {module}: {err}")
+            })?;
+            if minutes > 0 {
+                windows.insert(module.clone(), Duration::from_secs(minutes * 60));
+            }
+        }
+        Ok(Self {
+            windows,
+            sent: Default::default(),
+        })
+    }
+
+    /// Whether the module already sent this response in the room recently, in which case it must
+    /// be dropped. Otherwise, remembers it.
+    pub fn is_repeat(&self, module: &str, room_id: &RoomId, msg: &wasm::Message) -> bool {
+        let Some(window) = self.windows.get(module) else {
+            return false;
+        };
+
+        let now = Instant::now();
+        let fingerprint = fingerprint(msg);
+        let mut sent = self.sent.lock().unwrap();
+        let recent = sent
+            .entry((module.to_owned(), room_id.to_owned()))
+            .or_default();
+        recent.retain(|(_, at)| now.duration_since(*at) < *window);
+
+        if recent.iter().any(|(fp, _)| *fp == fingerprint) {
+            debug!("dropping a repeated response of {module} in {room_id}");
+            return true;
+        }
+        if recent.len() >= MAX_REMEMBERED {
+            recent.remove(0);
+        }
+        recent.push((fingerprint, now));
+        false
+    }
+}
@@ -0,0 +1,132 @@
+//! `!admin host replay MODULE ROOM EVENT_ID`: replays a message against a fresh, sandboxed
+//! instance of a module, to diagnose what it did with it, e.g. yesterday. The trace of the replay,
+//! with every host call of the module (storage reads and writes, requests...) and the actions it
+//! returned, is sent to the admin in direct message, as a file.
+
+use matrix_sdk::{
+    attachment::AttachmentConfig,
+    ruma::{
+        events::{
+            room::message::MessageType, AnyMessageLikeEvent, AnyTimelineEvent, MessageLikeEvent,
+        },
+        OwnedEventId,
+    },
+    Client,
+};
+
+use crate::{
+    diagnostics::APP_CTX_LOCK,
+    outbox,
+    utils::{dm_room, resolve_room, split_args},
+    wasm::{self, ReplayedEvent, Trigger},
+    App,
+};
+
+const USAGE: &str = "usage: !admin host replay MODULE ROOM EVENT_ID";
+
+/// The message to replay, as the module was passed it.
+async fn fetch_event(
+    app: &App,
+    client: &Client,
+    room: &str,
+    event_id: &str,
+) -> anyhow::Result<ReplayedEvent> {
+    let room_id = resolve_room(client, room).await?;
+    let event_id = OwnedEventId::try_from(event_id)?;
+    let room = client
+        .get_room(&room_id)
+        .ok_or_else(|| anyhow::anyhow!("the bot doesn't know about {room_id}"))?;
+
+    let event = room.event(&event_id).await?;
+    let AnyTimelineEvent::MessageLike(AnyMessageLikeEvent::RoomMessage(
+        MessageLikeEvent::Original(event),
+    )) = event.event.deserialize()?
+    else {
+        anyhow::bail!("{event_id} isn't a message");
+    };
+    let trigger = match &event.content.msgtype {
+        MessageType::Text(text) => Trigger::Message(text.body.clone()),
+        msgtype => match wasm::describe_media(&event_id, msgtype) {
+            Some((_, media)) => Trigger::Media(media),
+            None => anyhow::bail!("{event_id} is neither a text nor a media message"),
+        },
+    };
+    let trust = app.trust.level(&room, &event.sender).await;
+    Ok(ReplayedEvent {
+        trigger,
+        sender: event.sender,
+        room: room_id,
+        trust,
+    })
+}
+
+async fn replay(
+    app: &App,
+    client: &Client,
+    module: &str,
+    room: &str,
+    event_id: &str,
+) -> anyhow::Result<String> {
+    let event = fetch_event(app, client, room, event_id).await?;
+
+    // The replay runs on its own instance, so the modules keep handling the messages meanwhile.
+    let (path, engine_config, config, db, directory) = {
+        let mut ctx = APP_CTX_LOCK.lock(&app.inner, "replay").await;
+        let (_, mut modules) = ctx.modules.iter();
+        let Some(path) = modules
+            .find(|loaded| loaded.name() == module)
+            .map(|loaded| loaded.path().to_owned())
+        else {
+            anyhow::bail!("module {module} not found");
+        };
+        (
+            path,
+            ctx.engine_config.clone(),
+            ctx.modules_config.get(module).cloned(),
+            ctx.db.clone(),
+            ctx.directory.clone(),
+        )
+    };
+    let client_copy = client.clone();
+    let trace = tokio::task::spawn_blocking(move || {
+        wasm::replay(
+            &engine_config,
+            &path,
+            config.as_ref(),
+            db,
+            directory,
+            client_copy,
+            &event,
+        )
+    })
+    .await??;
+
+    // The trace may reveal the module's data, so it's only sent to the admin.
+    let dm = dm_room(client, &app.admin_user_id).await?;
+    let filename = format!("replay-{event_id}.txt");
+    outbox::send_attachment(
+        &dm,
+        &filename,
+        &mime::TEXT_PLAIN_UTF_8,
+        trace.into_bytes(),
+        AttachmentConfig::new(),
+    )
+    .await?;
+    Ok(format!("replayed {event_id}, trace sent in direct message"))
+}
+
+/// Try to handle an `!admin host replay` command.
+pub(crate) async fn try_handle_admin(app: &App, client: &Client, content: &str) -> Option<String> {
+    let rest = content.strip_prefix("!admin host replay")?;
+    if !rest.is_empty() && !rest.starts_with(' ') {
+        return None;
+    }
+
+    let args = split_args(rest);
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+    let result = match args.as_slice() {
+        [module, room, event_id] => replay(app, client, module, room, event_id).await,
+        _ => Ok(USAGE.to_owned()),
+    };
+    Some(result.unwrap_or_else(|err| format!("error: {err:#}")))
+}
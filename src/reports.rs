@@ -0,0 +1,142 @@
+//! `!report [reason]`, used as a reply to a message, forwards the message to the moderators.
+
+use std::collections::HashMap;
+
+use matrix_sdk::{
+    room::Room,
+    ruma::{
+        events::{
+            reaction::ReactionEventContent,
+            relation::Annotation,
+            room::message::{OriginalSyncRoomMessageEvent, Relation, RoomMessageEventContent},
+        },
+        OwnedRoomId, OwnedUserId,
+    },
+    Client,
+};
+use serde::Deserialize;
+
+use crate::{admin_dm, host_table, outbox, utils::strip_reply_fallback, ShareableDatabase};
+
+/// Name of the host table keeping track of the reported events, for deduplication.
+const TABLE: &str = "reports";
+
+/// Configuration for the reports.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ReportsConfig {
+    /// moderation room to forward the reports to, for each room.
+    #[serde(default)]
+    pub moderation_rooms: HashMap<OwnedRoomId, OwnedRoomId>,
+    /// moderation room for the rooms not listed above. If missing, reports are sent to the admin
+    /// by DM.
+    pub default_moderation_room: Option<OwnedRoomId>,
+}
+
+/// The parts of the reported event we're interested in.
+#[derive(Deserialize)]
+struct ReportedEvent {
+    sender: OwnedUserId,
+    content: ReportedContent,
+}
+
+#[derive(Deserialize)]
+struct ReportedContent {
+    body: Option<String>,
+}
+
+pub(crate) struct Reports {
+    config: ReportsConfig,
+    db: ShareableDatabase,
+    admin_user_id: OwnedUserId,
+}
+
+impl Reports {
+    pub fn new(config: ReportsConfig, db: ShareableDatabase, admin_user_id: OwnedUserId) -> Self {
+        Self {
+            config,
+            db,
+            admin_user_id,
+        }
+    }
+
+    async fn forward(&self, client: &Client, room: &Room, text: &str) -> anyhow::Result<()> {
+        let moderation_room = self
+            .config
+            .moderation_rooms
+            .get(room.room_id())
+            .or(self.config.default_moderation_room.as_ref())
+            .and_then(|room_id| client.get_room(room_id));
+        match moderation_room {
+            Some(moderation_room) => {
+                outbox::send(&moderation_room, RoomMessageEventContent::text_plain(text)).await?;
+            }
+            None => admin_dm::notify(client, &self.admin_user_id, text, None).await?,
+        }
+        Ok(())
+    }
+
+    async fn report(
+        &self,
+        client: &Client,
+        room: &Room,
+        ev: &OriginalSyncRoomMessageEvent,
+        reason: &str,
+    ) -> anyhow::Result<()> {
+        let Some(Relation::Reply { in_reply_to }) = &ev.content.relates_to else {
+            let text = "use !report as a reply to the message you want to report";
+            outbox::send(room, RoomMessageEventContent::text_plain(text)).await?;
+            return Ok(());
+        };
+        let reported_id = &in_reply_to.event_id;
+
+        if host_table::read(&self.db, TABLE, reported_id.as_str())?.is_some() {
+            let text = "this message has already been reported, thanks!";
+            outbox::send(room, RoomMessageEventContent::text_plain(text)).await?;
+            return Ok(());
+        }
+
+        let reported = room.event(reported_id).await?;
+        let reported = reported.event.deserialize_as::<ReportedEvent>()?;
+        let reason = if reason.is_empty() {
+            "no reason given"
+        } else {
+            reason
+        };
+        let text = format!(
+            "{reporter} reported a message from {sender} in {room} ({reported_id}): {reason}\n> {body}",
+            reporter = ev.sender,
+            sender = reported.sender,
+            room = room.room_id(),
+            body = reported.content.body.as_deref().unwrap_or("<no text>"),
+        );
+        self.forward(client, room, &text).await?;
+
+        host_table::write(&self.db, TABLE, reported_id.as_str(), ev.sender.as_str().as_bytes())?;
+
+        // Acknowledge the report discreetly.
+        let ack = ReactionEventContent::new(Annotation::new(ev.event_id.clone(), "✅".to_owned()));
+        outbox::send(room, ack).await?;
+        Ok(())
+    }
+
+    /// Try to handle a message assuming it's a `!report` command.
+    ///
+    /// Returns whether the message was such a command, in which case it mustn't be handled any
+    /// further.
+    pub async fn try_handle(
+        &self,
+        client: &Client,
+        room: &Room,
+        ev: &OriginalSyncRoomMessageEvent,
+        content: &str,
+    ) -> anyhow::Result<bool> {
+        let Some(rest) = strip_reply_fallback(content).strip_prefix("!report") else {
+            return Ok(false);
+        };
+        if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+            return Ok(false);
+        }
+        self.report(client, room, ev, rest.trim()).await?;
+        Ok(true)
+    }
+}
@@ -0,0 +1,156 @@
+//! Limits on the responses of the modules, so that a misbehaving module can't flood a room with
+//! hundreds of actions or huge messages.
+
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::wasm;
+
+/// Configuration for the limits on the modules' responses.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ResponseLimitsConfig {
+    /// maximum number of actions a module may emit for a single trigger.
+    #[serde(default = "default_max_actions")]
+    pub max_actions: usize,
+    /// maximum size of a single message, in bytes.
+    #[serde(default = "default_max_message_bytes")]
+    pub max_message_bytes: usize,
+    /// maximum size of a single uploaded file, in bytes.
+    #[serde(default = "default_max_upload_bytes")]
+    pub max_upload_bytes: usize,
+    /// size of the pages longer messages are split into, in bytes.
+    #[serde(default = "default_page_bytes")]
+    pub page_bytes: usize,
+}
+
+impl Default for ResponseLimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_actions: default_max_actions(),
+            max_message_bytes: default_max_message_bytes(),
+            max_upload_bytes: default_max_upload_bytes(),
+            page_bytes: default_page_bytes(),
+        }
+    }
+}
+
+fn default_max_actions() -> usize {
+    20
+}
+
+fn default_max_message_bytes() -> usize {
+    32 * 1024
+}
+
+fn default_max_upload_bytes() -> usize {
+    10 * 1024 * 1024
+}
+
+fn default_page_bytes() -> usize {
+    8 * 1024
+}
+
+/// Notice appended to the truncated messages.
+const TRUNCATED_NOTICE: &str = "… (truncated)";
+
+/// Truncates `text` to at most `max` bytes, on a char boundary.
+fn truncate(text: &mut String, max: usize) {
+    let mut end = max.min(text.len());
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    text.truncate(end);
+}
+
+pub(crate) struct ResponseLimits {
+    config: ResponseLimitsConfig,
+}
+
+impl ResponseLimits {
+    pub fn new(config: ResponseLimitsConfig) -> Self {
+        Self { config }
+    }
+
+    /// Size of the pages longer messages are split into, in bytes.
+    pub fn page_bytes(&self) -> usize {
+        self.config.page_bytes
+    }
+
+    fn limit_message(&self, module: &str, msg: &mut wasm::Message) {
+        let max = self.config.max_message_bytes;
+        let html_too_long = msg.html.as_ref().map_or(false, |html| html.len() > max);
+        if msg.text.len() <= max && !html_too_long {
+            return;
+        }
+
+        warn!(
+            "module {module} sent a message of {} bytes, truncating it to {max}",
+            msg.text
+                .len()
+                .max(msg.html.as_ref().map_or(0, |html| html.len()))
+        );
+        if msg.text.len() > max {
+            truncate(&mut msg.text, max.saturating_sub(TRUNCATED_NOTICE.len()));
+            msg.text.push_str(TRUNCATED_NOTICE);
+        }
+        if html_too_long {
+            // Cutting HTML would leave unbalanced tags, so fall back to the text.
+            msg.html = None;
+        }
+    }
+
+    /// Applies the limits to the actions emitted by a module for a single trigger.
+    pub fn apply(&self, module: &str, mut actions: Vec<wasm::Action>) -> Vec<wasm::Action> {
+        let max = self.config.max_actions;
+        let mut notice = None;
+        if actions.len() > max {
+            // Keep room for the notice.
+            let kept = max.saturating_sub(1);
+            let dropped = actions.split_off(kept);
+            warn!(
+                "module {module} emitted {} actions, dropping {} of them",
+                kept + dropped.len(),
+                dropped.len()
+            );
+            let to = dropped.iter().find_map(|action| match action {
+                wasm::Action::Respond(msg) | wasm::Action::Reply(msg) => Some(msg.to.clone()),
+                _ => None,
+            });
+            if max > 0 {
+                notice = Some(wasm::Message {
+                    text: format!(
+                        "({} more responses from {module} were dropped)",
+                        dropped.len()
+                    ),
+                    html: None,
+                    to: to.unwrap_or_default(),
+                    notice: None,
+                    urgency: None,
+                });
+            }
+        }
+
+        for action in &mut actions {
+            if let wasm::Action::Respond(msg) | wasm::Action::Reply(msg) = action {
+                self.limit_message(module, msg);
+            }
+        }
+
+        let max_upload = self.config.max_upload_bytes;
+        actions.retain(|action| match action {
+            wasm::Action::Upload(upload) if upload.data.len() > max_upload => {
+                warn!(
+                    "module {module} uploaded {} of {} bytes, over the limit of {max_upload}, \
+                     dropping it",
+                    upload.filename,
+                    upload.data.len()
+                );
+                false
+            }
+            _ => true,
+        });
+
+        actions.extend(notice.map(wasm::Action::Respond));
+        actions
+    }
+}
@@ -0,0 +1,126 @@
+//! `!admin host dump-room <room>`: what the bot believes about a room, sent to the admin in a
+//! direct message, to debug why the bot doesn't respond somewhere.
+
+use std::fmt::Write as _;
+
+use matrix_sdk::{room::Room, Client};
+
+use crate::{admin_dm, diagnostics::APP_CTX_LOCK, utils::resolve_room, App};
+
+fn yes_no(value: bool) -> &'static str {
+    if value {
+        "yes"
+    } else {
+        "no"
+    }
+}
+
+/// Names of the loaded modules.
+async fn module_names(app: &App) -> anyhow::Result<Vec<String>> {
+    let app_ctx = app.inner.clone();
+    Ok(tokio::task::spawn_blocking(move || {
+        let ctx = &mut *futures::executor::block_on(APP_CTX_LOCK.lock(&app_ctx, "room dump"));
+        let (_, modules) = ctx.modules.iter();
+        modules.map(|module| module.name().to_owned()).collect()
+    })
+    .await?)
+}
+
+async fn dump(app: &App, client: &Client, room: &Room) -> anyhow::Result<String> {
+    let room_id = room.room_id().to_owned();
+    let mut out = format!("{room_id}\n");
+
+    let _ = writeln!(out, "display name: {}", room.display_name().await?);
+    let _ = writeln!(out, "state: {:?}", room.state());
+    match room.canonical_alias() {
+        Some(alias) => {
+            let _ = writeln!(out, "canonical alias: {alias}");
+        }
+        None => out.push_str("canonical alias: none\n"),
+    }
+    let alt_aliases = room.alt_aliases();
+    if !alt_aliases.is_empty() {
+        let aliases = alt_aliases.iter().map(|a| a.as_str()).collect::<Vec<_>>();
+        let _ = writeln!(out, "other aliases: {}", aliases.join(", "));
+    }
+    let _ = writeln!(out, "encrypted: {}", yes_no(room.is_encrypted().await?));
+    let _ = writeln!(out, "joined members: {}", room.joined_members_count());
+
+    if let Some(bot_id) = client.user_id() {
+        match room.get_member(bot_id).await? {
+            Some(member) => {
+                let _ = writeln!(out, "bot power level: {}", member.power_level());
+                let _ = writeln!(
+                    out,
+                    "bot may redact: {}",
+                    yes_no(room.can_user_redact(bot_id).await?)
+                );
+            }
+            None => out.push_str("bot power level: not a member\n"),
+        }
+    }
+
+    // Modules aren't enabled per room: all of them see every message.
+    let _ = writeln!(out, "modules: {}", module_names(app).await?.join(", "));
+
+    out.push_str("stored settings:\n");
+    let summaries = [
+        app.slowmode.room_summary(&room_id),
+        app.room_policies.room_summary(&room_id),
+        app.tickets.room_summary(&room_id),
+        app.votes.room_summary(&room_id),
+        app.rsvps.room_summary(&room_id),
+        app.quotes.room_summary(&room_id),
+    ];
+    let mut any = false;
+    for summary in summaries {
+        match summary {
+            Ok(Some(summary)) => {
+                any = true;
+                let _ = writeln!(out, "  {summary}");
+            }
+            Ok(None) => {}
+            Err(err) => {
+                any = true;
+                let _ = writeln!(out, "  error when reading a setting: {err:#}");
+            }
+        }
+    }
+    if !any {
+        out.push_str("  none\n");
+    }
+
+    Ok(out)
+}
+
+/// Try to handle a message assuming it's an `!admin host dump-room <room>` command.
+pub(crate) async fn try_handle_admin(app: &App, client: &Client, content: &str) -> Option<String> {
+    let rest = content.strip_prefix("!admin host dump-room")?;
+    if !rest.is_empty() && !rest.starts_with(' ') {
+        return None;
+    }
+    let target = rest.trim();
+    if target.is_empty() {
+        return Some("usage: !admin host dump-room ROOM".to_owned());
+    }
+
+    let room_id = match resolve_room(client, target).await {
+        Ok(room_id) => room_id,
+        Err(err) => return Some(format!("couldn't resolve room {target}: {err:#}")),
+    };
+    let Some(room) = client.get_room(&room_id) else {
+        return Some(format!("the bot doesn't know about {target}"));
+    };
+
+    let report = match dump(app, client, &room).await {
+        Ok(report) => report,
+        Err(err) => return Some(format!("error when dumping {target}: {err:#}")),
+    };
+    // The dump may reveal things about the room, so it's only sent to the admin.
+    Some(
+        match admin_dm::notify(client, &app.admin_user_id, &report, None).await {
+            Ok(()) => format!("dump of {target} sent in direct message"),
+            Err(err) => format!("couldn't send the dump of {target}: {err:#}"),
+        },
+    )
+}
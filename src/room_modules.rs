@@ -0,0 +1,239 @@
+//! Modules enabled per room: the configuration can restrict a room to some modules, or disable
+//! some modules in a room, e.g. a meme module in the work rooms, and the admin can toggle modules
+//! in a room at runtime with `!admin host modules`, overriding the configuration. A module disabled
+//! in a room isn't passed its messages. Modules can also be disabled in all the rooms at once,
+//! e.g. from the dashboard.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    sync::Mutex,
+};
+
+use matrix_sdk::{
+    ruma::{OwnedRoomId, RoomId},
+    Client,
+};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{
+    host_table,
+    utils::{resolve_room, split_args},
+    ShareableDatabase,
+};
+
+/// Name of the host table keeping the runtime overrides, by room id.
+const TABLE: &str = "room_modules";
+/// Key of the modules disabled in all the rooms, which can't be a room id.
+const DISABLED_EVERYWHERE_KEY: &str = "disabled_everywhere";
+
+const USAGE: &str = "usage: !admin host modules ROOM [enable MODULE | disable MODULE | reset]";
+
+/// Configuration of the modules of a room.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct RoomModulesConfig {
+    /// the only modules enabled in the room, if set.
+    pub enabled: Option<Vec<String>>,
+    /// modules disabled in the room.
+    #[serde(default)]
+    pub disabled: Vec<String>,
+}
+
+pub(crate) struct RoomModules {
+    config: HashMap<OwnedRoomId, RoomModulesConfig>,
+    db: ShareableDatabase,
+    /// Whether modules are enabled, by room and module, as toggled by the admin; filled lazily.
+    overrides: Mutex<HashMap<OwnedRoomId, BTreeMap<String, bool>>>,
+    /// Modules disabled in all the rooms; read lazily.
+    disabled_everywhere: Mutex<Option<BTreeSet<String>>>,
+}
+
+impl RoomModules {
+    pub fn new(config: HashMap<OwnedRoomId, RoomModulesConfig>, db: ShareableDatabase) -> Self {
+        Self {
+            config,
+            db,
+            overrides: Default::default(),
+            disabled_everywhere: Default::default(),
+        }
+    }
+
+    /// The modules disabled in all the rooms.
+    pub fn disabled_everywhere(&self) -> anyhow::Result<BTreeSet<String>> {
+        let mut disabled = self.disabled_everywhere.lock().unwrap();
+        if let Some(disabled) = &*disabled {
+            return Ok(disabled.clone());
+        }
+        let read: BTreeSet<String> =
+            host_table::read_json(&self.db, TABLE, DISABLED_EVERYWHERE_KEY)?.unwrap_or_default();
+        *disabled = Some(read.clone());
+        Ok(read)
+    }
+
+    /// Enables or disables the module in all the rooms; a module enabled back is enabled in the
+    /// rooms where it's configured or toggled so.
+    pub fn set_enabled_everywhere(&self, module: &str, enabled: bool) -> anyhow::Result<()> {
+        let mut disabled = self.disabled_everywhere()?;
+        if enabled {
+            disabled.remove(module);
+        } else {
+            disabled.insert(module.to_owned());
+        }
+        if disabled.is_empty() {
+            host_table::remove(&self.db, TABLE, DISABLED_EVERYWHERE_KEY)?;
+        } else {
+            host_table::write_json(&self.db, TABLE, DISABLED_EVERYWHERE_KEY, &disabled)?;
+        }
+        *self.disabled_everywhere.lock().unwrap() = Some(disabled);
+        Ok(())
+    }
+
+    /// The configuration of the room, and the modules toggled by the admin in it.
+    pub fn room_settings(
+        &self,
+        room_id: &RoomId,
+    ) -> anyhow::Result<(RoomModulesConfig, BTreeMap<String, bool>)> {
+        let config = self.config.get(room_id).cloned().unwrap_or_default();
+        Ok((config, self.read_overrides(room_id)?))
+    }
+
+    fn read_overrides(&self, room_id: &RoomId) -> anyhow::Result<BTreeMap<String, bool>> {
+        if let Some(overrides) = self.overrides.lock().unwrap().get(room_id) {
+            return Ok(overrides.clone());
+        }
+        let overrides: BTreeMap<String, bool> =
+            host_table::read_json(&self.db, TABLE, room_id.as_str())?.unwrap_or_default();
+        self.overrides
+            .lock()
+            .unwrap()
+            .insert(room_id.to_owned(), overrides.clone());
+        Ok(overrides)
+    }
+
+    fn write_overrides(
+        &self,
+        room_id: &RoomId,
+        overrides: BTreeMap<String, bool>,
+    ) -> anyhow::Result<()> {
+        if overrides.is_empty() {
+            host_table::remove(&self.db, TABLE, room_id.as_str())?;
+        } else {
+            host_table::write_json(&self.db, TABLE, room_id.as_str(), &overrides)?;
+        }
+        self.overrides
+            .lock()
+            .unwrap()
+            .insert(room_id.to_owned(), overrides);
+        Ok(())
+    }
+
+    /// Whether the module is enabled in the room, as configured.
+    fn is_configured(&self, room_id: &RoomId, module: &str) -> bool {
+        let Some(config) = self.config.get(room_id) else {
+            return true;
+        };
+        let module = module.to_owned();
+        config
+            .enabled
+            .as_ref()
+            .map_or(true, |enabled| enabled.contains(&module))
+            && !config.disabled.contains(&module)
+    }
+
+    /// Whether the module is enabled in the room.
+    pub fn is_enabled(&self, room_id: &RoomId, module: &str) -> bool {
+        match self.disabled_everywhere() {
+            Ok(disabled) if disabled.contains(module) => return false,
+            Ok(_) => {}
+            Err(err) => warn!("couldn't read the modules disabled everywhere: {err:#}"),
+        }
+        match self.read_overrides(room_id) {
+            Ok(overrides) => match overrides.get(module) {
+                Some(enabled) => *enabled,
+                None => self.is_configured(room_id, module),
+            },
+            Err(err) => {
+                warn!("couldn't read the modules toggled in {room_id}: {err:#}");
+                self.is_configured(room_id, module)
+            }
+        }
+    }
+
+    fn describe(&self, room_id: &RoomId, room: &str) -> anyhow::Result<String> {
+        let mut lines = Vec::new();
+        if let Some(config) = self.config.get(room_id) {
+            if let Some(enabled) = &config.enabled {
+                lines.push(format!("configured to only enable: {}", enabled.join(", ")));
+            }
+            if !config.disabled.is_empty() {
+                lines.push(format!(
+                    "configured to disable: {}",
+                    config.disabled.join(", ")
+                ));
+            }
+        }
+        for (module, enabled) in self.read_overrides(room_id)? {
+            let state = if enabled { "enabled" } else { "disabled" };
+            lines.push(format!("{module}: {state} by the admin"));
+        }
+        let disabled = self.disabled_everywhere()?;
+        if !disabled.is_empty() {
+            let disabled = disabled.into_iter().collect::<Vec<_>>();
+            lines.push(format!(
+                "disabled in all the rooms: {}",
+                disabled.join(", ")
+            ));
+        }
+        if lines.is_empty() {
+            return Ok(format!("all the modules are enabled in {room}"));
+        }
+        Ok(lines.join("\n"))
+    }
+
+    pub fn toggle(
+        &self,
+        room_id: &RoomId,
+        room: &str,
+        module: &str,
+        enabled: bool,
+    ) -> anyhow::Result<String> {
+        let mut overrides = self.read_overrides(room_id)?;
+        if self.is_configured(room_id, module) == enabled {
+            overrides.remove(module);
+        } else {
+            overrides.insert(module.to_owned(), enabled);
+        }
+        self.write_overrides(room_id, overrides)?;
+        let state = if enabled { "enabled" } else { "disabled" };
+        Ok(format!("{module} is {state} in {room}"))
+    }
+
+    /// Try to handle an `!admin host modules` command.
+    pub async fn try_handle_admin(&self, client: &Client, content: &str) -> Option<String> {
+        let rest = content.strip_prefix("!admin host modules")?;
+        if !rest.is_empty() && !rest.starts_with(' ') {
+            return None;
+        }
+
+        let args = split_args(rest);
+        let Some((room, args)) = args.split_first() else {
+            return Some(USAGE.to_owned());
+        };
+        let room_id = match resolve_room(client, room).await {
+            Ok(room_id) => room_id,
+            Err(err) => return Some(format!("couldn't resolve room {room}: {err:#}")),
+        };
+
+        let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+        let result = match args.as_slice() {
+            [] => self.describe(&room_id, room),
+            ["enable", module] => self.toggle(&room_id, room, module, true),
+            ["disable", module] => self.toggle(&room_id, room, module, false),
+            ["reset"] => self
+                .write_overrides(&room_id, BTreeMap::new())
+                .map(|()| format!("back to the configured modules in {room}")),
+            _ => Ok(USAGE.to_owned()),
+        };
+        Some(result.unwrap_or_else(|err| format!("error: {err:#}")))
+    }
+}
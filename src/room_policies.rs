@@ -0,0 +1,265 @@
+//! Per-room content policies (e.g. "images only", "no links from new members"), enforced by
+//! redacting offending messages, or warning their senders if the bot can't redact.
+
+use std::{collections::HashMap, sync::Mutex};
+
+use matrix_sdk::{
+    room::Room,
+    ruma::{
+        events::room::{
+            member::{MembershipChange, OriginalSyncRoomMemberEvent},
+            message::{MessageType, OriginalSyncRoomMessageEvent, RoomMessageEventContent},
+        },
+        OwnedRoomId, UserId,
+    },
+    Client,
+};
+use tokio::time::Duration;
+use tracing::debug;
+
+use crate::{
+    host_table, outbox,
+    utils::{is_moderator, now_secs, resolve_room},
+    ShareableDatabase,
+};
+
+/// Name of the host table keeping the policies of each room.
+const TABLE: &str = "room_policies";
+
+/// Name of the host table keeping when members joined rooms with the `no-new-links` policy.
+const JOINS_TABLE: &str = "room_policies_joins";
+
+/// For how long members are considered new after joining a room.
+const NEW_MEMBER_DELAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Policy {
+    /// only images are allowed.
+    ImagesOnly,
+    /// only media (images, videos, audio, files) are allowed.
+    MediaOnly,
+    /// only messages containing links are allowed.
+    LinksOnly,
+    /// members who joined recently can't post links.
+    NoNewLinks,
+}
+
+impl Policy {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "images-only" => Self::ImagesOnly,
+            "media-only" => Self::MediaOnly,
+            "links-only" => Self::LinksOnly,
+            "no-new-links" => Self::NoNewLinks,
+            _ => return None,
+        })
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::ImagesOnly => "images-only",
+            Self::MediaOnly => "media-only",
+            Self::LinksOnly => "links-only",
+            Self::NoNewLinks => "no-new-links",
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            Self::ImagesOnly => "only images are allowed in this room",
+            Self::MediaOnly => "only media are allowed in this room",
+            Self::LinksOnly => "only links are allowed in this room",
+            Self::NoNewLinks => "new members can't post links in this room",
+        }
+    }
+}
+
+fn has_link(msgtype: &MessageType) -> bool {
+    let body = match msgtype {
+        MessageType::Text(text) => &text.body,
+        MessageType::Notice(notice) => &notice.body,
+        MessageType::Emote(emote) => &emote.body,
+        _ => return false,
+    };
+    body.contains("http://") || body.contains("https://")
+}
+
+pub(crate) struct RoomPolicies {
+    db: ShareableDatabase,
+    /// Cache of the policies stored in the database.
+    policies: Mutex<HashMap<OwnedRoomId, Vec<Policy>>>,
+}
+
+impl RoomPolicies {
+    pub fn new(db: ShareableDatabase) -> Self {
+        Self {
+            db,
+            policies: Default::default(),
+        }
+    }
+
+    fn policies(&self, room_id: &OwnedRoomId) -> anyhow::Result<Vec<Policy>> {
+        if let Some(policies) = self.policies.lock().unwrap().get(room_id) {
+            return Ok(policies.clone());
+        }
+        let policies = host_table::read(&self.db, TABLE, room_id.as_str())?
+            .map(String::from_utf8)
+            .transpose()?
+            .map(|list| list.split(',').filter_map(Policy::parse).collect())
+            .unwrap_or_default();
+        self.policies
+            .lock()
+            .unwrap()
+            .insert(room_id.clone(), policies.clone());
+        Ok(policies)
+    }
+
+    /// Describes the room's setting, if any, for `!admin host dump-room`.
+    pub fn room_summary(&self, room_id: &OwnedRoomId) -> anyhow::Result<Option<String>> {
+        let policies = self.policies(room_id)?;
+        Ok((!policies.is_empty()).then(|| {
+            let names = policies.iter().map(|p| p.name()).collect::<Vec<_>>();
+            format!("policies: {}", names.join(", "))
+        }))
+    }
+
+    fn set_policies(&self, room_id: OwnedRoomId, policies: Vec<Policy>) -> anyhow::Result<()> {
+        if policies.is_empty() {
+            host_table::remove(&self.db, TABLE, room_id.as_str())?;
+        } else {
+            let list = policies.iter().map(|p| p.name()).collect::<Vec<_>>().join(",");
+            host_table::write(&self.db, TABLE, room_id.as_str(), list.as_bytes())?;
+        }
+        self.policies.lock().unwrap().insert(room_id, policies);
+        Ok(())
+    }
+
+    /// Remembers when members join rooms where new members are restricted.
+    pub fn on_member(&self, ev: &OriginalSyncRoomMemberEvent, room: &Room) -> anyhow::Result<()> {
+        if !matches!(ev.membership_change(), MembershipChange::Joined) {
+            return Ok(());
+        }
+        if !self
+            .policies(&room.room_id().to_owned())?
+            .contains(&Policy::NoNewLinks)
+        {
+            return Ok(());
+        }
+        let key = format!("{}|{}", room.room_id(), ev.state_key);
+        host_table::write_u64(&self.db, JOINS_TABLE, &key, now_secs())
+    }
+
+    fn is_new_member(&self, room: &Room, user_id: &UserId) -> anyhow::Result<bool> {
+        let key = format!("{}|{user_id}", room.room_id());
+        let joined = host_table::read_u64(&self.db, JOINS_TABLE, &key)?;
+        Ok(joined > 0 && now_secs().saturating_sub(joined) < NEW_MEMBER_DELAY.as_secs())
+    }
+
+    /// Returns the first policy this message violates, if any.
+    fn violation(
+        &self,
+        room: &Room,
+        ev: &OriginalSyncRoomMessageEvent,
+    ) -> anyhow::Result<Option<Policy>> {
+        let msgtype = &ev.content.msgtype;
+        for policy in self.policies(&room.room_id().to_owned())? {
+            let violated = match policy {
+                Policy::ImagesOnly => !matches!(msgtype, MessageType::Image(_)),
+                Policy::MediaOnly => !matches!(
+                    msgtype,
+                    MessageType::Image(_)
+                        | MessageType::Video(_)
+                        | MessageType::Audio(_)
+                        | MessageType::File(_)
+                ),
+                Policy::LinksOnly => !has_link(msgtype),
+                Policy::NoNewLinks => has_link(msgtype) && self.is_new_member(room, &ev.sender)?,
+            };
+            if violated {
+                return Ok(Some(policy));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Enforces the room's policies for the given message. The admin and the room's moderators
+    /// are exempt.
+    ///
+    /// Returns whether the message violated a policy, in which case it mustn't be handled any
+    /// further.
+    pub async fn check(
+        &self,
+        client: &Client,
+        room: &Room,
+        ev: &OriginalSyncRoomMessageEvent,
+        admin_user_id: &UserId,
+    ) -> anyhow::Result<bool> {
+        let Some(policy) = self.violation(room, ev)? else {
+            return Ok(false);
+        };
+        if &*ev.sender == admin_user_id || is_moderator(room, &ev.sender).await? {
+            return Ok(false);
+        }
+
+        debug!("{} violated the {} policy in {}", ev.sender, policy.name(), room.room_id());
+        let bot_user_id = client.user_id().unwrap();
+        if room.can_user_redact(bot_user_id).await? {
+            outbox::redact(room, &ev.event_id, Some(policy.description())).await?;
+        } else {
+            let text = format!("{}, {}.", ev.sender, policy.description());
+            outbox::send(room, RoomMessageEventContent::text_plain(text)).await?;
+        }
+
+        Ok(true)
+    }
+
+    /// Try to handle a message assuming it's an `!admin host policy ROOM POLICIES` command.
+    pub async fn try_handle_admin(&self, client: &Client, content: &str) -> Option<String> {
+        let rest = content.strip_prefix("!admin host policy")?;
+        if !rest.is_empty() && !rest.starts_with(' ') {
+            return None;
+        }
+
+        let usage = "usage: !admin host policy ROOM (off|POLICY...), with policies among \
+            images-only, media-only, links-only, no-new-links";
+        let mut words = rest.split_whitespace();
+        let Some(room) = words.next() else {
+            return Some(usage.to_owned());
+        };
+
+        let room_id = match resolve_room(client, room).await {
+            Ok(room_id) => room_id,
+            Err(err) => return Some(format!("couldn't resolve room {room}: {err:#}")),
+        };
+
+        let words = words.collect::<Vec<_>>();
+        if words.is_empty() {
+            return Some(match self.policies(&room_id) {
+                Ok(policies) if policies.is_empty() => format!("no policies in {room}"),
+                Ok(policies) => format!(
+                    "policies in {room}: {}",
+                    policies.iter().map(|p| p.name()).collect::<Vec<_>>().join(", ")
+                ),
+                Err(err) => format!("error when reading policies: {err:#}"),
+            });
+        }
+
+        let policies = if words == ["off"] {
+            Vec::new()
+        } else {
+            let Some(policies) = words
+                .iter()
+                .map(|w| Policy::parse(w))
+                .collect::<Option<Vec<_>>>()
+            else {
+                return Some(usage.to_owned());
+            };
+            policies
+        };
+
+        Some(match self.set_policies(room_id, policies) {
+            Ok(()) => format!("policies updated for {room}"),
+            Err(err) => format!("error when setting policies: {err:#}"),
+        })
+    }
+}
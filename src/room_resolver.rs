@@ -5,6 +5,46 @@ use matrix_sdk::{
     Client,
 };
 
+/// Prefixes of the matrix.to links to rooms.
+const MATRIX_TO_PREFIXES: &[&str] = &["https://matrix.to/#/", "http://matrix.to/#/"];
+
+/// Decodes the percent-encoded characters of the text, e.g. `%23` into `#`.
+pub(crate) fn percent_decode(text: &str) -> String {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let decoded = (byte == b'%')
+            .then(|| std::str::from_utf8(tail.get(..2)?).ok())
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match decoded {
+            Some(decoded) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            None => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// The room id or alias of a matrix.to link, e.g. `https://matrix.to/#/%23room:example.com`, or
+/// the given text if it isn't a link.
+fn strip_matrix_to(room: &str) -> String {
+    let Some(link) = MATRIX_TO_PREFIXES
+        .iter()
+        .find_map(|prefix| room.strip_prefix(prefix))
+    else {
+        return room.to_owned();
+    };
+    // Drop the `?via=` parameters, and the event of links to events.
+    let link = link.split(['?', '/']).next().unwrap_or_default();
+    percent_decode(link)
+}
+
 pub(super) struct RoomResolver {
     client: Client,
     /// In-memory cache for the room alias to room id mapping.
@@ -19,7 +59,11 @@ impl RoomResolver {
         }
     }
 
+    /// Resolves a room id, alias or matrix.to link into a room id; none if it's not meant to be a
+    /// room.
     pub fn resolve_room(&mut self, room: &str) -> anyhow::Result<Option<String>> {
+        let room = strip_matrix_to(room.trim());
+        let room = room.as_str();
         if !room.starts_with('#') && !room.starts_with('!') {
             // This is likely not meant to be a room.
             return Ok(None);
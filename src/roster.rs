@@ -0,0 +1,313 @@
+//! Room memberships synced from an external roster, for organizations managing access centrally:
+//! the roster maps users to the rooms they should be in, and is periodically reconciled with the
+//! rooms' memberships, inviting the missing users and optionally removing the extra ones.
+//! `!admin host roster dry-run` reports what a sync would change, without changing anything.
+//!
+//! The roster is a JSON object, e.g. `{"@alice:example.com": ["#ops:example.com"]}`, read from a
+//! file or an HTTP endpoint; directories like LDAP are plugged in through an endpoint exporting
+//! their groups in that format.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use anyhow::Context as _;
+use matrix_sdk::{
+    ruma::{events::room::message::RoomMessageEventContent, OwnedRoomId, OwnedUserId},
+    Client, RoomMemberships,
+};
+use serde::Deserialize;
+use tokio::{
+    sync::Mutex,
+    time::{sleep, Duration},
+};
+use tracing::{error, info, warn};
+
+use crate::{
+    outbox,
+    utils::{is_moderator, resolve_room},
+};
+
+/// Configuration for the roster sync.
+#[derive(Clone, Debug, Deserialize)]
+pub struct RosterConfig {
+    /// where the roster is read from: a file path, or an `http://` or `https://` URL.
+    pub source: String,
+    /// bearer token the roster endpoint is requested with, if any.
+    pub token: Option<String>,
+    /// delay between two syncs, in minutes.
+    #[serde(default = "default_interval_minutes")]
+    pub interval_minutes: u64,
+    /// whether members missing from the roster are removed from the managed rooms. Moderators, the
+    /// admin and the bot are never removed.
+    #[serde(default)]
+    pub remove_extras: bool,
+    /// rooms (ids or aliases) managed in addition to the ones in the roster, so that they can be
+    /// emptied by removing them from the roster.
+    #[serde(default)]
+    pub rooms: Vec<String>,
+    /// room where the changes made by the periodic syncs are reported.
+    pub report_room: Option<OwnedRoomId>,
+}
+
+fn default_interval_minutes() -> u64 {
+    60
+}
+
+/// Timeout of the requests to the roster endpoint.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+/// Reason given when removing a member missing from the roster.
+const REMOVAL_REASON: &str = "not in the roster";
+
+const USAGE: &str = "usage: !admin host roster (sync | dry-run)";
+
+/// A membership change needed to match the roster.
+#[derive(Clone, Debug)]
+enum Change {
+    Invite(OwnedUserId),
+    Remove(OwnedUserId),
+}
+
+impl Change {
+    fn describe(&self, room_id: &OwnedRoomId) -> String {
+        match self {
+            Self::Invite(user) => format!("invite {user} to {room_id}"),
+            Self::Remove(user) => format!("remove {user} from {room_id}"),
+        }
+    }
+}
+
+/// The changes needed to match the roster, by room.
+#[derive(Default)]
+struct Plan {
+    changes: BTreeMap<OwnedRoomId, Vec<Change>>,
+    /// Problems that prevented some rooms from being synced.
+    errors: Vec<String>,
+}
+
+impl Plan {
+    fn is_empty(&self) -> bool {
+        self.changes.values().all(Vec::is_empty) && self.errors.is_empty()
+    }
+
+    fn report(&self, applied: bool) -> String {
+        let (mut invites, mut removals) = (0, 0);
+        let mut lines = Vec::new();
+        for (room_id, changes) in &self.changes {
+            for change in changes {
+                match change {
+                    Change::Invite(_) => invites += 1,
+                    Change::Remove(_) => removals += 1,
+                }
+                lines.push(format!("- {}", change.describe(room_id)));
+            }
+        }
+        lines.extend(self.errors.iter().map(|err| format!("⚠️ {err}")));
+
+        let verb = if applied { "made" } else { "would make" };
+        let mut report =
+            format!("roster sync {verb} {invites} invite(s) and {removals} removal(s)");
+        for line in lines {
+            report.push('\n');
+            report.push_str(&line);
+        }
+        report
+    }
+}
+
+pub(crate) struct Roster {
+    config: Option<RosterConfig>,
+    admin_user_id: OwnedUserId,
+    http: reqwest::Client,
+    /// Prevents concurrent syncs, e.g. a manual one during a periodic one.
+    lock: Mutex<()>,
+}
+
+impl Roster {
+    pub fn new(config: Option<RosterConfig>, admin_user_id: OwnedUserId) -> anyhow::Result<Self> {
+        let http = reqwest::Client::builder().timeout(FETCH_TIMEOUT).build()?;
+        Ok(Self {
+            config,
+            admin_user_id,
+            http,
+            lock: Mutex::new(()),
+        })
+    }
+
+    /// Reads the roster, as a list of rooms (ids or aliases) by user.
+    async fn fetch(
+        &self,
+        config: &RosterConfig,
+    ) -> anyhow::Result<HashMap<OwnedUserId, Vec<String>>> {
+        let source = &config.source;
+        let text = if source.starts_with("http://") || source.starts_with("https://") {
+            let mut request = self.http.get(source);
+            if let Some(token) = &config.token {
+                request = request.bearer_auth(token);
+            }
+            request.send().await?.error_for_status()?.text().await?
+        } else {
+            tokio::fs::read_to_string(source)
+                .await
+                .with_context(|| format!("couldn't read the roster at {source}"))?
+        };
+        serde_json::from_str(&text).context("invalid roster")
+    }
+
+    /// Computes the changes needed for the rooms' memberships to match the roster.
+    async fn plan(&self, client: &Client, config: &RosterConfig) -> anyhow::Result<Plan> {
+        let roster = self.fetch(config).await?;
+        let bot_user_id = client.user_id().context("missing bot user id")?;
+        let mut plan = Plan::default();
+
+        // Expected members of each managed room.
+        let mut expected = BTreeMap::<OwnedRoomId, BTreeSet<OwnedUserId>>::new();
+        let mut resolved = HashMap::<String, OwnedRoomId>::new();
+        let rooms = config.rooms.iter().map(|room| (None, room));
+        let roster_rooms = roster
+            .iter()
+            .flat_map(|(user, rooms)| rooms.iter().map(move |room| (Some(user), room)));
+        for (user, room) in rooms.chain(roster_rooms) {
+            let room_id = match resolved.get(room) {
+                Some(room_id) => room_id.clone(),
+                None => match resolve_room(client, room).await {
+                    Ok(room_id) => {
+                        resolved.insert(room.clone(), room_id.clone());
+                        room_id
+                    }
+                    Err(err) => {
+                        plan.errors
+                            .push(format!("couldn't resolve {room}: {err:#}"));
+                        continue;
+                    }
+                },
+            };
+            let members = expected.entry(room_id).or_default();
+            if let Some(user) = user {
+                members.insert(user.clone());
+            }
+        }
+
+        for (room_id, expected) in expected {
+            let Some(room) = client.get_room(&room_id) else {
+                plan.errors.push(format!("the bot isn't in {room_id}"));
+                continue;
+            };
+            let members = room
+                .members(RoomMemberships::JOIN | RoomMemberships::INVITE)
+                .await?
+                .into_iter()
+                .map(|member| member.user_id().to_owned())
+                .collect::<BTreeSet<_>>();
+
+            let mut changes = expected
+                .difference(&members)
+                .map(|user| Change::Invite(user.clone()))
+                .collect::<Vec<_>>();
+            if config.remove_extras {
+                for user in members.difference(&expected) {
+                    if user == bot_user_id
+                        || *user == self.admin_user_id
+                        || is_moderator(&room, user).await?
+                    {
+                        continue;
+                    }
+                    changes.push(Change::Remove(user.clone()));
+                }
+            }
+            plan.changes.insert(room_id, changes);
+        }
+
+        Ok(plan)
+    }
+
+    /// Applies the changes of the plan, recording the failed ones in its errors.
+    async fn apply(&self, client: &Client, plan: &mut Plan) {
+        for (room_id, changes) in &plan.changes {
+            let Some(room) = client.get_room(room_id) else {
+                continue;
+            };
+            for change in changes {
+                let result = match change {
+                    Change::Invite(user) => outbox::invite(&room, user).await,
+                    Change::Remove(user) => outbox::kick(&room, user, Some(REMOVAL_REASON)).await,
+                };
+                if let Err(err) = result {
+                    plan.errors
+                        .push(format!("couldn't {}: {err:#}", change.describe(room_id)));
+                }
+            }
+        }
+    }
+
+    /// Reconciles the memberships with the roster, and returns a report of the changes; only
+    /// computes them when `dry_run` is set.
+    async fn sync(
+        &self,
+        client: &Client,
+        config: &RosterConfig,
+        dry_run: bool,
+    ) -> anyhow::Result<(String, bool)> {
+        let _guard = self.lock.lock().await;
+        let mut plan = self.plan(client, config).await?;
+        if !dry_run {
+            self.apply(client, &mut plan).await;
+        }
+        Ok((plan.report(!dry_run), plan.is_empty()))
+    }
+
+    /// Periodically syncs the memberships, and reports the changes.
+    pub async fn run(&self, client: Client) {
+        let Some(config) = &self.config else {
+            return;
+        };
+        let interval = Duration::from_secs(config.interval_minutes.max(1) * 60);
+
+        loop {
+            let report = match self.sync(&client, config, false).await {
+                Ok((_, true)) => None,
+                Ok((report, false)) => {
+                    info!("{report}");
+                    Some(report)
+                }
+                Err(err) => {
+                    error!("roster sync failed: {err:#}");
+                    Some(format!("⚠️ roster sync failed: {err:#}"))
+                }
+            };
+
+            if let (Some(report), Some(room_id)) = (report, &config.report_room) {
+                match client.get_room(room_id) {
+                    Some(room) => {
+                        let content = RoomMessageEventContent::text_plain(report);
+                        if let Err(err) = outbox::send(&room, content).await {
+                            warn!("couldn't post the roster sync report: {err:#}");
+                        }
+                    }
+                    None => warn!("unknown roster report room {room_id}"),
+                }
+            }
+
+            sleep(interval).await;
+        }
+    }
+
+    /// Try to handle an `!admin host roster` command.
+    pub async fn try_handle_admin(&self, client: &Client, content: &str) -> Option<String> {
+        let rest = content.strip_prefix("!admin host roster")?;
+        if !rest.is_empty() && !rest.starts_with(' ') {
+            return None;
+        }
+
+        let Some(config) = &self.config else {
+            return Some("the roster sync isn't configured".to_owned());
+        };
+        let dry_run = match rest.trim() {
+            "sync" => false,
+            "dry-run" => true,
+            _ => return Some(USAGE.to_owned()),
+        };
+        Some(match self.sync(client, config, dry_run).await {
+            Ok((report, _)) => report,
+            Err(err) => format!("roster sync failed: {err:#}"),
+        })
+    }
+}
@@ -0,0 +1,552 @@
+//! Events with RSVPs: `!event create` posts an event that members answer by reacting, attaches an
+//! iCal file for calendars, and pings the attendees before the event starts.
+
+use std::{collections::HashMap, sync::Mutex};
+
+use chrono::{DateTime, NaiveDateTime, TimeZone as _, Utc};
+use chrono_tz::Tz;
+use matrix_sdk::{
+    attachment::AttachmentConfig,
+    room::Room,
+    ruma::{
+        events::{
+            reaction::ReactionEventContent, relation::Annotation,
+            room::message::RoomMessageEventContent,
+        },
+        OwnedEventId, OwnedRoomId, OwnedUserId, UserId,
+    },
+    Client,
+};
+use serde::{Deserialize, Serialize};
+use tokio::time::{sleep, Duration};
+use tracing::error;
+
+use crate::{
+    host_table,
+    maintenance::Maintenance,
+    outbox,
+    utils::{is_moderator, now_secs, parse_duration, split_args},
+    ShareableDatabase,
+};
+
+/// Name of the host table keeping the events of each room.
+const TABLE: &str = "rsvp";
+
+/// Key, in the events table, for the list of rooms having events.
+const ROOMS_KEY: &str = "@rooms";
+
+/// How long before the start attendees are reminded, when not specified.
+const DEFAULT_REMINDER: Duration = Duration::from_secs(60 * 60);
+
+/// Duration of an event, when not specified.
+const DEFAULT_DURATION: Duration = Duration::from_secs(60 * 60);
+
+/// For how long events are kept after they started, for the attendance lists.
+const KEEP_AFTER_START: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// How often reminders are looked for.
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+const USAGE: &str = "usage: !event create \"TITLE\" YYYY-MM-DD HH:MM [--timezone TZ] \
+    [--duration 2h] [--remind 1h] | !event list | !event attendees NUMBER | !event cancel NUMBER";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+enum Answer {
+    Going,
+    Maybe,
+    NotGoing,
+}
+
+impl Answer {
+    const ALL: [Answer; 3] = [Answer::Going, Answer::Maybe, Answer::NotGoing];
+
+    fn key(&self) -> &'static str {
+        match self {
+            Self::Going => "✅",
+            Self::Maybe => "🤔",
+            Self::NotGoing => "❌",
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Going => "going",
+            Self::Maybe => "maybe",
+            Self::NotGoing => "not going",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Event {
+    id: u64,
+    title: String,
+    author: OwnedUserId,
+    /// start and end of the event, in seconds since the Unix epoch.
+    start: i64,
+    end: i64,
+    /// how long before the start attendees are pinged, in seconds.
+    remind_before: u64,
+    reminded: bool,
+    /// message members react to in order to answer.
+    event_id: Option<OwnedEventId>,
+    /// answer of each member; the last reaction wins.
+    answers: HashMap<OwnedUserId, Answer>,
+}
+
+impl Event {
+    fn start(&self) -> DateTime<Utc> {
+        Utc.timestamp_opt(self.start, 0)
+            .single()
+            .unwrap_or_default()
+    }
+
+    fn end(&self) -> DateTime<Utc> {
+        Utc.timestamp_opt(self.end, 0).single().unwrap_or_default()
+    }
+
+    fn announcement(&self) -> String {
+        let mut text = format!(
+            "Event #{}: {}\nStarts: {}",
+            self.id,
+            self.title,
+            self.start().format("%Y-%m-%d %H:%M UTC")
+        );
+        text.push_str("\nReact with");
+        for answer in Answer::ALL {
+            text.push_str(&format!(" {} ({})", answer.key(), answer.name()));
+        }
+        text.push_str(" to RSVP.");
+        text
+    }
+
+    fn attendees(&self, answer: Answer) -> Vec<&OwnedUserId> {
+        let mut users = self
+            .answers
+            .iter()
+            .filter(|(_, a)| **a == answer)
+            .map(|(user, _)| user)
+            .collect::<Vec<_>>();
+        users.sort();
+        users
+    }
+
+    fn attendance(&self) -> String {
+        let mut text = format!("Attendance for event #{}: {}", self.id, self.title);
+        for answer in Answer::ALL {
+            let users = self.attendees(answer);
+            text.push_str(&format!("\n{} ({}):", answer.name(), users.len()));
+            for user in users {
+                text.push_str(&format!(" {user}"));
+            }
+        }
+        text
+    }
+
+    fn attendance_csv(&self) -> String {
+        let mut csv = String::from("user,answer\n");
+        for answer in Answer::ALL {
+            for user in self.attendees(answer) {
+                csv.push_str(&format!("{user},{}\n", answer.name()));
+            }
+        }
+        csv
+    }
+
+    fn ical(&self, room_id: &OwnedRoomId) -> String {
+        fn escape(text: &str) -> String {
+            text.replace('\\', "\\\\")
+                .replace(';', "\\;")
+                .replace(',', "\\,")
+                .replace('\n', "\\n")
+        }
+        const FORMAT: &str = "%Y%m%dT%H%M%SZ";
+        [
+            "BEGIN:VCALENDAR".to_owned(),
+            "VERSION:2.0".to_owned(),
+            "PRODID:-//tritongue//events//EN".to_owned(),
+            "BEGIN:VEVENT".to_owned(),
+            format!(
+                "UID:event-{}-{}",
+                self.id,
+                room_id.as_str().trim_start_matches('!')
+            ),
+            format!("DTSTAMP:{}", Utc::now().format(FORMAT)),
+            format!("DTSTART:{}", self.start().format(FORMAT)),
+            format!("DTEND:{}", self.end().format(FORMAT)),
+            format!("SUMMARY:{}", escape(&self.title)),
+            "END:VEVENT".to_owned(),
+            "END:VCALENDAR".to_owned(),
+        ]
+        .join("\r\n")
+            + "\r\n"
+    }
+}
+
+/// All the events of a single room.
+#[derive(Default, Serialize, Deserialize)]
+struct RoomEvents {
+    next_id: u64,
+    events: Vec<Event>,
+}
+
+pub(crate) struct Rsvps {
+    db: ShareableDatabase,
+    /// Serializes the read-modify-write cycles on the events.
+    lock: Mutex<()>,
+}
+
+impl Rsvps {
+    pub fn new(db: ShareableDatabase) -> Self {
+        Self {
+            db,
+            lock: Mutex::new(()),
+        }
+    }
+
+    fn read_room(&self, room_id: &OwnedRoomId) -> anyhow::Result<RoomEvents> {
+        Ok(host_table::read_json(&self.db, TABLE, room_id.as_str())?.unwrap_or_default())
+    }
+
+    /// Describes what's stored for the room, if anything, for `!admin host dump-room`.
+    pub fn room_summary(&self, room_id: &OwnedRoomId) -> anyhow::Result<Option<String>> {
+        let count = self.read_room(room_id)?.events.len();
+        Ok((count > 0).then(|| format!("events: {count}")))
+    }
+
+    /// Applies `f` to the events of the room, and saves them.
+    fn update_room<T>(
+        &self,
+        room_id: &OwnedRoomId,
+        f: impl FnOnce(&mut RoomEvents) -> T,
+    ) -> anyhow::Result<T> {
+        let _guard = self.lock.lock().unwrap();
+        let mut room_events = self.read_room(room_id)?;
+        let result = f(&mut room_events);
+        host_table::write_json(&self.db, TABLE, room_id.as_str(), &room_events)?;
+        Ok(result)
+    }
+
+    fn register_room(&self, room_id: &OwnedRoomId) -> anyhow::Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        let mut rooms: Vec<OwnedRoomId> =
+            host_table::read_json(&self.db, TABLE, ROOMS_KEY)?.unwrap_or_default();
+        if !rooms.contains(room_id) {
+            rooms.push(room_id.clone());
+            host_table::write_json(&self.db, TABLE, ROOMS_KEY, &rooms)?;
+        }
+        Ok(())
+    }
+
+    /// Records an RSVP, if the reaction is to an event's message.
+    pub fn on_reaction(
+        &self,
+        room: &Room,
+        sender: &UserId,
+        relates_to: &OwnedEventId,
+        key: &str,
+    ) -> anyhow::Result<()> {
+        let Some(answer) = Answer::ALL.into_iter().find(|a| a.key() == key) else {
+            return Ok(());
+        };
+        let room_id = room.room_id().to_owned();
+        let is_event = self
+            .read_room(&room_id)?
+            .events
+            .iter()
+            .any(|e| e.event_id.as_ref() == Some(relates_to));
+        if !is_event {
+            return Ok(());
+        }
+        self.update_room(&room_id, |room_events| {
+            if let Some(event) = room_events
+                .events
+                .iter_mut()
+                .find(|e| e.event_id.as_ref() == Some(relates_to))
+            {
+                event.answers.insert(sender.to_owned(), answer);
+            }
+        })
+    }
+
+    /// Sends the due reminders in a room, and forgets about old events.
+    async fn check_room(&self, client: &Client, room_id: &OwnedRoomId) -> anyhow::Result<()> {
+        let now = now_secs() as i64;
+        let due = self.update_room(room_id, |room_events| {
+            room_events
+                .events
+                .retain(|e| e.start + KEEP_AFTER_START.as_secs() as i64 > now);
+            let mut due = Vec::new();
+            for event in &mut room_events.events {
+                if !event.reminded && event.start - event.remind_before as i64 <= now {
+                    event.reminded = true;
+                    due.push(event.clone());
+                }
+            }
+            due
+        })?;
+
+        let Some(room) = client.get_room(room_id) else {
+            return Ok(());
+        };
+        for event in due {
+            if event.start < now {
+                // Missed while the bot was down, no need to remind anymore.
+                continue;
+            }
+            let mut text = format!(
+                "Reminder: event #{} \"{}\" starts at {}.",
+                event.id,
+                event.title,
+                event.start().format("%H:%M UTC")
+            );
+            let attendees = event
+                .attendees(Answer::Going)
+                .into_iter()
+                .chain(event.attendees(Answer::Maybe))
+                .map(|user| user.to_string())
+                .collect::<Vec<_>>();
+            if !attendees.is_empty() {
+                text.push_str(&format!(" {}", attendees.join(", ")));
+            }
+            outbox::send(&room, RoomMessageEventContent::text_plain(text)).await?;
+        }
+        Ok(())
+    }
+
+    /// Periodically pings the attendees of the events about to start.
+    pub async fn run(&self, client: Client, maintenance: &Maintenance) {
+        loop {
+            sleep(CHECK_INTERVAL).await;
+            if maintenance.is_on() {
+                // The reminders are sent once maintenance is over.
+                continue;
+            }
+
+            let rooms: Vec<OwnedRoomId> = match host_table::read_json(&self.db, TABLE, ROOMS_KEY) {
+                Ok(rooms) => rooms.unwrap_or_default(),
+                Err(err) => {
+                    error!("couldn't read the rooms with events: {err:#}");
+                    continue;
+                }
+            };
+            for room_id in rooms {
+                if let Err(err) = self.check_room(&client, &room_id).await {
+                    error!("couldn't check the events of {room_id}: {err:#}");
+                }
+            }
+        }
+    }
+
+    async fn create(
+        &self,
+        room: &Room,
+        sender: &UserId,
+        args: Vec<String>,
+    ) -> anyhow::Result<String> {
+        let mut words = Vec::new();
+        let mut timezone = Tz::UTC;
+        let mut duration = DEFAULT_DURATION;
+        let mut remind = DEFAULT_REMINDER;
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let flag = match arg.as_str() {
+                "--timezone" | "--duration" | "--remind" => arg,
+                _ => {
+                    words.push(arg);
+                    continue;
+                }
+            };
+            let Some(value) = args.next() else {
+                return Ok(format!("missing value for {flag}"));
+            };
+            let parsed = match flag.as_str() {
+                "--timezone" => value.parse().map(|tz| timezone = tz).is_ok(),
+                "--duration" => parse_duration(&value).map(|d| duration = d).is_some(),
+                _ => parse_duration(&value).map(|d| remind = d).is_some(),
+            };
+            if !parsed {
+                return Ok(format!("invalid value for {flag}: {value}"));
+            }
+        }
+
+        let [title, date, time] = words.as_slice() else {
+            return Ok(USAGE.to_owned());
+        };
+        let Ok(local) = NaiveDateTime::parse_from_str(&format!("{date} {time}"), "%Y-%m-%d %H:%M")
+        else {
+            return Ok(format!(
+                "invalid date {date} {time}, expected YYYY-MM-DD HH:MM"
+            ));
+        };
+        let Some(start) = timezone.from_local_datetime(&local).earliest() else {
+            return Ok(format!("{date} {time} doesn't exist in {timezone}"));
+        };
+        let start = start.timestamp();
+        if start <= now_secs() as i64 {
+            return Ok("the event must start in the future".to_owned());
+        }
+
+        let room_id = room.room_id().to_owned();
+        self.register_room(&room_id)?;
+        let mut event = self.update_room(&room_id, |room_events| {
+            room_events.next_id += 1;
+            let event = Event {
+                id: room_events.next_id,
+                title: title.clone(),
+                author: sender.to_owned(),
+                start,
+                end: start + duration.as_secs() as i64,
+                remind_before: remind.as_secs(),
+                reminded: false,
+                event_id: None,
+                answers: HashMap::new(),
+            };
+            room_events.events.push(event.clone());
+            event
+        })?;
+
+        let event_id = outbox::send(
+            room,
+            RoomMessageEventContent::text_plain(event.announcement()),
+        )
+        .await?;
+        event.event_id = Some(event_id.clone());
+        self.update_room(&room_id, |room_events| {
+            if let Some(e) = room_events.events.iter_mut().find(|e| e.id == event.id) {
+                e.event_id = event.event_id.clone();
+            }
+        })?;
+
+        for answer in Answer::ALL {
+            let reaction = ReactionEventContent::new(Annotation::new(
+                event_id.clone(),
+                answer.key().to_owned(),
+            ));
+            outbox::send(room, reaction).await?;
+        }
+
+        outbox::send_attachment(
+            room,
+            &format!("event-{}.ics", event.id),
+            &"text/calendar".parse::<mime::Mime>()?,
+            event.ical(&room_id).into_bytes(),
+            AttachmentConfig::new(),
+        )
+        .await?;
+
+        // The announcement is the response.
+        Ok(String::new())
+    }
+
+    async fn handle(&self, room: &Room, sender: &UserId, rest: &str) -> anyhow::Result<String> {
+        let room_id = room.room_id().to_owned();
+        let args = split_args(rest);
+        let id = args
+            .get(1)
+            .and_then(|a| a.trim_start_matches('#').parse::<u64>().ok());
+
+        Ok(match args.first().map(String::as_str) {
+            Some("create") => self.create(room, sender, args[1..].to_vec()).await?,
+
+            Some("list") => {
+                let now = now_secs() as i64;
+                let upcoming = self
+                    .read_room(&room_id)?
+                    .events
+                    .into_iter()
+                    .filter(|e| e.start > now)
+                    .collect::<Vec<_>>();
+                if upcoming.is_empty() {
+                    "no upcoming events".to_owned()
+                } else {
+                    let mut text = format!("{} upcoming event(s):", upcoming.len());
+                    for event in upcoming {
+                        text.push_str(&format!(
+                            "\n- #{} {}: {} ({} going)",
+                            event.id,
+                            event.start().format("%Y-%m-%d %H:%M UTC"),
+                            event.title,
+                            event.attendees(Answer::Going).len()
+                        ));
+                    }
+                    text
+                }
+            }
+
+            Some("attendees") => {
+                let Some(id) = id else {
+                    return Ok(USAGE.to_owned());
+                };
+                let Some(event) = self
+                    .read_room(&room_id)?
+                    .events
+                    .into_iter()
+                    .find(|e| e.id == id)
+                else {
+                    return Ok(format!("no event #{id} in this room"));
+                };
+                outbox::send_attachment(
+                    room,
+                    &format!("event-{id}-attendance.csv"),
+                    &mime::TEXT_CSV,
+                    event.attendance_csv().into_bytes(),
+                    AttachmentConfig::new(),
+                )
+                .await?;
+                event.attendance()
+            }
+
+            Some("cancel") => {
+                let Some(id) = id else {
+                    return Ok(USAGE.to_owned());
+                };
+                let is_author = self
+                    .read_room(&room_id)?
+                    .events
+                    .iter()
+                    .any(|e| e.id == id && &*e.author == sender);
+                if !is_author && !is_moderator(room, sender).await? {
+                    return Ok("only the author or a moderator can cancel an event".to_owned());
+                }
+                let removed = self.update_room(&room_id, |room_events| {
+                    let len = room_events.events.len();
+                    room_events.events.retain(|e| e.id != id);
+                    room_events.events.len() != len
+                })?;
+                if removed {
+                    format!("event #{id} cancelled")
+                } else {
+                    format!("no event #{id} in this room")
+                }
+            }
+
+            _ => USAGE.to_owned(),
+        })
+    }
+
+    /// Try to handle a message assuming it's an `!event` command.
+    ///
+    /// Returns whether the message was such a command, in which case it mustn't be handled any
+    /// further.
+    pub async fn try_handle(
+        &self,
+        room: &Room,
+        sender: &UserId,
+        content: &str,
+    ) -> anyhow::Result<bool> {
+        let Some(rest) = content.strip_prefix("!event") else {
+            return Ok(false);
+        };
+        if !rest.is_empty() && !rest.starts_with(' ') {
+            return Ok(false);
+        }
+
+        let response = self.handle(room, sender, rest.trim()).await?;
+        if !response.is_empty() {
+            outbox::send(room, RoomMessageEventContent::text_plain(response)).await?;
+        }
+        Ok(true)
+    }
+}
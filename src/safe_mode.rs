@@ -0,0 +1,18 @@
+//! Safe mode, to recover from a module crashing the bot at startup or spamming rooms as soon as
+//! it's loaded: the bot starts without loading any module, without running the background tasks
+//! that post on their own (timers, cron jobs, stand-ups, scheduled messages, bridges...), and only
+//! answers the host admin commands, e.g. to look around before fixing the configuration.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Whether the process runs in safe mode.
+static SAFE_MODE: AtomicBool = AtomicBool::new(false);
+
+/// Enables the safe mode, for all the bots of the process.
+pub fn enable_safe_mode() {
+    SAFE_MODE.store(true, Ordering::Relaxed);
+}
+
+pub(crate) fn is_enabled() -> bool {
+    SAFE_MODE.load(Ordering::Relaxed)
+}
@@ -0,0 +1,399 @@
+//! Timezone-aware schedules: daily ones, for the host features running at a given time of the day,
+//! and cron-style ones, for the modules' recurring jobs.
+
+use chrono::{DateTime, Datelike as _, NaiveTime, TimeZone as _, Utc, Weekday};
+use chrono_tz::Tz;
+use tokio::time::Duration;
+
+/// The days of the week, in order.
+const ALL_WEEKDAYS: [Weekday; 7] = [
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+    Weekday::Thu,
+    Weekday::Fri,
+    Weekday::Sat,
+    Weekday::Sun,
+];
+
+/// Something happening every day at the same local time, except on some days of the week.
+#[derive(Clone, Debug)]
+pub struct DailySchedule {
+    time: NaiveTime,
+    timezone: Tz,
+    skip_days: Vec<Weekday>,
+}
+
+impl DailySchedule {
+    /// Creates a new schedule, from a `HH:MM` time, an IANA timezone name (e.g. `Europe/Paris`)
+    /// and a list of week days (e.g. `sat`) to skip.
+    pub fn new(time: &str, timezone: Option<&str>, skip_days: &[String]) -> anyhow::Result<Self> {
+        let time = NaiveTime::parse_from_str(time, "%H:%M")
+            .map_err(|err| anyhow::anyhow!("invalid time {time}: {err}"))?;
+        let timezone = parse_timezone(timezone)?;
+        let skip_days = skip_days
+            .iter()
+            .map(|day| {
+                day.parse::<Weekday>()
+                    .map_err(|_| anyhow::anyhow!("invalid week day {day}"))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        anyhow::ensure!(
+            ALL_WEEKDAYS.iter().any(|day| !skip_days.contains(day)),
+            "all the week days are skipped"
+        );
+        Ok(Self {
+            time,
+            timezone,
+            skip_days,
+        })
+    }
+
+    /// Returns the next occurrence strictly after `now`, if there's one within a week.
+    pub fn next_after(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let local_now = now.with_timezone(&self.timezone);
+        let mut date = local_now.date_naive();
+        // Today, then the whole next week, as today's occurrence may be over already.
+        for _ in 0..=ALL_WEEKDAYS.len() {
+            if !self.skip_days.contains(&date.weekday()) {
+                if let Some(candidate) = self
+                    .timezone
+                    .from_local_datetime(&date.and_time(self.time))
+                    .earliest()
+                {
+                    if candidate > local_now {
+                        return Some(candidate.with_timezone(&Utc));
+                    }
+                }
+            }
+            date = date.succ_opt()?;
+        }
+        None
+    }
+
+    /// Returns how long to wait until the next occurrence, if any.
+    pub fn until_next(&self) -> Option<Duration> {
+        let now = Utc::now();
+        Some((self.next_after(now)? - now).to_std().unwrap_or_default())
+    }
+}
+
+/// Parses the timezone of a schedule, UTC if missing.
+pub(crate) fn parse_timezone(timezone: Option<&str>) -> anyhow::Result<Tz> {
+    match timezone {
+        Some(tz) => tz
+            .parse::<Tz>()
+            .map_err(|err| anyhow::anyhow!("invalid timezone {tz}: {err}")),
+        None => Ok(Tz::UTC),
+    }
+}
+
+/// Parses a `HH:MM-HH:MM` range of hours, which may span midnight.
+pub(crate) fn parse_hours(value: &str) -> anyhow::Result<(NaiveTime, NaiveTime)> {
+    let (start, end) = value
+        .split_once('-')
+        .ok_or_else(|| anyhow::anyhow!("invalid hours {value}, expected HH:MM-HH:MM"))?;
+    let parse = |time: &str| {
+        NaiveTime::parse_from_str(time.trim(), "%H:%M")
+            .map_err(|err| anyhow::anyhow!("invalid time {time}: {err}"))
+    };
+    Ok((parse(start)?, parse(end)?))
+}
+
+/// Whether a range of hours includes the time; a range ending before it starts spans midnight.
+pub(crate) fn hours_include((start, end): (NaiveTime, NaiveTime), time: NaiveTime) -> bool {
+    if start <= end {
+        start <= time && time < end
+    } else {
+        start <= time || time < end
+    }
+}
+
+/// Parses a field of a cron expression (e.g. `*`, `*/15`, `1-5` or `0,30`) into the list of the
+/// values it matches, or `None` if it matches everything.
+fn parse_cron_field(field: &str, min: u32, max: u32) -> anyhow::Result<Option<Vec<u32>>> {
+    if field == "*" {
+        return Ok(None);
+    }
+    let mut values = Vec::new();
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>()?),
+            None => (part, 1),
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (start.parse()?, end.parse()?)
+        } else {
+            let value = range.parse()?;
+            // `N/STEP` means from N to the end.
+            (value, if step > 1 { max } else { value })
+        };
+        if step == 0 || start < min || end > max || start > end {
+            anyhow::bail!("invalid cron field {field}");
+        }
+        values.extend((start..=end).step_by(step as usize));
+    }
+    values.sort_unstable();
+    values.dedup();
+    Ok(Some(values))
+}
+
+fn cron_field_matches(field: &Option<Vec<u32>>, value: u32) -> bool {
+    match field {
+        Some(values) => values.contains(&value),
+        None => true,
+    }
+}
+
+/// Something happening at the times matching a cron expression: `minute hour day-of-month month
+/// day-of-week`, day-of-week 0 (or 7) being Sunday, in a given timezone.
+#[derive(Clone, Debug)]
+pub struct CronSchedule {
+    minutes: Option<Vec<u32>>,
+    hours: Option<Vec<u32>>,
+    days_of_month: Option<Vec<u32>>,
+    months: Option<Vec<u32>>,
+    days_of_week: Option<Vec<u32>>,
+    timezone: Tz,
+}
+
+impl CronSchedule {
+    /// Longest time to look ahead for an occurrence, in days, to bail out of impossible schedules
+    /// like `0 0 31 2 *`.
+    const MAX_LOOKAHEAD_DAYS: u32 = 5 * 366;
+
+    /// Creates a new schedule, from a cron expression and an IANA timezone name.
+    pub fn new(expression: &str, timezone: Option<&str>) -> anyhow::Result<Self> {
+        let fields = expression.split_whitespace().collect::<Vec<_>>();
+        let [minutes, hours, days_of_month, months, days_of_week] = fields[..] else {
+            anyhow::bail!("a cron expression has 5 fields: {expression}");
+        };
+        let days_of_week = parse_cron_field(days_of_week, 0, 7)?.map(|days| {
+            let mut days = days.into_iter().map(|day| day % 7).collect::<Vec<_>>();
+            days.sort_unstable();
+            days.dedup();
+            days
+        });
+        Ok(Self {
+            minutes: parse_cron_field(minutes, 0, 59)?,
+            hours: parse_cron_field(hours, 0, 23)?,
+            days_of_month: parse_cron_field(days_of_month, 1, 31)?,
+            months: parse_cron_field(months, 1, 12)?,
+            days_of_week,
+            timezone: parse_timezone(timezone)?,
+        })
+    }
+
+    fn day_matches(&self, date: chrono::NaiveDate) -> bool {
+        if !cron_field_matches(&self.months, date.month()) {
+            return false;
+        }
+        let day_of_month = cron_field_matches(&self.days_of_month, date.day());
+        let day_of_week =
+            cron_field_matches(&self.days_of_week, date.weekday().num_days_from_sunday());
+        // As in cron, when both are restricted, matching either is enough.
+        match (&self.days_of_month, &self.days_of_week) {
+            (Some(_), Some(_)) => day_of_month || day_of_week,
+            _ => day_of_month && day_of_week,
+        }
+    }
+
+    /// Returns the next occurrence strictly after `now`, if there's one in the next few years.
+    pub fn next_after(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let local_now = now.with_timezone(&self.timezone);
+        let mut date = local_now.date_naive();
+        for _ in 0..Self::MAX_LOOKAHEAD_DAYS {
+            if self.day_matches(date) {
+                for hour in 0..24 {
+                    if !cron_field_matches(&self.hours, hour) {
+                        continue;
+                    }
+                    for minute in 0..60 {
+                        if !cron_field_matches(&self.minutes, minute) {
+                            continue;
+                        }
+                        let Some(time) = NaiveTime::from_hms_opt(hour, minute, 0) else {
+                            continue;
+                        };
+                        // Times skipped by a DST change don't happen.
+                        if let Some(candidate) = self
+                            .timezone
+                            .from_local_datetime(&date.and_time(time))
+                            .earliest()
+                        {
+                            if candidate > local_now {
+                                return Some(candidate.with_timezone(&Utc));
+                            }
+                        }
+                    }
+                }
+            }
+            date = date.succ_opt()?;
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(time: &str) -> DateTime<Utc> {
+        time.parse().unwrap()
+    }
+
+    #[test]
+    fn cron_fields() {
+        assert_eq!(parse_cron_field("*", 0, 59).unwrap(), None);
+        assert_eq!(
+            parse_cron_field("*/15", 0, 59).unwrap(),
+            Some(vec![0, 15, 30, 45])
+        );
+        assert_eq!(
+            parse_cron_field("1-5", 0, 6).unwrap(),
+            Some(vec![1, 2, 3, 4, 5])
+        );
+        assert_eq!(
+            parse_cron_field("30,0,30", 0, 59).unwrap(),
+            Some(vec![0, 30])
+        );
+        assert_eq!(
+            parse_cron_field("10/20", 0, 59).unwrap(),
+            Some(vec![10, 30, 50])
+        );
+    }
+
+    #[test]
+    fn malformed_cron_fields() {
+        for field in [
+            "", "60", "5-1", "1-", "-1", "*/0", "1,,2", "a", "1.5", "*/x", "0-60",
+        ] {
+            assert!(
+                parse_cron_field(field, 0, 59).is_err(),
+                "{field:?} should be invalid"
+            );
+        }
+        assert!(parse_cron_field("0", 1, 31).is_err());
+    }
+
+    #[test]
+    fn malformed_cron_expressions() {
+        assert!(CronSchedule::new("* * * *", None).is_err());
+        assert!(CronSchedule::new("* * * * * *", None).is_err());
+        assert!(CronSchedule::new("", None).is_err());
+        assert!(CronSchedule::new("0 24 * * *", None).is_err());
+        assert!(CronSchedule::new("0 0 * 13 *", None).is_err());
+        assert!(CronSchedule::new("0 0 * * 8", None).is_err());
+        assert!(CronSchedule::new("0 0 * * *", Some("Mars/Olympus")).is_err());
+    }
+
+    #[test]
+    fn cron_next_after() {
+        // 2024-01-01 is a Monday.
+        let monday = utc("2024-01-01T00:00:00Z");
+        let every_quarter = CronSchedule::new("*/15 * * * *", None).unwrap();
+        assert_eq!(
+            every_quarter.next_after(monday),
+            Some(utc("2024-01-01T00:15:00Z"))
+        );
+        // Sunday is both 0 and 7.
+        let sunday = CronSchedule::new("0 9 * * 7", None).unwrap();
+        assert_eq!(sunday.next_after(monday), Some(utc("2024-01-07T09:00:00Z")));
+        // When both days are restricted, either matches: Friday the 5th comes before the 13th.
+        let either = CronSchedule::new("0 0 13 * 5", None).unwrap();
+        assert_eq!(either.next_after(monday), Some(utc("2024-01-05T00:00:00Z")));
+        // February never has 31 days.
+        let never = CronSchedule::new("0 0 31 2 *", None).unwrap();
+        assert_eq!(never.next_after(monday), None);
+    }
+
+    #[test]
+    fn cron_timezone() {
+        let paris = CronSchedule::new("30 2 * * *", Some("Europe/Paris")).unwrap();
+        assert_eq!(
+            paris.next_after(utc("2024-01-01T00:00:00Z")),
+            Some(utc("2024-01-01T01:30:00Z"))
+        );
+        // 02:30 doesn't exist on the night of the switch to summer time.
+        assert_eq!(
+            paris.next_after(utc("2024-03-30T12:00:00Z")),
+            Some(utc("2024-04-01T00:30:00Z"))
+        );
+    }
+
+    fn days(days: &[&str]) -> Vec<String> {
+        days.iter().map(|day| day.to_string()).collect()
+    }
+
+    #[test]
+    fn daily() {
+        let weekdays = DailySchedule::new("09:30", None, &days(&["sat", "sun"])).unwrap();
+        // 2024-01-05 is a Friday.
+        assert_eq!(
+            weekdays.next_after(utc("2024-01-05T09:00:00Z")),
+            Some(utc("2024-01-05T09:30:00Z"))
+        );
+        assert_eq!(
+            weekdays.next_after(utc("2024-01-05T09:30:00Z")),
+            Some(utc("2024-01-08T09:30:00Z"))
+        );
+
+        let mondays = days(&["tue", "wed", "thu", "fri", "sat", "sun"]);
+        let mondays = DailySchedule::new("09:30", None, &mondays).unwrap();
+        assert_eq!(
+            mondays.next_after(utc("2024-01-01T10:00:00Z")),
+            Some(utc("2024-01-08T09:30:00Z"))
+        );
+    }
+
+    #[test]
+    fn daily_skipping_every_day() {
+        let all = days(&["mon", "tue", "wed", "thu", "fri", "sat", "sun"]);
+        assert!(DailySchedule::new("09:30", None, &all).is_err());
+        assert!(DailySchedule::new("09:30", None, &days(&["someday"])).is_err());
+    }
+
+    fn time(time: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(time, "%H:%M").unwrap()
+    }
+
+    #[test]
+    fn hours() {
+        assert_eq!(
+            parse_hours(" 09:00 - 17:30 ").unwrap(),
+            (time("09:00"), time("17:30"))
+        );
+        for hours in [
+            "",
+            "09:00",
+            "09:00-",
+            "9h-17h",
+            "25:00-08:00",
+            "09:00-17:00-18:00",
+        ] {
+            assert!(parse_hours(hours).is_err(), "{hours:?} should be invalid");
+        }
+    }
+
+    #[test]
+    fn hours_within_a_day() {
+        let office = parse_hours("09:00-17:00").unwrap();
+        assert!(!hours_include(office, time("08:59")));
+        assert!(hours_include(office, time("09:00")));
+        assert!(hours_include(office, time("16:59")));
+        assert!(!hours_include(office, time("17:00")));
+    }
+
+    #[test]
+    fn hours_spanning_midnight() {
+        let night = parse_hours("22:00-08:00").unwrap();
+        assert!(!hours_include(night, time("21:59")));
+        assert!(hours_include(night, time("22:00")));
+        assert!(hours_include(night, time("00:00")));
+        assert!(hours_include(night, time("07:59")));
+        assert!(!hours_include(night, time("08:00")));
+        assert!(!hours_include(night, time("12:00")));
+    }
+}
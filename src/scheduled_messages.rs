@@ -0,0 +1,405 @@
+//! Scheduled messages, set up in a conversation with the bot: a user sends `schedule a message` in
+//! a direct message, the bot asks for the room, the time and the content, and once the user
+//! confirms, posts the message in the room at that time. No module is involved.
+//!
+//! The conversations are kept in memory, and abandoned after a while; the scheduled messages are
+//! persisted, so they're delivered after restarts too.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, NaiveDateTime, TimeZone as _, Utc};
+use matrix_sdk::{
+    room::Room,
+    ruma::{events::room::message::RoomMessageEventContent, OwnedRoomId, OwnedUserId, UserId},
+    Client, RoomMemberships,
+};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::Mutex,
+    time::{sleep, Duration, Instant},
+};
+use tracing::{info, warn};
+
+use crate::{
+    host_table,
+    maintenance::Maintenance,
+    outbox,
+    schedule::parse_timezone,
+    utils::{parse_duration, resolve_room},
+    ShareableDatabase,
+};
+
+/// Name of the host table keeping the scheduled messages.
+const TABLE: &str = "scheduled_messages";
+/// Key of the list of the pending scheduled messages.
+const MESSAGES_KEY: &str = "messages";
+/// Delay between two checks of the due messages.
+const DELIVERY_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// How long a conversation may stay idle before it's abandoned.
+const CONVERSATION_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+/// Longest delay before a scheduled message.
+const MAX_DELAY: chrono::Duration = chrono::Duration::days(366);
+
+/// A message waiting to be posted.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct ScheduledMessage {
+    /// Identifies the message for its author, to cancel it.
+    id: u64,
+    author: OwnedUserId,
+    room: OwnedRoomId,
+    at: DateTime<Utc>,
+    text: String,
+}
+
+/// What the bot is waiting for in a conversation.
+#[derive(Clone, Debug)]
+enum Step {
+    Room,
+    Time {
+        room: OwnedRoomId,
+    },
+    Content {
+        room: OwnedRoomId,
+        at: DateTime<Utc>,
+    },
+    Confirmation {
+        room: OwnedRoomId,
+        at: DateTime<Utc>,
+        text: String,
+    },
+}
+
+/// A scheduling conversation in progress with a user.
+struct Conversation {
+    /// The direct message room the conversation takes place in.
+    room: OwnedRoomId,
+    step: Step,
+    last_activity: Instant,
+}
+
+/// Parses when to post a message: a delay (e.g. `in 2h`), or a date and time
+/// (`YYYY-MM-DD HH:MM`) optionally followed by an IANA timezone, UTC by default.
+fn parse_time(input: &str) -> anyhow::Result<DateTime<Utc>> {
+    let input = input.trim();
+    if let Some(delay) = input.strip_prefix("in ") {
+        let delay = parse_duration(delay)
+            .ok_or_else(|| anyhow::anyhow!("invalid delay {delay}, e.g. 90m or 2d"))?;
+        return Ok(Utc::now() + chrono::Duration::from_std(delay)?);
+    }
+
+    let mut parts = input.split_whitespace();
+    let (Some(date), Some(time)) = (parts.next(), parts.next()) else {
+        anyhow::bail!("expected e.g. \"in 2h\" or \"2024-05-17 09:30 Europe/Paris\"");
+    };
+    let timezone = parse_timezone(parts.next())?;
+    let local = NaiveDateTime::parse_from_str(&format!("{date} {time}"), "%Y-%m-%d %H:%M")
+        .map_err(|err| anyhow::anyhow!("invalid date and time {date} {time}: {err}"))?;
+    Ok(timezone
+        .from_local_datetime(&local)
+        .earliest()
+        .ok_or_else(|| anyhow::anyhow!("{date} {time} doesn't exist in {timezone}"))?
+        .with_timezone(&Utc))
+}
+
+fn format_time(at: DateTime<Utc>) -> String {
+    at.format("%Y-%m-%d %H:%M UTC").to_string()
+}
+
+pub(crate) struct ScheduledMessages {
+    db: ShareableDatabase,
+    conversations: Mutex<HashMap<OwnedUserId, Conversation>>,
+    /// Serializes the read-modify-write cycles on the scheduled messages.
+    lock: Mutex<()>,
+}
+
+impl ScheduledMessages {
+    pub fn new(db: ShareableDatabase) -> Self {
+        Self {
+            db,
+            conversations: Default::default(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    fn read_messages(&self) -> anyhow::Result<Vec<ScheduledMessage>> {
+        Ok(host_table::read_json(&self.db, TABLE, MESSAGES_KEY)?.unwrap_or_default())
+    }
+
+    fn write_messages(&self, messages: &[ScheduledMessage]) -> anyhow::Result<()> {
+        host_table::write_json(&self.db, TABLE, MESSAGES_KEY, &messages)
+    }
+
+    async fn add(
+        &self,
+        author: &UserId,
+        room: OwnedRoomId,
+        at: DateTime<Utc>,
+        text: String,
+    ) -> anyhow::Result<u64> {
+        let _guard = self.lock.lock().await;
+        let mut messages = self.read_messages()?;
+        let id = messages
+            .iter()
+            .filter(|m| m.author == author)
+            .map(|m| m.id)
+            .max()
+            .unwrap_or(0)
+            + 1;
+        messages.push(ScheduledMessage {
+            id,
+            author: author.to_owned(),
+            room,
+            at,
+            text,
+        });
+        self.write_messages(&messages)?;
+        Ok(id)
+    }
+
+    fn list(&self, author: &UserId) -> anyhow::Result<String> {
+        let mut messages = self.read_messages()?;
+        messages.retain(|m| m.author == author);
+        if messages.is_empty() {
+            return Ok("you have no scheduled messages".to_owned());
+        }
+        messages.sort_by_key(|m| m.at);
+        Ok(messages
+            .iter()
+            .map(|m| {
+                format!(
+                    "{}. in {} at {}: {}",
+                    m.id,
+                    m.room,
+                    format_time(m.at),
+                    m.text
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+
+    async fn cancel(&self, author: &UserId, id: &str) -> anyhow::Result<String> {
+        let Ok(id) = id.trim().parse::<u64>() else {
+            return Ok(format!("invalid message number {id}"));
+        };
+        let _guard = self.lock.lock().await;
+        let mut messages = self.read_messages()?;
+        let before = messages.len();
+        messages.retain(|m| m.author != author || m.id != id);
+        if messages.len() == before {
+            return Ok(format!("you have no scheduled message {id}"));
+        }
+        self.write_messages(&messages)?;
+        Ok(format!("scheduled message {id} cancelled"))
+    }
+
+    /// Advances the conversation with the answer, and returns the bot's next question.
+    async fn advance(
+        &self,
+        client: &Client,
+        author: &UserId,
+        step: Step,
+        answer: &str,
+    ) -> anyhow::Result<(Option<Step>, String)> {
+        Ok(match step {
+            Step::Room => {
+                let Ok(room_id) = resolve_room(client, answer).await else {
+                    return Ok((
+                        Some(Step::Room),
+                        format!("I don't know about {answer}, which room?"),
+                    ));
+                };
+                let Some(room) = client.get_room(&room_id) else {
+                    return Ok((
+                        Some(Step::Room),
+                        format!("I'm not in {answer}, which room?"),
+                    ));
+                };
+                let is_member = room
+                    .members(RoomMemberships::JOIN)
+                    .await?
+                    .iter()
+                    .any(|member| member.user_id() == author);
+                if !is_member {
+                    return Ok((
+                        Some(Step::Room),
+                        format!("you're not in {answer}, which room?"),
+                    ));
+                }
+                (
+                    Some(Step::Time { room: room_id }),
+                    "when? e.g. \"in 2h\" or \"2024-05-17 09:30 Europe/Paris\"".to_owned(),
+                )
+            }
+            Step::Time { room } => match parse_time(answer) {
+                Ok(at) if at <= Utc::now() => (
+                    Some(Step::Time { room }),
+                    "that's in the past, when?".to_owned(),
+                ),
+                Ok(at) if at > Utc::now() + MAX_DELAY => (
+                    Some(Step::Time { room }),
+                    "that's too far away, when?".to_owned(),
+                ),
+                Ok(at) => (
+                    Some(Step::Content { room, at }),
+                    "what should I post?".to_owned(),
+                ),
+                Err(err) => (Some(Step::Time { room }), format!("{err:#}, when?")),
+            },
+            Step::Content { room, at } => {
+                let text = answer.to_owned();
+                let question = format!(
+                    "I'll post this in {room} at {}:\n{text}\nOK? (yes/no)",
+                    format_time(at)
+                );
+                (Some(Step::Confirmation { room, at, text }), question)
+            }
+            Step::Confirmation { room, at, text } => match answer.to_lowercase().as_str() {
+                "yes" | "y" | "ok" => {
+                    let id = self.add(author, room, at, text).await?;
+                    (
+                        None,
+                        format!("scheduled as message {id}; \"unschedule {id}\" cancels it"),
+                    )
+                }
+                "no" | "n" => (None, "OK, nothing scheduled".to_owned()),
+                _ => (
+                    Some(Step::Confirmation { room, at, text }),
+                    "yes or no?".to_owned(),
+                ),
+            },
+        })
+    }
+
+    /// Handles the commands starting a conversation, listing or cancelling scheduled messages.
+    async fn handle_command(
+        &self,
+        conversations: &mut HashMap<OwnedUserId, Conversation>,
+        room: &Room,
+        sender: &UserId,
+        command: &str,
+    ) -> anyhow::Result<String> {
+        if command == "schedule a message" {
+            conversations.insert(
+                sender.to_owned(),
+                Conversation {
+                    room: room.room_id().to_owned(),
+                    step: Step::Room,
+                    last_activity: Instant::now(),
+                },
+            );
+            Ok("in which room? (\"cancel\" to stop)".to_owned())
+        } else if command == "scheduled messages" {
+            self.list(sender)
+        } else if let Some(id) = command.strip_prefix("unschedule ") {
+            self.cancel(sender, id).await
+        } else {
+            unreachable!("not a scheduling command: {command}")
+        }
+    }
+
+    /// Try to handle a message sent in a direct message, as a scheduling command or an answer in
+    /// a scheduling conversation.
+    ///
+    /// Returns the response to send back, if the message was handled.
+    pub async fn try_handle(
+        &self,
+        client: &Client,
+        room: &Room,
+        sender: &UserId,
+        content: &str,
+    ) -> Option<String> {
+        let content = content.trim();
+        let mut conversations = self.conversations.lock().await;
+        conversations.retain(|_, c| c.last_activity.elapsed() < CONVERSATION_TIMEOUT);
+
+        let in_conversation = conversations
+            .get(sender)
+            .is_some_and(|c| c.room == room.room_id());
+        if !in_conversation {
+            let command = content.to_lowercase();
+            let is_command = command == "schedule a message"
+                || command == "scheduled messages"
+                || command.starts_with("unschedule ");
+            if !is_command || !room.is_direct().await.unwrap_or(false) {
+                return None;
+            }
+            let result = self
+                .handle_command(&mut conversations, room, sender, &command)
+                .await;
+            return Some(result.unwrap_or_else(|err| format!("error: {err:#}")));
+        }
+
+        let conversation = conversations.remove(sender)?;
+        if content.eq_ignore_ascii_case("cancel") {
+            return Some("OK, nothing scheduled".to_owned());
+        }
+        let (next, response) = match self
+            .advance(client, sender, conversation.step, content)
+            .await
+        {
+            Ok(advanced) => advanced,
+            Err(err) => (None, format!("error, nothing scheduled: {err:#}")),
+        };
+        if let Some(step) = next {
+            conversations.insert(
+                sender.to_owned(),
+                Conversation {
+                    room: conversation.room,
+                    step,
+                    last_activity: Instant::now(),
+                },
+            );
+        }
+        Some(response)
+    }
+
+    /// Removes the due messages, and returns them.
+    async fn take_due(&self) -> anyhow::Result<Vec<ScheduledMessage>> {
+        let _guard = self.lock.lock().await;
+        let now = Utc::now();
+        let (due, pending): (Vec<_>, Vec<_>) =
+            self.read_messages()?.into_iter().partition(|m| m.at <= now);
+        if !due.is_empty() {
+            self.write_messages(&pending)?;
+        }
+        Ok(due)
+    }
+
+    /// Periodically posts the due messages.
+    pub async fn run(&self, client: Client, maintenance: &Maintenance) {
+        loop {
+            sleep(DELIVERY_CHECK_INTERVAL).await;
+            if maintenance.is_on() {
+                // Like the timers, the messages are posted once maintenance is over.
+                continue;
+            }
+            let due = match self.take_due().await {
+                Ok(due) => due,
+                Err(err) => {
+                    warn!("couldn't read the scheduled messages: {err:#}");
+                    continue;
+                }
+            };
+            for message in due {
+                let Some(room) = client.get_room(&message.room) else {
+                    warn!(
+                        "the bot isn't in {} anymore, dropping a scheduled message",
+                        message.room
+                    );
+                    continue;
+                };
+                info!(
+                    "posting a message scheduled by {} in {}",
+                    message.author, message.room
+                );
+                let text = format!("{} (scheduled by {})", message.text, message.author);
+                if let Err(err) =
+                    outbox::send(&room, RoomMessageEventContent::text_plain(text)).await
+                {
+                    warn!("couldn't post a scheduled message: {err:#}");
+                }
+            }
+        }
+    }
+}
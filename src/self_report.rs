@@ -0,0 +1,239 @@
+//! Self-reporting of the bot's resource use (CPU, memory, open file descriptors, database size),
+//! sampled periodically, to catch slow leaks in long-running deployments: a warning is posted in
+//! the configured room when a value crosses its threshold, and another one when it gets back
+//! under it. The current values are also part of `!admin host diag`.
+//!
+//! The values are read from `/proc`, so they're only available on Linux.
+
+use std::{fmt::Write as _, path::PathBuf};
+
+use matrix_sdk::{
+    ruma::{events::room::message::RoomMessageEventContent, OwnedRoomId},
+    Client,
+};
+use serde::Deserialize;
+use tokio::time::{sleep, Duration, Instant};
+use tracing::{error, warn};
+
+use crate::outbox;
+
+/// Configuration for the self-reports.
+#[derive(Clone, Debug, Deserialize)]
+pub struct SelfReportConfig {
+    /// room where the reports and warnings are posted.
+    pub room: OwnedRoomId,
+    /// delay between two samples, in minutes.
+    #[serde(default = "default_interval_minutes")]
+    pub interval_minutes: u64,
+    /// whether every sample is posted, rather than only the warnings.
+    #[serde(default)]
+    pub post_reports: bool,
+    /// CPU use, in percent of a core, averaged since the previous sample.
+    pub max_cpu_percent: Option<f64>,
+    /// resident memory, in megabytes.
+    pub max_memory_mb: Option<u64>,
+    pub max_open_fds: Option<u64>,
+    /// size of the database file, in megabytes.
+    pub max_db_mb: Option<u64>,
+}
+
+fn default_interval_minutes() -> u64 {
+    15
+}
+
+/// Clock ticks per second of the CPU times in `/proc`, which is 100 on all mainstream Linux
+/// platforms.
+const CLOCK_TICKS_PER_SEC: f64 = 100.0;
+const MB: u64 = 1024 * 1024;
+
+/// Resource use of the bot's process at a given time.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ProcessStats {
+    /// CPU time spent since the process started, in clock ticks.
+    cpu_ticks: u64,
+    pub rss_bytes: u64,
+    pub open_fds: u64,
+}
+
+impl ProcessStats {
+    /// Reads the resource use of the current process from `/proc`.
+    pub fn sample() -> anyhow::Result<Self> {
+        let stat = std::fs::read_to_string("/proc/self/stat")?;
+        // The command name may contain spaces, so the fields are counted from its end.
+        let (_, fields) = stat
+            .rsplit_once(')')
+            .ok_or_else(|| anyhow::anyhow!("unexpected /proc/self/stat format"))?;
+        let fields = fields.split_whitespace().collect::<Vec<_>>();
+        // utime and stime are the 14th and 15th fields, the 2nd being the command name.
+        let tick = |index: usize| -> anyhow::Result<u64> {
+            Ok(fields
+                .get(index - 3)
+                .ok_or_else(|| anyhow::anyhow!("missing field {index} in /proc/self/stat"))?
+                .parse()?)
+        };
+        let cpu_ticks = tick(14)? + tick(15)?;
+
+        let status = std::fs::read_to_string("/proc/self/status")?;
+        let rss_kb = status
+            .lines()
+            .find_map(|line| line.strip_prefix("VmRSS:"))
+            .and_then(|value| {
+                value
+                    .trim()
+                    .trim_end_matches("kB")
+                    .trim()
+                    .parse::<u64>()
+                    .ok()
+            })
+            .unwrap_or(0);
+
+        let open_fds = std::fs::read_dir("/proc/self/fd")?.count() as u64;
+
+        Ok(Self {
+            cpu_ticks,
+            rss_bytes: rss_kb * 1024,
+            open_fds,
+        })
+    }
+
+    /// CPU time spent since the process started.
+    pub fn cpu_time(&self) -> Duration {
+        Duration::from_secs_f64(self.cpu_ticks as f64 / CLOCK_TICKS_PER_SEC)
+    }
+}
+
+/// A value that's compared to its threshold.
+struct Measure {
+    name: &'static str,
+    value: f64,
+    threshold: Option<f64>,
+    unit: &'static str,
+}
+
+impl Measure {
+    fn is_over(&self) -> bool {
+        self.threshold
+            .is_some_and(|threshold| self.value > threshold)
+    }
+
+    fn describe(&self) -> String {
+        format!("{} {:.0}{}", self.name, self.value, self.unit)
+    }
+}
+
+pub(crate) struct SelfReport {
+    config: Option<SelfReportConfig>,
+    db_path: PathBuf,
+}
+
+impl SelfReport {
+    pub fn new(config: Option<SelfReportConfig>, db_path: PathBuf) -> Self {
+        Self { config, db_path }
+    }
+
+    fn measures(
+        &self,
+        config: &SelfReportConfig,
+        stats: &ProcessStats,
+        cpu_percent: Option<f64>,
+    ) -> Vec<Measure> {
+        let mut measures = Vec::new();
+        if let Some(cpu_percent) = cpu_percent {
+            measures.push(Measure {
+                name: "CPU",
+                value: cpu_percent,
+                threshold: config.max_cpu_percent,
+                unit: "%",
+            });
+        }
+        measures.push(Measure {
+            name: "memory",
+            value: (stats.rss_bytes / MB) as f64,
+            threshold: config.max_memory_mb.map(|mb| mb as f64),
+            unit: " MB",
+        });
+        measures.push(Measure {
+            name: "open files",
+            value: stats.open_fds as f64,
+            threshold: config.max_open_fds.map(|fds| fds as f64),
+            unit: "",
+        });
+        match std::fs::metadata(&self.db_path) {
+            Ok(metadata) => measures.push(Measure {
+                name: "database",
+                value: (metadata.len() / MB) as f64,
+                threshold: config.max_db_mb.map(|mb| mb as f64),
+                unit: " MB",
+            }),
+            Err(err) => warn!("couldn't read the size of the database: {err}"),
+        }
+        measures
+    }
+
+    /// Periodically samples the resource use, and posts the warnings and reports.
+    pub async fn run(&self, client: Client) {
+        let Some(config) = &self.config else {
+            return;
+        };
+        let interval = Duration::from_secs(config.interval_minutes.max(1) * 60);
+        let mut previous: Option<(Instant, ProcessStats)> = None;
+        // Names of the measures currently over their threshold.
+        let mut over = Vec::<&'static str>::new();
+
+        loop {
+            sleep(interval).await;
+            let stats = match ProcessStats::sample() {
+                Ok(stats) => stats,
+                Err(err) => {
+                    error!("couldn't sample the resource use, stopping self-reports: {err:#}");
+                    return;
+                }
+            };
+            let now = Instant::now();
+            let cpu_percent = previous.map(|(at, prev)| {
+                let cpu = stats.cpu_time().saturating_sub(prev.cpu_time());
+                100.0 * cpu.as_secs_f64() / now.duration_since(at).as_secs_f64()
+            });
+            previous = Some((now, stats));
+
+            let measures = self.measures(config, &stats, cpu_percent);
+            let mut msg = String::new();
+            for measure in &measures {
+                let was_over = over.contains(&measure.name);
+                if measure.is_over() && !was_over {
+                    over.push(measure.name);
+                    let _ = writeln!(
+                        msg,
+                        "⚠️ {}, over the threshold of {:.0}{}",
+                        measure.describe(),
+                        measure.threshold.unwrap_or_default(),
+                        measure.unit
+                    );
+                } else if !measure.is_over() && was_over {
+                    over.retain(|name| *name != measure.name);
+                    let _ = writeln!(msg, "✅ {}, back under the threshold", measure.describe());
+                }
+            }
+            if config.post_reports {
+                let report = measures
+                    .iter()
+                    .map(Measure::describe)
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let _ = writeln!(msg, "📊 {report}");
+            }
+            if msg.is_empty() {
+                continue;
+            }
+
+            let Some(room) = client.get_room(&config.room) else {
+                warn!("unknown self-report room {}", config.room);
+                continue;
+            };
+            let content = RoomMessageEventContent::text_plain(msg.trim_end());
+            if let Err(err) = outbox::send(&room, content).await {
+                warn!("couldn't post a self-report: {err:#}");
+            }
+        }
+    }
+}
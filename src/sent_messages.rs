@@ -0,0 +1,55 @@
+//! Record of the last messages each module sent in each room, so modules can learn their event
+//! ids, with the `sent-messages` function of the `sys` API, and edit them with `edit` actions.
+
+use matrix_sdk::ruma::{EventId, RoomId};
+use tracing::warn;
+
+use crate::{host_table, ShareableDatabase};
+
+/// Name of the host table keeping the event ids, per module and room.
+const TABLE: &str = "sent_messages";
+/// Most event ids remembered per module and room.
+const MAX_PER_ROOM: usize = 50;
+
+fn key(module: &str, room_id: &str) -> String {
+    format!("{module}/{room_id}")
+}
+
+/// Event ids of the last messages the module sent in the room, oldest first.
+pub(crate) fn list(
+    db: &ShareableDatabase,
+    module: &str,
+    room_id: &str,
+) -> anyhow::Result<Vec<String>> {
+    Ok(host_table::read_json(db, TABLE, &key(module, room_id))?.unwrap_or_default())
+}
+
+pub(crate) struct SentMessages {
+    db: ShareableDatabase,
+}
+
+impl SentMessages {
+    pub fn new(db: ShareableDatabase) -> Self {
+        Self { db }
+    }
+
+    /// Remembers a message the module sent in the room.
+    pub fn record(&self, module: &str, room_id: &RoomId, event_id: &EventId) {
+        let result = list(&self.db, module, room_id.as_str()).and_then(|mut event_ids| {
+            event_ids.push(event_id.to_string());
+            let excess = event_ids.len().saturating_sub(MAX_PER_ROOM);
+            event_ids.drain(..excess);
+            host_table::write_json(&self.db, TABLE, &key(module, room_id.as_str()), &event_ids)
+        });
+        if let Err(err) = result {
+            warn!("couldn't record a message {module} sent in {room_id}: {err:#}");
+        }
+    }
+
+    /// Whether the event is one of the last messages the module sent in the room.
+    pub fn contains(&self, module: &str, room_id: &RoomId, event_id: &str) -> anyhow::Result<bool> {
+        Ok(list(&self.db, module, room_id.as_str())?
+            .iter()
+            .any(|id| id == event_id))
+    }
+}
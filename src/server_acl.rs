@@ -0,0 +1,152 @@
+//! Federation-aware filtering: events from blocked homeservers are ignored altogether, so that
+//! known-abusive servers can't reach the modules or the storage. The block list can also be
+//! applied as the `m.room.server_acl` of the rooms where the bot has enough power.
+
+use matrix_sdk::{
+    deserialized_responses::RawAnySyncOrStrippedState,
+    room::Room,
+    ruma::{
+        events::{room::server_acl::RoomServerAclEventContent, StateEventType},
+        ServerName, UserId,
+    },
+    Client,
+};
+use serde::Deserialize;
+use tracing::{debug, info, warn};
+
+use crate::outbox;
+
+/// Configuration for the server ACL.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ServerAclConfig {
+    /// servers whose events are ignored; `*` and `?` wildcards are supported, as in
+    /// `m.room.server_acl`, e.g. `*.evil.example`.
+    #[serde(default)]
+    pub blocked_servers: Vec<String>,
+    /// whether the blocked servers are added to the server ACL of the rooms where the bot may
+    /// change it, at startup.
+    #[serde(default)]
+    pub apply_to_rooms: bool,
+}
+
+#[derive(Deserialize)]
+struct ServerAclEvent {
+    content: RoomServerAclEventContent,
+}
+
+/// Matches a server name against a glob, as specified for `m.room.server_acl`.
+fn glob_matches(glob: &[u8], name: &[u8]) -> bool {
+    match (glob.split_first(), name.split_first()) {
+        (None, None) => true,
+        (Some((b'*', rest)), _) => {
+            glob_matches(rest, name) || (!name.is_empty() && glob_matches(glob, &name[1..]))
+        }
+        (Some((b'?', rest)), Some((_, name_rest))) => glob_matches(rest, name_rest),
+        (Some((g, rest)), Some((n, name_rest))) => {
+            g.eq_ignore_ascii_case(n) && glob_matches(rest, name_rest)
+        }
+        _ => false,
+    }
+}
+
+pub(crate) struct ServerAcl {
+    config: ServerAclConfig,
+}
+
+impl ServerAcl {
+    pub fn new(config: ServerAclConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn is_server_blocked(&self, server: &ServerName) -> bool {
+        // The ACL matches the host, without the port.
+        let host = server.host();
+        self.config
+            .blocked_servers
+            .iter()
+            .any(|glob| glob_matches(glob.as_bytes(), host.as_bytes()))
+    }
+
+    /// Whether the events of the user should be ignored.
+    pub fn is_blocked(&self, user_id: &UserId) -> bool {
+        let blocked = self.is_server_blocked(user_id.server_name());
+        if blocked {
+            debug!("ignoring event from {user_id}, whose server is blocked");
+        }
+        blocked
+    }
+
+    async fn current_acl(room: &Room) -> anyhow::Result<Option<RoomServerAclEventContent>> {
+        let Some(event) = room
+            .get_state_event(StateEventType::RoomServerAcl, "")
+            .await?
+        else {
+            return Ok(None);
+        };
+        let event = match event {
+            RawAnySyncOrStrippedState::Sync(raw) => raw.deserialize_as::<ServerAclEvent>(),
+            RawAnySyncOrStrippedState::Stripped(raw) => raw.deserialize_as::<ServerAclEvent>(),
+        }?;
+        Ok(Some(event.content))
+    }
+
+    /// Adds the blocked servers to the room's server ACL, if they're not all denied already.
+    async fn apply_to_room(&self, client: &Client, room: &Room) -> anyhow::Result<()> {
+        let Some(bot_id) = client.user_id() else {
+            return Ok(());
+        };
+        if !room
+            .can_user_send_state(bot_id, StateEventType::RoomServerAcl)
+            .await?
+        {
+            return Ok(());
+        }
+
+        // Without an ACL, all servers are allowed.
+        let mut acl = Self::current_acl(room)
+            .await?
+            .unwrap_or_else(|| RoomServerAclEventContent::new(true, vec!["*".to_owned()], vec![]));
+        let missing = self
+            .config
+            .blocked_servers
+            .iter()
+            .filter(|server| !acl.deny.contains(server))
+            .cloned()
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            return Ok(());
+        }
+
+        // Don't lock ourselves out.
+        if missing
+            .iter()
+            .any(|glob| glob_matches(glob.as_bytes(), bot_id.server_name().host().as_bytes()))
+        {
+            anyhow::bail!("the blocked servers include the bot's own server");
+        }
+
+        info!(
+            "adding {} to the server ACL of {}",
+            missing.join(", "),
+            room.room_id()
+        );
+        acl.deny.extend(missing);
+        outbox::send_state(room, acl).await?;
+        Ok(())
+    }
+
+    /// Applies the blocked servers to the rooms' server ACLs, if configured to.
+    pub async fn run(&self, client: Client) {
+        if !self.config.apply_to_rooms || self.config.blocked_servers.is_empty() {
+            return;
+        }
+        for room in client.joined_rooms() {
+            if let Err(err) = self.apply_to_room(&client, &room).await {
+                warn!(
+                    "couldn't update the server ACL of {}: {err:#}",
+                    room.room_id()
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! Per-room slow mode: users can't post more often than a given interval. Moderators are exempt.
+
+use std::{collections::HashMap, sync::Mutex, time::Instant};
+
+use matrix_sdk::{
+    room::Room,
+    ruma::{
+        events::room::message::RoomMessageEventContent, EventId, OwnedRoomId, OwnedUserId, RoomId,
+        UserId,
+    },
+    Client,
+};
+use tokio::time::Duration;
+use tracing::debug;
+
+use crate::{
+    host_table, outbox,
+    utils::{is_moderator, parse_duration, resolve_room},
+    ShareableDatabase,
+};
+
+/// Name of the host table keeping the slow mode interval (in seconds) per room.
+const TABLE: &str = "slowmode";
+
+pub(crate) struct SlowMode {
+    db: ShareableDatabase,
+    /// Cache of the intervals stored in the database.
+    intervals: Mutex<HashMap<OwnedRoomId, Option<Duration>>>,
+    /// When a user last posted in a room with slow mode enabled.
+    last_message: Mutex<HashMap<(OwnedRoomId, OwnedUserId), Instant>>,
+}
+
+impl SlowMode {
+    pub fn new(db: ShareableDatabase) -> Self {
+        Self {
+            db,
+            intervals: Default::default(),
+            last_message: Default::default(),
+        }
+    }
+
+    /// Forgets when a user last posted in a room, e.g. once they left it.
+    pub fn forget(&self, room_id: &RoomId, user_id: &UserId) {
+        self.last_message
+            .lock()
+            .unwrap()
+            .remove(&(room_id.to_owned(), user_id.to_owned()));
+    }
+
+    fn interval(&self, room_id: &OwnedRoomId) -> anyhow::Result<Option<Duration>> {
+        if let Some(interval) = self.intervals.lock().unwrap().get(room_id) {
+            return Ok(*interval);
+        }
+        let secs = host_table::read_u64(&self.db, TABLE, room_id.as_str())?;
+        let interval = (secs > 0).then(|| Duration::from_secs(secs));
+        self.intervals
+            .lock()
+            .unwrap()
+            .insert(room_id.clone(), interval);
+        Ok(interval)
+    }
+
+    /// Describes the room's setting, if any, for `!admin host dump-room`.
+    pub fn room_summary(&self, room_id: &OwnedRoomId) -> anyhow::Result<Option<String>> {
+        Ok(self
+            .interval(room_id)?
+            .map(|interval| format!("slow mode: {}s", interval.as_secs())))
+    }
+
+    fn set_interval(&self, room_id: OwnedRoomId, interval: Option<Duration>) -> anyhow::Result<()> {
+        match interval {
+            Some(interval) => {
+                host_table::write_u64(&self.db, TABLE, room_id.as_str(), interval.as_secs())?
+            }
+            None => host_table::remove(&self.db, TABLE, room_id.as_str())?,
+        }
+        self.intervals.lock().unwrap().insert(room_id, interval);
+        Ok(())
+    }
+
+    /// Enforces the slow mode for the given message.
+    ///
+    /// Returns whether the message was sent too early, in which case it mustn't be handled any
+    /// further.
+    pub async fn check(
+        &self,
+        client: &Client,
+        room: &Room,
+        event_id: &EventId,
+        sender: &UserId,
+    ) -> anyhow::Result<bool> {
+        let room_id = room.room_id().to_owned();
+        let Some(interval) = self.interval(&room_id)? else {
+            return Ok(false);
+        };
+
+        if is_moderator(room, sender).await? {
+            return Ok(false);
+        }
+
+        let key = (room_id, sender.to_owned());
+        let too_early = {
+            let mut last_message = self.last_message.lock().unwrap();
+            let now = Instant::now();
+            match last_message.get(&key) {
+                Some(last) if now.duration_since(*last) < interval => true,
+                _ => {
+                    last_message.insert(key, now);
+                    false
+                }
+            }
+        };
+
+        if !too_early {
+            return Ok(false);
+        }
+
+        debug!("{sender} is posting too fast in {}", room.room_id());
+        let bot_user_id = client.user_id().unwrap();
+        if room.can_user_redact(bot_user_id).await? {
+            outbox::redact(room, event_id, Some("slow mode")).await?;
+        } else {
+            let text = format!(
+                "{sender}, slow mode is enabled in this room: please wait {}s between messages.",
+                interval.as_secs()
+            );
+            outbox::send(room, RoomMessageEventContent::text_plain(text)).await?;
+        }
+
+        Ok(true)
+    }
+
+    /// Try to handle a message assuming it's an `!admin host slowmode ROOM INTERVAL` command.
+    pub async fn try_handle_admin(&self, client: &Client, content: &str) -> Option<String> {
+        let rest = content.strip_prefix("!admin host slowmode")?;
+        if !rest.is_empty() && !rest.starts_with(' ') {
+            return None;
+        }
+
+        let usage = "usage: !admin host slowmode ROOM (INTERVAL|off), e.g. 30s";
+        let Some((room, interval)) = rest.trim().split_once(' ') else {
+            return Some(usage.to_owned());
+        };
+
+        let interval = match interval.trim() {
+            "off" | "0" => None,
+            interval => match parse_duration(interval) {
+                Some(interval) => Some(interval),
+                None => return Some(usage.to_owned()),
+            },
+        };
+
+        let room_id = match resolve_room(client, room).await {
+            Ok(room_id) => room_id,
+            Err(err) => return Some(format!("couldn't resolve room {room}: {err:#}")),
+        };
+
+        Some(match self.set_interval(room_id, interval) {
+            Ok(()) => match interval {
+                Some(interval) => {
+                    format!("slow mode enabled in {room}: {}s", interval.as_secs())
+                }
+                None => format!("slow mode disabled in {room}"),
+            },
+            Err(err) => format!("error when setting slow mode: {err:#}"),
+        })
+    }
+}
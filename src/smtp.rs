@@ -0,0 +1,128 @@
+//! Sending emails on behalf of the modules, with `send-email` actions, e.g. so that escalation
+//! modules can page people who aren't on Matrix. Emails are sent through the configured SMTP
+//! server, from its configured address, and only to the allowed recipients.
+
+use lettre::{
+    message::Mailbox, transport::smtp::authentication::Credentials, AsyncSmtpTransport,
+    AsyncTransport as _, Message, Tokio1Executor,
+};
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use crate::{outbox, wasm};
+
+/// Configuration for sending emails.
+#[derive(Clone, Debug, Deserialize)]
+pub struct SmtpConfig {
+    /// host name of the SMTP server.
+    pub host: String,
+    /// port of the SMTP server.
+    #[serde(default = "default_port")]
+    pub port: u16,
+    /// whether the connection uses TLS from the start (usually on port 465), rather than
+    /// STARTTLS.
+    #[serde(default)]
+    pub implicit_tls: bool,
+    pub username: String,
+    pub password: String,
+    /// address the emails are sent from, e.g. `Tritongue <bot@example.com>`.
+    pub from: String,
+    /// addresses, or domains like `@example.com`, the modules may send emails to; any if empty.
+    #[serde(default)]
+    pub allowed_recipients: Vec<String>,
+}
+
+fn default_port() -> u16 {
+    587
+}
+
+/// Most recipients of a single email.
+const MAX_RECIPIENTS: usize = 10;
+
+pub(crate) struct Smtp {
+    config: Option<SmtpConfig>,
+    transport: Option<AsyncSmtpTransport<Tokio1Executor>>,
+}
+
+impl Smtp {
+    pub fn new(config: Option<SmtpConfig>) -> anyhow::Result<Self> {
+        let transport = match &config {
+            Some(config) => {
+                config.from.parse::<Mailbox>().map_err(|err| {
+                    anyhow::anyhow!("invalid smtp from address {}: {err}", config.from)
+                })?;
+                let builder = if config.implicit_tls {
+                    AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)?
+                } else {
+                    AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)?
+                };
+                let credentials =
+                    Credentials::new(config.username.clone(), config.password.clone());
+                Some(builder.port(config.port).credentials(credentials).build())
+            }
+            None => None,
+        };
+        Ok(Self { config, transport })
+    }
+
+    fn is_allowed(config: &SmtpConfig, recipient: &Mailbox) -> bool {
+        let address = recipient.email.to_string().to_lowercase();
+        config.allowed_recipients.is_empty()
+            || config.allowed_recipients.iter().any(|allowed| {
+                let allowed = allowed.to_lowercase();
+                if allowed.starts_with('@') {
+                    address.ends_with(&allowed)
+                } else {
+                    address == allowed
+                }
+            })
+    }
+
+    /// Sends an email requested by a module, if its recipients are allowed.
+    pub async fn send(&self, module: &str, email: wasm::Email) {
+        let (Some(config), Some(transport)) = (&self.config, &self.transport) else {
+            warn!("{module} can't send emails, no SMTP server is configured");
+            return;
+        };
+        if let Err(err) = Self::try_send(config, transport, module, email).await {
+            warn!("couldn't send an email for {module}: {err:#}");
+        }
+    }
+
+    async fn try_send(
+        config: &SmtpConfig,
+        transport: &AsyncSmtpTransport<Tokio1Executor>,
+        module: &str,
+        email: wasm::Email,
+    ) -> anyhow::Result<()> {
+        anyhow::ensure!(!email.to.is_empty(), "no recipients");
+        anyhow::ensure!(
+            email.to.len() <= MAX_RECIPIENTS,
+            "more than {MAX_RECIPIENTS} recipients"
+        );
+
+        let mut builder = Message::builder()
+            .from(config.from.parse()?)
+            .subject(email.subject);
+        for to in &email.to {
+            let recipient: Mailbox = to
+                .parse()
+                .map_err(|err| anyhow::anyhow!("invalid recipient {to}: {err}"))?;
+            anyhow::ensure!(
+                Self::is_allowed(config, &recipient),
+                "recipient {to} isn't in allowed_recipients"
+            );
+            builder = builder.to(recipient);
+        }
+        let message = builder.body(email.body)?;
+
+        let recipients = email.to.join(", ");
+        if outbox::is_dry_run() {
+            info!("dry run: {module} would send an email to {recipients}");
+            return Ok(());
+        }
+        transport.send(message).await?;
+        info!("sent an email to {recipients} for {module}");
+        Ok(())
+    }
+}
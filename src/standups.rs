@@ -0,0 +1,191 @@
+//! Scheduled stand-ups: at a given time, the bot asks a room (or team members, by DM) for status
+//! updates, collects the replies for a while, then posts a summary in the room.
+
+use std::{collections::HashMap, sync::Mutex};
+
+use matrix_sdk::{
+    room::Room,
+    ruma::{events::room::message::RoomMessageEventContent, OwnedRoomId, OwnedUserId, UserId},
+    Client,
+};
+use serde::Deserialize;
+use tokio::time::{sleep, Duration};
+use tracing::{debug, error};
+
+use crate::{maintenance::Maintenance, outbox, schedule::DailySchedule, utils::dm_room};
+
+/// Configuration for a single stand-up.
+#[derive(Clone, Debug, Deserialize)]
+pub struct StandupConfig {
+    /// room where the summary is posted, and where the question is asked if there's no members.
+    pub room: OwnedRoomId,
+    /// local time of the day at which the stand-up starts, as `HH:MM`.
+    pub time: String,
+    /// IANA timezone of `time`, e.g. `Europe/Paris`. Defaults to UTC.
+    pub timezone: Option<String>,
+    /// days on which there's no stand-up, e.g. `["sat", "sun"]`.
+    #[serde(default)]
+    pub skip_days: Vec<String>,
+    /// for how long replies are collected, in minutes.
+    #[serde(default = "default_window_minutes")]
+    pub window_minutes: u64,
+    /// question asked to the team.
+    #[serde(default = "default_question")]
+    pub question: String,
+    /// if set, these members get asked by DM instead of asking in the room.
+    #[serde(default)]
+    pub members: Vec<OwnedUserId>,
+}
+
+fn default_window_minutes() -> u64 {
+    60
+}
+
+fn default_question() -> String {
+    "Stand-up time! What did you do since last time, what are you up to next, anything blocking?"
+        .to_owned()
+}
+
+struct Standup {
+    config: StandupConfig,
+    schedule: DailySchedule,
+}
+
+pub(crate) struct Standups {
+    standups: Vec<Standup>,
+    /// Replies collected for the running stand-ups, indexed by stand-up.
+    replies: Mutex<HashMap<usize, Vec<(OwnedUserId, String)>>>,
+}
+
+impl Standups {
+    pub fn new(configs: Vec<StandupConfig>) -> anyhow::Result<Self> {
+        let standups = configs
+            .into_iter()
+            .map(|config| {
+                let schedule = DailySchedule::new(
+                    &config.time,
+                    config.timezone.as_deref(),
+                    &config.skip_days,
+                )?;
+                Ok(Standup { config, schedule })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self {
+            standups,
+            replies: Default::default(),
+        })
+    }
+
+    /// Collects replies to the running stand-ups.
+    pub async fn on_message(&self, room: &Room, sender: &UserId, content: &str) {
+        let mut is_direct = None;
+        for (index, standup) in self.standups.iter().enumerate() {
+            if !self.replies.lock().unwrap().contains_key(&index) {
+                continue;
+            }
+
+            let matches = if standup.config.members.is_empty() {
+                standup.config.room == room.room_id()
+            } else {
+                if is_direct.is_none() {
+                    is_direct = Some(room.is_direct().await.unwrap_or(false));
+                }
+                is_direct == Some(true) && standup.config.members.iter().any(|m| m == sender)
+            };
+
+            if matches {
+                if let Some(replies) = self.replies.lock().unwrap().get_mut(&index) {
+                    replies.push((sender.to_owned(), content.to_owned()));
+                }
+            }
+        }
+    }
+
+    async fn run_one(&self, client: &Client, index: usize) -> anyhow::Result<()> {
+        let standup = &self.standups[index];
+        let config = &standup.config;
+
+        let Some(room) = client.get_room(&config.room) else {
+            anyhow::bail!("not in the stand-up room {}", config.room);
+        };
+
+        debug!("starting stand-up in {}", config.room);
+        self.replies.lock().unwrap().insert(index, Vec::new());
+
+        let question = RoomMessageEventContent::text_plain(&config.question);
+        if config.members.is_empty() {
+            outbox::send(&room, question).await?;
+        } else {
+            for member in &config.members {
+                match dm_room(client, member).await {
+                    Ok(dm) => {
+                        outbox::send(&dm, question.clone()).await?;
+                    }
+                    Err(err) => error!("couldn't DM {member} for the stand-up: {err:#}"),
+                }
+            }
+        }
+
+        sleep(Duration::from_secs(config.window_minutes * 60)).await;
+
+        let replies = self
+            .replies
+            .lock()
+            .unwrap()
+            .remove(&index)
+            .unwrap_or_default();
+
+        let mut summary = String::from("Stand-up summary:");
+        if replies.is_empty() {
+            summary.push_str("\nno updates.");
+        }
+        let mut by_author: Vec<(OwnedUserId, Vec<String>)> = Vec::new();
+        for (author, text) in replies {
+            match by_author.iter_mut().find(|(a, _)| *a == author) {
+                Some((_, texts)) => texts.push(text),
+                None => by_author.push((author, vec![text])),
+            }
+        }
+        for (author, texts) in &by_author {
+            summary.push_str(&format!("\n- {author}: {}", texts.join(" / ")));
+        }
+
+        let missing = config
+            .members
+            .iter()
+            .filter(|m| !by_author.iter().any(|(author, _)| author == *m))
+            .map(|m| m.to_string())
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            summary.push_str(&format!("\nNo update from: {}", missing.join(", ")));
+        }
+
+        outbox::send(&room, RoomMessageEventContent::text_plain(summary)).await?;
+        Ok(())
+    }
+
+    async fn run_schedule(&self, client: &Client, maintenance: &Maintenance, index: usize) {
+        loop {
+            let Some(delay) = self.standups[index].schedule.until_next() else {
+                error!("stand-up {index} has no next occurrence, stopping it");
+                return;
+            };
+            sleep(delay).await;
+            if maintenance.is_on() {
+                // Like while the bot is down, the stand-up is skipped.
+                debug!("skipping stand-up {index} during maintenance");
+                continue;
+            }
+            if let Err(err) = self.run_one(client, index).await {
+                error!("error when running a stand-up: {err:#}");
+            }
+        }
+    }
+
+    /// Runs all the configured stand-ups. Never returns if there's any.
+    pub async fn run(&self, client: Client, maintenance: &Maintenance) {
+        let schedules =
+            (0..self.standups.len()).map(|index| self.run_schedule(&client, maintenance, index));
+        futures::future::join_all(schedules).await;
+    }
+}
@@ -0,0 +1,445 @@
+//! Streams the modules subscribe to with `subscribe` actions, for integrating with streaming APIs,
+//! e.g. server-sent events from a CI system: the host keeps a connection to the URL open, and calls
+//! the module's `on-stream-event` export back with each event, in the room where it subscribed.
+//! Responses that aren't server-sent events streams, e.g. long-polls, are delivered chunk by chunk.
+//!
+//! The connection is reopened when it drops or fails, with a backoff, and the id of the last event
+//! received. The subscriptions are persisted, so they survive restarts and hot reloads; events
+//! received during maintenance are dropped.
+
+use std::{collections::HashMap, sync::Mutex};
+
+use matrix_sdk::{
+    ruma::{OwnedRoomId, RoomId},
+    Client,
+};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::mpsc,
+    task::JoinHandle,
+    time::{sleep, timeout, Duration},
+};
+use tracing::{debug, error, warn};
+
+use crate::{
+    diagnostics::APP_CTX_LOCK, handle_module_actions, host_table, wasm, App, ShareableDatabase,
+};
+
+/// Name of the host table keeping the subscriptions.
+const TABLE: &str = "streams";
+/// Key of the list of all the subscriptions.
+const SUBSCRIPTIONS_KEY: &str = "subscriptions";
+/// Most subscriptions a module may have at once.
+const MAX_SUBSCRIPTIONS_PER_MODULE: usize = 10;
+/// Most events waiting to be delivered to the modules.
+const MAX_PENDING_EVENTS: usize = 1000;
+/// Largest event delivered to a module; a stream sending larger ones is reconnected.
+const MAX_EVENT_BYTES: usize = 64 * 1024;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Reconnect when nothing has been received for that long, assuming the connection is dead.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Subscription {
+    module: String,
+    room: OwnedRoomId,
+    name: String,
+    url: String,
+}
+
+impl Subscription {
+    fn is(&self, module: &str, room: &RoomId, name: &str) -> bool {
+        self.module == module && &*self.room == room && self.name == name
+    }
+}
+
+struct Event {
+    subscription: Subscription,
+    event: wasm::StreamEvent,
+}
+
+/// Parser of a server-sent events stream, fed with the chunks of the body.
+#[derive(Default)]
+struct SseParser {
+    /// Incomplete line at the end of the last chunk.
+    line: Vec<u8>,
+    kind: Option<String>,
+    data: String,
+    /// Id of the last event, sent back when reconnecting.
+    last_id: Option<String>,
+    /// Reconnection delay asked for by the server.
+    retry: Option<Duration>,
+}
+
+impl SseParser {
+    fn feed(&mut self, chunk: &[u8]) -> anyhow::Result<Vec<wasm::StreamEvent>> {
+        let mut events = Vec::new();
+        for &byte in chunk {
+            if byte != b'\n' {
+                self.line.push(byte);
+                if self.line.len() > MAX_EVENT_BYTES {
+                    anyhow::bail!("line longer than {MAX_EVENT_BYTES} bytes");
+                }
+                continue;
+            }
+            let line = std::mem::take(&mut self.line);
+            let line = String::from_utf8_lossy(&line);
+            let line = line.strip_suffix('\r').unwrap_or(&line);
+            events.extend(self.line_event(line)?);
+        }
+        Ok(events)
+    }
+
+    /// Handles a complete line, returning the event it completes, if any.
+    fn line_event(&mut self, line: &str) -> anyhow::Result<Option<wasm::StreamEvent>> {
+        if line.is_empty() {
+            let kind = self.kind.take();
+            if self.data.is_empty() {
+                return Ok(None);
+            }
+            let mut data = std::mem::take(&mut self.data);
+            data.pop();
+            return Ok(Some(wasm::StreamEvent {
+                kind: kind.unwrap_or_else(|| "message".to_owned()),
+                data,
+                id: self.last_id.clone(),
+            }));
+        }
+        if line.starts_with(':') {
+            // A comment, usually to keep the connection alive.
+            return Ok(None);
+        }
+
+        let (field, value) = line.split_once(':').unwrap_or((line, ""));
+        let value = value.strip_prefix(' ').unwrap_or(value);
+        match field {
+            "event" => self.kind = Some(value.to_owned()),
+            "data" => {
+                if self.data.len() + value.len() >= MAX_EVENT_BYTES {
+                    anyhow::bail!("event larger than {MAX_EVENT_BYTES} bytes");
+                }
+                self.data.push_str(value);
+                self.data.push('\n');
+            }
+            "id" if !value.contains('\0') => self.last_id = Some(value.to_owned()),
+            "retry" => {
+                if let Ok(millis) = value.parse() {
+                    self.retry = Some(Duration::from_millis(millis));
+                }
+            }
+            _ => {}
+        }
+        Ok(None)
+    }
+}
+
+/// Connects to the subscription's URL once, and forwards its events until the response ends.
+async fn stream_once(
+    client: &reqwest::Client,
+    subscription: &Subscription,
+    parser: &mut SseParser,
+    delay: &mut Duration,
+    events: &mpsc::Sender<Event>,
+) -> anyhow::Result<()> {
+    let mut request = client
+        .get(&subscription.url)
+        .header("Accept", "text/event-stream")
+        .header("Cache-Control", "no-cache");
+    if let Some(id) = &parser.last_id {
+        request = request.header("Last-Event-ID", id);
+    }
+    let mut response = request.send().await?.error_for_status()?;
+    *delay = MIN_RECONNECT_DELAY;
+
+    let is_sse = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/event-stream"));
+
+    loop {
+        let chunk = timeout(IDLE_TIMEOUT, response.chunk())
+            .await
+            .map_err(|_| anyhow::anyhow!("nothing received for {}s", IDLE_TIMEOUT.as_secs()))??;
+        let Some(chunk) = chunk else {
+            return Ok(());
+        };
+        let received = if is_sse {
+            parser.feed(&chunk)?
+        } else if chunk.len() > MAX_EVENT_BYTES {
+            anyhow::bail!("chunk larger than {MAX_EVENT_BYTES} bytes");
+        } else {
+            vec![wasm::StreamEvent {
+                kind: "chunk".to_owned(),
+                data: String::from_utf8_lossy(&chunk).into_owned(),
+                id: None,
+            }]
+        };
+        for event in received {
+            let event = Event {
+                subscription: subscription.clone(),
+                event,
+            };
+            if events.send(event).await.is_err() {
+                // The host is shutting down.
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Keeps the subscription's connection open, reconnecting with a backoff; the redirections are
+/// only followed to the allowed hosts of the module.
+async fn maintain(
+    subscription: Subscription,
+    allowed_hosts: Option<Vec<String>>,
+    events: mpsc::Sender<Event>,
+) {
+    let client = match reqwest::Client::builder()
+        .connect_timeout(CONNECT_TIMEOUT)
+        .redirect(wasm::redirect_policy(allowed_hosts))
+        .build()
+    {
+        Ok(client) => client,
+        Err(err) => {
+            error!(
+                "couldn't create the client of stream {}: {err}",
+                subscription.name
+            );
+            return;
+        }
+    };
+
+    let mut parser = SseParser::default();
+    let mut delay = MIN_RECONNECT_DELAY;
+    loop {
+        match stream_once(&client, &subscription, &mut parser, &mut delay, &events).await {
+            Ok(()) => debug!(
+                "stream {} of {} ended, reconnecting",
+                subscription.name, subscription.module
+            ),
+            Err(err) => warn!(
+                "stream {} of {} failed: {err:#}",
+                subscription.name, subscription.module
+            ),
+        }
+        if events.is_closed() {
+            return;
+        }
+        // Events cut by the reconnection are lost.
+        parser.line.clear();
+        parser.kind = None;
+        parser.data.clear();
+
+        sleep(parser.retry.unwrap_or_default().max(delay)).await;
+        delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+}
+
+pub(crate) struct Streams {
+    db: ShareableDatabase,
+    /// Hosts each module may connect to, as for its HTTP requests.
+    allowed_hosts: HashMap<String, Option<Vec<String>>>,
+    /// The subscriptions, with the tasks maintaining their connection once running.
+    subscriptions: Mutex<Vec<(Subscription, Option<JoinHandle<()>>)>>,
+    events: mpsc::Sender<Event>,
+    receiver: Mutex<Option<mpsc::Receiver<Event>>>,
+}
+
+impl Streams {
+    /// Creates the streams, with the subscriptions persisted in the database, which get connected
+    /// by [`run`].
+    pub fn new(
+        db: ShareableDatabase,
+        modules_config: &HashMap<String, HashMap<String, String>>,
+    ) -> anyhow::Result<Self> {
+        let subscriptions: Vec<Subscription> =
+            host_table::read_json(&db, TABLE, SUBSCRIPTIONS_KEY)?.unwrap_or_default();
+        let allowed_hosts = modules_config
+            .iter()
+            .map(|(module, config)| (module.clone(), wasm::allowed_hosts(Some(config))))
+            .collect();
+        let (events, receiver) = mpsc::channel(MAX_PENDING_EVENTS);
+        Ok(Self {
+            db,
+            allowed_hosts,
+            subscriptions: Mutex::new(subscriptions.into_iter().map(|s| (s, None)).collect()),
+            events,
+            receiver: Mutex::new(Some(receiver)),
+        })
+    }
+
+    fn save(&self, subscriptions: &[(Subscription, Option<JoinHandle<()>>)]) -> anyhow::Result<()> {
+        let subscriptions = subscriptions.iter().map(|(s, _)| s).collect::<Vec<_>>();
+        host_table::write_json(&self.db, TABLE, SUBSCRIPTIONS_KEY, &subscriptions)
+    }
+
+    fn check_url(&self, module: &str, url: &str) -> anyhow::Result<()> {
+        let url = reqwest::Url::parse(url)?;
+        if !matches!(url.scheme(), "http" | "https") {
+            anyhow::bail!("unsupported scheme {}", url.scheme());
+        }
+        let host = url.host_str().unwrap_or_default();
+        let allowed_hosts = self.allowed_hosts.get(module).and_then(Option::as_deref);
+        if !wasm::is_host_allowed(allowed_hosts, host) {
+            anyhow::bail!("the host {host} isn't in http_allowed_hosts");
+        }
+        Ok(())
+    }
+
+    fn start(&self, subscription: &Subscription) -> JoinHandle<()> {
+        let allowed_hosts = self
+            .allowed_hosts
+            .get(&subscription.module)
+            .cloned()
+            .flatten();
+        tokio::spawn(maintain(
+            subscription.clone(),
+            allowed_hosts,
+            self.events.clone(),
+        ))
+    }
+
+    fn add(&self, module: &str, room: &RoomId, sub: wasm::Subscription) -> anyhow::Result<()> {
+        self.check_url(module, &sub.url)?;
+        let subscription = Subscription {
+            module: module.to_owned(),
+            room: room.to_owned(),
+            name: sub.name,
+            url: sub.url,
+        };
+
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        let existing = subscriptions
+            .iter()
+            .position(|(s, _)| s.is(module, room, &subscription.name));
+        let task = Some(self.start(&subscription));
+        match existing {
+            Some(index) => {
+                let (_, old_task) =
+                    std::mem::replace(&mut subscriptions[index], (subscription, task));
+                if let Some(old_task) = old_task {
+                    old_task.abort();
+                }
+            }
+            None => {
+                let count = subscriptions
+                    .iter()
+                    .filter(|(s, _)| s.module == module)
+                    .count();
+                if count >= MAX_SUBSCRIPTIONS_PER_MODULE {
+                    if let Some(task) = task {
+                        task.abort();
+                    }
+                    anyhow::bail!("too many subscriptions ({count})");
+                }
+                subscriptions.push((subscription, task));
+            }
+        }
+        self.save(&subscriptions)
+    }
+
+    fn remove(&self, module: &str, room: &RoomId, name: &str) -> anyhow::Result<()> {
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        let Some(index) = subscriptions
+            .iter()
+            .position(|(s, _)| s.is(module, room, name))
+        else {
+            return Ok(());
+        };
+        if let (_, Some(task)) = subscriptions.remove(index) {
+            task.abort();
+        }
+        self.save(&subscriptions)
+    }
+
+    /// Subscribes a module to a stream, in a room.
+    pub fn subscribe(&self, module: &str, room: &RoomId, subscription: wasm::Subscription) {
+        let name = subscription.name.clone();
+        if let Err(err) = self.add(module, room, subscription) {
+            warn!("couldn't subscribe {module} to stream {name}: {err:#}");
+        }
+    }
+
+    /// Removes a subscription of a module in a room.
+    pub fn unsubscribe(&self, module: &str, room: &RoomId, name: &str) {
+        if let Err(err) = self.remove(module, room, name) {
+            warn!("couldn't unsubscribe {module} from stream {name}: {err:#}");
+        }
+    }
+}
+
+/// Connects the subscriptions, and delivers their events to the modules, handling their responses.
+pub(crate) async fn run(app: App, client: Client) {
+    let Some(mut receiver) = app.streams.receiver.lock().unwrap().take() else {
+        error!("the streams are already running");
+        return;
+    };
+    for (subscription, task) in app.streams.subscriptions.lock().unwrap().iter_mut() {
+        if task.is_none() {
+            *task = Some(app.streams.start(subscription));
+        }
+    }
+
+    while let Some(Event {
+        subscription,
+        event,
+    }) = receiver.recv().await
+    {
+        if app.maintenance.is_on() {
+            debug!(
+                "dropping an event of stream {} during maintenance",
+                subscription.name
+            );
+            continue;
+        }
+
+        let inner = app.inner.clone();
+        let crash_reporter = app.crash_reporter.clone();
+        let response_limits = app.response_limits.clone();
+        let (module, room) = (subscription.module.clone(), subscription.room.clone());
+        let name = subscription.name.clone();
+        let actions = tokio::task::spawn_blocking(move || {
+            let ctx = &mut *futures::executor::block_on(APP_CTX_LOCK.lock(&inner, "streams"));
+            let (store, mut modules) = ctx.modules.iter();
+            let Some(module) = modules.find(|m| m.name() == module) else {
+                debug!("dropping an event of stream {name} of {module}, which isn't loaded");
+                return None;
+            };
+            match module.on_stream_event(&mut *store, &name, &room, &event) {
+                Ok(actions) => Some(response_limits.apply(module.name(), actions)),
+                Err(err) => {
+                    warn!("wasm module {} ran into an error: {err}", module.name());
+                    module.record_error(&err);
+                    crash_reporter.module_error(module.name(), Some(&room), None, &err);
+                    None
+                }
+            }
+        })
+        .await;
+
+        let actions = match actions {
+            Ok(Some(actions)) => actions,
+            Ok(None) => continue,
+            Err(err) => {
+                error!("delivering a stream event failed: {err}");
+                continue;
+            }
+        };
+        let Some(room) = client.get_room(&subscription.room) else {
+            debug!(
+                "stream {} of {} is in unknown room {}",
+                subscription.name, subscription.module, subscription.room
+            );
+            continue;
+        };
+        if let Err(err) = handle_module_actions(&app, &room, &subscription.module, actions).await {
+            warn!(
+                "couldn't handle the actions of stream {} of {}: {err:#}",
+                subscription.name, subscription.module
+            );
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! Supervisor mode: runs one bot instance per configuration file of a directory, all within the
+//! same process. Each instance has its own client, stores and modules, and is restarted with a
+//! backoff when it fails, without affecting the others.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use chrono::{DateTime, Utc};
+use tokio::time::{sleep, Duration, Instant};
+use tracing::{error, info, info_span, Instrument as _};
+
+use crate::BotConfig;
+
+/// Delay before restarting a failed instance for the first time; doubled after each failure.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(10);
+/// Maximum delay before restarting a failed instance.
+const MAX_BACKOFF: Duration = Duration::from_secs(5 * 60);
+
+enum InstanceState {
+    Running,
+    Failed { error: String },
+    Stopped,
+}
+
+struct InstanceStatus {
+    state: InstanceState,
+    since: DateTime<Utc>,
+    restarts: u32,
+}
+
+/// Status of the supervised instances, indexed by name. Empty if the process doesn't run in
+/// supervisor mode.
+static INSTANCES: Mutex<BTreeMap<String, InstanceStatus>> = Mutex::new(BTreeMap::new());
+
+fn set_state(name: &str, state: InstanceState) {
+    let mut instances = INSTANCES.lock().unwrap();
+    let status = instances
+        .entry(name.to_owned())
+        .or_insert_with(|| InstanceStatus {
+            state: InstanceState::Stopped,
+            since: Utc::now(),
+            restarts: 0,
+        });
+    if matches!(state, InstanceState::Running) && !matches!(status.state, InstanceState::Stopped) {
+        status.restarts += 1;
+    }
+    status.state = state;
+    status.since = Utc::now();
+}
+
+/// Returns the configuration files of the directory, indexed by instance name.
+fn config_files(dir: &Path) -> anyhow::Result<BTreeMap<String, PathBuf>> {
+    let mut files = BTreeMap::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().map_or(true, |ext| ext != "toml") {
+            continue;
+        }
+        let Some(name) = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+        else {
+            continue;
+        };
+        files.insert(name, path);
+    }
+    Ok(files)
+}
+
+/// Runs an instance until it exits cleanly, restarting it when it fails.
+async fn supervise_instance(name: String, path: PathBuf) {
+    let mut backoff = INITIAL_BACKOFF;
+    loop {
+        set_state(&name, InstanceState::Running);
+        let started = Instant::now();
+
+        // The configuration is read again on every start, so that it can be fixed in place.
+        let path = path.to_string_lossy().into_owned();
+        let result = match BotConfig::from_config(Some(path)) {
+            // Spawned, so that a panic in an instance is caught as a failure.
+            Ok(config) => match tokio::spawn(crate::run(config)).await {
+                Ok(result) => result,
+                Err(err) => Err(anyhow::anyhow!("instance panicked: {err}")),
+            },
+            Err(err) => Err(err.context("reading the configuration")),
+        };
+
+        match result {
+            Ok(()) => {
+                info!("instance {name} exited");
+                set_state(&name, InstanceState::Stopped);
+                return;
+            }
+            Err(err) => {
+                // An instance that ran for a while isn't crash-looping.
+                if started.elapsed() > MAX_BACKOFF {
+                    backoff = INITIAL_BACKOFF;
+                }
+                error!(
+                    "instance {name} failed, restarting it in {}s: {err:#}",
+                    backoff.as_secs()
+                );
+                set_state(
+                    &name,
+                    InstanceState::Failed {
+                        error: format!("{err:#}"),
+                    },
+                );
+                sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+        }
+    }
+}
+
+/// Runs one instance for each `.toml` configuration file of `dir`, named after the file, until
+/// they all exit.
+pub async fn supervise(dir: &Path) -> anyhow::Result<()> {
+    let files = config_files(dir)?;
+    anyhow::ensure!(
+        !files.is_empty(),
+        "no configuration file found in {}",
+        dir.display()
+    );
+
+    let mut tasks = Vec::with_capacity(files.len());
+    for (name, path) in files {
+        info!("starting instance {name} from {}", path.display());
+        let span = info_span!("instance", name = name.as_str());
+        tasks.push(tokio::spawn(
+            supervise_instance(name, path).instrument(span),
+        ));
+    }
+    for task in tasks {
+        task.await?;
+    }
+
+    info!("all instances exited");
+    Ok(())
+}
+
+/// Handles `!admin host instances`, listing the status of the supervised instances.
+pub(crate) fn try_handle_admin(content: &str) -> Option<String> {
+    let rest = content.strip_prefix("!admin host instances")?;
+    if !rest.trim().is_empty() {
+        return None;
+    }
+
+    let instances = INSTANCES.lock().unwrap();
+    if instances.is_empty() {
+        return Some("not running in supervisor mode".to_owned());
+    }
+
+    let mut lines = Vec::with_capacity(instances.len());
+    for (name, status) in instances.iter() {
+        let since = status.since.format("%Y-%m-%d %H:%M:%S UTC");
+        let state = match &status.state {
+            InstanceState::Running => format!("running since {since}"),
+            InstanceState::Failed { error } => format!("failed at {since}: {error}"),
+            InstanceState::Stopped => format!("stopped at {since}"),
+        };
+        lines.push(format!("- {name}: {state} ({} restarts)", status.restarts));
+    }
+    Some(lines.join("\n"))
+}
@@ -0,0 +1,198 @@
+//! Timed room access: `!admin host temp-invite USER ROOM DURATION` invites a user to a room for a
+//! limited time, e.g. a guest speaker, after which the bot kicks them and forgets the state it kept
+//! about them in the room. The grants are persisted, so they expire across restarts too.
+
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use matrix_sdk::{
+    ruma::{OwnedRoomId, OwnedUserId},
+    Client,
+};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::Mutex,
+    time::{sleep, Duration},
+};
+use tracing::{error, info, warn};
+
+use crate::{
+    host_table, outbox,
+    utils::{parse_duration, resolve_room},
+    App, ShareableDatabase,
+};
+
+/// Name of the host table keeping the grants.
+const TABLE: &str = "temp_access";
+/// Key of the list of all the grants.
+const GRANTS_KEY: &str = "grants";
+/// Delay between two checks of the expired grants.
+const EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// Reason given when kicking a user whose access expired.
+const KICK_REASON: &str = "temporary access expired";
+
+const USAGE: &str = "usage: !admin host temp-invite (USER ROOM DURATION | list | \
+                     revoke USER ROOM), where DURATION is e.g. 90m or 2d";
+
+/// A user's temporary access to a room.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Grant {
+    user: OwnedUserId,
+    room: OwnedRoomId,
+    until: DateTime<Utc>,
+}
+
+pub(crate) struct TempAccess {
+    db: ShareableDatabase,
+    /// Serializes the read-modify-write cycles on the grants.
+    lock: Mutex<()>,
+}
+
+impl TempAccess {
+    pub fn new(db: ShareableDatabase) -> Self {
+        Self {
+            db,
+            lock: Mutex::new(()),
+        }
+    }
+
+    fn read_grants(&self) -> anyhow::Result<Vec<Grant>> {
+        Ok(host_table::read_json(&self.db, TABLE, GRANTS_KEY)?.unwrap_or_default())
+    }
+
+    fn write_grants(&self, grants: &[Grant]) -> anyhow::Result<()> {
+        host_table::write_json(&self.db, TABLE, GRANTS_KEY, &grants)
+    }
+
+    /// Removes the expired grants, and returns them.
+    async fn take_expired(&self) -> anyhow::Result<Vec<Grant>> {
+        let _guard = self.lock.lock().await;
+        let now = Utc::now();
+        let (expired, grants): (Vec<_>, Vec<_>) = self
+            .read_grants()?
+            .into_iter()
+            .partition(|g| g.until <= now);
+        if !expired.is_empty() {
+            self.write_grants(&grants)?;
+        }
+        Ok(expired)
+    }
+
+    async fn grant(
+        &self,
+        client: &Client,
+        user: &str,
+        room: &str,
+        duration: &str,
+    ) -> anyhow::Result<String> {
+        let Ok(user) = OwnedUserId::try_from(user) else {
+            return Ok(format!("invalid user id {user}"));
+        };
+        let Some(duration) = parse_duration(duration) else {
+            return Ok(format!("invalid duration {duration}, e.g. 90m or 2d"));
+        };
+        let room_id = resolve_room(client, room).await?;
+        let Some(joined) = client.get_room(&room_id) else {
+            return Ok(format!("the bot isn't in {room}"));
+        };
+
+        outbox::invite(&joined, &user).await?;
+
+        let until = Utc::now() + ChronoDuration::seconds(duration.as_secs() as i64);
+        let _guard = self.lock.lock().await;
+        let mut grants = self.read_grants()?;
+        grants.retain(|g| g.user != user || g.room != room_id);
+        grants.push(Grant {
+            user: user.clone(),
+            room: room_id,
+            until,
+        });
+        self.write_grants(&grants)?;
+        Ok(format!(
+            "invited {user} to {room} until {}",
+            until.format("%Y-%m-%d %H:%M UTC")
+        ))
+    }
+
+    async fn revoke(&self, client: &Client, user: &str, room: &str) -> anyhow::Result<String> {
+        let room_id = resolve_room(client, room).await?;
+        let _guard = self.lock.lock().await;
+        let mut grants = self.read_grants()?;
+        let Some(grant) = grants
+            .iter_mut()
+            .find(|g| g.user.as_str() == user && g.room == room_id)
+        else {
+            return Ok(format!("{user} has no temporary access to {room}"));
+        };
+        // Expire it right away, so it's handled like any other expired access.
+        grant.until = Utc::now();
+        self.write_grants(&grants)?;
+        Ok(format!("{user}'s access to {room} will be removed shortly"))
+    }
+
+    /// Try to handle an `!admin host temp-invite` command.
+    pub async fn try_handle_admin(&self, client: &Client, content: &str) -> Option<String> {
+        let rest = content.strip_prefix("!admin host temp-invite")?;
+        if !rest.is_empty() && !rest.starts_with(' ') {
+            return None;
+        }
+
+        let args = rest.split_whitespace().collect::<Vec<_>>();
+        let result = match args.as_slice() {
+            ["list"] => self.read_grants().map(|grants| {
+                if grants.is_empty() {
+                    return "no temporary access".to_owned();
+                }
+                grants
+                    .iter()
+                    .map(|g| {
+                        format!(
+                            "{} in {} until {}",
+                            g.user,
+                            g.room,
+                            g.until.format("%Y-%m-%d %H:%M UTC")
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            }),
+            ["revoke", user, room] => self.revoke(client, user, room).await,
+            [user, room, duration] => self.grant(client, user, room, duration).await,
+            _ => Ok(USAGE.to_owned()),
+        };
+        Some(result.unwrap_or_else(|err| format!("error: {err:#}")))
+    }
+}
+
+/// Periodically removes the users whose temporary access expired from their rooms.
+pub(crate) async fn run(app: App, client: Client) {
+    loop {
+        sleep(EXPIRY_CHECK_INTERVAL).await;
+        let expired = match app.temp_access.take_expired().await {
+            Ok(expired) => expired,
+            Err(err) => {
+                error!("error when reading the temporary accesses: {err:#}");
+                continue;
+            }
+        };
+
+        for grant in expired {
+            info!(
+                "temporary access of {} to {} expired",
+                grant.user, grant.room
+            );
+            match client.get_room(&grant.room) {
+                Some(room) => {
+                    // Kicking also revokes a pending invite.
+                    if let Err(err) = outbox::kick(&room, &grant.user, Some(KICK_REASON)).await {
+                        warn!(
+                            "couldn't remove {} from {}: {err:#}",
+                            grant.user, grant.room
+                        );
+                    }
+                }
+                None => warn!("the bot isn't in {} anymore", grant.room),
+            }
+            app.slowmode.forget(&grant.room, &grant.user);
+            app.gatekeeper.forget(&grant.room, &grant.user);
+        }
+    }
+}
@@ -0,0 +1,51 @@
+//! Thread-aware responses: a module responding to a message posted in a thread responds in the
+//! same thread, rather than in the main timeline. A module's `respond_in_threads` configuration
+//! key set to `false` opts it out, e.g. for announcements meant for the whole room. Reactions
+//! annotate the message itself, so they show up in its thread anyway.
+
+use std::collections::{HashMap, HashSet};
+
+use matrix_sdk::ruma::events::room::message::{
+    AddMentions, OriginalRoomMessageEvent, Relation, ReplyWithinThread, RoomMessageEventContent,
+};
+
+/// Module configuration key opting a module out of responding in threads.
+const RESPOND_IN_THREADS_KEY: &str = "respond_in_threads";
+
+pub(crate) struct Threads {
+    /// Modules responding in the main timeline, even to messages in threads.
+    opted_out: HashSet<String>,
+}
+
+impl Threads {
+    pub fn new(modules_config: &HashMap<String, HashMap<String, String>>) -> anyhow::Result<Self> {
+        let mut opted_out = HashSet::new();
+        for (module, config) in modules_config {
+            let Some(value) = config.get(RESPOND_IN_THREADS_KEY) else {
+                continue;
+            };
+            let respond_in_threads: bool = value.trim().parse().map_err(|err| {
+                anyhow::anyhow!("invalid {RESPOND_IN_THREADS_KEY} for module {module}: {err}")
+            })?;
+            if !respond_in_threads {
+                opted_out.insert(module.clone());
+            }
+        }
+        Ok(Self { opted_out })
+    }
+
+    /// Puts the module's response to the original message in the original's thread, if it's in
+    /// one and the module didn't opt out.
+    pub fn apply(
+        &self,
+        module: &str,
+        original: &OriginalRoomMessageEvent,
+        content: RoomMessageEventContent,
+    ) -> RoomMessageEventContent {
+        let in_thread = matches!(original.content.relates_to, Some(Relation::Thread(_)));
+        if !in_thread || self.opted_out.contains(module) {
+            return content;
+        }
+        content.make_for_thread(original, ReplyWithinThread::No, AddMentions::No)
+    }
+}
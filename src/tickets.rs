@@ -0,0 +1,289 @@
+//! Help-desk tickets for support rooms: `!ticket open TEXT` creates a numbered ticket, that
+//! moderators can then claim and close.
+
+use std::sync::Mutex;
+
+use matrix_sdk::{
+    room::Room,
+    ruma::{events::room::message::RoomMessageEventContent, OwnedRoomId, OwnedUserId, UserId},
+    Client,
+};
+use serde::{Deserialize, Serialize};
+use tokio::time::{sleep, Duration};
+use tracing::error;
+
+use crate::{host_table, outbox, utils::is_moderator, wasm, ShareableDatabase};
+
+/// Name of the host table keeping the tickets of each room.
+const TABLE: &str = "tickets";
+
+/// Key, in the tickets table, for the list of rooms having tickets.
+const ROOMS_KEY: &str = "@rooms";
+
+/// Configuration for the tickets.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct TicketsConfig {
+    /// if set, a summary of the open tickets is posted in each room at this interval.
+    pub summary_interval_minutes: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum Status {
+    Open,
+    Claimed,
+    Closed,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct Ticket {
+    pub id: u64,
+    pub room: OwnedRoomId,
+    pub author: OwnedUserId,
+    pub text: String,
+    pub status: Status,
+    pub assignee: Option<OwnedUserId>,
+}
+
+impl Ticket {
+    /// Converts the ticket into its representation for the wasm modules.
+    pub fn to_wasm(&self) -> wasm::Ticket {
+        wasm::Ticket {
+            id: self.id,
+            room: self.room.to_string(),
+            author: self.author.to_string(),
+            text: self.text.clone(),
+            status: match self.status {
+                Status::Open => wasm::TicketStatus::Open,
+                Status::Claimed => wasm::TicketStatus::Claimed,
+                Status::Closed => wasm::TicketStatus::Closed,
+            },
+            assignee: self.assignee.as_ref().map(|a| a.to_string()),
+        }
+    }
+
+    fn summary(&self) -> String {
+        match &self.assignee {
+            Some(assignee) if self.status == Status::Claimed => {
+                format!("#{} ({}, claimed by {assignee}): {}", self.id, self.author, self.text)
+            }
+            _ => format!("#{} ({}): {}", self.id, self.author, self.text),
+        }
+    }
+}
+
+/// All the tickets of a single room.
+#[derive(Default, Serialize, Deserialize)]
+struct RoomTickets {
+    next_id: u64,
+    tickets: Vec<Ticket>,
+}
+
+pub(crate) struct Tickets {
+    config: TicketsConfig,
+    db: ShareableDatabase,
+    /// Serializes the read-modify-write cycles on the tickets.
+    lock: Mutex<()>,
+}
+
+impl Tickets {
+    pub fn new(config: TicketsConfig, db: ShareableDatabase) -> Self {
+        Self {
+            config,
+            db,
+            lock: Mutex::new(()),
+        }
+    }
+
+    fn read_room(&self, room_id: &OwnedRoomId) -> anyhow::Result<RoomTickets> {
+        Ok(host_table::read_json(&self.db, TABLE, room_id.as_str())?.unwrap_or_default())
+    }
+
+    /// Describes what's stored for the room, if anything, for `!admin host dump-room`.
+    pub fn room_summary(&self, room_id: &OwnedRoomId) -> anyhow::Result<Option<String>> {
+        let count = self.read_room(room_id)?.tickets.len();
+        Ok((count > 0).then(|| format!("open tickets: {count}")))
+    }
+
+    fn open(&self, room_id: OwnedRoomId, author: &UserId, text: &str) -> anyhow::Result<Ticket> {
+        let _guard = self.lock.lock().unwrap();
+
+        let mut room_tickets = self.read_room(&room_id)?;
+        if room_tickets.tickets.is_empty() {
+            let mut rooms: Vec<OwnedRoomId> =
+                host_table::read_json(&self.db, TABLE, ROOMS_KEY)?.unwrap_or_default();
+            if !rooms.contains(&room_id) {
+                rooms.push(room_id.clone());
+                host_table::write_json(&self.db, TABLE, ROOMS_KEY, &rooms)?;
+            }
+        }
+
+        room_tickets.next_id += 1;
+        let ticket = Ticket {
+            id: room_tickets.next_id,
+            room: room_id.clone(),
+            author: author.to_owned(),
+            text: text.to_owned(),
+            status: Status::Open,
+            assignee: None,
+        };
+        room_tickets.tickets.push(ticket.clone());
+        host_table::write_json(&self.db, TABLE, room_id.as_str(), &room_tickets)?;
+        Ok(ticket)
+    }
+
+    /// Changes the status of a ticket, returning the updated ticket or an error message.
+    fn update(
+        &self,
+        room_id: &OwnedRoomId,
+        id: u64,
+        status: Status,
+        assignee: Option<OwnedUserId>,
+    ) -> anyhow::Result<Result<Ticket, String>> {
+        let _guard = self.lock.lock().unwrap();
+
+        let mut room_tickets = self.read_room(room_id)?;
+        let Some(ticket) = room_tickets.tickets.iter_mut().find(|t| t.id == id) else {
+            return Ok(Err(format!("no open ticket #{id} in this room")));
+        };
+        ticket.status = status;
+        if assignee.is_some() {
+            ticket.assignee = assignee;
+        }
+        let ticket = ticket.clone();
+
+        // Closed tickets aren't kept around.
+        room_tickets.tickets.retain(|t| t.status != Status::Closed);
+
+        host_table::write_json(&self.db, TABLE, room_id.as_str(), &room_tickets)?;
+        Ok(Ok(ticket))
+    }
+
+    fn summary(&self, room_id: &OwnedRoomId) -> anyhow::Result<Option<String>> {
+        let open = self.read_room(room_id)?.tickets;
+        if open.is_empty() {
+            return Ok(None);
+        }
+        let mut msg = format!("{} open ticket(s):", open.len());
+        for ticket in open {
+            msg.push_str("\n- ");
+            msg.push_str(&ticket.summary());
+        }
+        Ok(Some(msg))
+    }
+
+    /// Periodically posts the summary of the open tickets in each room, if configured to do so.
+    pub async fn run(&self, client: Client) {
+        let Some(minutes) = self.config.summary_interval_minutes else {
+            return;
+        };
+        loop {
+            sleep(Duration::from_secs(minutes * 60)).await;
+
+            let rooms: Vec<OwnedRoomId> = match host_table::read_json(&self.db, TABLE, ROOMS_KEY) {
+                Ok(rooms) => rooms.unwrap_or_default(),
+                Err(err) => {
+                    error!("couldn't read the rooms with tickets: {err:#}");
+                    continue;
+                }
+            };
+
+            for room_id in rooms {
+                let Some(room) = client.get_room(&room_id) else {
+                    continue;
+                };
+                match self.summary(&room_id) {
+                    Ok(Some(summary)) => {
+                        if let Err(err) =
+                            outbox::send(&room, RoomMessageEventContent::text_plain(summary)).await
+                        {
+                            error!("couldn't post the tickets summary in {room_id}: {err:#}");
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(err) => error!("couldn't read the tickets of {room_id}: {err:#}"),
+                }
+            }
+        }
+    }
+
+    async fn handle(
+        &self,
+        room: &Room,
+        sender: &UserId,
+        rest: &str,
+    ) -> anyhow::Result<(String, Option<Ticket>)> {
+        let room_id = room.room_id().to_owned();
+        let (cmd, arg) = rest.split_once(' ').unwrap_or((rest, ""));
+        let arg = arg.trim();
+
+        Ok(match cmd {
+            "open" if !arg.is_empty() => {
+                let ticket = self.open(room_id, sender, arg)?;
+                (format!("ticket #{} opened", ticket.id), Some(ticket))
+            }
+
+            "" | "list" => (
+                self.summary(&room_id)?
+                    .unwrap_or_else(|| "no open tickets".to_owned()),
+                None,
+            ),
+
+            "claim" | "close" => {
+                let Some(id) = arg.trim_start_matches('#').parse::<u64>().ok() else {
+                    return Ok((format!("usage: !ticket {cmd} NUMBER"), None));
+                };
+
+                let is_moderator = is_moderator(room, sender).await?;
+                let is_author = self
+                    .read_room(&room_id)?
+                    .tickets
+                    .iter()
+                    .any(|t| t.id == id && &*t.author == sender);
+                if !is_moderator && !(cmd == "close" && is_author) {
+                    return Ok(("only moderators can do that".to_owned(), None));
+                }
+
+                let (status, assignee) = if cmd == "claim" {
+                    (Status::Claimed, Some(sender.to_owned()))
+                } else {
+                    (Status::Closed, None)
+                };
+
+                match self.update(&room_id, id, status, assignee)? {
+                    Ok(ticket) => {
+                        let verb = if cmd == "claim" { "claimed" } else { "closed" };
+                        (format!("ticket #{id} {verb}"), Some(ticket))
+                    }
+                    Err(msg) => (msg, None),
+                }
+            }
+
+            _ => (
+                "usage: !ticket (open TEXT|list|claim NUMBER|close NUMBER)".to_owned(),
+                None,
+            ),
+        })
+    }
+
+    /// Try to handle a message assuming it's a `!ticket` command.
+    ///
+    /// Returns `None` if the message wasn't such a command; otherwise, the ticket that changed as
+    /// a result, if any, so modules can be notified about it.
+    pub async fn try_handle(
+        &self,
+        room: &Room,
+        sender: &UserId,
+        content: &str,
+    ) -> anyhow::Result<Option<Option<Ticket>>> {
+        let Some(rest) = content.strip_prefix("!ticket") else {
+            return Ok(None);
+        };
+        if !rest.is_empty() && !rest.starts_with(' ') {
+            return Ok(None);
+        }
+
+        let (response, changed) = self.handle(room, sender, rest.trim()).await?;
+        outbox::send(room, RoomMessageEventContent::text_plain(response)).await?;
+        Ok(Some(changed))
+    }
+}
@@ -0,0 +1,159 @@
+//! Background ticks of the modules, listed by their `ticks` export when they're loaded: the host
+//! runs a task per tick, which calls the module's `on-tick` export back every interval, in the
+//! tick's room, e.g. to poll a service or watch over something, independently of the messages.
+//!
+//! The calls take the modules' lock, so they're serialized with the handling of the messages. The
+//! tasks are restarted when the modules are reloaded, unless their tick didn't change.
+
+use std::{collections::HashMap, sync::Arc};
+
+use matrix_sdk::Client;
+use tokio::{
+    sync::Notify,
+    task::JoinHandle,
+    time::{interval, Duration, MissedTickBehavior},
+};
+use tracing::{debug, error, warn};
+
+use crate::{diagnostics::APP_CTX_LOCK, handle_module_actions, utils::resolve_room, wasm, App};
+
+/// Shortest interval between two ticks.
+const MIN_INTERVAL_SECS: u32 = 5;
+/// Most ticks a module may have.
+const MAX_TICKS_PER_MODULE: usize = 10;
+
+struct RunningTick {
+    tick: wasm::Tick,
+    task: JoinHandle<()>,
+}
+
+/// Calls the module back every time the tick is due, until the task is aborted.
+async fn run_tick(app: App, client: Client, module: String, tick: wasm::Tick) {
+    let room = match resolve_room(&client, &tick.room).await {
+        Ok(room_id) => client.get_room(&room_id),
+        Err(err) => {
+            warn!("not running tick {} of {module}: {err:#}", tick.name);
+            return;
+        }
+    };
+    let Some(room) = room else {
+        warn!(
+            "not running tick {} of {module}, the bot isn't in {}",
+            tick.name, tick.room
+        );
+        return;
+    };
+
+    let mut ticks = interval(Duration::from_secs(u64::from(tick.interval_secs)));
+    // A tick running late, e.g. waiting for a long message handling, doesn't make up for it.
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    // The first tick is immediate, the module is called back after a first interval.
+    ticks.tick().await;
+    loop {
+        ticks.tick().await;
+        if app.maintenance.is_on() {
+            debug!("skipping tick {} of {module} during maintenance", tick.name);
+            continue;
+        }
+
+        let inner = app.inner.clone();
+        let crash_reporter = app.crash_reporter.clone();
+        let response_limits = app.response_limits.clone();
+        let (module_name, tick_name, room_id) =
+            (module.clone(), tick.name.clone(), room.room_id().to_owned());
+        let actions = tokio::task::spawn_blocking(move || {
+            let ctx = &mut *futures::executor::block_on(APP_CTX_LOCK.lock(&inner, "tick"));
+            let (store, mut modules) = ctx.modules.iter();
+            let module = modules.find(|m| m.name() == module_name)?;
+            match module.on_tick(&mut *store, &tick_name, &room_id) {
+                Ok(actions) => Some(response_limits.apply(module.name(), actions)),
+                Err(err) => {
+                    warn!("wasm module {} ran into an error: {err}", module.name());
+                    module.record_error(&err);
+                    crash_reporter.module_error(module.name(), Some(&room_id), None, &err);
+                    None
+                }
+            }
+        })
+        .await;
+
+        match actions {
+            Ok(Some(actions)) => {
+                if let Err(err) = handle_module_actions(&app, &room, &module, actions).await {
+                    warn!(
+                        "couldn't handle the actions of tick {} of {module}: {err:#}",
+                        tick.name
+                    );
+                }
+            }
+            Ok(None) => {}
+            Err(err) => error!("running tick {} of {module} failed: {err}", tick.name),
+        }
+    }
+}
+
+/// The ticks of the loaded modules, by module and tick name, keeping the valid ones.
+async fn declared_ticks(app: &App) -> HashMap<(String, String), wasm::Tick> {
+    let mut ctx = APP_CTX_LOCK.lock(&app.inner, "ticks listing").await;
+    let (_, modules) = ctx.modules.iter();
+    let mut declared = HashMap::new();
+    for module in modules {
+        let ticks = module.ticks();
+        if ticks.len() > MAX_TICKS_PER_MODULE {
+            warn!(
+                "ignoring the ticks of {}: too many ({})",
+                module.name(),
+                ticks.len()
+            );
+            continue;
+        }
+        for tick in ticks {
+            if tick.interval_secs < MIN_INTERVAL_SECS {
+                warn!(
+                    "ignoring tick {} of {}: its interval is shorter than {MIN_INTERVAL_SECS} s",
+                    tick.name,
+                    module.name()
+                );
+                continue;
+            }
+            declared.insert((module.name().to_owned(), tick.name.clone()), tick.clone());
+        }
+    }
+    declared
+}
+
+/// Runs the ticks of the modules, restarting them every time the modules are (re)loaded.
+pub(crate) async fn run(app: App, client: Client, modules_loaded: Arc<Notify>) {
+    let mut running: HashMap<(String, String), RunningTick> = HashMap::new();
+    loop {
+        modules_loaded.notified().await;
+        let mut declared = declared_ticks(&app).await;
+
+        // The ticks that didn't change keep running, on their current schedule.
+        running.retain(|key, running| {
+            let unchanged = declared.get(key).is_some_and(|tick| {
+                tick.interval_secs == running.tick.interval_secs && tick.room == running.tick.room
+            });
+            if unchanged {
+                declared.remove(key);
+            } else {
+                running.task.abort();
+            }
+            unchanged
+        });
+
+        for ((module, name), tick) in declared {
+            debug!(
+                "starting tick {name} of {module}, every {} s",
+                tick.interval_secs
+            );
+            let task = tokio::spawn(run_tick(
+                app.clone(),
+                client.clone(),
+                module.clone(),
+                tick.clone(),
+            ));
+            running.insert((module, name), RunningTick { tick, task });
+        }
+    }
+}
@@ -0,0 +1,148 @@
+//! Timers set by the modules with `delayed` actions: the host calls the module's `on-timer`
+//! export back with the timer's payload when it fires, in the room where it was set. This lets
+//! modules implement reminders or polls without blocking in a guest call.
+//!
+//! The timers are kept in memory only, so they're lost on restart.
+
+use std::collections::BTreeMap;
+
+use matrix_sdk::{
+    ruma::{OwnedRoomId, RoomId},
+    Client,
+};
+use tokio::time::{Duration, Instant};
+use tracing::{debug, error, warn};
+
+use crate::{diagnostics::APP_CTX_LOCK, handle_module_actions, wasm, App};
+
+/// Granularity of the timers.
+const TICK: Duration = Duration::from_secs(1);
+/// Longest delay a module may ask for.
+const MAX_DELAY: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+/// Most timers a module may have pending at once.
+const MAX_PENDING_PER_MODULE: usize = 1000;
+
+pub(crate) struct Timer {
+    module: String,
+    room: OwnedRoomId,
+    payload: String,
+}
+
+/// The pending timers, ordered by deadline.
+#[derive(Default)]
+pub(crate) struct TimerWheel {
+    timers: BTreeMap<(Instant, u64), Timer>,
+    next_id: u64,
+}
+
+impl TimerWheel {
+    /// Schedules a timer for the module, unless it's got too many pending already.
+    pub fn schedule(
+        &mut self,
+        module: &str,
+        room: &RoomId,
+        delayed: wasm::Delayed,
+    ) -> anyhow::Result<()> {
+        let pending = self.timers.values().filter(|t| t.module == module).count();
+        if pending >= MAX_PENDING_PER_MODULE {
+            anyhow::bail!("too many pending timers ({pending})");
+        }
+
+        let delay = Duration::from_secs(delayed.delay_secs.into()).min(MAX_DELAY);
+        self.next_id += 1;
+        self.timers.insert(
+            (Instant::now() + delay, self.next_id),
+            Timer {
+                module: module.to_owned(),
+                room: room.to_owned(),
+                payload: delayed.payload,
+            },
+        );
+        Ok(())
+    }
+
+    /// Removes and returns the timers whose deadline has passed.
+    fn pop_due(&mut self, now: Instant) -> Vec<Timer> {
+        let later = self.timers.split_off(&(now, u64::MAX));
+        std::mem::replace(&mut self.timers, later)
+            .into_values()
+            .collect()
+    }
+}
+
+/// Schedules a timer requested by a module in a room.
+pub(crate) async fn schedule(app: &App, module: &str, room: &RoomId, delayed: wasm::Delayed) {
+    let result = APP_CTX_LOCK
+        .lock(&app.inner, "timer scheduling")
+        .await
+        .timers
+        .schedule(module, room, delayed);
+    if let Err(err) = result {
+        warn!("couldn't schedule a timer for {module}: {err:#}");
+    }
+}
+
+/// Fires the timers when they're due, calling the modules back and handling their responses.
+pub(crate) async fn run(app: App, client: Client) {
+    let mut interval = tokio::time::interval(TICK);
+    loop {
+        interval.tick().await;
+        if app.maintenance.is_on() {
+            // The timers fire once maintenance is over.
+            continue;
+        }
+
+        let inner = app.inner.clone();
+        let crash_reporter = app.crash_reporter.clone();
+        let response_limits = app.response_limits.clone();
+        let fired = tokio::task::spawn_blocking(move || {
+            let ctx = &mut *futures::executor::block_on(APP_CTX_LOCK.lock(&inner, "timers"));
+            let due = ctx.timers.pop_due(Instant::now());
+            let (store, modules) = ctx.modules.iter();
+
+            let mut fired = Vec::new();
+            for timer in due {
+                let Some(module) = modules.clone().find(|m| m.name() == timer.module) else {
+                    debug!("dropping a timer of {}, which isn't loaded", timer.module);
+                    continue;
+                };
+                match module.on_timer(&mut *store, &timer.room, &timer.payload) {
+                    Ok(actions) => {
+                        let actions = response_limits.apply(module.name(), actions);
+                        fired.push((timer, actions));
+                    }
+                    Err(err) => {
+                        warn!("wasm module {} ran into an error: {err}", module.name());
+                        module.record_error(&err);
+                        crash_reporter.module_error(module.name(), Some(&timer.room), None, &err);
+                    }
+                }
+            }
+            fired
+        })
+        .await;
+
+        let fired = match fired {
+            Ok(fired) => fired,
+            Err(err) => {
+                error!("firing the timers failed: {err}");
+                continue;
+            }
+        };
+        for (timer, actions) in fired {
+            let Some(room) = client.get_room(&timer.room) else {
+                debug!(
+                    "dropping a timer of {} in unknown room {}",
+                    timer.module, timer.room
+                );
+                continue;
+            };
+            if let Err(err) = handle_module_actions(&app, &room, &timer.module, actions).await {
+                warn!(
+                    "couldn't handle the timer actions of {}: {err:#}",
+                    timer.module
+                );
+            }
+        }
+    }
+}
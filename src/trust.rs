@@ -0,0 +1,157 @@
+//! Trust levels of the senders, from 0 (nothing known) to 100 (fully trusted), passed to the
+//! modules so that they can calibrate their responses (e.g. anti-spam, or rate-limited fun).
+//!
+//! The level is the one an admin assigned with `!admin host trust`, if any. Otherwise it's
+//! derived from what the room tells us about the sender: how long they've been a member, whether
+//! they're on the bot's (or a trusted) server, and whether they have a raised power level.
+
+use matrix_sdk::{
+    room::Room,
+    ruma::{OwnedServerName, OwnedUserId, UserId},
+};
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::{host_table, utils::now_secs, ShareableDatabase};
+
+const TABLE: &str = "trust";
+
+/// Highest trust level.
+const MAX_TRUST: u8 = 100;
+
+/// Base level of any sender.
+const BASE_TRUST: u8 = 10;
+/// Bonus for senders on the bot's server, or a trusted one.
+const SERVER_BONUS: u8 = 30;
+/// Bonus for senders with a power level above the default.
+const POWER_BONUS: u8 = 30;
+/// Bonuses depending on how long the sender has been a member of the room, in days.
+const MEMBERSHIP_BONUSES: &[(u64, u8)] = &[(30, 30), (7, 20), (1, 10)];
+
+/// Configuration for the trust levels.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct TrustConfig {
+    /// servers whose users are trusted as much as the bot's own server's.
+    #[serde(default)]
+    pub trusted_servers: Vec<OwnedServerName>,
+}
+
+pub(crate) struct Trust {
+    config: TrustConfig,
+    db: ShareableDatabase,
+    admin_user_id: OwnedUserId,
+}
+
+impl Trust {
+    pub fn new(config: TrustConfig, db: ShareableDatabase, admin_user_id: OwnedUserId) -> Self {
+        Self {
+            config,
+            db,
+            admin_user_id,
+        }
+    }
+
+    /// Level assigned by an admin to the user, if any.
+    fn assigned(&self, user_id: &UserId) -> anyhow::Result<Option<u8>> {
+        Ok(host_table::read(&self.db, TABLE, user_id.as_str())?
+            .and_then(|bytes| bytes.first().copied()))
+    }
+
+    /// Level derived from the user's membership in the room.
+    async fn derived(&self, room: &Room, user_id: &UserId) -> anyhow::Result<u8> {
+        let mut level = BASE_TRUST;
+
+        let bot_server = room
+            .client()
+            .user_id()
+            .map(|bot| bot.server_name().to_owned());
+        let server = user_id.server_name();
+        if bot_server.as_deref() == Some(server)
+            || self
+                .config
+                .trusted_servers
+                .iter()
+                .any(|trusted| trusted.as_str() == server.as_str())
+        {
+            level += SERVER_BONUS;
+        }
+
+        let Some(member) = room.get_member(user_id).await? else {
+            return Ok(level);
+        };
+        if member.power_level() > 0 {
+            level += POWER_BONUS;
+        }
+        // The membership event is the latest one, so changing one's display name resets this.
+        if let Some(since) = member.event().origin_server_ts() {
+            let days = now_secs().saturating_sub(u64::from(since.as_secs())) / (24 * 60 * 60);
+            if let Some((_, bonus)) = MEMBERSHIP_BONUSES.iter().find(|(min, _)| days >= *min) {
+                level += bonus;
+            }
+        }
+
+        Ok(level.min(MAX_TRUST))
+    }
+
+    /// Returns the trust level of the sender of a message in the room.
+    pub async fn level(&self, room: &Room, user_id: &UserId) -> u8 {
+        if user_id == self.admin_user_id {
+            return MAX_TRUST;
+        }
+        match self.assigned(user_id) {
+            Ok(Some(level)) => return level,
+            Ok(None) => {}
+            Err(err) => warn!("couldn't read the trust level of {user_id}: {err:#}"),
+        }
+        match self.derived(room, user_id).await {
+            Ok(level) => level,
+            Err(err) => {
+                warn!("couldn't derive the trust level of {user_id}: {err:#}");
+                BASE_TRUST
+            }
+        }
+    }
+
+    /// Try to handle a message assuming it's an `!admin host trust` command.
+    pub async fn try_handle_admin(&self, room: &Room, content: &str) -> Option<String> {
+        let rest = content.strip_prefix("!admin host trust")?;
+        if !rest.is_empty() && !rest.starts_with(' ') {
+            return None;
+        }
+
+        let usage =
+            "usage: !admin host trust (set USER LEVEL|unset USER|show USER), LEVEL being 0-100";
+        let mut args = rest.split_whitespace();
+        let (Some(cmd), Some(user)) = (args.next(), args.next()) else {
+            return Some(usage.to_owned());
+        };
+        let Ok(user_id) = UserId::parse(user) else {
+            return Some(format!("invalid user id: {user}"));
+        };
+
+        Some(match (cmd, args.next()) {
+            ("set", Some(level)) => match level.parse::<u8>() {
+                Ok(level) if level <= MAX_TRUST => {
+                    match host_table::write(&self.db, TABLE, user_id.as_str(), &[level]) {
+                        Ok(()) => format!("trust level of {user_id} set to {level}"),
+                        Err(err) => format!("error when setting the trust level: {err:#}"),
+                    }
+                }
+                _ => usage.to_owned(),
+            },
+            ("unset", None) => match host_table::remove(&self.db, TABLE, user_id.as_str()) {
+                Ok(()) => format!("trust level of {user_id} is derived again"),
+                Err(err) => format!("error when unsetting the trust level: {err:#}"),
+            },
+            ("show", None) => {
+                let assigned = self.assigned(&user_id).ok().flatten();
+                let level = self.level(room, &user_id).await;
+                match assigned {
+                    Some(_) => format!("trust level of {user_id}: {level} (assigned)"),
+                    None => format!("trust level of {user_id} in this room: {level} (derived)"),
+                }
+            }
+            _ => usage.to_owned(),
+        })
+    }
+}
@@ -0,0 +1,158 @@
+//! Small helpers shared by the host features.
+
+use matrix_sdk::{
+    room::Room,
+    ruma::{OwnedRoomAliasId, OwnedRoomId, UserId},
+    Client,
+};
+use tokio::time::Duration;
+
+/// Power level from which users are considered moderators.
+const MODERATOR_POWER_LEVEL: i64 = 50;
+
+/// Longest duration `parse_duration` accepts, a century, so that the callers can add it to the
+/// current time without overflowing.
+const MAX_DURATION_SECS: u64 = 100 * 365 * 24 * 60 * 60;
+
+/// Returns whether the user is a moderator in the given room.
+pub async fn is_moderator(room: &Room, user_id: &UserId) -> anyhow::Result<bool> {
+    Ok(room
+        .get_member(user_id)
+        .await?
+        .map_or(false, |member| member.power_level() >= MODERATOR_POWER_LEVEL))
+}
+
+/// Returns the direct message room with the given user, creating it if it doesn't exist yet.
+pub async fn dm_room(client: &Client, user_id: &UserId) -> anyhow::Result<Room> {
+    if let Some(room) = client.get_dm_room(user_id) {
+        return Ok(room);
+    }
+    Ok(client.create_dm(user_id).await?)
+}
+
+/// Resolves a room id or a room alias into a room id.
+pub async fn resolve_room(client: &Client, room: &str) -> anyhow::Result<OwnedRoomId> {
+    if let Ok(room_id) = OwnedRoomId::try_from(room) {
+        return Ok(room_id);
+    }
+    let alias = OwnedRoomAliasId::try_from(room)?;
+    Ok(client.resolve_room_alias(&alias).await?.room_id)
+}
+
+/// Parses a human readable duration, like `30s`, `5m`, `2h` or `1d`. A number without unit is
+/// a number of seconds. Returns `None` for durations longer than a century.
+pub fn parse_duration(input: &str) -> Option<Duration> {
+    let input = input.trim();
+    let (number, unit) = match input.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => input.split_at(index),
+        None => (input, "s"),
+    };
+    let number: u64 = number.parse().ok()?;
+    let multiplier = match unit.trim() {
+        "s" | "sec" | "secs" | "seconds" => 1,
+        "m" | "min" | "mins" | "minutes" => 60,
+        "h" | "hour" | "hours" => 60 * 60,
+        "d" | "day" | "days" => 24 * 60 * 60,
+        _ => return None,
+    };
+    number
+        .checked_mul(multiplier)
+        .filter(|secs| *secs <= MAX_DURATION_SECS)
+        .map(Duration::from_secs)
+}
+
+/// Strips the fallback quoting the original message from the body of a reply.
+pub fn strip_reply_fallback(body: &str) -> &str {
+    let mut rest = body;
+    while rest.starts_with("> ") || rest.starts_with(">\n") {
+        rest = rest.split_once('\n').map_or("", |(_, r)| r);
+    }
+    rest.trim_start_matches('\n')
+}
+
+/// Splits command arguments on whitespace, keeping double-quoted strings together.
+pub fn split_args(input: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut has_arg = false;
+    for c in input.chars() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                has_arg = true;
+            }
+            c if c.is_whitespace() && !in_quotes => {
+                if has_arg {
+                    args.push(std::mem::take(&mut current));
+                    has_arg = false;
+                }
+            }
+            c => {
+                current.push(c);
+                has_arg = true;
+            }
+        }
+    }
+    if has_arg {
+        args.push(current);
+    }
+    args
+}
+
+/// Returns the current time, in seconds since the Unix epoch.
+pub fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_duration_units() {
+        let secs = |secs| Some(Duration::from_secs(secs));
+        assert_eq!(parse_duration("30s"), secs(30));
+        assert_eq!(parse_duration("5m"), secs(5 * 60));
+        assert_eq!(parse_duration("2 hours"), secs(2 * 60 * 60));
+        assert_eq!(parse_duration("1d"), secs(24 * 60 * 60));
+        assert_eq!(parse_duration(" 42 "), secs(42));
+    }
+
+    #[test]
+    fn parse_duration_invalid() {
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("m"), None);
+        assert_eq!(parse_duration("-5m"), None);
+        assert_eq!(parse_duration("5w"), None);
+        assert_eq!(parse_duration("1.5h"), None);
+    }
+
+    #[test]
+    fn parse_duration_overflow() {
+        assert_eq!(parse_duration("999999999999999999d"), None);
+        assert_eq!(parse_duration("99999999999999999999"), None);
+        assert_eq!(parse_duration(&u64::MAX.to_string()), None);
+        let century = Duration::from_secs(MAX_DURATION_SECS);
+        assert_eq!(parse_duration("36500d"), Some(century));
+        assert_eq!(parse_duration("36501d"), None);
+    }
+
+    #[test]
+    fn split_args_quotes() {
+        assert_eq!(split_args(r#"add "a b"  c"#), ["add", "a b", "c"]);
+        assert_eq!(split_args(r#"say "" x"#), ["say", "", "x"]);
+        assert_eq!(split_args(r#"a"b c"d"#), ["ab cd"]);
+        // An unclosed quote runs until the end.
+        assert_eq!(split_args(r#"say "a b"#), ["say", "a b"]);
+    }
+
+    #[test]
+    fn split_args_empty() {
+        assert!(split_args("").is_empty());
+        assert!(split_args(" \t\n ").is_empty());
+        assert_eq!(split_args("  a  "), ["a"]);
+    }
+}
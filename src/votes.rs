@@ -0,0 +1,467 @@
+//! Votes with quorum and eligibility rules: `!vote "question" option1 option2` posts the options,
+//! members vote by reacting with the option's emoji, and the vote closes automatically after its
+//! duration. Results are kept for `!vote results`.
+
+use std::{collections::HashMap, sync::Mutex};
+
+use matrix_sdk::{
+    room::Room,
+    ruma::{
+        events::{
+            reaction::ReactionEventContent, relation::Annotation,
+            room::message::RoomMessageEventContent,
+        },
+        OwnedEventId, OwnedRoomId, OwnedUserId, UserId,
+    },
+    Client,
+};
+use serde::{Deserialize, Serialize};
+use tokio::time::{sleep, Duration};
+use tracing::{debug, error};
+
+use crate::{
+    host_table,
+    maintenance::Maintenance,
+    outbox,
+    utils::{is_moderator, now_secs, parse_duration, split_args},
+    ShareableDatabase,
+};
+
+/// Name of the host table keeping the votes of each room.
+const TABLE: &str = "votes";
+
+/// Key, in the votes table, for the list of rooms having votes.
+const ROOMS_KEY: &str = "@rooms";
+
+/// Emojis used to vote for each option, in order.
+const OPTION_KEYS: [&str; 10] = ["1️⃣", "2️⃣", "3️⃣", "4️⃣", "5️⃣", "6️⃣", "7️⃣", "8️⃣", "9️⃣", "🔟"];
+
+/// Duration of a vote, when none is given.
+const DEFAULT_DURATION: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How many closed votes are kept per room, for `!vote results`.
+const MAX_CLOSED_VOTES: usize = 20;
+
+/// How often expired votes are looked for.
+const CLOSE_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+const USAGE: &str = "usage: !vote \"QUESTION\" OPTION1 OPTION2... [--quorum N] [--duration 1h] \
+    [--min-power N] [--min-member-days N] | !vote results [NUMBER] | !vote close NUMBER";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Vote {
+    id: u64,
+    question: String,
+    options: Vec<String>,
+    author: OwnedUserId,
+    /// message members react to in order to vote.
+    event_id: Option<OwnedEventId>,
+    /// minimal number of ballots for the result to be valid.
+    quorum: u64,
+    /// minimal power level of the voters.
+    min_power: i64,
+    /// minimal number of days since the voters joined the room.
+    min_member_days: Option<u64>,
+    /// when the vote closes, in seconds since the Unix epoch.
+    ends_at: u64,
+    /// chosen option of each voter; the last reaction wins.
+    ballots: HashMap<OwnedUserId, usize>,
+    closed: bool,
+}
+
+impl Vote {
+    fn announcement(&self) -> String {
+        let mut text = format!("Vote #{}: {}", self.id, self.question);
+        for (key, option) in OPTION_KEYS.iter().zip(&self.options) {
+            text.push_str(&format!("\n{key} {option}"));
+        }
+        text.push_str("\nReact with the option's emoji to vote.");
+        if self.quorum > 0 {
+            text.push_str(&format!(" Quorum: {} vote(s).", self.quorum));
+        }
+        text
+    }
+
+    fn results(&self) -> String {
+        let mut counts = vec![0u64; self.options.len()];
+        for &choice in self.ballots.values() {
+            counts[choice] += 1;
+        }
+        let total = self.ballots.len() as u64;
+
+        let state = if self.closed {
+            "results"
+        } else {
+            "current tally"
+        };
+        let mut text = format!("Vote #{} {state}: {}", self.id, self.question);
+        for (option, count) in self.options.iter().zip(&counts) {
+            text.push_str(&format!("\n- {option}: {count}"));
+        }
+        text.push_str(&format!("\n{total} vote(s)"));
+
+        if !self.closed {
+            return text;
+        }
+        if total < self.quorum {
+            text.push_str(&format!(", quorum of {} not reached.", self.quorum));
+            return text;
+        }
+        let max = counts.iter().copied().max().unwrap_or(0);
+        let winners = self
+            .options
+            .iter()
+            .zip(&counts)
+            .filter(|(_, &count)| count == max)
+            .map(|(option, _)| option.as_str())
+            .collect::<Vec<_>>();
+        match winners.as_slice() {
+            _ if max == 0 => text.push('.'),
+            [winner] => text.push_str(&format!(", winner: {winner}.")),
+            tied => text.push_str(&format!(", tie between {}.", tied.join(", "))),
+        }
+        text
+    }
+}
+
+/// All the votes of a single room.
+#[derive(Default, Serialize, Deserialize)]
+struct RoomVotes {
+    next_id: u64,
+    votes: Vec<Vote>,
+}
+
+pub(crate) struct Votes {
+    db: ShareableDatabase,
+    /// Serializes the read-modify-write cycles on the votes.
+    lock: Mutex<()>,
+}
+
+impl Votes {
+    pub fn new(db: ShareableDatabase) -> Self {
+        Self {
+            db,
+            lock: Mutex::new(()),
+        }
+    }
+
+    fn read_room(&self, room_id: &OwnedRoomId) -> anyhow::Result<RoomVotes> {
+        Ok(host_table::read_json(&self.db, TABLE, room_id.as_str())?.unwrap_or_default())
+    }
+
+    /// Describes what's stored for the room, if anything, for `!admin host dump-room`.
+    pub fn room_summary(&self, room_id: &OwnedRoomId) -> anyhow::Result<Option<String>> {
+        let votes = self.read_room(room_id)?.votes;
+        if votes.is_empty() {
+            return Ok(None);
+        }
+        let open = votes.iter().filter(|v| !v.closed).count();
+        let closed = votes.len() - open;
+        Ok(Some(format!("votes: {open} open, {closed} closed")))
+    }
+
+    /// Applies `f` to the votes of the room, and saves them.
+    fn update_room<T>(
+        &self,
+        room_id: &OwnedRoomId,
+        f: impl FnOnce(&mut RoomVotes) -> T,
+    ) -> anyhow::Result<T> {
+        let _guard = self.lock.lock().unwrap();
+        let mut room_votes = self.read_room(room_id)?;
+        let result = f(&mut room_votes);
+        host_table::write_json(&self.db, TABLE, room_id.as_str(), &room_votes)?;
+        Ok(result)
+    }
+
+    fn register_room(&self, room_id: &OwnedRoomId) -> anyhow::Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        let mut rooms: Vec<OwnedRoomId> =
+            host_table::read_json(&self.db, TABLE, ROOMS_KEY)?.unwrap_or_default();
+        if !rooms.contains(room_id) {
+            rooms.push(room_id.clone());
+            host_table::write_json(&self.db, TABLE, ROOMS_KEY, &rooms)?;
+        }
+        Ok(())
+    }
+
+    async fn is_eligible(room: &Room, vote: &Vote, user_id: &UserId) -> anyhow::Result<bool> {
+        let Some(member) = room.get_member(user_id).await? else {
+            return Ok(false);
+        };
+        if member.power_level() < vote.min_power {
+            return Ok(false);
+        }
+        if let Some(days) = vote.min_member_days {
+            // The membership event is the latest one, so changing one's display name resets this.
+            let since = member
+                .event()
+                .origin_server_ts()
+                .map(|ts| u64::from(ts.as_secs()));
+            match since {
+                Some(since) if now_secs().saturating_sub(since) >= days * 24 * 60 * 60 => {}
+                _ => return Ok(false),
+            }
+        }
+        Ok(true)
+    }
+
+    /// Records a ballot, if the reaction is to an open vote's message.
+    pub async fn on_reaction(
+        &self,
+        room: &Room,
+        sender: &UserId,
+        relates_to: &OwnedEventId,
+        key: &str,
+    ) -> anyhow::Result<()> {
+        let Some(choice) = OPTION_KEYS.iter().position(|k| *k == key) else {
+            return Ok(());
+        };
+        let room_id = room.room_id().to_owned();
+        let Some(vote) = self
+            .read_room(&room_id)?
+            .votes
+            .into_iter()
+            .find(|v| !v.closed && v.event_id.as_ref() == Some(relates_to))
+        else {
+            return Ok(());
+        };
+        if choice >= vote.options.len() {
+            return Ok(());
+        }
+        if !Self::is_eligible(room, &vote, sender).await? {
+            debug!("{sender} isn't eligible to vote #{} in {room_id}", vote.id);
+            return Ok(());
+        }
+
+        self.update_room(&room_id, |room_votes| {
+            if let Some(vote) = room_votes
+                .votes
+                .iter_mut()
+                .find(|v| v.id == vote.id && !v.closed)
+            {
+                vote.ballots.insert(sender.to_owned(), choice);
+            }
+        })
+    }
+
+    /// Closes the vote, returning its results if it was still open.
+    fn close(&self, room_id: &OwnedRoomId, id: u64) -> anyhow::Result<Option<String>> {
+        self.update_room(room_id, |room_votes| {
+            let vote = room_votes
+                .votes
+                .iter_mut()
+                .find(|v| v.id == id && !v.closed)?;
+            vote.closed = true;
+            let results = vote.results();
+
+            let closed = room_votes.votes.iter().filter(|v| v.closed).count();
+            if closed > MAX_CLOSED_VOTES {
+                let mut to_remove = closed - MAX_CLOSED_VOTES;
+                room_votes.votes.retain(|v| {
+                    if v.closed && to_remove > 0 {
+                        to_remove -= 1;
+                        false
+                    } else {
+                        true
+                    }
+                });
+            }
+
+            Some(results)
+        })
+    }
+
+    async fn close_expired(&self, client: &Client) -> anyhow::Result<()> {
+        let rooms: Vec<OwnedRoomId> =
+            host_table::read_json(&self.db, TABLE, ROOMS_KEY)?.unwrap_or_default();
+        let now = now_secs();
+        for room_id in rooms {
+            let expired = self
+                .read_room(&room_id)?
+                .votes
+                .iter()
+                .filter(|v| !v.closed && v.ends_at <= now)
+                .map(|v| v.id)
+                .collect::<Vec<_>>();
+            for id in expired {
+                let Some(results) = self.close(&room_id, id)? else {
+                    continue;
+                };
+                if let Some(room) = client.get_room(&room_id) {
+                    outbox::send(&room, RoomMessageEventContent::text_plain(results)).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Periodically closes the votes which duration is over, and posts their results.
+    pub async fn run(&self, client: Client, maintenance: &Maintenance) {
+        loop {
+            sleep(CLOSE_CHECK_INTERVAL).await;
+            if maintenance.is_on() {
+                // The expired votes get closed once maintenance is over.
+                continue;
+            }
+            if let Err(err) = self.close_expired(&client).await {
+                error!("error when closing votes: {err:#}");
+            }
+        }
+    }
+
+    async fn start(
+        &self,
+        room: &Room,
+        sender: &UserId,
+        args: Vec<String>,
+    ) -> anyhow::Result<String> {
+        let mut words = Vec::new();
+        let mut quorum = 0;
+        let mut duration = DEFAULT_DURATION;
+        let mut min_power = 0;
+        let mut min_member_days = None;
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let flag = match arg.as_str() {
+                "--quorum" | "--duration" | "--min-power" | "--min-member-days" => arg,
+                _ => {
+                    words.push(arg);
+                    continue;
+                }
+            };
+            let Some(value) = args.next() else {
+                return Ok(format!("missing value for {flag}"));
+            };
+            let parsed = match flag.as_str() {
+                "--quorum" => value.parse().map(|v| quorum = v).is_ok(),
+                "--duration" => parse_duration(&value).map(|v| duration = v).is_some(),
+                "--min-power" => value.parse().map(|v| min_power = v).is_ok(),
+                _ => value.parse().map(|v| min_member_days = Some(v)).is_ok(),
+            };
+            if !parsed {
+                return Ok(format!("invalid value for {flag}: {value}"));
+            }
+        }
+
+        let mut words = words.into_iter();
+        let Some(question) = words.next() else {
+            return Ok(USAGE.to_owned());
+        };
+        let options = words.collect::<Vec<_>>();
+        if options.len() < 2 || options.len() > OPTION_KEYS.len() {
+            return Ok(format!(
+                "a vote needs between 2 and {} options",
+                OPTION_KEYS.len()
+            ));
+        }
+
+        let room_id = room.room_id().to_owned();
+        self.register_room(&room_id)?;
+        let mut vote = self.update_room(&room_id, |room_votes| {
+            room_votes.next_id += 1;
+            let vote = Vote {
+                id: room_votes.next_id,
+                question,
+                options,
+                author: sender.to_owned(),
+                event_id: None,
+                quorum,
+                min_power,
+                min_member_days,
+                ends_at: now_secs() + duration.as_secs(),
+                ballots: HashMap::new(),
+                closed: false,
+            };
+            room_votes.votes.push(vote.clone());
+            vote
+        })?;
+
+        let event_id = outbox::send(
+            room,
+            RoomMessageEventContent::text_plain(vote.announcement()),
+        )
+        .await?;
+        vote.event_id = Some(event_id.clone());
+        self.update_room(&room_id, |room_votes| {
+            if let Some(v) = room_votes.votes.iter_mut().find(|v| v.id == vote.id) {
+                v.event_id = vote.event_id.clone();
+            }
+        })?;
+
+        for key in OPTION_KEYS.iter().take(vote.options.len()) {
+            let reaction =
+                ReactionEventContent::new(Annotation::new(event_id.clone(), (*key).to_owned()));
+            outbox::send(room, reaction).await?;
+        }
+
+        // The announcement is the response.
+        Ok(String::new())
+    }
+
+    async fn handle(&self, room: &Room, sender: &UserId, rest: &str) -> anyhow::Result<String> {
+        let room_id = room.room_id().to_owned();
+        let args = split_args(rest);
+        let arg = args.get(1).map(|a| a.trim_start_matches('#'));
+
+        Ok(match args.first().map(String::as_str) {
+            None => USAGE.to_owned(),
+
+            Some("results") => {
+                let votes = self.read_room(&room_id)?.votes;
+                let vote = match arg {
+                    Some(id) => {
+                        let Ok(id) = id.parse::<u64>() else {
+                            return Ok(USAGE.to_owned());
+                        };
+                        votes.iter().find(|v| v.id == id)
+                    }
+                    None => votes.iter().filter(|v| v.closed).last(),
+                };
+                vote.map_or_else(|| "no such vote in this room".to_owned(), Vote::results)
+            }
+
+            Some("close") => {
+                let Some(id) = arg.and_then(|id| id.parse::<u64>().ok()) else {
+                    return Ok(USAGE.to_owned());
+                };
+                let is_author = self
+                    .read_room(&room_id)?
+                    .votes
+                    .iter()
+                    .any(|v| v.id == id && &*v.author == sender);
+                if !is_author && !is_moderator(room, sender).await? {
+                    return Ok("only the author or a moderator can close a vote".to_owned());
+                }
+                self.close(&room_id, id)?
+                    .unwrap_or_else(|| format!("no open vote #{id} in this room"))
+            }
+
+            Some(_) => self.start(room, sender, args).await?,
+        })
+    }
+
+    /// Try to handle a message assuming it's a `!vote` command.
+    ///
+    /// Returns whether the message was such a command, in which case it mustn't be handled any
+    /// further.
+    pub async fn try_handle(
+        &self,
+        room: &Room,
+        sender: &UserId,
+        content: &str,
+    ) -> anyhow::Result<bool> {
+        let Some(rest) = content.strip_prefix("!vote") else {
+            return Ok(false);
+        };
+        if !rest.is_empty() && !rest.starts_with(' ') {
+            return Ok(false);
+        }
+
+        let response = self.handle(room, sender, rest.trim()).await?;
+        if !response.is_empty() {
+            outbox::send(room, RoomMessageEventContent::text_plain(response)).await?;
+        }
+        Ok(true)
+    }
+}
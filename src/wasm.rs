@@ -6,17 +6,65 @@ mod module {
 
 use crate::wasm::module::exports::trinity::module::messaging;
 pub(crate) use messaging::Action;
+pub(crate) use messaging::Alert;
+pub(crate) use messaging::BusEvent;
+pub(crate) use messaging::DirectMessage;
+pub(crate) use messaging::Edit;
+pub(crate) use messaging::Email;
+pub(crate) use messaging::{Media, MediaKind};
 pub(crate) use messaging::Message;
+pub(crate) use messaging::Progress;
+pub(crate) use messaging::Reaction;
+pub(crate) use messaging::{Poll, PollTally};
+pub(crate) use messaging::Preview;
+pub(crate) use messaging::Redaction;
+pub(crate) use messaging::RoomMessage;
+pub(crate) use messaging::Upload;
+pub(crate) use messaging::Urgency;
+pub(crate) use messaging::MqttMessage;
+pub(crate) use messaging::{CronJob, Delayed};
+pub(crate) use messaging::{StreamEvent, Subscription};
+pub(crate) use messaging::{Ticket, TicketStatus};
+pub(crate) use messaging::Tick;
 
 mod apis;
+mod cache;
+mod capabilities;
+mod engine;
+mod file_info;
+mod host_version;
+mod limits;
+mod replay;
+
+pub(crate) use apis::{allowed_hosts, describe_media, is_host_allowed, redirect_policy};
+use apis::WasiState;
+pub(crate) use cache::ModuleCache;
+pub(crate) use capabilities::{Capabilities, Capability};
+pub use engine::WasmEngineConfig;
+pub(crate) use engine::new_engine;
+pub(crate) use file_info::FileInfo;
+pub(crate) use host_version::{required as required_host_version, HOST_VERSION};
+use limits::{EpochTicker, Limits};
+pub(crate) use replay::{replay, ReplayedEvent, Trigger};
 
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use matrix_sdk::ruma::{RoomId, UserId};
+use chrono::{DateTime, Utc};
+
+use matrix_sdk::{
+    ruma::{EventId, RoomId, UserId},
+    Client,
+};
 use wasmtime::AsContextMut;
 
-use crate::{wasm::apis::Apis, ShareableDatabase};
+use crate::{
+    directory::Directory,
+    wasm::apis::{Apis, HostTrace},
+    ShareableDatabase,
+};
 
 pub struct ModuleState {
     apis: Apis,
@@ -25,10 +73,29 @@ pub struct ModuleState {
 #[derive(Default)]
 pub(crate) struct GuestState {
     imports: Vec<ModuleState>,
+    /// Limits of the module being called.
+    limits: Limits,
+    wasi: WasiState,
 }
 
 pub(crate) struct Module {
     name: String,
+    path: PathBuf,
+    file_info: FileInfo,
+    /// Capabilities granted to the module.
+    capabilities: Capabilities,
+    limits: Limits,
+    /// Topics of the bus events the module listens to.
+    bus_topics: Vec<String>,
+    /// Types of the state events the module listens to.
+    state_event_types: Vec<String>,
+    /// Background ticks the module wants.
+    ticks: Vec<Tick>,
+    loaded_at: DateTime<Utc>,
+    /// Last error the module ran into, with its time.
+    last_error: Mutex<Option<(DateTime<Utc>, String)>>,
+    /// Trace of the module's host calls, shared with its APIs.
+    trace: HostTrace,
     exports: module::TrinityModule,
     _instance: wasmtime::component::Instance,
 }
@@ -38,51 +105,454 @@ impl Module {
         self.name.as_str()
     }
 
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn file_info(&self) -> &FileInfo {
+        &self.file_info
+    }
+
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+
+    pub fn listens_to(&self, topic: &str) -> bool {
+        self.bus_topics.iter().any(|t| t == topic)
+    }
+
+    pub fn wants_state_event(&self, event_type: &str) -> bool {
+        self.state_event_types.iter().any(|t| t == event_type)
+    }
+
+    pub fn ticks(&self) -> &[Tick] {
+        &self.ticks
+    }
+
+    pub fn loaded_at(&self) -> DateTime<Utc> {
+        self.loaded_at
+    }
+
+    pub fn last_error(&self) -> Option<(DateTime<Utc>, String)> {
+        self.last_error.lock().unwrap().clone()
+    }
+
+    /// Remembers an error the module ran into, for introspection.
+    pub fn record_error(&self, err: &anyhow::Error) {
+        *self.last_error.lock().unwrap() = Some((Utc::now(), format!("{err:#}")));
+    }
+
+    /// Logs the module's host calls, with their arguments and results, at debug level, for the
+    /// given duration.
+    pub fn trace_for(&self, duration: Duration) {
+        self.trace.log_until(Instant::now() + duration);
+    }
+
+    /// Stops logging the module's host calls; returns whether they were logged.
+    pub fn stop_trace(&self) -> bool {
+        self.trace.stop_logging()
+    }
+
+    /// Until when the module's host calls are logged, if they are.
+    pub fn traced_until(&self) -> Option<Instant> {
+        self.trace.logged_until()
+    }
+
+    /// Sets the module's limits for the next call.
+    fn set_limits(&self, mut store: impl AsContextMut<Data = GuestState>) -> anyhow::Result<()> {
+        let mut store = store.as_context_mut();
+        store.data_mut().limits = self.limits;
+        store.set_epoch_deadline(self.limits.epoch_deadline());
+        store.set_fuel(self.limits.fuel())
+    }
+
     pub fn help(
         &self,
-        store: impl AsContextMut<Data = GuestState>,
+        mut store: impl AsContextMut<Data = GuestState>,
         topic: Option<&str>,
     ) -> anyhow::Result<String> {
+        self.set_limits(&mut store)?;
         self.exports
             .trinity_module_messaging()
             .call_help(store, topic)
+            .map_err(|err| self.limits.explain(err))
     }
 
     pub fn admin(
         &self,
-        store: impl AsContextMut<Data = GuestState>,
+        mut store: impl AsContextMut<Data = GuestState>,
         cmd: &str,
         sender: &UserId,
         room: &str,
     ) -> anyhow::Result<Vec<messaging::Action>> {
-        self.exports
+        self.set_limits(&mut store)?;
+        let actions = self
+            .exports
             .trinity_module_messaging()
             .call_admin(store, cmd, sender.as_str(), room)
+            .map_err(|err| self.limits.explain(err))?;
+        Ok(self.capabilities.restrict(&self.name, actions))
     }
 
     pub fn handle(
         &self,
-        store: impl AsContextMut<Data = GuestState>,
+        mut store: impl AsContextMut<Data = GuestState>,
         content: &str,
         sender: &UserId,
         room: &RoomId,
+        trust: u8,
     ) -> anyhow::Result<Vec<messaging::Action>> {
-        self.exports.trinity_module_messaging().call_on_msg(
-            store,
-            content,
-            sender.as_str(),
-            "author name NYI",
-            room.as_str(),
-        )
+        self.set_limits(&mut store)?;
+        let actions = self
+            .exports
+            .trinity_module_messaging()
+            .call_on_msg(
+                store,
+                content,
+                sender.as_str(),
+                "author name NYI",
+                room.as_str(),
+                trust,
+            )
+            .map_err(|err| self.limits.explain(err))?;
+        Ok(self.capabilities.restrict(&self.name, actions))
+    }
+
+    pub fn on_media(
+        &self,
+        mut store: impl AsContextMut<Data = GuestState>,
+        media: &Media,
+        sender: &UserId,
+        room: &RoomId,
+        trust: u8,
+    ) -> anyhow::Result<Vec<messaging::Action>> {
+        self.set_limits(&mut store)?;
+        let actions = self
+            .exports
+            .trinity_module_messaging()
+            .call_on_media(store, media, sender.as_str(), room.as_str(), trust)
+            .map_err(|err| self.limits.explain(err))?;
+        Ok(self.capabilities.restrict(&self.name, actions))
+    }
+
+    pub fn on_ticket(
+        &self,
+        mut store: impl AsContextMut<Data = GuestState>,
+        ticket: &Ticket,
+    ) -> anyhow::Result<Vec<messaging::Action>> {
+        self.set_limits(&mut store)?;
+        let actions = self
+            .exports
+            .trinity_module_messaging()
+            .call_on_ticket(store, ticket)
+            .map_err(|err| self.limits.explain(err))?;
+        Ok(self.capabilities.restrict(&self.name, actions))
+    }
+
+    pub fn on_timer(
+        &self,
+        mut store: impl AsContextMut<Data = GuestState>,
+        room: &RoomId,
+        payload: &str,
+    ) -> anyhow::Result<Vec<messaging::Action>> {
+        self.set_limits(&mut store)?;
+        let actions = self
+            .exports
+            .trinity_module_messaging()
+            .call_on_timer(store, room.as_str(), payload)
+            .map_err(|err| self.limits.explain(err))?;
+        Ok(self.capabilities.restrict(&self.name, actions))
+    }
+
+    pub fn on_cron(
+        &self,
+        mut store: impl AsContextMut<Data = GuestState>,
+        job: &str,
+        room: &RoomId,
+        payload: &str,
+    ) -> anyhow::Result<Vec<messaging::Action>> {
+        self.set_limits(&mut store)?;
+        let actions = self
+            .exports
+            .trinity_module_messaging()
+            .call_on_cron(store, job, room.as_str(), payload)
+            .map_err(|err| self.limits.explain(err))?;
+        Ok(self.capabilities.restrict(&self.name, actions))
+    }
+
+    pub fn on_stream_event(
+        &self,
+        mut store: impl AsContextMut<Data = GuestState>,
+        subscription: &str,
+        room: &RoomId,
+        event: &StreamEvent,
+    ) -> anyhow::Result<Vec<messaging::Action>> {
+        self.set_limits(&mut store)?;
+        let actions = self
+            .exports
+            .trinity_module_messaging()
+            .call_on_stream_event(store, subscription, room.as_str(), event)
+            .map_err(|err| self.limits.explain(err))?;
+        Ok(self.capabilities.restrict(&self.name, actions))
+    }
+
+    pub fn on_bus_event(
+        &self,
+        mut store: impl AsContextMut<Data = GuestState>,
+        sender: &str,
+        room: &RoomId,
+        event: &BusEvent,
+    ) -> anyhow::Result<Vec<messaging::Action>> {
+        self.set_limits(&mut store)?;
+        let actions = self
+            .exports
+            .trinity_module_messaging()
+            .call_on_bus_event(store, sender, room.as_str(), event)
+            .map_err(|err| self.limits.explain(err))?;
+        Ok(self.capabilities.restrict(&self.name, actions))
+    }
+
+    pub fn on_poll_update(
+        &self,
+        mut store: impl AsContextMut<Data = GuestState>,
+        room: &RoomId,
+        tally: &PollTally,
+    ) -> anyhow::Result<Vec<messaging::Action>> {
+        self.set_limits(&mut store)?;
+        let actions = self
+            .exports
+            .trinity_module_messaging()
+            .call_on_poll_update(store, room.as_str(), tally)
+            .map_err(|err| self.limits.explain(err))?;
+        Ok(self.capabilities.restrict(&self.name, actions))
+    }
+
+    pub fn on_confirm(
+        &self,
+        mut store: impl AsContextMut<Data = GuestState>,
+        payload: &str,
+        author: &UserId,
+        room: &RoomId,
+    ) -> anyhow::Result<Vec<messaging::Action>> {
+        self.set_limits(&mut store)?;
+        let actions = self
+            .exports
+            .trinity_module_messaging()
+            .call_on_confirm(store, payload, author.as_str(), room.as_str())
+            .map_err(|err| self.limits.explain(err))?;
+        Ok(self.capabilities.restrict(&self.name, actions))
+    }
+
+    pub fn on_reaction(
+        &self,
+        mut store: impl AsContextMut<Data = GuestState>,
+        event_id: &EventId,
+        key: &str,
+        author: &UserId,
+        room: &RoomId,
+    ) -> anyhow::Result<Vec<messaging::Action>> {
+        self.set_limits(&mut store)?;
+        let actions = self
+            .exports
+            .trinity_module_messaging()
+            .call_on_reaction(
+                store,
+                event_id.as_str(),
+                key,
+                author.as_str(),
+                room.as_str(),
+            )
+            .map_err(|err| self.limits.explain(err))?;
+        Ok(self.capabilities.restrict(&self.name, actions))
+    }
+
+    pub fn on_join(
+        &self,
+        mut store: impl AsContextMut<Data = GuestState>,
+        user: &UserId,
+        display_name: Option<&str>,
+        room: &RoomId,
+    ) -> anyhow::Result<Vec<messaging::Action>> {
+        self.set_limits(&mut store)?;
+        let actions = self
+            .exports
+            .trinity_module_messaging()
+            .call_on_join(store, user.as_str(), display_name, room.as_str())
+            .map_err(|err| self.limits.explain(err))?;
+        Ok(self.capabilities.restrict(&self.name, actions))
+    }
+
+    pub fn on_leave(
+        &self,
+        mut store: impl AsContextMut<Data = GuestState>,
+        user: &UserId,
+        room: &RoomId,
+    ) -> anyhow::Result<Vec<messaging::Action>> {
+        self.set_limits(&mut store)?;
+        let actions = self
+            .exports
+            .trinity_module_messaging()
+            .call_on_leave(store, user.as_str(), room.as_str())
+            .map_err(|err| self.limits.explain(err))?;
+        Ok(self.capabilities.restrict(&self.name, actions))
+    }
+
+    pub fn on_state_event(
+        &self,
+        mut store: impl AsContextMut<Data = GuestState>,
+        event_type: &str,
+        state_key: &str,
+        sender: &UserId,
+        room: &RoomId,
+        content: &str,
+    ) -> anyhow::Result<Vec<messaging::Action>> {
+        self.set_limits(&mut store)?;
+        let actions = self
+            .exports
+            .trinity_module_messaging()
+            .call_on_state_event(
+                store,
+                event_type,
+                state_key,
+                sender.as_str(),
+                room.as_str(),
+                content,
+            )
+            .map_err(|err| self.limits.explain(err))?;
+        Ok(self.capabilities.restrict(&self.name, actions))
+    }
+
+    pub fn on_tick(
+        &self,
+        mut store: impl AsContextMut<Data = GuestState>,
+        tick: &str,
+        room: &RoomId,
+    ) -> anyhow::Result<Vec<messaging::Action>> {
+        self.set_limits(&mut store)?;
+        let actions = self
+            .exports
+            .trinity_module_messaging()
+            .call_on_tick(store, tick, room.as_str())
+            .map_err(|err| self.limits.explain(err))?;
+        Ok(self.capabilities.restrict(&self.name, actions))
     }
 }
 
 pub(crate) type WasmStore = wasmtime::Store<GuestState>;
 
+/// Checks that the module at `path` doesn't need a newer host, compiles, only imports the host's
+/// APIs, and exports the module interface, without initializing it.
+pub(crate) fn check_module(engine: &wasmtime::Engine, path: &Path) -> anyhow::Result<()> {
+    host_version::check(path)?;
+    let component = wasmtime::component::Component::from_file(engine, path)?;
+    let mut linker = wasmtime::component::Linker::<GuestState>::new(engine);
+    apis::Apis::link(0, &mut linker, &Capabilities::all())?;
+    // Instantiating doesn't run any of the module's code, so the host APIs don't need a state.
+    let mut store = wasmtime::Store::new(engine, GuestState::default());
+    store.set_epoch_deadline(Limits::default().epoch_deadline());
+    store.set_fuel(Limits::default().fuel())?;
+    module::TrinityModule::instantiate(&mut store, &component, &linker)?;
+    Ok(())
+}
+
+/// The capabilities the module at `path` declares, if they're all granted by its configuration.
+fn checked_capabilities(
+    path: &Path,
+    file_info: &FileInfo,
+    config: Option<&HashMap<String, String>>,
+) -> anyhow::Result<Capabilities> {
+    let declared = Capabilities::declared(path, file_info)?;
+    let granted = Capabilities::granted(config)?;
+    let denied = declared.missing_from(&granted);
+    if !denied.is_empty() {
+        anyhow::bail!("capabilities not granted: {denied}");
+    }
+    Ok(declared)
+}
+
+/// Instantiates the compiled module at `path` in the store, with the given APIs, and initializes
+/// it.
+fn instantiate(
+    store: &mut WasmStore,
+    apis: Apis,
+    path: PathBuf,
+    file_info: FileInfo,
+    capabilities: Capabilities,
+    component: &wasmtime::component::Component,
+    config: Option<&HashMap<String, String>>,
+) -> anyhow::Result<Module> {
+    let name = path
+        .file_stem()
+        .map(|s| s.to_string_lossy())
+        .unwrap_or_else(|| path.to_string_lossy())
+        .to_string();
+
+    let trace = apis.trace().clone();
+    let entry = store.data_mut().imports.len();
+    store.data_mut().imports.push(ModuleState { apis });
+
+    let mut linker = wasmtime::component::Linker::<GuestState>::new(store.engine());
+    apis::Apis::link(entry, &mut linker, &capabilities)?;
+
+    tracing::debug!("instantiating wasm component: {name}...");
+
+    let limits = Limits::new(&name, config)?;
+    store.data_mut().limits = limits;
+    store.set_epoch_deadline(limits.epoch_deadline());
+    store.set_fuel(limits.fuel())?;
+
+    let (exports, instance) = module::TrinityModule::instantiate(&mut *store, component, &linker)?;
+
+    // Convert the module config to Vec of tuples to satisfy wasm interface types.
+    let init_config: Option<Vec<(String, String)>> = config.map(|mc| Vec::from_iter(mc.clone()));
+
+    tracing::debug!("calling module's init function...");
+    exports
+        .trinity_module_messaging()
+        .call_init(&mut *store, init_config.as_deref())
+        .map_err(|err| limits.explain(err))?;
+
+    store.set_epoch_deadline(limits.epoch_deadline());
+    store.set_fuel(limits.fuel())?;
+    let bus_topics = exports
+        .trinity_module_messaging()
+        .call_bus_topics(&mut *store)
+        .map_err(|err| limits.explain(err))?;
+    let state_event_types = exports
+        .trinity_module_messaging()
+        .call_state_event_types(&mut *store)
+        .map_err(|err| limits.explain(err))?;
+    let ticks = exports
+        .trinity_module_messaging()
+        .call_ticks(&mut *store)
+        .map_err(|err| limits.explain(err))?;
+
+    tracing::debug!("great success!");
+    Ok(Module {
+        name,
+        path,
+        file_info,
+        capabilities,
+        limits,
+        bus_topics,
+        state_event_types,
+        ticks,
+        loaded_at: Utc::now(),
+        last_error: Mutex::new(None),
+        trace,
+        exports,
+        _instance: instance,
+    })
+}
+
 #[derive(Default)]
 pub(crate) struct WasmModules {
     store: WasmStore,
     modules: Vec<Module>,
+    /// Why some modules weren't loaded, for the admin to be told.
+    refused: Vec<String>,
+    /// Drives the timeouts of the calls into the modules.
+    _epoch_ticker: Option<EpochTicker>,
 }
 
 impl WasmModules {
@@ -91,21 +561,26 @@ impl WasmModules {
     /// Must be called from a blocking context.
     pub fn new(
         db: ShareableDatabase,
+        directory: &Arc<Directory>,
+        client: &Client,
         modules_paths: &[PathBuf],
         modules_config: &HashMap<String, HashMap<String, String>>,
+        priority: &[String],
+        engine_config: &WasmEngineConfig,
+        cache: &ModuleCache,
     ) -> anyhow::Result<Self> {
         tracing::debug!("setting up wasm context...");
 
-        let mut config = wasmtime::Config::new();
-        config.wasm_component_model(true);
-
-        let engine = wasmtime::Engine::new(&config)?;
+        let engine = new_engine(engine_config)?;
+        let epoch_ticker = EpochTicker::start(&engine)?;
 
         let mut compiled_modules = Vec::new();
+        let mut refused = Vec::new();
 
         let state = GuestState::default();
 
         let mut store = wasmtime::Store::new(&engine, state);
+        store.limiter(|state| state as &mut dyn wasmtime::ResourceLimiter);
 
         tracing::debug!("precompiling wasm modules...");
         for modules_path in modules_paths {
@@ -126,55 +601,82 @@ impl WasmModules {
                     .unwrap_or_else(|| module_path.to_string_lossy())
                     .to_string();
 
-                tracing::debug!("creating APIs...");
-                let module_state = ModuleState {
-                    apis: Apis::new(name.clone(), db.clone())?,
-                };
+                let bytes = std::fs::read(&module_path)?;
+                let file_info = FileInfo::parse(&bytes)?;
 
-                let entry = store.data_mut().imports.len();
-                store.data_mut().imports.push(module_state);
+                if let Err(err) = host_version::check(&module_path) {
+                    tracing::error!("not loading wasm module {name}: {err:#}");
+                    refused.push(format!("not loading the module {name}: {err:#}"));
+                    continue;
+                }
 
-                let mut linker = wasmtime::component::Linker::<GuestState>::new(&engine);
+                // Refuse modules asking for more than they're granted, rather than failing to
+                // start the bot because of a third-party module.
+                let config = modules_config.get(&name);
+                let capabilities = match checked_capabilities(&module_path, &file_info, config) {
+                    Ok(capabilities) => capabilities,
+                    Err(err) => {
+                        tracing::error!("not loading wasm module {name}: {err:#}");
+                        continue;
+                    }
+                };
 
-                apis::Apis::link(entry, &mut linker)?;
+                tracing::debug!("creating APIs...");
+                let apis = Apis::new(
+                    name.clone(),
+                    db.clone(),
+                    directory.clone(),
+                    client.clone(),
+                    config,
+                    HostTrace::new(&name),
+                    false,
+                )?;
 
                 tracing::debug!(
                     "compiling wasm module: {name} @ {}...",
                     module_path.to_string_lossy()
                 );
-
-                let component = wasmtime::component::Component::from_file(&engine, &module_path)?;
-
-                tracing::debug!("instantiating wasm component: {name}...");
-
-                let (exports, instance) =
-                    module::TrinityModule::instantiate(&mut store, &component, &linker)?;
-
-                // Convert the module config to Vec of tuples to satisfy wasm interface types.
-                let init_config: Option<Vec<(String, String)>> = modules_config
-                    .get(&name)
-                    .map(|mc| Vec::from_iter(mc.clone()));
-
-                tracing::debug!("calling module's init function...");
-                exports
-                    .trinity_module_messaging()
-                    .call_init(&mut store, init_config.as_deref())?;
-
-                tracing::debug!("great success!");
-                compiled_modules.push(Module {
-                    name,
-                    exports,
-                    _instance: instance,
-                });
+                let component = cache.load(&engine, &bytes, &file_info.hash)?;
+
+                compiled_modules.push(instantiate(
+                    &mut store,
+                    apis,
+                    module_path,
+                    file_info,
+                    capabilities,
+                    &component,
+                    config,
+                )?);
             }
         }
 
+        // The modules are tried in the order of the priority list, then by name, rather than in
+        // the order of the directory listing.
+        compiled_modules.sort_by_cached_key(|module| {
+            let rank = priority.iter().position(|name| *name == module.name);
+            (rank.unwrap_or(priority.len()), module.name.clone())
+        });
+
+        cache.retain(
+            &compiled_modules
+                .iter()
+                .map(|module| module.file_info.hash.as_str())
+                .collect(),
+        );
+
         Ok(Self {
             store,
             modules: compiled_modules,
+            refused,
+            _epoch_ticker: Some(epoch_ticker),
         })
     }
 
+    /// Why some modules weren't loaded, since the last call.
+    pub fn take_refused(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.refused)
+    }
+
     pub(crate) fn iter(&mut self) -> (&mut WasmStore, impl Clone + Iterator<Item = &Module>) {
         (&mut self.store, self.modules.iter())
     }
@@ -1,6 +1,9 @@
+use std::collections::HashMap;
+
 use redb::{ReadableTable as _, TableDefinition};
 
 use crate::wasm::apis::kv_store::trinity::api::kv;
+use crate::wasm::apis::trace::{self, HostTrace};
 use crate::{wasm::GuestState, ShareableDatabase};
 
 wasmtime::component::bindgen!({
@@ -11,13 +14,24 @@ wasmtime::component::bindgen!({
 pub(super) struct KeyValueStoreApi {
     db: ShareableDatabase,
     module_name: String,
+    trace: HostTrace,
+    /// Writes of the module when sandboxed, kept out of the database, by key; `None` for the
+    /// removed keys.
+    overlay: Option<HashMap<Vec<u8>, Option<Vec<u8>>>>,
 }
 
 impl KeyValueStoreApi {
-    pub fn new(db: ShareableDatabase, module_name: &str) -> anyhow::Result<Self> {
+    pub fn new(
+        db: ShareableDatabase,
+        module_name: &str,
+        trace: HostTrace,
+        sandboxed: bool,
+    ) -> anyhow::Result<Self> {
         Ok(Self {
             db,
             module_name: module_name.to_owned(),
+            trace,
+            overlay: sandboxed.then(HashMap::new),
         })
     }
 
@@ -31,17 +45,64 @@ impl KeyValueStoreApi {
 
 impl kv::Host for KeyValueStoreApi {
     fn set(&mut self, key: Vec<u8>, value: Vec<u8>) -> anyhow::Result<()> {
+        let call = self
+            .trace
+            .call(|| format!("kv.set({}, {})", trace::bytes(&key), trace::bytes(&value)));
+        let result = match &mut self.overlay {
+            Some(overlay) => {
+                overlay.insert(key, Some(value));
+                Ok(())
+            }
+            None => self.set_stored(&key, &value),
+        };
+        self.trace.record(call, &result);
+        result
+    }
+
+    fn get(&mut self, key: Vec<u8>) -> anyhow::Result<Option<Vec<u8>>> {
+        let call = self
+            .trace
+            .call(|| format!("kv.get({})", trace::bytes(&key)));
+        let result = match self.overlay.as_ref().and_then(|overlay| overlay.get(&key)) {
+            Some(value) => Ok(value.clone()),
+            None => self.get_stored(&key),
+        };
+        let shown = result
+            .as_ref()
+            .map(|value| value.as_deref().map(trace::bytes));
+        self.trace.record(call, &shown);
+        result
+    }
+
+    fn remove(&mut self, key: Vec<u8>) -> anyhow::Result<()> {
+        let call = self
+            .trace
+            .call(|| format!("kv.remove({})", trace::bytes(&key)));
+        let result = match &mut self.overlay {
+            Some(overlay) => {
+                overlay.insert(key, None);
+                Ok(())
+            }
+            None => self.remove_stored(&key),
+        };
+        self.trace.record(call, &result);
+        result
+    }
+}
+
+impl KeyValueStoreApi {
+    fn set_stored(&self, key: &[u8], value: &[u8]) -> anyhow::Result<()> {
         let table_def = TableDefinition::<[u8], [u8]>::new(&self.module_name);
         let txn = self.db.begin_write()?;
         {
             let mut table = txn.open_table(table_def)?;
-            table.insert(&key, &value)?;
+            table.insert(key, value)?;
         }
         txn.commit()?;
         Ok(())
     }
 
-    fn get(&mut self, key: Vec<u8>) -> anyhow::Result<Option<Vec<u8>>> {
+    fn get_stored(&self, key: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
         let table_def = TableDefinition::<[u8], [u8]>::new(&self.module_name);
         let txn = self.db.begin_read()?;
         let table = match txn.open_table(table_def) {
@@ -59,15 +120,15 @@ impl kv::Host for KeyValueStoreApi {
                 redb::Error::TableDoesNotExist(_) => return Ok(None),
             },
         };
-        Ok(table.get(&key)?.map(|val| val.to_vec()))
+        Ok(table.get(key)?.map(|val| val.to_vec()))
     }
 
-    fn remove(&mut self, key: Vec<u8>) -> anyhow::Result<()> {
+    fn remove_stored(&self, key: &[u8]) -> anyhow::Result<()> {
         let table_def = TableDefinition::<[u8], [u8]>::new(&self.module_name);
         let txn = self.db.begin_write()?;
         {
             let mut table = txn.open_table(table_def)?;
-            table.remove(&key)?;
+            table.remove(key)?;
         }
         txn.commit()?;
         Ok(())
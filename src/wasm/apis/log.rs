@@ -1,4 +1,5 @@
 use crate::wasm::apis::log::trinity::api::log;
+use crate::wasm::apis::trace::HostTrace;
 use crate::wasm::GuestState;
 
 wasmtime::component::bindgen!({
@@ -8,12 +9,14 @@ wasmtime::component::bindgen!({
 
 pub(super) struct LogApi {
     module_name: String,
+    trace: HostTrace,
 }
 
 impl LogApi {
-    pub fn new(module_name: &str) -> Self {
+    pub fn new(module_name: &str, trace: HostTrace) -> Self {
         Self {
             module_name: module_name.to_owned(),
+            trace,
         }
     }
 
@@ -23,26 +26,36 @@ impl LogApi {
     ) -> anyhow::Result<()> {
         log::add_to_linker(linker, move |s| &mut s.imports[id].apis.log)
     }
+
+    fn traced(&self, level: &str, msg: &str) {
+        let call = self.trace.call(|| format!("log.{level}({msg:?})"));
+        self.trace.record(call, &());
+    }
 }
 
 impl log::Host for LogApi {
     fn trace(&mut self, msg: String) -> anyhow::Result<()> {
+        self.traced("trace", &msg);
         tracing::trace!("{} - {msg}", self.module_name);
         Ok(())
     }
     fn debug(&mut self, msg: String) -> anyhow::Result<()> {
+        self.traced("debug", &msg);
         tracing::debug!("{} - {msg}", self.module_name);
         Ok(())
     }
     fn info(&mut self, msg: String) -> anyhow::Result<()> {
+        self.traced("info", &msg);
         tracing::info!("{} - {msg}", self.module_name);
         Ok(())
     }
     fn warn(&mut self, msg: String) -> anyhow::Result<()> {
+        self.traced("warn", &msg);
         tracing::warn!("{} - {msg}", self.module_name);
         Ok(())
     }
     fn error(&mut self, msg: String) -> anyhow::Result<()> {
+        self.traced("error", &msg);
         tracing::error!("{} - {msg}", self.module_name);
         Ok(())
     }
@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+
+use matrix_sdk::{
+    media::{MediaFormat, MediaRequest},
+    ruma::{
+        events::{
+            room::{message::MessageType, MediaSource},
+            AnyMessageLikeEvent, AnyTimelineEvent, MessageLikeEvent,
+        },
+        EventId, RoomId,
+    },
+    Client, RoomState,
+};
+
+use crate::wasm::apis::media::trinity::api::media;
+use crate::wasm::apis::trace::HostTrace;
+use crate::wasm::{GuestState, Media, MediaKind};
+
+wasmtime::component::bindgen!({
+    path: "./wit/media.wit",
+    world: "media-world"
+});
+
+/// Module configuration key setting the largest content the module may download, in MiB.
+const MAX_SIZE_KEY: &str = "media_max_mb";
+
+/// Largest content a module may download, in MiB, unless configured otherwise.
+const DEFAULT_MAX_SIZE_MB: u64 = 25;
+
+/// The source of the content of a media message, and its metadata for the modules; none if the
+/// message isn't a media message.
+pub(crate) fn describe_media(
+    event_id: &EventId,
+    msgtype: &MessageType,
+) -> Option<(MediaSource, Media)> {
+    let (kind, body, source, mime_type, size) = match msgtype {
+        MessageType::Image(content) => {
+            let info = content.info.as_deref();
+            (
+                MediaKind::Image,
+                &content.body,
+                &content.source,
+                info.and_then(|info| info.mimetype.clone()),
+                info.and_then(|info| info.size),
+            )
+        }
+        MessageType::File(content) => {
+            let info = content.info.as_deref();
+            (
+                MediaKind::File,
+                content.filename.as_ref().unwrap_or(&content.body),
+                &content.source,
+                info.and_then(|info| info.mimetype.clone()),
+                info.and_then(|info| info.size),
+            )
+        }
+        MessageType::Audio(content) => {
+            let info = content.info.as_deref();
+            (
+                MediaKind::Audio,
+                &content.body,
+                &content.source,
+                info.and_then(|info| info.mimetype.clone()),
+                info.and_then(|info| info.size),
+            )
+        }
+        MessageType::Video(content) => {
+            let info = content.info.as_deref();
+            (
+                MediaKind::Video,
+                &content.body,
+                &content.source,
+                info.and_then(|info| info.mimetype.clone()),
+                info.and_then(|info| info.size),
+            )
+        }
+        _ => return None,
+    };
+    let mxc_uri = match source {
+        MediaSource::Plain(uri) => uri.to_string(),
+        MediaSource::Encrypted(file) => file.url.to_string(),
+    };
+    let media = Media {
+        event_id: event_id.to_string(),
+        kind,
+        body: body.clone(),
+        mxc_uri,
+        mime_type,
+        size: size.map(u64::from),
+    };
+    Some((source.clone(), media))
+}
+
+pub(super) struct MediaApi {
+    module_name: String,
+    client: Client,
+    max_size: u64,
+    trace: HostTrace,
+}
+
+impl MediaApi {
+    pub fn new(
+        module_name: &str,
+        client: Client,
+        config: Option<&HashMap<String, String>>,
+        trace: HostTrace,
+    ) -> anyhow::Result<Self> {
+        let max_size_mb = match config.and_then(|config| config.get(MAX_SIZE_KEY)) {
+            Some(mb) => mb.trim().parse().map_err(|err| {
+                anyhow::anyhow!("invalid {MAX_SIZE_KEY} for module {module_name}: {err}")
+            })?,
+            None => DEFAULT_MAX_SIZE_MB,
+        };
+        Ok(Self {
+            module_name: module_name.to_owned(),
+            client,
+            max_size: max_size_mb * 1024 * 1024,
+            trace,
+        })
+    }
+
+    pub fn link(
+        id: usize,
+        linker: &mut wasmtime::component::Linker<GuestState>,
+    ) -> anyhow::Result<()> {
+        media::add_to_linker(linker, move |s| &mut s.imports[id].apis.media)
+    }
+
+    async fn fetch(&self, room: &str, event_id: &str) -> anyhow::Result<Vec<u8>> {
+        let room_id = <&RoomId>::try_from(room)?;
+        let event_id = <&EventId>::try_from(event_id)?;
+        let room = self
+            .client
+            .get_room(room_id)
+            .filter(|room| room.state() == RoomState::Joined)
+            .ok_or_else(|| anyhow::anyhow!("the bot isn't in {room_id}"))?;
+
+        let event = room.event(event_id).await?;
+        let AnyTimelineEvent::MessageLike(AnyMessageLikeEvent::RoomMessage(
+            MessageLikeEvent::Original(event),
+        )) = event.event.deserialize()?
+        else {
+            anyhow::bail!("{event_id} isn't a message");
+        };
+        let Some((source, media)) = describe_media(event_id, &event.content.msgtype) else {
+            anyhow::bail!("{event_id} isn't a media message");
+        };
+        if media.size.is_some_and(|size| size > self.max_size) {
+            anyhow::bail!("{event_id} is larger than {MAX_SIZE_KEY}");
+        }
+
+        let request = MediaRequest {
+            source,
+            format: MediaFormat::File,
+        };
+        let data = self
+            .client
+            .media()
+            .get_media_content(&request, false)
+            .await?;
+        // The announced size may be missing, or wrong.
+        if data.len() as u64 > self.max_size {
+            anyhow::bail!("{event_id} is larger than {MAX_SIZE_KEY}");
+        }
+        Ok(data)
+    }
+}
+
+impl media::Host for MediaApi {
+    fn download(&mut self, room: String, event_id: String) -> anyhow::Result<Result<Vec<u8>, ()>> {
+        let call = self
+            .trace
+            .call(|| format!("media.download({room:?}, {event_id:?})"));
+        let result = futures::executor::block_on(self.fetch(&room, &event_id));
+        let shown = result.as_ref().map(|data| format!("{} bytes", data.len()));
+        self.trace.record(call, &shown);
+        // Failures are the module's to handle, not a reason to trap.
+        match result {
+            Ok(data) => Ok(Ok(data)),
+            Err(err) => {
+                tracing::warn!(
+                    "{} - couldn't download {event_id}: {err:#}",
+                    self.module_name
+                );
+                Ok(Err(()))
+            }
+        }
+    }
+}
@@ -1,42 +1,118 @@
 mod kv_store;
 mod log;
+mod media;
 mod sync_request;
 mod sys;
+mod trace;
+mod wasi;
 
-use crate::ShareableDatabase;
+use std::{collections::HashMap, sync::Arc};
+
+use matrix_sdk::Client;
+
+use crate::{directory::Directory, ShareableDatabase};
 
 use self::kv_store::KeyValueStoreApi;
 use self::log::LogApi;
+use self::media::MediaApi;
 use self::sync_request::SyncRequestApi;
 use self::sys::SysApi;
 
-use super::GuestState;
+pub(crate) use self::media::describe_media;
+pub(crate) use self::sync_request::{allowed_hosts, is_host_allowed, redirect_policy};
+pub(crate) use self::trace::HostTrace;
+pub(crate) use self::wasi::WasiState;
+
+use super::{Capabilities, Capability, GuestState};
+
+/// Interfaces the host provides to the modules.
+pub(crate) const INTERFACES: &[&str] = &[
+    "trinity:api/sys",
+    "trinity:api/log",
+    "trinity:api/sync-request",
+    "trinity:api/kv",
+    "trinity:api/media",
+];
+
+/// Whether the host provides the interface, given without its version.
+pub(crate) fn provides(interface: &str) -> bool {
+    INTERFACES.contains(&interface)
+        || interface
+            .split_once('/')
+            .is_some_and(|(package, _)| wasi::PACKAGES.contains(&package))
+}
+
+/// Whether the host links the interface, given without its version, but grants nothing through
+/// it, e.g. the WASI filesystem without any directory.
+pub(crate) fn denies(interface: &str) -> bool {
+    interface
+        .split_once('/')
+        .is_some_and(|(package, _)| wasi::DENIED_PACKAGES.contains(&package))
+}
 
 pub(crate) struct Apis {
     sys: SysApi,
     log: LogApi,
     sync_request: SyncRequestApi,
     kv_store: KeyValueStoreApi,
+    media: MediaApi,
+    trace: HostTrace,
 }
 
 impl Apis {
-    pub fn new(module_name: String, db: ShareableDatabase) -> anyhow::Result<Self> {
+    /// Creates the APIs of a module, tracing its calls into `trace`; when `sandboxed`, they keep
+    /// its writes to the storage and its requests other than `GET` from having effects, and
+    /// their random numbers are reproducible.
+    pub fn new(
+        module_name: String,
+        db: ShareableDatabase,
+        directory: Arc<Directory>,
+        client: Client,
+        config: Option<&HashMap<String, String>>,
+        trace: HostTrace,
+        sandboxed: bool,
+    ) -> anyhow::Result<Self> {
         Ok(Self {
-            sys: SysApi {},
-            log: LogApi::new(&module_name),
-            sync_request: SyncRequestApi::default(),
-            kv_store: KeyValueStoreApi::new(db, &module_name)?,
+            sys: SysApi::new(
+                &module_name,
+                db.clone(),
+                directory,
+                client.clone(),
+                trace.clone(),
+                sandboxed,
+            ),
+            log: LogApi::new(&module_name, trace.clone()),
+            sync_request: SyncRequestApi::new(&module_name, config, trace.clone(), sandboxed)?,
+            kv_store: KeyValueStoreApi::new(db, &module_name, trace.clone(), sandboxed)?,
+            media: MediaApi::new(&module_name, client, config, trace.clone())?,
+            trace,
         })
     }
 
+    /// The trace of the module's calls to the APIs.
+    pub fn trace(&self) -> &HostTrace {
+        &self.trace
+    }
+
+    /// Links the APIs the capabilities give access to; the others stay unresolved, so a module
+    /// importing them fails to instantiate.
     pub fn link(
         id: usize,
         linker: &mut wasmtime::component::Linker<GuestState>,
+        capabilities: &Capabilities,
     ) -> anyhow::Result<()> {
+        wasi::link(linker)?;
         sys::SysApi::link(id, linker)?;
         log::LogApi::link(id, linker)?;
-        sync_request::SyncRequestApi::link(id, linker)?;
-        kv_store::KeyValueStoreApi::link(id, linker)?;
+        if capabilities.contains(Capability::Http) {
+            sync_request::SyncRequestApi::link(id, linker)?;
+        }
+        if capabilities.contains(Capability::Storage) {
+            kv_store::KeyValueStoreApi::link(id, linker)?;
+        }
+        if capabilities.contains(Capability::Media) {
+            media::MediaApi::link(id, linker)?;
+        }
         Ok(())
     }
 }
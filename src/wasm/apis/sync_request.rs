@@ -1,4 +1,8 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
 use crate::wasm::apis::sync_request::trinity::api::sync_request;
+use crate::wasm::apis::trace::{self, HostTrace};
 use crate::wasm::GuestState;
 
 wasmtime::component::bindgen!({
@@ -8,12 +12,111 @@ wasmtime::component::bindgen!({
 
 use sync_request::*;
 
-#[derive(Default)]
+/// Module configuration key listing the hosts the module may send requests to, comma-separated.
+/// A `*.` prefix also allows the subdomains of a host. Without it, all hosts are allowed.
+const ALLOWED_HOSTS_KEY: &str = "http_allowed_hosts";
+
+/// Module configuration key setting the timeout of the module's requests, in seconds.
+const TIMEOUT_KEY: &str = "http_timeout_secs";
+
+/// Timeout of the requests, unless configured otherwise.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Most redirections followed by a request.
+const MAX_REDIRECTS: usize = 10;
+
+/// Hosts a module may send requests to, according to its configuration, or `None` for all.
+pub(crate) fn allowed_hosts(config: Option<&HashMap<String, String>>) -> Option<Vec<String>> {
+    config
+        .and_then(|config| config.get(ALLOWED_HOSTS_KEY))
+        .map(|hosts| {
+            hosts
+                .split(',')
+                .map(|host| host.trim().to_lowercase())
+                .filter(|host| !host.is_empty())
+                .collect()
+        })
+}
+
+/// Whether a request to the host is allowed by the allowed hosts, `None` meaning all.
+pub(crate) fn is_host_allowed(allowed_hosts: Option<&[String]>, host: &str) -> bool {
+    let Some(allowed_hosts) = allowed_hosts else {
+        return true;
+    };
+    let host = host.to_lowercase();
+    allowed_hosts
+        .iter()
+        .any(|allowed| match allowed.strip_prefix("*.") {
+            Some(domain) => {
+                host == domain
+                    || host
+                        .strip_suffix(domain)
+                        .is_some_and(|sub| sub.ends_with('.'))
+            }
+            None => host == *allowed,
+        })
+}
+
+/// The redirections policy of the requests to the allowed hosts, `None` meaning all: the
+/// redirections are checked against them too, or an allowed host could send the requests anywhere.
+pub(crate) fn redirect_policy(allowed_hosts: Option<Vec<String>>) -> reqwest::redirect::Policy {
+    let Some(allowed_hosts) = allowed_hosts else {
+        return reqwest::redirect::Policy::limited(MAX_REDIRECTS);
+    };
+    reqwest::redirect::Policy::custom(move |attempt| {
+        if attempt.previous().len() >= MAX_REDIRECTS {
+            attempt.error("too many redirects")
+        } else if attempt
+            .url()
+            .host_str()
+            .is_some_and(|host| is_host_allowed(Some(allowed_hosts.as_slice()), host))
+        {
+            attempt.follow()
+        } else {
+            attempt.stop()
+        }
+    })
+}
+
 pub(super) struct SyncRequestApi {
+    module_name: String,
     client: reqwest::blocking::Client,
+    allowed_hosts: Option<Vec<String>>,
+    trace: HostTrace,
+    /// Whether only the `GET` requests are sent, the others failing, not to have effects
+    /// elsewhere.
+    sandboxed: bool,
 }
 
 impl SyncRequestApi {
+    pub fn new(
+        module_name: &str,
+        config: Option<&HashMap<String, String>>,
+        trace: HostTrace,
+        sandboxed: bool,
+    ) -> anyhow::Result<Self> {
+        let allowed_hosts = allowed_hosts(config);
+
+        let timeout = match config.and_then(|config| config.get(TIMEOUT_KEY)) {
+            Some(secs) => Duration::from_secs(secs.trim().parse().map_err(|err| {
+                anyhow::anyhow!("invalid {TIMEOUT_KEY} for module {module_name}: {err}")
+            })?),
+            None => DEFAULT_TIMEOUT,
+        };
+        let client = reqwest::blocking::Client::builder()
+            .timeout(timeout)
+            .redirect(redirect_policy(allowed_hosts.clone()))
+            .build()?;
+
+        Ok(Self {
+            module_name: module_name.to_owned(),
+            client,
+            allowed_hosts,
+            trace,
+            sandboxed,
+        })
+    }
+
     pub fn link(
         id: usize,
         linker: &mut wasmtime::component::Linker<GuestState>,
@@ -24,6 +127,38 @@ impl SyncRequestApi {
 
 impl sync_request::Host for SyncRequestApi {
     fn run_request(&mut self, req: Request) -> anyhow::Result<Result<Response, ()>> {
+        let call = self.trace.call(|| {
+            let headers = req
+                .headers
+                .iter()
+                .map(|header| {
+                    let value = trace::header_value(&header.key, &header.value);
+                    format!("{}: {value}", header.key)
+                })
+                .collect::<Vec<_>>();
+            let body = req.body.as_deref().map(trace::truncate);
+            format!(
+                "sync-request.run-request({:?} {:?}, headers {headers:?}, body {body:?})",
+                req.verb,
+                trace::url(&req.url)
+            )
+        });
+        let result = self.run(req);
+        self.trace.record(call, &result);
+        Ok(result)
+    }
+}
+
+impl SyncRequestApi {
+    fn run(&self, req: Request) -> Result<Response, ()> {
+        if self.sandboxed && !matches!(req.verb, RequestVerb::Get) {
+            tracing::warn!(
+                "{} - sandboxed, not sending a {:?} request",
+                self.module_name,
+                req.verb
+            );
+            return Err(());
+        }
         let url = req.url;
         let mut builder = match req.verb {
             RequestVerb::Get => self.client.get(url),
@@ -37,9 +172,31 @@ impl sync_request::Host for SyncRequestApi {
         if let Some(body) = req.body {
             builder = builder.body(body);
         }
-        let req = builder.build()?;
+        let req = match builder.build() {
+            Ok(req) => req,
+            Err(err) => {
+                tracing::warn!("{} - invalid request: {err}", self.module_name);
+                return Err(());
+            }
+        };
+
+        let host = req.url().host_str().unwrap_or_default();
+        if !is_host_allowed(self.allowed_hosts.as_deref(), host) {
+            tracing::warn!(
+                "{} - request to {host} denied, the host isn't in {ALLOWED_HOSTS_KEY}",
+                self.module_name
+            );
+            return Err(());
+        }
 
-        let resp = self.client.execute(req)?;
+        // Failures, including timeouts, are the module's to handle, not a reason to trap.
+        let resp = match self.client.execute(req) {
+            Ok(resp) => resp,
+            Err(err) => {
+                tracing::warn!("{} - request failed: {err}", self.module_name);
+                return Err(());
+            }
+        };
 
         let status = match resp.status().as_u16() / 100 {
             2 => ResponseStatus::Success,
@@ -48,6 +205,6 @@ impl sync_request::Host for SyncRequestApi {
 
         let body = resp.text().ok();
 
-        Ok(Ok(Response { status, body }))
+        Ok(Response { status, body })
     }
 }
@@ -1,24 +1,179 @@
+use std::sync::Arc;
+
+use matrix_sdk::{
+    room::Room,
+    ruma::{RoomId, UserId},
+    Client, RoomState,
+};
+use rand::{rngs::StdRng, Rng as _, SeedableRng as _};
+use tracing::debug;
+
+use crate::directory::Directory;
+use crate::room_resolver::RoomResolver;
 use crate::wasm::apis::sys::trinity::api::sys;
+use crate::wasm::apis::trace::HostTrace;
 use crate::wasm::GuestState;
+use crate::{html_text, opt_out, sent_messages, ShareableDatabase};
 
 wasmtime::component::bindgen!({
     path: "./wit/sys.wit",
     world: "sys-world"
 });
 
-pub(super) struct SysApi;
+pub(super) struct SysApi {
+    module_name: String,
+    db: ShareableDatabase,
+    directory: Arc<Directory>,
+    client: Client,
+    room_resolver: RoomResolver,
+    trace: HostTrace,
+    /// Generator of the random numbers when sandboxed, seeded for the runs to be reproducible.
+    rng: Option<StdRng>,
+}
 
 impl SysApi {
+    pub fn new(
+        module_name: &str,
+        db: ShareableDatabase,
+        directory: Arc<Directory>,
+        client: Client,
+        trace: HostTrace,
+        sandboxed: bool,
+    ) -> Self {
+        Self {
+            module_name: module_name.to_owned(),
+            db,
+            directory,
+            room_resolver: RoomResolver::new(client.clone()),
+            client,
+            trace,
+            rng: sandboxed.then(|| StdRng::seed_from_u64(0)),
+        }
+    }
+
     pub fn link(
         id: usize,
         linker: &mut wasmtime::component::Linker<GuestState>,
     ) -> anyhow::Result<()> {
         sys::add_to_linker(linker, move |s| &mut s.imports[id].apis.sys)
     }
+
+    /// The room the bot is in, given by id.
+    fn joined_room(&self, room: &str) -> Option<Room> {
+        let room_id = <&RoomId>::try_from(room).ok()?;
+        self.client
+            .get_room(room_id)
+            .filter(|room| room.state() == RoomState::Joined)
+    }
+}
+
+impl SysApi {
+    fn describe_room_inner(&self, room: &str) -> anyhow::Result<Option<sys::RoomInfo>> {
+        let Some(room) = self.joined_room(room) else {
+            return Ok(None);
+        };
+        let display_name = futures::executor::block_on(room.display_name())?;
+        Ok(Some(sys::RoomInfo {
+            display_name: display_name.to_string(),
+            topic: room.topic(),
+            canonical_alias: room.canonical_alias().map(|alias| alias.to_string()),
+            join_rule: room.join_rule().as_str().to_owned(),
+            member_count: room.joined_members_count(),
+        }))
+    }
+
+    fn describe_member_inner(
+        &self,
+        room: &str,
+        user: &str,
+    ) -> anyhow::Result<Option<sys::MemberInfo>> {
+        let Some(room) = self.joined_room(room) else {
+            return Ok(None);
+        };
+        let Ok(user_id) = <&UserId>::try_from(user) else {
+            return Ok(None);
+        };
+        let Some(member) = futures::executor::block_on(room.get_member(user_id))? else {
+            return Ok(None);
+        };
+        Ok(Some(sys::MemberInfo {
+            display_name: member.display_name().map(ToOwned::to_owned),
+            avatar_url: member.avatar_url().map(ToString::to_string),
+            power_level: member.power_level(),
+        }))
+    }
 }
 
 impl sys::Host for SysApi {
     fn rand_u64(&mut self) -> anyhow::Result<u64> {
-        Ok(rand::random())
+        let value = match &mut self.rng {
+            Some(rng) => rng.gen(),
+            None => rand::random(),
+        };
+        let call = self.trace.call(|| "sys.rand-u64()".to_owned());
+        self.trace.record(call, &value);
+        Ok(value)
+    }
+
+    fn sent_messages(&mut self, room: String) -> anyhow::Result<Vec<String>> {
+        let call = self.trace.call(|| format!("sys.sent-messages({room:?})"));
+        let result = sent_messages::list(&self.db, &self.module_name, &room);
+        self.trace.record(call, &result);
+        result
+    }
+
+    fn is_member_of(&mut self, user: String, group: String) -> anyhow::Result<bool> {
+        let call = self
+            .trace
+            .call(|| format!("sys.is-member-of({user:?}, {group:?})"));
+        let result = futures::executor::block_on(self.directory.is_member_of(&user, &group));
+        self.trace.record(call, &result);
+        Ok(result)
+    }
+
+    fn render_markdown(&mut self, markdown: String) -> anyhow::Result<String> {
+        Ok(html_text::markdown_to_html(&markdown))
+    }
+
+    fn describe_room(&mut self, room: String) -> anyhow::Result<Option<sys::RoomInfo>> {
+        let call = self.trace.call(|| format!("sys.describe-room({room:?})"));
+        let result = self.describe_room_inner(&room);
+        self.trace.record(call, &result);
+        result
+    }
+
+    fn describe_member(
+        &mut self,
+        room: String,
+        user: String,
+    ) -> anyhow::Result<Option<sys::MemberInfo>> {
+        let call = self
+            .trace
+            .call(|| format!("sys.describe-member({room:?}, {user:?})"));
+        let result = self.describe_member_inner(&room, &user);
+        self.trace.record(call, &result);
+        result
+    }
+
+    fn resolve_room(&mut self, room: String) -> anyhow::Result<Option<String>> {
+        let call = self.trace.call(|| format!("sys.resolve-room({room:?})"));
+        let result = self
+            .room_resolver
+            .resolve_room(&room)
+            .unwrap_or_else(|err| {
+                debug!("{} couldn't resolve {room}: {err:#}", self.module_name);
+                None
+            });
+        self.trace.record(call, &result);
+        Ok(result)
+    }
+
+    fn is_opted_out(&mut self, user: String, room: String) -> anyhow::Result<bool> {
+        let call = self
+            .trace
+            .call(|| format!("sys.is-opted-out({user:?}, {room:?})"));
+        let result = opt_out::is_opted_out(&self.db, &user, &room);
+        self.trace.record(call, &result);
+        Ok(result)
     }
 }
@@ -0,0 +1,145 @@
+//! Tracing of the host calls of a module, with their arguments and results, recorded to see what
+//! it did when replaying an event against it, or logged for a while, when the admin asks for it.
+//! Long values are truncated, and the credentials in the headers and URLs are redacted.
+
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use tracing::{debug, info};
+
+/// Number of characters of a value kept in the trace.
+const MAX_VALUE_CHARS: usize = 500;
+
+/// Headers whose values are redacted, lowercase.
+const SENSITIVE_HEADERS: &[&str] = &[
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+    "x-api-key",
+];
+
+/// Parts of the names of the headers and query parameters whose values are redacted, lowercase.
+const SENSITIVE_PARAMETERS: &[&str] = &["token", "key", "secret", "password", "auth"];
+
+#[derive(Clone)]
+pub(crate) struct HostTrace {
+    module_name: Arc<str>,
+    /// The calls, when they're recorded, e.g. for a replay.
+    calls: Option<Arc<Mutex<Vec<String>>>>,
+    /// Until when the calls are logged, at debug level, if they are.
+    logged_until: Arc<Mutex<Option<Instant>>>,
+}
+
+impl HostTrace {
+    /// A trace of the module's calls, neither recorded nor logged until asked to.
+    pub fn new(module_name: &str) -> Self {
+        Self {
+            module_name: module_name.into(),
+            calls: None,
+            logged_until: Default::default(),
+        }
+    }
+
+    /// A trace recording the calls.
+    pub fn recording(module_name: &str) -> Self {
+        Self {
+            calls: Some(Default::default()),
+            ..Self::new(module_name)
+        }
+    }
+
+    /// Logs the calls until the deadline.
+    pub fn log_until(&self, deadline: Instant) {
+        *self.logged_until.lock().unwrap() = Some(deadline);
+    }
+
+    /// Stops logging the calls; returns whether they were logged.
+    pub fn stop_logging(&self) -> bool {
+        self.logged_until.lock().unwrap().take().is_some()
+    }
+
+    /// Until when the calls are logged, if they are.
+    pub fn logged_until(&self) -> Option<Instant> {
+        let mut logged_until = self.logged_until.lock().unwrap();
+        if logged_until.is_some_and(|deadline| deadline <= Instant::now()) {
+            *logged_until = None;
+            info!("{} - stopped tracing the host calls", self.module_name);
+        }
+        *logged_until
+    }
+
+    /// Describes a call about to be made, if the calls are traced.
+    pub fn call(&self, describe: impl FnOnce() -> String) -> Option<String> {
+        (self.calls.is_some() || self.logged_until().is_some()).then(describe)
+    }
+
+    /// Records a call described with [`Self::call`], with its result.
+    pub fn record(&self, call: Option<String>, result: &impl fmt::Debug) {
+        let Some(call) = call else {
+            return;
+        };
+        let line = format!("{call} -> {}", truncate(&format!("{result:?}")));
+        if self.logged_until().is_some() {
+            debug!("{} - {line}", self.module_name);
+        }
+        if let Some(calls) = &self.calls {
+            calls.lock().unwrap().push(line);
+        }
+    }
+
+    /// The calls recorded so far, oldest first.
+    pub fn calls(&self) -> Vec<String> {
+        self.calls
+            .as_ref()
+            .map(|calls| calls.lock().unwrap().clone())
+            .unwrap_or_default()
+    }
+}
+
+/// Truncates a value to [`MAX_VALUE_CHARS`].
+pub(crate) fn truncate(value: &str) -> String {
+    match value.char_indices().nth(MAX_VALUE_CHARS) {
+        Some((end, _)) => format!("{}... ({} bytes)", &value[..end], value.len()),
+        None => value.to_owned(),
+    }
+}
+
+/// Binary data, shown as text where it is text.
+pub(crate) fn bytes(data: &[u8]) -> String {
+    truncate(&format!("{:?}", String::from_utf8_lossy(data)))
+}
+
+/// Whether a header or query parameter may carry credentials, from its name.
+fn is_sensitive(name: &str) -> bool {
+    let name = name.to_lowercase();
+    SENSITIVE_HEADERS.contains(&name.as_str())
+        || SENSITIVE_PARAMETERS.iter().any(|part| name.contains(part))
+}
+
+/// A URL, with the values of the query parameters carrying credentials redacted.
+pub(crate) fn url(url: &str) -> String {
+    let Some((base, query)) = url.split_once('?') else {
+        return url.to_owned();
+    };
+    let query = query
+        .split('&')
+        .map(|parameter| match parameter.split_once('=') {
+            Some((name, _)) if is_sensitive(name) => format!("{name}=<redacted>"),
+            _ => parameter.to_owned(),
+        })
+        .collect::<Vec<_>>();
+    format!("{base}?{}", query.join("&"))
+}
+
+/// The value of a header, redacted if it carries credentials.
+pub(crate) fn header_value<'a>(key: &str, value: &'a str) -> &'a str {
+    if is_sensitive(key) {
+        "<redacted>"
+    } else {
+        value
+    }
+}
@@ -0,0 +1,52 @@
+//! WASI preview 2 interfaces, so that modules can be built by any toolchain targeting components
+//! (e.g. `wasm32-wasi` with `cargo component`, TinyGo, or `jco componentize` for JavaScript),
+//! which import them for their standard library.
+//!
+//! The context is sandboxed: no preopened directories, environment variables or arguments, and the
+//! modules' standard error is forwarded to the host's. Modules still go through the host APIs to
+//! reach the network or the storage, which their capabilities restrict.
+
+use wasmtime_wasi::preview2::{Table, WasiCtx, WasiCtxBuilder, WasiView};
+
+use crate::wasm::GuestState;
+
+/// WASI packages the host provides; their interfaces don't need capabilities.
+pub(crate) const PACKAGES: &[&str] = &["wasi:cli", "wasi:clocks", "wasi:io", "wasi:random"];
+
+/// WASI packages the host links, for the standard libraries importing them to load, but grants
+/// nothing through: there are no preopened directories, and no network access.
+pub(crate) const DENIED_PACKAGES: &[&str] = &["wasi:filesystem", "wasi:sockets"];
+
+/// WASI state, shared by all the modules since they share a store.
+pub(crate) struct WasiState {
+    ctx: WasiCtx,
+    table: Table,
+}
+
+impl Default for WasiState {
+    fn default() -> Self {
+        Self {
+            ctx: WasiCtxBuilder::new().inherit_stderr().build(),
+            table: Table::new(),
+        }
+    }
+}
+
+impl WasiView for GuestState {
+    fn table(&self) -> &Table {
+        &self.wasi.table
+    }
+    fn table_mut(&mut self) -> &mut Table {
+        &mut self.wasi.table
+    }
+    fn ctx(&self) -> &WasiCtx {
+        &self.wasi.ctx
+    }
+    fn ctx_mut(&mut self) -> &mut WasiCtx {
+        &mut self.wasi.ctx
+    }
+}
+
+pub(super) fn link(linker: &mut wasmtime::component::Linker<GuestState>) -> anyhow::Result<()> {
+    wasmtime_wasi::preview2::command::sync::add_to_linker(linker)
+}
@@ -0,0 +1,92 @@
+//! Cache of the compiled modules, so that a hot reload only compiles the modules whose file
+//! changed. The compiled modules are stored next to the database, or in the configured directory,
+//! keyed by the hash of their file; the entries of the modules that aren't loaded anymore are
+//! removed after each load.
+
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use wasmtime::{component::Component, Engine};
+
+/// Extension of the compiled modules.
+const EXTENSION: &str = "cwasm";
+
+#[derive(Clone)]
+pub(crate) struct ModuleCache {
+    dir: PathBuf,
+}
+
+impl ModuleCache {
+    /// Creates a cache in a directory next to the database at the given path.
+    pub fn next_to(db_path: &Path) -> Self {
+        Self {
+            dir: db_path.with_extension("cwasm-cache"),
+        }
+    }
+
+    /// Creates a cache in the given directory.
+    pub fn in_dir(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    fn entry_path(&self, hash: &str) -> PathBuf {
+        self.dir.join(hash).with_extension(EXTENSION)
+    }
+
+    /// Returns the component of the module file with the given bytes and hash, from the cache if
+    /// it's there, or compiling it and storing it in the cache otherwise.
+    pub fn load(&self, engine: &Engine, bytes: &[u8], hash: &str) -> anyhow::Result<Component> {
+        let path = self.entry_path(hash);
+        if path.is_file() {
+            // SAFETY: the cache only contains what `precompile_component` produced, and
+            // deserializing checks that it's compatible with the engine.
+            match unsafe { Component::deserialize_file(engine, &path) } {
+                Ok(component) => {
+                    tracing::debug!("loaded compiled module {} from the cache", path.display());
+                    return Ok(component);
+                }
+                Err(err) => {
+                    // E.g. compiled by another version of wasmtime, or with other settings.
+                    tracing::debug!("ignoring cached module {}: {err:#}", path.display());
+                }
+            }
+        }
+
+        let compiled = engine.precompile_component(bytes)?;
+        if let Err(err) = self.store(&path, &compiled) {
+            tracing::warn!("couldn't cache compiled module {}: {err:#}", path.display());
+        }
+        // SAFETY: the bytes were just produced by `precompile_component`, with the same engine.
+        unsafe { Component::deserialize(engine, &compiled) }
+    }
+
+    fn store(&self, path: &Path, compiled: &[u8]) -> anyhow::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        // Write then rename, so that a crash never leaves a truncated entry behind.
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, compiled)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// Removes the entries whose hash isn't in `used`.
+    pub fn retain(&self, used: &HashSet<&str>) {
+        let Ok(entries) = std::fs::read_dir(&self.dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let unused = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .is_some_and(|hash| !used.contains(hash));
+            if unused {
+                if let Err(err) = std::fs::remove_file(&path) {
+                    tracing::warn!("couldn't remove cached module {}: {err}", path.display());
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,241 @@
+//! Capabilities of the modules: what a module may do beyond computing responses, as declared in
+//! its manifest and granted in its configuration.
+//!
+//! A module declares its capabilities in a `<name>.manifest.toml` file next to its `.wasm` file:
+//!
+//! ```toml
+//! capabilities = ["storage", "http", "room-send"]
+//! ```
+//!
+//! Without a manifest, a module declares the capabilities its imports need, `room-send` and
+//! `timers`. The grants are the `capabilities` key of the module's configuration, a
+//! comma-separated list; all the capabilities are granted to modules without it.
+
+use std::{collections::BTreeSet, collections::HashMap, fmt, path::Path};
+
+use serde::Deserialize;
+
+use super::{Action, FileInfo};
+
+/// Module configuration key listing the capabilities granted to the module.
+const GRANTS_KEY: &str = "capabilities";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Capability {
+    /// Storing data in the module's key-value store.
+    Storage,
+    /// Sending HTTP requests, and subscribing to streams.
+    Http,
+    /// Sending messages and reactions in rooms.
+    RoomSend,
+    /// Setting timers and recurring jobs.
+    Timers,
+    /// Moderating rooms, e.g. redacting messages.
+    Moderation,
+    /// Setting the rooms' metadata, e.g. their topic.
+    RoomState,
+    /// Publishing to the MQTT broker.
+    Mqtt,
+    /// Emitting events on the bus between modules.
+    Bus,
+    /// Sending emails.
+    Email,
+    /// Raising alerts.
+    Alerts,
+    /// Downloading the content of the media messages.
+    Media,
+}
+
+impl Capability {
+    const ALL: [Capability; 11] = [
+        Capability::Storage,
+        Capability::Http,
+        Capability::RoomSend,
+        Capability::Timers,
+        Capability::Moderation,
+        Capability::RoomState,
+        Capability::Mqtt,
+        Capability::Bus,
+        Capability::Email,
+        Capability::Alerts,
+        Capability::Media,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Capability::Storage => "storage",
+            Capability::Http => "http",
+            Capability::RoomSend => "room-send",
+            Capability::Timers => "timers",
+            Capability::Moderation => "moderation",
+            Capability::RoomState => "room-state",
+            Capability::Mqtt => "mqtt",
+            Capability::Bus => "bus",
+            Capability::Email => "email",
+            Capability::Alerts => "alerts",
+            Capability::Media => "media",
+        }
+    }
+
+    fn parse(name: &str) -> anyhow::Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|cap| cap.name() == name)
+            .ok_or_else(|| anyhow::anyhow!("unknown capability {name}"))
+    }
+
+    /// Host interface the capability gives access to, if any.
+    pub fn interface(self) -> Option<&'static str> {
+        match self {
+            Capability::Storage => Some("trinity:api/kv"),
+            Capability::Http => Some("trinity:api/sync-request"),
+            Capability::Media => Some("trinity:api/media"),
+            Capability::RoomSend
+            | Capability::Timers
+            | Capability::Moderation
+            | Capability::RoomState
+            | Capability::Mqtt
+            | Capability::Bus
+            | Capability::Email
+            | Capability::Alerts => None,
+        }
+    }
+
+    /// Capability needed to use the host interface, if any.
+    fn for_interface(interface: &str) -> Option<Self> {
+        // Ignore the version, e.g. `trinity:api/kv@0.1.0`.
+        let interface = interface.split('@').next().unwrap_or(interface);
+        Self::ALL
+            .into_iter()
+            .find(|cap| cap.interface() == Some(interface))
+    }
+}
+
+#[derive(Deserialize)]
+struct Manifest {
+    capabilities: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Capabilities(BTreeSet<Capability>);
+
+impl Capabilities {
+    pub fn all() -> Self {
+        Self(Capability::ALL.into_iter().collect())
+    }
+
+    fn parse<'a>(names: impl IntoIterator<Item = &'a str>) -> anyhow::Result<Self> {
+        Ok(Self(
+            names
+                .into_iter()
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(Capability::parse)
+                .collect::<anyhow::Result<_>>()?,
+        ))
+    }
+
+    /// Path of the manifest of the module at the given path.
+    pub fn manifest_path(module_path: &Path) -> std::path::PathBuf {
+        module_path.with_extension("manifest.toml")
+    }
+
+    /// Capabilities declared by the module at the given path, checking that they cover what its
+    /// imports need.
+    pub fn declared(module_path: &Path, file_info: &FileInfo) -> anyhow::Result<Self> {
+        let needed = file_info
+            .imports
+            .iter()
+            .filter_map(|import| Capability::for_interface(import));
+
+        let manifest_path = Self::manifest_path(module_path);
+        if !manifest_path.is_file() {
+            let mut declared = Self(needed.collect());
+            declared.0.insert(Capability::RoomSend);
+            declared.0.insert(Capability::Timers);
+            return Ok(declared);
+        }
+
+        let manifest: Manifest = toml::from_str(&std::fs::read_to_string(&manifest_path)?)
+            .map_err(|err| anyhow::anyhow!("invalid {}: {err}", manifest_path.display()))?;
+        let declared = Self::parse(manifest.capabilities.iter().map(String::as_str))?;
+        let undeclared = Self(needed.filter(|cap| !declared.contains(*cap)).collect());
+        if !undeclared.0.is_empty() {
+            anyhow::bail!("the module's imports need undeclared capabilities: {undeclared}");
+        }
+        Ok(declared)
+    }
+
+    /// Capabilities granted to a module by its configuration.
+    pub fn granted(config: Option<&HashMap<String, String>>) -> anyhow::Result<Self> {
+        match config.and_then(|config| config.get(GRANTS_KEY)) {
+            Some(list) => Self::parse(list.split(',')),
+            None => Ok(Self::all()),
+        }
+    }
+
+    pub fn contains(&self, cap: Capability) -> bool {
+        self.0.contains(&cap)
+    }
+
+    /// Capabilities of `self` missing from `other`.
+    pub fn missing_from(&self, other: &Capabilities) -> Capabilities {
+        Self(self.0.difference(&other.0).copied().collect())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Drops the actions the capabilities don't allow.
+    pub fn restrict(&self, module: &str, actions: Vec<Action>) -> Vec<Action> {
+        actions
+            .into_iter()
+            .filter(|action| {
+                let needed = match action {
+                    Action::Respond(_)
+                    | Action::Reply(_)
+                    | Action::React(_)
+                    | Action::Unreact(_)
+                    | Action::SendToRoom(_)
+                    | Action::Dm(_)
+                    | Action::Edit(_)
+                    | Action::Upload(_)
+                    | Action::Progress(_)
+                    | Action::StartPoll(_)
+                    | Action::EndPoll(_)
+                    | Action::Preview(_) => Capability::RoomSend,
+                    Action::Delayed(_) | Action::Schedule(_) | Action::Unschedule(_) => {
+                        Capability::Timers
+                    }
+                    Action::Subscribe(_) | Action::Unsubscribe(_) => Capability::Http,
+                    Action::Publish(_) => Capability::Mqtt,
+                    Action::Emit(_) => Capability::Bus,
+                    Action::SendEmail(_) => Capability::Email,
+                    Action::RaiseAlert(_) => Capability::Alerts,
+                    Action::Redact(_) => Capability::Moderation,
+                    Action::SetTopic(_) | Action::SetRoomName(_) => Capability::RoomState,
+                    Action::FallThrough => return true,
+                };
+                let allowed = self.contains(needed);
+                if !allowed {
+                    tracing::warn!(
+                        "dropping an action of {module}, which lacks the {} capability",
+                        needed.name()
+                    );
+                }
+                allowed
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return f.write_str("none");
+        }
+        let names = self.0.iter().map(|cap| cap.name()).collect::<Vec<_>>();
+        f.write_str(&names.join(", "))
+    }
+}
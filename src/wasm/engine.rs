@@ -0,0 +1,70 @@
+//! Settings of the wasmtime engine the modules are compiled and run with, for operators to trade
+//! memory and compilation time for speed, e.g. on a small VPS rather than a big server.
+
+use std::{path::PathBuf, sync::Once};
+
+use serde::Deserialize;
+use wasmtime::{InstanceAllocationStrategy, PoolingAllocationConfig};
+
+use super::limits::DEFAULT_MAX_MEMORY_MB;
+
+/// Size of a wasm page.
+const PAGE_SIZE: u64 = 64 * 1024;
+
+/// Configuration of the wasmtime engine.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+pub struct WasmEngineConfig {
+    /// whether the modules may use SIMD instructions; enabled by default.
+    pub simd: Option<bool>,
+    /// whether the modules may use reference types; enabled by default.
+    pub reference_types: Option<bool>,
+    /// preallocate the instances of the modules, making (re)loading them faster at the cost of
+    /// reserving memory up front.
+    #[serde(default)]
+    pub pooling_allocator: bool,
+    /// size of the memory of each pooled instance, in MiB; a module's `max_memory_mb` can't be
+    /// larger. Defaults to the default `max_memory_mb`.
+    pub pooling_max_memory_mb: Option<u64>,
+    /// number of threads compiling the modules; 1 compiles them one by one, on the loading
+    /// thread. Defaults to the number of CPUs.
+    pub compilation_threads: Option<usize>,
+    /// directory the compiled modules are cached in; defaults to one next to the database.
+    pub cache_dir: Option<PathBuf>,
+}
+
+/// Creates the engine the modules are compiled with.
+pub(crate) fn new_engine(config: &WasmEngineConfig) -> anyhow::Result<wasmtime::Engine> {
+    let mut engine_config = wasmtime::Config::new();
+    engine_config.wasm_component_model(true);
+    engine_config.consume_fuel(true);
+    engine_config.epoch_interruption(true);
+
+    if let Some(simd) = config.simd {
+        engine_config.wasm_simd(simd);
+    }
+    if let Some(reference_types) = config.reference_types {
+        engine_config.wasm_reference_types(reference_types);
+    }
+    if config.pooling_allocator {
+        let memory_mb = config
+            .pooling_max_memory_mb
+            .unwrap_or(DEFAULT_MAX_MEMORY_MB as u64);
+        let mut pooling = PoolingAllocationConfig::default();
+        pooling.memory_pages(memory_mb * 1024 * 1024 / PAGE_SIZE);
+        engine_config.allocation_strategy(InstanceAllocationStrategy::Pooling(pooling));
+    }
+    match config.compilation_threads {
+        Some(0 | 1) => {
+            engine_config.parallel_compilation(false);
+        }
+        Some(threads) => {
+            // wasmtime compiles on rayon's global thread pool, which is sized from this variable
+            // when it's first used.
+            static SET_THREADS: Once = Once::new();
+            SET_THREADS.call_once(|| std::env::set_var("RAYON_NUM_THREADS", threads.to_string()));
+        }
+        None => {}
+    }
+
+    wasmtime::Engine::new(&engine_config)
+}
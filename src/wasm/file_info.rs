@@ -0,0 +1,84 @@
+//! Static information about a module's file, read without instantiating it.
+
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+use wasmparser::{Parser, Payload};
+
+use super::apis;
+
+pub(crate) struct FileInfo {
+    /// SHA-256 of the file, in hexadecimal.
+    pub hash: String,
+    pub size: usize,
+    /// Interfaces imported by the component, i.e. the host APIs it asks for.
+    pub imports: Vec<String>,
+    /// Interfaces exported by the component.
+    pub exports: Vec<String>,
+}
+
+impl FileInfo {
+    pub fn read(path: &Path) -> anyhow::Result<Self> {
+        Self::parse(&std::fs::read(path)?)
+    }
+
+    pub fn parse(bytes: &[u8]) -> anyhow::Result<Self> {
+        let hash = Sha256::digest(bytes)
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+
+        let mut imports = Vec::new();
+        let mut exports = Vec::new();
+        // Only the outer component's imports and exports matter, not the nested ones'.
+        let mut depth = 0usize;
+        for payload in Parser::new(0).parse_all(bytes) {
+            match payload? {
+                Payload::ModuleSection { .. } | Payload::ComponentSection { .. } => depth += 1,
+                Payload::End(_) => depth = depth.saturating_sub(1),
+                Payload::ComponentImportSection(reader) if depth == 0 => {
+                    for import in reader {
+                        imports.push(import?.name.0.to_owned());
+                    }
+                }
+                Payload::ComponentExportSection(reader) if depth == 0 => {
+                    for export in reader {
+                        exports.push(export?.name.0.to_owned());
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            hash,
+            size: bytes.len(),
+            imports,
+            exports,
+        })
+    }
+
+    /// Returns the imports that the host doesn't provide, linked without access or not at all.
+    pub fn missing_imports(&self) -> Vec<&str> {
+        self.imports
+            .iter()
+            .map(String::as_str)
+            .filter(|import| !apis::provides(interface(import)))
+            .collect()
+    }
+
+    /// Returns the imports that the host links, but grants nothing through.
+    pub fn denied_imports(&self) -> Vec<&str> {
+        self.imports
+            .iter()
+            .map(String::as_str)
+            .filter(|import| apis::denies(interface(import)))
+            .collect()
+    }
+}
+
+/// The interface of an import, without its version, e.g. `trinity:api/kv` for
+/// `trinity:api/kv@0.1.0`.
+fn interface(import: &str) -> &str {
+    import.split('@').next().unwrap_or(import)
+}
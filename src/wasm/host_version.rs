@@ -0,0 +1,78 @@
+//! Minimum version of the host a module may require, in its manifest, next to its capabilities:
+//!
+//! ```toml
+//! capabilities = ["room-send"]
+//! requires_host = ">= 0.2"
+//! ```
+//!
+//! A module needing a newer host, e.g. for APIs or exports this one doesn't have, is refused when
+//! loaded, with a clear message, rather than trapping the first time it calls them.
+
+use std::path::Path;
+
+use serde::Deserialize;
+
+use super::Capabilities;
+
+/// Version of this host.
+pub(crate) const HOST_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[derive(Deserialize)]
+struct Manifest {
+    requires_host: Option<String>,
+}
+
+/// Parses a `major[.minor[.patch]]` version, the missing parts being 0.
+fn parse_version(version: &str) -> anyhow::Result<[u64; 3]> {
+    let mut parts = [0; 3];
+    let mut split = version.trim().split('.');
+    for part in &mut parts {
+        let Some(number) = split.next() else {
+            break;
+        };
+        *part = number
+            .parse()
+            .map_err(|_| anyhow::anyhow!("invalid version {version}"))?;
+    }
+    if split.next().is_some() {
+        anyhow::bail!("invalid version {version}");
+    }
+    Ok(parts)
+}
+
+/// The minimum host version the module at the given path requires, if any.
+pub(crate) fn required(module_path: &Path) -> anyhow::Result<Option<String>> {
+    let manifest_path = Capabilities::manifest_path(module_path);
+    if !manifest_path.is_file() {
+        return Ok(None);
+    }
+    let manifest: Manifest = toml::from_str(&std::fs::read_to_string(&manifest_path)?)
+        .map_err(|err| anyhow::anyhow!("invalid {}: {err}", manifest_path.display()))?;
+    let Some(requirement) = manifest.requires_host else {
+        return Ok(None);
+    };
+    // Only minimum versions are supported, with or without the operator.
+    let version = requirement.trim();
+    let version = version.strip_prefix(">=").unwrap_or(version).trim();
+    parse_version(version).map_err(|err| {
+        anyhow::anyhow!(
+            "invalid requires_host in {}: {err}",
+            manifest_path.display()
+        )
+    })?;
+    Ok(Some(version.to_owned()))
+}
+
+/// Checks that this host is recent enough for the module at the given path.
+pub(crate) fn check(module_path: &Path) -> anyhow::Result<()> {
+    let Some(required) = required(module_path)? else {
+        return Ok(());
+    };
+    if parse_version(HOST_VERSION)? < parse_version(&required)? {
+        anyhow::bail!(
+            "the module requires host version {required} or newer, this host is {HOST_VERSION}; \
+             upgrade the host to use it"
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,163 @@
+//! Limits on the resources a module may use in a single call: fuel, i.e. roughly the number of
+//! instructions it may run, so that a module stuck in a loop can't hold the app's lock forever, the
+//! size of its memory, and wall-clock time, enforced with wasmtime's epoch interruption.
+//!
+//! They're configured per module, with the `fuel_per_call`, `max_memory_mb` and
+//! `call_timeout_secs` keys of its configuration.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use wasmtime::{Engine, ResourceLimiter, Trap};
+
+use super::GuestState;
+
+/// Module configuration key setting the fuel of a call.
+const FUEL_KEY: &str = "fuel_per_call";
+/// Module configuration key setting the maximum size of the memory, in MiB.
+const MEMORY_KEY: &str = "max_memory_mb";
+/// Module configuration key setting the timeout of a call, in seconds.
+const TIMEOUT_KEY: &str = "call_timeout_secs";
+/// Fuel of a call, unless configured otherwise: a few seconds of computation.
+const DEFAULT_FUEL: u64 = 10_000_000_000;
+/// Maximum size of a module's memory, unless configured otherwise.
+pub(super) const DEFAULT_MAX_MEMORY_MB: usize = 256;
+/// Timeout of a call, unless configured otherwise.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Interval at which the engine's epoch is incremented, i.e. the granularity of the timeouts.
+const EPOCH_TICK: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Limits {
+    fuel: u64,
+    max_memory_mb: usize,
+    timeout: Duration,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            fuel: DEFAULT_FUEL,
+            max_memory_mb: DEFAULT_MAX_MEMORY_MB,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+}
+
+impl Limits {
+    pub fn new(
+        module_name: &str,
+        config: Option<&HashMap<String, String>>,
+    ) -> anyhow::Result<Self> {
+        let mut limits = Self::default();
+        if let Some(fuel) = config.and_then(|config| config.get(FUEL_KEY)) {
+            limits.fuel = fuel.trim().parse().map_err(|err| {
+                anyhow::anyhow!("invalid {FUEL_KEY} for module {module_name}: {err}")
+            })?;
+        }
+        if let Some(mb) = config.and_then(|config| config.get(MEMORY_KEY)) {
+            limits.max_memory_mb = mb.trim().parse().map_err(|err| {
+                anyhow::anyhow!("invalid {MEMORY_KEY} for module {module_name}: {err}")
+            })?;
+        }
+        if let Some(secs) = config.and_then(|config| config.get(TIMEOUT_KEY)) {
+            limits.timeout = Duration::from_secs(secs.trim().parse().map_err(|err| {
+                anyhow::anyhow!("invalid {TIMEOUT_KEY} for module {module_name}: {err}")
+            })?);
+        }
+        Ok(limits)
+    }
+
+    pub fn fuel(&self) -> u64 {
+        self.fuel
+    }
+
+    pub fn max_memory_bytes(&self) -> usize {
+        self.max_memory_mb.saturating_mul(1024 * 1024)
+    }
+
+    /// Number of epoch ticks after which a call times out.
+    pub fn epoch_deadline(&self) -> u64 {
+        let ticks = self.timeout.as_millis().div_ceil(EPOCH_TICK.as_millis());
+        u64::try_from(ticks).unwrap_or(u64::MAX).max(1)
+    }
+
+    /// Turns running out of fuel or time into an error explaining it.
+    pub fn explain(&self, err: anyhow::Error) -> anyhow::Error {
+        match err.downcast_ref::<Trap>() {
+            Some(Trap::OutOfFuel) => anyhow::anyhow!(
+                "ran out of fuel ({} units per call), it may be stuck in a loop",
+                self.fuel
+            ),
+            Some(Trap::Interrupt) => anyhow::anyhow!(
+                "timed out after {} seconds, it may be stuck in a loop",
+                self.timeout.as_secs_f32()
+            ),
+            _ => err,
+        }
+    }
+}
+
+/// The memory limit is the one of the module being called, since all the modules share a store.
+impl ResourceLimiter for GuestState {
+    fn memory_growing(
+        &mut self,
+        _current: usize,
+        desired: usize,
+        _maximum: Option<usize>,
+    ) -> anyhow::Result<bool> {
+        let max = self.limits.max_memory_bytes();
+        if desired > max {
+            // Trap rather than failing the allocation, which modules rarely handle gracefully.
+            anyhow::bail!(
+                "exceeded the memory limit of {} MiB ({desired} bytes requested)",
+                max / (1024 * 1024)
+            );
+        }
+        Ok(true)
+    }
+
+    fn table_growing(
+        &mut self,
+        _current: u32,
+        _desired: u32,
+        _maximum: Option<u32>,
+    ) -> anyhow::Result<bool> {
+        Ok(true)
+    }
+}
+
+/// Increments the epoch of an engine at a regular interval, for the timeouts of the calls, until
+/// it's dropped.
+pub(crate) struct EpochTicker {
+    stop: Arc<AtomicBool>,
+}
+
+impl EpochTicker {
+    pub fn start(engine: &Engine) -> anyhow::Result<Self> {
+        let stop = Arc::new(AtomicBool::new(false));
+        let engine = engine.clone();
+        let stopped = stop.clone();
+        std::thread::Builder::new()
+            .name("wasm-epoch".to_owned())
+            .spawn(move || {
+                while !stopped.load(Ordering::Relaxed) {
+                    std::thread::sleep(EPOCH_TICK);
+                    engine.increment_epoch();
+                }
+            })?;
+        Ok(Self { stop })
+    }
+}
+
+impl Drop for EpochTicker {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,138 @@
+//! Replays of an event against a module, in a fresh instance of it, sandboxed: its writes to the
+//! storage and its requests other than `GET` have no effect, its random numbers are the same from
+//! one replay to the next, and its actions are listed rather than carried out. Every host call it
+//! makes is traced, with its arguments and results.
+
+use std::{collections::HashMap, fmt::Write as _, path::Path, sync::Arc, time::Instant};
+
+use matrix_sdk::{
+    ruma::{OwnedRoomId, OwnedUserId},
+    Client,
+};
+
+use super::{
+    apis::{Apis, HostTrace},
+    checked_capabilities, host_version, instantiate, new_engine, EpochTicker, FileInfo, GuestState,
+    Media, WasmEngineConfig,
+};
+use crate::{directory::Directory, ShareableDatabase};
+
+/// What triggered the module.
+pub(crate) enum Trigger {
+    /// A text message, passed to `on-msg`.
+    Message(String),
+    /// A media message, passed to `on-media`.
+    Media(Media),
+}
+
+/// An event to replay.
+pub(crate) struct ReplayedEvent {
+    pub trigger: Trigger,
+    pub sender: OwnedUserId,
+    pub room: OwnedRoomId,
+    /// Trust level of the sender, as the module is passed it.
+    pub trust: u8,
+}
+
+fn write_calls(out: &mut String, title: &str, calls: &[String]) {
+    let _ = writeln!(out, "\n{title}:");
+    if calls.is_empty() {
+        out.push_str("  none\n");
+    }
+    for call in calls {
+        let _ = writeln!(out, "  {call}");
+    }
+}
+
+/// Replays the event against the module file at `path`, with its configuration; returns the
+/// trace of the replay.
+///
+/// Must be called from a blocking context.
+pub(crate) fn replay(
+    engine_config: &WasmEngineConfig,
+    path: &Path,
+    config: Option<&HashMap<String, String>>,
+    db: ShareableDatabase,
+    directory: Arc<Directory>,
+    client: Client,
+    event: &ReplayedEvent,
+) -> anyhow::Result<String> {
+    let engine = new_engine(engine_config)?;
+    let _epoch_ticker = EpochTicker::start(&engine)?;
+    let mut store = wasmtime::Store::new(&engine, GuestState::default());
+    store.limiter(|state| state as &mut dyn wasmtime::ResourceLimiter);
+
+    let bytes = std::fs::read(path)?;
+    let file_info = FileInfo::parse(&bytes)?;
+    host_version::check(path)?;
+    let capabilities = checked_capabilities(path, &file_info, config)?;
+
+    let mut out = format!("replay of {}\n", path.display());
+    let _ = writeln!(out, "sha256: {}", file_info.hash);
+    let _ = writeln!(out, "capabilities: {capabilities}");
+    let _ = writeln!(out, "sender: {} (trust {})", event.sender, event.trust);
+    let _ = writeln!(out, "room: {}", event.room);
+    match &event.trigger {
+        Trigger::Message(content) => {
+            let _ = writeln!(out, "message: {content:?}");
+        }
+        Trigger::Media(media) => {
+            let _ = writeln!(out, "media: {media:?}");
+        }
+    }
+
+    let name = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let trace = HostTrace::recording(&name);
+    let apis = Apis::new(name, db, directory, client, config, trace.clone(), true)?;
+    // A fresh compilation, not to depend on the cache.
+    let component = wasmtime::component::Component::new(&engine, &bytes)?;
+    let module = instantiate(
+        &mut store,
+        apis,
+        path.to_owned(),
+        file_info,
+        capabilities,
+        &component,
+        config,
+    )?;
+    let init_calls = trace.calls();
+    write_calls(&mut out, "host calls during init", &init_calls);
+
+    let start = Instant::now();
+    let result = match &event.trigger {
+        Trigger::Message(content) => {
+            module.handle(&mut store, content, &event.sender, &event.room, event.trust)
+        }
+        Trigger::Media(media) => {
+            module.on_media(&mut store, media, &event.sender, &event.room, event.trust)
+        }
+    };
+    let elapsed = start.elapsed();
+    let fuel_left = store.get_fuel().unwrap_or_default();
+
+    write_calls(&mut out, "host calls", &trace.calls()[init_calls.len()..]);
+    match result {
+        Ok(actions) => {
+            let _ = writeln!(out, "\nactions, not carried out:");
+            if actions.is_empty() {
+                out.push_str("  none\n");
+            }
+            for action in actions {
+                let _ = writeln!(out, "  {action:?}");
+            }
+        }
+        Err(err) => {
+            let _ = writeln!(out, "\nerror: {err:#}");
+        }
+    }
+    let _ = writeln!(
+        out,
+        "\ntook {} ms, {} fuel",
+        elapsed.as_millis(),
+        module.limits.fuel().saturating_sub(fuel_left)
+    );
+    Ok(out)
+}
@@ -0,0 +1,151 @@
+//! HTTP listener for the webhooks of external services, e.g. monitoring systems raising alerts.
+//!
+//! Requests must carry the configured token or an API key with the `send` scope (`metrics` for
+//! `/ws`), either as a bearer token in the `Authorization` header, or in the `token` query
+//! parameter for services that can't set headers.
+//!
+//! `/ws` streams the bot's activity live over a WebSocket, see [`crate::live_events`].
+
+use std::net::SocketAddr;
+
+use hyper::{body::HttpBody as _, Body, Method, Request, Response, StatusCode};
+use matrix_sdk::Client;
+use serde::Deserialize;
+use tracing::{error, warn};
+
+use crate::{
+    alertmanager::Notification, alerts::NewAlert, api_keys::Scope, live_events, App, ListenConfig,
+};
+
+/// Configuration for the webhooks listener.
+#[derive(Clone, Debug, Deserialize)]
+pub struct WebhooksConfig {
+    /// secret the requests can carry; without it, only the API keys are accepted.
+    pub token: Option<String>,
+}
+
+/// Address the listener binds to, unless configured otherwise.
+const DEFAULT_ADDRESS: ([u8; 4], u16) = ([0, 0, 0, 0], 43211);
+/// Largest request body accepted.
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+
+pub(crate) fn respond(status: StatusCode, body: &str) -> Response<Body> {
+    let mut response = Response::new(Body::from(body.to_owned()));
+    *response.status_mut() = status;
+    response
+}
+
+/// Whether the request carries the configured token, or an API key allowing the scope.
+fn is_authorized(app: &App, config: &WebhooksConfig, req: &Request<Body>, scope: Scope) -> bool {
+    let bearer = req
+        .headers()
+        .get(hyper::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let query = req.uri().query().and_then(|query| {
+        query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("token="))
+    });
+    let Some(token) = bearer.or(query) else {
+        return false;
+    };
+    config.token.as_deref() == Some(token) || app.api_keys.authorize(token, scope, None)
+}
+
+/// Reads the body of the request, up to [`MAX_BODY_BYTES`].
+pub(crate) async fn read_body(req: Request<Body>) -> anyhow::Result<Vec<u8>> {
+    let mut body = req.into_body();
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        bytes.extend_from_slice(&chunk?);
+        anyhow::ensure!(bytes.len() <= MAX_BODY_BYTES, "body too large");
+    }
+    Ok(bytes)
+}
+
+async fn handle(
+    app: &App,
+    client: &Client,
+    config: &WebhooksConfig,
+    req: Request<Body>,
+) -> Response<Body> {
+    let scope = if req.uri().path() == "/ws" {
+        Scope::Metrics
+    } else {
+        Scope::Send
+    };
+    if !is_authorized(app, config, &req, scope) {
+        return respond(StatusCode::UNAUTHORIZED, "missing or invalid token");
+    }
+    if req.uri().path() == "/ws" && req.method() == Method::GET {
+        return live_events::upgrade(app, req);
+    }
+    if req.method() != Method::POST {
+        return respond(StatusCode::METHOD_NOT_ALLOWED, "only POST is supported");
+    }
+
+    let path = req.uri().path().to_owned();
+    let body = match read_body(req).await {
+        Ok(body) => body,
+        Err(err) => return respond(StatusCode::BAD_REQUEST, &format!("{err}")),
+    };
+
+    match path.as_str() {
+        "/alerts" => {
+            let alert: NewAlert = match serde_json::from_slice(&body) {
+                Ok(alert) => alert,
+                Err(err) => return respond(StatusCode::BAD_REQUEST, &format!("{err}")),
+            };
+            match app.alerts.raise(client, "webhook", alert).await {
+                Ok(()) => respond(StatusCode::OK, "ok"),
+                Err(err) => {
+                    warn!("couldn't raise an alert from a webhook: {err:#}");
+                    respond(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "couldn't raise the alert",
+                    )
+                }
+            }
+        }
+        "/alertmanager" if app.alertmanager.is_enabled() => {
+            let notification: Notification = match serde_json::from_slice(&body) {
+                Ok(notification) => notification,
+                Err(err) => return respond(StatusCode::BAD_REQUEST, &format!("{err}")),
+            };
+            match app.alertmanager.receive(client, notification).await {
+                Ok(()) => respond(StatusCode::OK, "ok"),
+                Err(err) => {
+                    warn!("couldn't post an Alertmanager notification: {err:#}");
+                    respond(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "couldn't post the notification",
+                    )
+                }
+            }
+        }
+        _ => respond(StatusCode::NOT_FOUND, "unknown webhook"),
+    }
+}
+
+/// Serves the webhooks, if they're configured.
+pub(crate) async fn run(
+    app: App,
+    client: Client,
+    config: Option<WebhooksConfig>,
+    listen: ListenConfig,
+) {
+    let Some(config) = config else {
+        return;
+    };
+    let handler = move |req| {
+        let (app, client, config) = (app.clone(), client.clone(), config.clone());
+        async move { handle(&app, &client, &config, req).await }
+    };
+    if let Err(err) = listen
+        .serve("webhooks", SocketAddr::from(DEFAULT_ADDRESS), handler)
+        .await
+    {
+        error!("couldn't serve the webhooks: {err:#}");
+    }
+}